@@ -0,0 +1,70 @@
+// Completion latency benchmark - end-to-end `get_completions` on a large file
+//
+// Run with `cargo bench --bench completion_latency`. The program is parsed
+// once; each sample times a single `get_completions` call, and p50/p95 are
+// reported per cursor position.
+//
+// Baseline expectation (release build, recent desktop CPU): every position
+// stays well under 50ms at p95. A p95 above that on the same machine means a
+// caching or laziness regression in the completion path.
+
+use std::time::{Duration, Instant};
+
+use pain_compiler::parse_with_recovery;
+use tower_lsp::lsp_types::Position;
+
+const SAMPLES: usize = 200;
+const WARMUP: usize = 10;
+
+// Same shape as the stress tests' 1000-function file, plus a `main` using it
+fn build_program() -> String {
+    let mut code = String::new();
+    for i in 0..1000 {
+        code.push_str(&format!("fn func_{}() -> int:\n    return {}\n\n", i, i));
+    }
+    code.push_str("fn main():\n    let total = func_0()\n    let label = \"sum\"\n    print(total)\n    \n");
+    code
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let index = ((sorted.len() as f64 - 1.0) * p).round() as usize;
+    sorted[index]
+}
+
+fn main() {
+    let code = build_program();
+    let (parse_result, _) = parse_with_recovery(&code);
+    let program = parse_result.expect("benchmark program should parse");
+    let backend = pain_lsp::Backend::detached();
+
+    let main_line = 3000;
+    let positions = [
+        ("start of main body", Position { line: main_line + 4, character: 4 }),
+        ("after `let total = `", Position { line: main_line + 1, character: 16 }),
+        ("inside func_500", Position { line: 1501, character: 4 }),
+        ("end of file", Position { line: main_line + 5, character: 0 }),
+    ];
+
+    println!("{:<24} {:>8} {:>12} {:>12}", "position", "items", "p50", "p95");
+    for (name, position) in positions {
+        for _ in 0..WARMUP {
+            std::hint::black_box(backend.get_completions(&program, &code, position));
+        }
+        let mut samples = Vec::with_capacity(SAMPLES);
+        let mut items = 0;
+        for _ in 0..SAMPLES {
+            let start = Instant::now();
+            let result = std::hint::black_box(backend.get_completions(&program, &code, position));
+            samples.push(start.elapsed());
+            items = result.len();
+        }
+        samples.sort();
+        println!(
+            "{:<24} {:>8} {:>12?} {:>12?}",
+            name,
+            items,
+            percentile(&samples, 0.50),
+            percentile(&samples, 0.95)
+        );
+    }
+}
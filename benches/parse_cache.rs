@@ -0,0 +1,64 @@
+// Parse cache benchmark - what a cache hit costs now that the program is
+// shared, compared with the deep clone it used to make
+//
+// Run with `cargo bench --bench parse_cache`. Both variants take the same
+// 1000-function program from the cache; p50/p95 are reported per variant.
+//
+// Baseline expectation: a shared hit stays in the nanoseconds regardless of
+// program size, while a deep clone grows with it.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const SAMPLES: usize = 200;
+const WARMUP: usize = 10;
+
+// Same 1000-function file as the stress tests
+fn build_program() -> String {
+    let mut code = String::new();
+    for i in 0..1000 {
+        code.push_str(&format!("fn func_{}() -> int:\n    return {}\n\n", i, i));
+    }
+    code
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let index = ((sorted.len() as f64 - 1.0) * p).round() as usize;
+    sorted[index]
+}
+
+fn measure(mut run: impl FnMut()) -> Vec<Duration> {
+    for _ in 0..WARMUP {
+        run();
+    }
+    let mut samples: Vec<Duration> = (0..SAMPLES)
+        .map(|_| {
+            let start = Instant::now();
+            run();
+            start.elapsed()
+        })
+        .collect();
+    samples.sort();
+    samples
+}
+
+fn main() {
+    let code = build_program();
+    let backend = pain_lsp::Backend::detached();
+    let uri = url::Url::parse("file:///bench.pain").unwrap();
+    let program = tokio_test::block_on(backend.get_or_parse(&uri, &code))
+        .program
+        .expect("benchmark program should parse");
+
+    let deep_clone = measure(|| {
+        std::hint::black_box((*program).clone());
+    });
+    let shared = measure(|| {
+        std::hint::black_box(Arc::clone(&program));
+    });
+
+    println!("{:<12} {:>12} {:>12}", "cache hit", "p50", "p95");
+    for (name, samples) in [("deep clone", deep_clone), ("shared", shared)] {
+        println!("{:<12} {:>12?} {:>12?}", name, percentile(&samples, 0.50), percentile(&samples, 0.95));
+    }
+}
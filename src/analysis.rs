@@ -0,0 +1,773 @@
+// Program analysis shared by navigation, hover and completion
+//
+// The AST only carries spans for functions, so exact positions of names are
+// recovered from the lexer token stream (see lexer.rs). Lines in the AST are
+// 1-based; everything in this module uses 0-based LSP lines.
+
+use crate::consteval::{fold_tokens, ConstValue};
+use crate::lexer::{token_at, tokenize, Token, TokenKind};
+use crate::position::clamp_to_u32;
+use pain_compiler::{ast::*, span::Span, stdlib::get_stdlib_functions};
+use tower_lsp::lsp_types::{Position, Range};
+
+// Limit for recursive type inference (`let a = b`, `let b = c`, ...)
+const MAX_INFERENCE_DEPTH: usize = 8;
+
+/// A field declared with `let name: Type` directly in a class body
+#[derive(Debug, Clone)]
+pub struct FieldSite {
+    pub name: String,
+    pub range: Range,
+    pub ty: Option<Type>,
+    /// A `let` field initialized in the class body - the same for every instance
+    pub constant: bool,
+}
+
+/// Declaration a name resolves to
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedSymbol {
+    /// Range of the declared name
+    pub range: Range,
+    /// Qualified path within the document, e.g. `["Point", "distance"]`
+    pub path: Vec<String>,
+    /// Locals and parameters are not visible outside their function
+    pub local: bool,
+}
+
+impl ResolvedSymbol {
+    fn exported(range: Range, path: Vec<String>) -> Self {
+        Self { range, path, local: false }
+    }
+}
+
+/// Kind of the innermost node under a position
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    /// A class: its name in the `class` header, or anywhere else in its body
+    Class,
+    /// A function or method: its name in the `fn` header, or anywhere else in its body
+    Function,
+    Parameter,
+    /// Name bound by `let`, `var` or `for`
+    Declaration,
+    /// Name followed by `(`
+    Call,
+    /// Name after `.`
+    Member,
+    /// Any other use of a name
+    Identifier,
+}
+
+/// Innermost node containing a position, with the function and class around it
+#[derive(Debug, Clone)]
+pub struct NodeRef<'a> {
+    pub kind: NodeKind,
+    pub name: String,
+    /// The name under the cursor, or the whole item when the cursor is not on a name
+    pub range: Range,
+    pub function: Option<&'a Function>,
+    pub class: Option<&'a Class>,
+}
+
+/// Innermost node at the 0-based line and byte column - see `DocumentIndex::node_at`
+pub fn node_at_position<'a>(program: &'a Program, text: &str, line: usize, column: usize) -> Option<NodeRef<'a>> {
+    DocumentIndex::new(program, text).node_at(Position { line: clamp_to_u32(line), character: clamp_to_u32(column) })
+}
+
+/// Program plus its token stream, for position-based queries on one document
+pub struct DocumentIndex<'a> {
+    pub program: &'a Program,
+    pub text: &'a str,
+    pub tokens: Vec<Token>,
+}
+
+impl<'a> DocumentIndex<'a> {
+    pub fn new(program: &'a Program, text: &'a str) -> Self {
+        Self {
+            program,
+            text,
+            tokens: tokenize(text),
+        }
+    }
+
+    /// Index of the token under the cursor
+    pub fn token_index_at(&self, position: Position) -> Option<usize> {
+        let token = token_at(&self.tokens, position.line as usize, position.character as usize)?;
+        self.tokens.iter().position(|t| std::ptr::eq(t, token))
+    }
+
+    /// Function or method whose body contains the given line, with its class for methods
+    pub fn enclosing_function(&self, line: usize) -> Option<(&'a Function, Option<&'a Class>)> {
+        for item in &self.program.items {
+            match item {
+                Item::Function(func) if function_contains_line(func, line) => {
+                    return Some((func, None));
+                }
+                Item::Class(class) => {
+                    if let Some(method) = class.methods.iter().find(|m| function_contains_line(m, line)) {
+                        return Some((method, Some(class)));
+                    }
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    pub fn find_class(&self, name: &str) -> Option<&'a Class> {
+        self.program.items.iter().find_map(|item| match item {
+            Item::Class(class) if class.name == name => Some(class),
+            _ => None,
+        })
+    }
+
+    pub fn find_function(&self, name: &str) -> Option<&'a Function> {
+        self.program.items.iter().find_map(|item| match item {
+            Item::Function(func) if func.name == name => Some(func),
+            _ => None,
+        })
+    }
+
+    pub fn class_of(&self, ty: &Type) -> Option<&'a Class> {
+        match ty {
+            Type::Named(name) => self.find_class(name),
+            _ => None,
+        }
+    }
+
+    /// Range of the name in the `class Name` header
+    pub fn class_name_range(&self, class: &Class) -> Option<Range> {
+        self.tokens
+            .windows(2)
+            .find(|pair| pair[0].is_keyword("class") && pair[1].is_ident(&class.name))
+            .map(|pair| token_range(&pair[1]))
+    }
+
+    /// Ranges of every `class name` header with the given name, in document order
+    pub fn class_name_ranges(&self, name: &str) -> Vec<Range> {
+        self.tokens
+            .windows(2)
+            .filter(|pair| pair[0].is_keyword("class") && pair[1].is_ident(name))
+            .map(|pair| token_range(&pair[1]))
+            .collect()
+    }
+
+    /// Range of the name in the `fn name(...)` header
+    pub fn function_name_range(&self, func: &Function) -> Option<Range> {
+        let first_line = func.span.start.line.saturating_sub(1);
+        self.tokens
+            .windows(2)
+            .find(|pair| pair[0].line >= first_line && pair[0].is_keyword("fn") && pair[1].is_ident(&func.name))
+            .map(|pair| token_range(&pair[1]))
+    }
+
+    /// First and last line of a class body, derived from indentation
+    pub fn class_lines(&self, class: &Class) -> Option<(usize, usize)> {
+        let header = self.class_name_range(class)?.start.line as usize;
+        Some((header, block_end_line(self.text, header)))
+    }
+
+    /// Fields declared directly in the class body (not inside its methods)
+    pub fn class_fields(&self, class: &Class) -> Vec<FieldSite> {
+        let Some((header, last)) = self.class_lines(class) else { return Vec::new() };
+
+        self.tokens
+            .iter()
+            .enumerate()
+            .filter(|(i, token)| {
+                token.kind == TokenKind::Ident
+                    && token.line > header
+                    && token.line <= last
+                    && *i > 0
+                    && matches!(self.tokens[i - 1].text.as_str(), "let" | "var")
+                    && self.tokens[i - 1].kind == TokenKind::Keyword
+                    && !class.methods.iter().any(|m| function_contains_line(m, token.line))
+            })
+            .map(|(i, token)| FieldSite {
+                name: token.text.clone(),
+                range: token_range(token),
+                ty: self.annotation_after(i),
+                constant: self.tokens[i - 1].text == "let"
+                    && self.tokens[i + 1..]
+                        .iter()
+                        .take_while(|next| next.line == token.line)
+                        .any(|next| next.is_punct("=")),
+            })
+            .collect()
+    }
+
+    /// Type of `.member` on an instance of the class: a field's type or a method's return type
+    pub fn member_type(&self, class: &Class, member: &str) -> Option<Type> {
+        if let Some(method) = class.methods.iter().find(|m| m.name == member) {
+            return method.return_type.clone();
+        }
+        self.class_fields(class)
+            .into_iter()
+            .find(|field| field.name == member)
+            .and_then(|field| field.ty)
+    }
+
+    /// Token index of the latest `let`/`var`/`for` binding of `name` in the function at or before `line`
+    pub fn local_declaration(&self, func: &Function, name: &str, line: usize) -> Option<usize> {
+        let first_line = func.span.start.line.saturating_sub(1);
+        (1..self.tokens.len())
+            .filter(|&i| {
+                let token = &self.tokens[i];
+                token.is_ident(name)
+                    && token.line >= first_line
+                    && token.line <= line
+                    && matches!(self.tokens[i - 1].text.as_str(), "let" | "var" | "for")
+                    && self.tokens[i - 1].kind == TokenKind::Keyword
+            })
+            .last()
+    }
+
+    /// Value of a local's initializer when it is a constant expression
+    pub fn constant_value(&self, func: &Function, name: &str, line: usize) -> Option<ConstValue> {
+        let i = self.local_declaration(func, name, line)?;
+        if self.tokens[i - 1].is_keyword("for") {
+            return None;
+        }
+        let decl_line = self.tokens[i].line;
+        let on_line: Vec<&Token> = self.tokens[i + 1..].iter().take_while(|t| t.line == decl_line).collect();
+        let assign = on_line.iter().position(|t| t.is_punct("="))?;
+        fold_tokens(&on_line[assign + 1..])
+    }
+
+    /// Declared or inferred type of a variable visible at `line`
+    pub fn variable_type(
+        &self,
+        func: &Function,
+        class: Option<&Class>,
+        name: &str,
+        line: usize,
+    ) -> Option<Type> {
+        self.variable_type_with_depth(func, class, name, line, 0)
+    }
+
+    fn variable_type_with_depth(
+        &self,
+        func: &Function,
+        class: Option<&Class>,
+        name: &str,
+        line: usize,
+        depth: usize,
+    ) -> Option<Type> {
+        if depth > MAX_INFERENCE_DEPTH {
+            return None;
+        }
+        if name == "self" {
+            return class.map(|class| Type::Named(class.name.clone()));
+        }
+
+        if let Some(i) = self.local_declaration(func, name, line) {
+            if self.tokens[i - 1].is_keyword("for") {
+                // `for name in expr:` - the element type of what is iterated
+                let in_keyword = self.tokens.get(i + 1)?;
+                if !in_keyword.is_keyword("in") || in_keyword.line != self.tokens[i].line {
+                    return None;
+                }
+                let decl_line = self.tokens[i].line.saturating_sub(1);
+                let iterated = self.infer_expression_type_with_depth(i + 2, func, class, decl_line, depth + 1)?;
+                return element_type(&iterated);
+            }
+            if let Some(ty) = self.annotation_after(i) {
+                return Some(ty);
+            }
+            let next = self.tokens.get(i + 1)?;
+            if next.is_punct("=") && next.line == self.tokens[i].line {
+                // Infer from the initializer, looking only at earlier lines to avoid cycles
+                let decl_line = self.tokens[i].line.saturating_sub(1);
+                return self.infer_expression_type_with_depth(i + 2, func, class, decl_line, depth + 1);
+            }
+            return None;
+        }
+
+        func.params
+            .iter()
+            .find(|param| param.name == name)
+            .map(|param| param.ty.clone())
+    }
+
+    /// Infer the type of the expression starting at token `start` and ending at end of line.
+    /// Variables are resolved as visible at `line`.
+    pub fn infer_expression_type(
+        &self,
+        start: usize,
+        func: &Function,
+        class: Option<&Class>,
+        line: usize,
+    ) -> Option<Type> {
+        self.infer_expression_type_with_depth(start, func, class, line, 0)
+    }
+
+    fn infer_expression_type_with_depth(
+        &self,
+        start: usize,
+        func: &Function,
+        class: Option<&Class>,
+        line: usize,
+        depth: usize,
+    ) -> Option<Type> {
+        if depth > MAX_INFERENCE_DEPTH {
+            return None;
+        }
+        let first = self.tokens.get(start)?;
+        let end = self.tokens[start..]
+            .iter()
+            .position(|t| t.line != first.line || t.kind == TokenKind::Comment)
+            .map_or(self.tokens.len(), |offset| start + offset);
+        let expr = &self.tokens[start..end];
+
+        if has_top_level_comparison(expr) {
+            return Some(Type::Bool);
+        }
+
+        match first.kind {
+            TokenKind::Number if first.text.contains('.') => Some(Type::Float64),
+            TokenKind::Number => Some(Type::Int),
+            TokenKind::String => Some(Type::Str),
+            TokenKind::Keyword if first.text == "true" || first.text == "false" => Some(Type::Bool),
+            TokenKind::Punct if first.text == "(" => {
+                self.infer_expression_type_with_depth(start + 1, func, class, line, depth + 1)
+            }
+            TokenKind::Punct if first.text == "[" => {
+                let element = match expr.get(1) {
+                    Some(t) if !t.is_punct("]") => {
+                        self.infer_expression_type_with_depth(start + 1, func, class, line, depth + 1)
+                    }
+                    _ => None,
+                };
+                Some(Type::List(Box::new(element.unwrap_or(Type::Dynamic))))
+            }
+            TokenKind::Punct if first.text == "{" => {
+                let key = match expr.get(1) {
+                    Some(t) if !t.is_punct("}") => {
+                        self.infer_expression_type_with_depth(start + 1, func, class, line, depth + 1)
+                    }
+                    _ => None,
+                };
+                let value = expr
+                    .iter()
+                    .position(|t| t.is_punct(":"))
+                    .and_then(|colon| {
+                        self.infer_expression_type_with_depth(start + colon + 1, func, class, line, depth + 1)
+                    });
+                Some(Type::Map(
+                    Box::new(key.unwrap_or(Type::Dynamic)),
+                    Box::new(value.unwrap_or(Type::Dynamic)),
+                ))
+            }
+            TokenKind::Ident => self.infer_path_type(expr, func, class, line, depth),
+            _ => None,
+        }
+    }
+
+    // Type of `name`, `name(...)`, `Class.method(...)` or `value.member` chains
+    fn infer_path_type(
+        &self,
+        expr: &[Token],
+        func: &Function,
+        class: Option<&Class>,
+        line: usize,
+        depth: usize,
+    ) -> Option<Type> {
+        let name = expr[0].text.as_str();
+        let is_call = expr.get(1).is_some_and(|t| t.is_punct("("));
+
+        let mut ty = if is_call {
+            if let Some(class) = self.find_class(name) {
+                Some(Type::Named(class.name.clone()))
+            } else if let Some(func) = self.find_function(name) {
+                func.return_type.clone()
+            } else {
+                get_stdlib_functions()
+                    .into_iter()
+                    .find(|f| f.name == name)
+                    .map(|f| f.return_type)
+            }
+        } else if let Some(class) = self.find_class(name) {
+            // Static access like `Point.new`
+            Some(Type::Named(class.name.clone()))
+        } else {
+            self.variable_type_with_depth(func, class, name, line, depth + 1)
+        };
+
+        // Follow `.member` accesses at the top level of the expression
+        let mut i = if is_call { skip_group(expr, 1) } else { 1 };
+        while i + 1 < expr.len() && expr[i].is_punct(".") && expr[i + 1].kind == TokenKind::Ident {
+            let receiver = self.class_of(ty.as_ref()?)?;
+            ty = self.member_type(receiver, &expr[i + 1].text);
+            i += 2;
+            if expr.get(i).is_some_and(|t| t.is_punct("(")) {
+                i = skip_group(expr, i);
+            }
+        }
+        ty
+    }
+
+    /// Parse a type annotation following the identifier at token `i` (`name: Type`)
+    pub fn annotation_after(&self, i: usize) -> Option<Type> {
+        let colon = self.tokens.get(i + 1)?;
+        if !colon.is_punct(":") || colon.line != self.tokens[i].line {
+            return None;
+        }
+        parse_type_tokens(&self.tokens, i + 2).map(|(ty, _)| ty)
+    }
+
+    /// Class of the receiver expression ending at token `i` (the token before a `.`)
+    pub fn receiver_class(
+        &self,
+        i: usize,
+        func: Option<&Function>,
+        class: Option<&'a Class>,
+    ) -> Option<&'a Class> {
+        let token = &self.tokens[i];
+        if token.kind != TokenKind::Ident {
+            return None;
+        }
+
+        // Chained access: `a.b.c` - resolve `a.b` first
+        if i >= 2 && self.tokens[i - 1].is_punct(".") && self.tokens[i - 1].line == token.line {
+            let outer = self.receiver_class(i - 2, func, class)?;
+            return self.class_of(&self.member_type(outer, &token.text)?);
+        }
+
+        if token.text == "self" {
+            return class;
+        }
+        if let Some(class) = self.find_class(&token.text) {
+            return Some(class);
+        }
+        let ty = self.variable_type(func?, class, &token.text, token.line)?;
+        self.class_of(&ty)
+    }
+
+    /// Class whose body (fields and methods) contains the given line
+    pub fn enclosing_class(&self, line: usize) -> Option<&'a Class> {
+        self.program.items.iter().find_map(|item| match item {
+            Item::Class(class) => {
+                let (header, last) = self.class_lines(class)?;
+                (header..=last).contains(&line).then_some(class)
+            }
+            _ => None,
+        })
+    }
+
+    /// Innermost node at the position: the name under the cursor classified by
+    /// the tokens around it, otherwise the enclosing function or class
+    pub fn node_at(&self, position: Position) -> Option<NodeRef<'a>> {
+        let line = position.line as usize;
+        let enclosing = self.enclosing_function(line);
+        let function = enclosing.map(|(func, _)| func);
+        let class = enclosing.and_then(|(_, class)| class).or_else(|| self.enclosing_class(line));
+
+        let name_token = self
+            .token_index_at(position)
+            .filter(|&i| self.tokens[i].kind == TokenKind::Ident);
+        let Some(i) = name_token else {
+            if let Some(func) = function {
+                return Some(NodeRef {
+                    kind: NodeKind::Function,
+                    name: func.name.clone(),
+                    range: span_range(&func.span),
+                    function,
+                    class,
+                });
+            }
+            let class = class?;
+            let (header, last) = self.class_lines(class)?;
+            let end = self.text.lines().nth(last).map_or(0, str::len);
+            return Some(NodeRef {
+                kind: NodeKind::Class,
+                name: class.name.clone(),
+                range: Range {
+                    start: Position { line: clamp_to_u32(header), character: 0 },
+                    end: Position { line: clamp_to_u32(last), character: clamp_to_u32(end) },
+                },
+                function,
+                class: Some(class),
+            });
+        };
+
+        let token = &self.tokens[i];
+        let same_line = |t: &&Token| t.line == token.line;
+        let prev = i.checked_sub(1).map(|j| &self.tokens[j]).filter(same_line);
+        let next = self.tokens.get(i + 1).filter(same_line);
+        let is_parameter = || {
+            function.is_some_and(|func| {
+                let header_line = func.span.start.line.saturating_sub(1);
+                parameter_sites(&self.tokens, header_line, &func.name)
+                    .iter()
+                    .any(|(_, range)| *range == token_range(token))
+            })
+        };
+
+        let kind = match prev {
+            Some(prev) if prev.is_keyword("fn") => NodeKind::Function,
+            Some(prev) if prev.is_keyword("class") => NodeKind::Class,
+            Some(prev) if prev.kind == TokenKind::Keyword && matches!(prev.text.as_str(), "let" | "var" | "for") => {
+                NodeKind::Declaration
+            }
+            Some(prev) if prev.is_punct(".") => NodeKind::Member,
+            _ if is_parameter() => NodeKind::Parameter,
+            _ if next.is_some_and(|next| next.is_punct("(")) => NodeKind::Call,
+            _ => NodeKind::Identifier,
+        };
+        Some(NodeRef {
+            kind,
+            name: token.text.clone(),
+            range: token_range(token),
+            function,
+            class,
+        })
+    }
+
+    /// Definition site of the symbol under the cursor. `self` in a method
+    /// body goes to the enclosing class header.
+    pub fn find_definition(&self, position: Position) -> Option<Range> {
+        let i = self.token_index_at(position)?;
+        let token = &self.tokens[i];
+        if token.is_ident("self") {
+            if let Some((_, Some(class))) = self.enclosing_function(token.line) {
+                return self.class_name_range(class);
+            }
+        }
+        self.resolve_symbol(position).map(|symbol| symbol.range)
+    }
+
+    /// Declaration site of the symbol under the cursor - a parameter in its
+    /// signature, a field at its `let x: T`. Pain has no separate forward
+    /// declarations, so this is the definition site except for `self`, which
+    /// is never declared.
+    pub fn find_declaration(&self, position: Position) -> Option<Range> {
+        self.resolve_symbol(position).map(|symbol| symbol.range)
+    }
+
+    /// Resolve the identifier under the cursor to its declaration
+    pub fn resolve_symbol(&self, position: Position) -> Option<ResolvedSymbol> {
+        let i = self.token_index_at(position)?;
+        let token = &self.tokens[i];
+        if token.kind != TokenKind::Ident {
+            return None;
+        }
+        let enclosing = self.enclosing_function(token.line);
+        let func = enclosing.map(|(func, _)| func);
+        let class = enclosing.and_then(|(_, class)| class);
+
+        // Member access: `receiver.name`
+        if i >= 2 && self.tokens[i - 1].is_punct(".") && self.tokens[i - 1].line == token.line {
+            let receiver = self.receiver_class(i - 2, func, class)?;
+            let range = match self.class_fields(receiver).into_iter().find(|f| f.name == token.text) {
+                Some(field) => field.range,
+                None => {
+                    let method = receiver.methods.iter().find(|m| m.name == token.text)?;
+                    self.function_name_range(method)?
+                }
+            };
+            return Some(ResolvedSymbol::exported(range, vec![receiver.name.clone(), token.text.clone()]));
+        }
+
+        if let Some(func) = func {
+            let local = self.local_declaration(func, &token.text, token.line).map(|decl| token_range(&self.tokens[decl]));
+            let param = || {
+                let header_line = func.span.start.line.saturating_sub(1);
+                parameter_sites(&self.tokens, header_line, &func.name)
+                    .into_iter()
+                    .find(|(name, _)| *name == token.text)
+                    .map(|(_, range)| range)
+            };
+            if let Some(range) = local.or_else(param) {
+                let mut path: Vec<String> = class.map(|class| class.name.clone()).into_iter().collect();
+                path.push(func.name.clone());
+                path.push(token.text.clone());
+                return Some(ResolvedSymbol { range, path, local: true });
+            }
+        }
+
+        if let Some(func) = self.find_function(&token.text) {
+            let range = self.function_name_range(func)?;
+            return Some(ResolvedSymbol::exported(range, vec![func.name.clone()]));
+        }
+        let class = self.find_class(&token.text)?;
+        let range = self.class_name_range(class)?;
+        Some(ResolvedSymbol::exported(range, vec![class.name.clone()]))
+    }
+}
+
+/// Type of the items produced by iterating a value: list/array/tensor
+/// elements, map keys, or single-character strings
+pub fn element_type(iterated: &Type) -> Option<Type> {
+    match iterated {
+        Type::List(inner) | Type::Array(inner) => Some((**inner).clone()),
+        Type::Map(key, _) => Some((**key).clone()),
+        Type::Tensor(inner, _) => Some((**inner).clone()),
+        Type::Str => Some(Type::Str),
+        _ => None,
+    }
+}
+
+fn function_contains_line(func: &Function, line: usize) -> bool {
+    let line = line + 1;
+    line >= func.span.start.line && line <= func.span.end.line
+}
+
+// Last line of the indented block introduced by the header line
+fn block_end_line(text: &str, header: usize) -> usize {
+    let indent_of = |line: &str| line.len() - line.trim_start().len();
+    let lines: Vec<&str> = text.lines().collect();
+    let Some(header_text) = lines.get(header) else { return header };
+    let header_indent = indent_of(header_text);
+
+    let mut last = header;
+    for (idx, line) in lines.iter().enumerate().skip(header + 1) {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        if indent_of(line) <= header_indent {
+            break;
+        }
+        last = idx;
+    }
+    last
+}
+
+// Index just past the bracketed group opening at `open`
+fn skip_group(tokens: &[Token], open: usize) -> usize {
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate().skip(open) {
+        if matches!(token.text.as_str(), "(" | "[" | "{") && token.kind == TokenKind::Punct {
+            depth += 1;
+        } else if matches!(token.text.as_str(), ")" | "]" | "}") && token.kind == TokenKind::Punct {
+            depth -= 1;
+            if depth == 0 {
+                return i + 1;
+            }
+        }
+    }
+    tokens.len()
+}
+
+fn has_top_level_comparison(expr: &[Token]) -> bool {
+    let mut depth = 0i32;
+    for token in expr {
+        match token.text.as_str() {
+            "(" | "[" | "{" if token.kind == TokenKind::Punct => depth += 1,
+            ")" | "]" | "}" if token.kind == TokenKind::Punct => depth -= 1,
+            "==" | "!=" | "<" | ">" | "<=" | ">=" if depth == 0 => return true,
+            "and" | "or" | "not" if depth == 0 && token.kind == TokenKind::Keyword => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Parse a type written in source (`int`, `list[str]`, `map[str, int]`, `Point`).
+/// Returns the type and the index of the token after it.
+pub fn parse_type_tokens(tokens: &[Token], start: usize) -> Option<(Type, usize)> {
+    let first = tokens.get(start)?;
+    if first.kind != TokenKind::Ident {
+        return None;
+    }
+
+    let simple = match first.text.as_str() {
+        "int" => Some(Type::Int),
+        "str" => Some(Type::Str),
+        "float32" => Some(Type::Float32),
+        "float64" => Some(Type::Float64),
+        "bool" => Some(Type::Bool),
+        "dynamic" => Some(Type::Dynamic),
+        _ => None,
+    };
+    if let Some(ty) = simple {
+        return Some((ty, start + 1));
+    }
+
+    let has_args = tokens.get(start + 1).is_some_and(|t| t.is_punct("["));
+    match first.text.as_str() {
+        "list" | "array" if has_args => {
+            let (inner, next) = parse_type_tokens(tokens, start + 2)?;
+            let inner = Box::new(inner);
+            let ty = if first.text == "list" { Type::List(inner) } else { Type::Array(inner) };
+            Some((ty, next + 1))
+        }
+        "map" if has_args => {
+            let (key, next) = parse_type_tokens(tokens, start + 2)?;
+            let (value, next) = parse_type_tokens(tokens, next + 1)?;
+            Some((Type::Map(Box::new(key), Box::new(value)), next + 1))
+        }
+        "Tensor" => None,
+        name => Some((Type::Named(name.to_string()), start + 1)),
+    }
+}
+
+// Names introduced by `let`, `var` and `for` between the given 0-based lines
+pub(crate) fn declaration_sites(tokens: &[Token], first_line: usize, last_line: usize) -> Vec<(String, Range)> {
+    tokens
+        .windows(2)
+        .filter(|pair| pair[0].line >= first_line && pair[0].line <= last_line)
+        .filter(|pair| {
+            matches!(pair[0].text.as_str(), "let" | "var" | "for")
+                && pair[0].kind == TokenKind::Keyword
+                && pair[1].kind == TokenKind::Ident
+        })
+        .map(|pair| (pair[1].text.clone(), token_range(&pair[1])))
+        .collect()
+}
+
+// Parameter names declared in the `fn name(...)` header starting at the given 0-based line
+pub(crate) fn parameter_sites(tokens: &[Token], header_line: usize, func_name: &str) -> Vec<(String, Range)> {
+    let Some(start) = tokens
+        .windows(2)
+        .position(|pair| pair[0].line >= header_line && pair[0].is_keyword("fn") && pair[1].is_ident(func_name))
+    else {
+        return Vec::new();
+    };
+
+    let mut params = Vec::new();
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate().skip(start + 2) {
+        if token.is_punct("(") || token.is_punct("[") {
+            depth += 1;
+        } else if token.is_punct(")") || token.is_punct("]") {
+            depth -= 1;
+            if depth == 0 {
+                break;
+            }
+        } else if depth == 1
+            && token.kind == TokenKind::Ident
+            && tokens.get(i + 1).is_some_and(|next| next.is_punct(":"))
+        {
+            params.push((token.text.clone(), token_range(token)));
+        }
+    }
+    params
+}
+
+// Range of a 1-based AST span in 0-based LSP positions
+fn span_range(span: &Span) -> Range {
+    Range {
+        start: Position {
+            line: clamp_to_u32(span.start.line.saturating_sub(1)),
+            character: clamp_to_u32(span.start.column.saturating_sub(1)),
+        },
+        end: Position {
+            line: clamp_to_u32(span.end.line.saturating_sub(1)),
+            character: clamp_to_u32(span.end.column.saturating_sub(1)),
+        },
+    }
+}
+
+pub fn token_range(token: &Token) -> Range {
+    Range {
+        start: Position {
+            line: clamp_to_u32(token.line),
+            character: clamp_to_u32(token.start),
+        },
+        end: Position {
+            line: clamp_to_u32(token.line),
+            character: clamp_to_u32(token.end),
+        },
+    }
+}
@@ -0,0 +1,169 @@
+// Headless analysis - diagnostics for a snippet without any LSP machinery
+
+use pain_compiler::{
+    ast::*, error::ErrorFormatter, parse_with_recovery, span::Span, type_check_program_with_context,
+    type_checker::TypeContext, warnings::WarningCollector, TypeError, Warning,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::position::normalize_line_endings;
+
+/// Everything the checker reports for a piece of source, grouped by pass
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AnalysisResult {
+    pub parse_errors: Vec<Problem>,
+    pub type_errors: Vec<Problem>,
+    pub warnings: Vec<Problem>,
+}
+
+impl AnalysisResult {
+    /// Whether the source parsed and type checked
+    pub fn is_ok(&self) -> bool {
+        self.parse_errors.is_empty() && self.type_errors.is_empty()
+    }
+}
+
+/// One finding. Positions are 0-based, with byte columns.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Problem {
+    /// First line of the message
+    pub message: String,
+    /// The complete message when it runs over several lines (type errors
+    /// with explanations and hints)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub full_message: Option<String>,
+    /// Set for warnings only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<WarningCategory>,
+    pub start: TextPosition,
+    pub end: TextPosition,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct TextPosition {
+    pub line: u32,
+    pub column: u32,
+}
+
+/// Warning kinds, named like their `warningSeverity` settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum WarningCategory {
+    UnusedVariable,
+    UnusedFunction,
+    DeadCode,
+    UnreachableCode,
+}
+
+/// Parse and type check `text`, collecting warnings when it type checks.
+/// Never panics - a checker panic leaves its pass empty.
+pub fn analyze(text: &str) -> AnalysisResult {
+    let normalized = normalize_line_endings(text);
+    let text = normalized.as_ref();
+    let mut result = AnalysisResult::default();
+    if text.trim().is_empty() {
+        return result;
+    }
+
+    let Ok((parse_result, parse_errors)) = std::panic::catch_unwind(|| parse_with_recovery(text)) else {
+        return result;
+    };
+    result.parse_errors = parse_errors
+        .iter()
+        .map(|err| point_problem(err.message.clone(), None, &err.span))
+        .collect();
+    let Ok(program) = parse_result else { return result };
+
+    let mut ctx = TypeContext::new();
+    for item in &program.items {
+        match item {
+            Item::Function(func) => ctx.add_function(func.name.clone(), func.clone()),
+            Item::Class(class) => ctx.add_class(class.name.clone(), class.clone()),
+        }
+    }
+    let checked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        type_check_program_with_context(&program, &mut ctx)
+    }));
+    match checked {
+        Ok(Ok(_)) => {
+            let warnings = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                WarningCollector::collect_warnings(&program, &ctx)
+            }))
+            .unwrap_or_default();
+            result.warnings = warnings
+                .iter()
+                .map(|warning| {
+                    let (category, message, span) = describe_warning(warning);
+                    let range = crate::lsp::span_to_range(&span, text);
+                    Problem {
+                        message,
+                        full_message: None,
+                        category: Some(category),
+                        start: TextPosition { line: range.start.line, column: range.start.character },
+                        end: TextPosition { line: range.end.line, column: range.end.character },
+                    }
+                })
+                .collect();
+        }
+        Ok(Err(err)) => {
+            let formatted = format_type_error(text, &ctx, &err);
+            let message = formatted.lines().next().unwrap_or(&formatted).to_string();
+            let full_message = (message != formatted).then_some(formatted);
+            result.type_errors.push(point_problem(message, full_message, &type_error_span(&err)));
+        }
+        Err(_) => {}
+    }
+    result
+}
+
+/// Where the type checker places a type error
+pub fn type_error_span(err: &TypeError) -> Span {
+    match err {
+        TypeError::UndefinedVariable { span, .. } => *span,
+        TypeError::TypeMismatch { span, .. } => *span,
+        TypeError::CannotInferType { span, .. } => *span,
+        TypeError::InvalidOperation { span, .. } => *span,
+    }
+}
+
+/// The compiler's full explanation of a type error, or its debug form if
+/// formatting fails
+pub fn format_type_error(text: &str, ctx: &TypeContext, err: &TypeError) -> String {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let formatter = ErrorFormatter::new(text).with_context(ctx);
+        formatter.format_error(err)
+    }))
+    .unwrap_or_else(|_| format!("Type error: {:?}", err))
+}
+
+/// Category, message and location of a compiler warning
+pub fn describe_warning(warning: &Warning) -> (WarningCategory, String, Span) {
+    match warning {
+        Warning::UnusedVariable { name, span } => {
+            (WarningCategory::UnusedVariable, format!("unused variable `{}`", name), *span)
+        }
+        Warning::UnusedFunction { name, span } => {
+            (WarningCategory::UnusedFunction, format!("unused function `{}`", name), *span)
+        }
+        Warning::DeadCode { span, reason } => (WarningCategory::DeadCode, format!("dead code: {}", reason), *span),
+        Warning::UnreachableCode { span } => (WarningCategory::UnreachableCode, "unreachable code".to_string(), *span),
+    }
+}
+
+// A problem one character wide at the start of the span, as the parser and
+// type checker only pin down where the error begins
+fn point_problem(message: String, full_message: Option<String>, span: &Span) -> Problem {
+    let start = TextPosition {
+        line: crate::position::clamp_to_u32(span.line().saturating_sub(1)),
+        column: crate::position::clamp_to_u32(span.column().saturating_sub(1)),
+    };
+    Problem {
+        message,
+        full_message,
+        category: None,
+        start,
+        end: TextPosition { line: start.line, column: start.column.saturating_add(1) },
+    }
+}
@@ -0,0 +1,132 @@
+// Pain LSP configuration - settings sent by the client
+
+use serde::Deserialize;
+use tower_lsp::lsp_types::DiagnosticSeverity;
+
+/// Characters that can trigger completion: member access, attributes and
+/// map keys
+pub const COMPLETION_TRIGGER_CHARACTERS: &[&str] = &[".", "@", "\""];
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Settings {
+    /// Report warnings (unused variables, dead code, shadowing, ...)
+    pub enable_warnings: bool,
+    /// Severity reported for each compiler warning category
+    pub warning_severity: WarningSeverities,
+    /// Documents larger than this many bytes are not analyzed (server default when unset)
+    pub max_document_size: Option<usize>,
+    /// Seconds a parsed document may go unused before its cache entry is dropped
+    pub cache_idle_seconds: u64,
+    /// Renaming a function or class also renames whole-word mentions in doc comments
+    pub rename_updates_doc_comments: bool,
+    /// Most items returned by one completion request; longer lists are marked incomplete
+    pub completion_max_items: usize,
+    /// Tidy the finished line when Enter is pressed
+    pub format_on_type: bool,
+    /// While the document does not parse, keep showing the type errors and
+    /// warnings from its last version that did
+    pub keep_stale_diagnostics_while_typing: bool,
+    /// Characters that open completion as they are typed, out of
+    /// `COMPLETION_TRIGGER_CHARACTERS`
+    pub completion_trigger_characters: Vec<String>,
+    /// What relative paths in string literals (e.g. `pml_load_file("...")`)
+    /// resolve against
+    pub path_resolution_base: PathResolutionBase,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            enable_warnings: true,
+            warning_severity: WarningSeverities::default(),
+            max_document_size: None,
+            cache_idle_seconds: 300,
+            rename_updates_doc_comments: false,
+            completion_max_items: 200,
+            format_on_type: false,
+            keep_stale_diagnostics_while_typing: false,
+            completion_trigger_characters: COMPLETION_TRIGGER_CHARACTERS.iter().map(|c| c.to_string()).collect(),
+            path_resolution_base: PathResolutionBase::Document,
+        }
+    }
+}
+
+/// Base directory for relative paths
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PathResolutionBase {
+    /// The directory of the document containing the path
+    Document,
+    /// The workspace folder containing the document
+    WorkspaceRoot,
+}
+
+/// Severity a warning category is reported with, or `off` to hide it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SeverityLevel {
+    Error,
+    Warning,
+    Information,
+    Hint,
+    Off,
+}
+
+impl SeverityLevel {
+    pub fn to_lsp(self) -> Option<DiagnosticSeverity> {
+        match self {
+            SeverityLevel::Error => Some(DiagnosticSeverity::ERROR),
+            SeverityLevel::Warning => Some(DiagnosticSeverity::WARNING),
+            SeverityLevel::Information => Some(DiagnosticSeverity::INFORMATION),
+            SeverityLevel::Hint => Some(DiagnosticSeverity::HINT),
+            SeverityLevel::Off => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct WarningSeverities {
+    pub unused_variable: SeverityLevel,
+    pub unused_function: SeverityLevel,
+    pub dead_code: SeverityLevel,
+    pub unreachable_code: SeverityLevel,
+    /// Top-level functions without a return type whose returns all infer
+    /// to one type. Off unless enabled.
+    pub missing_return_type: SeverityLevel,
+}
+
+impl Default for WarningSeverities {
+    fn default() -> Self {
+        Self {
+            unused_variable: SeverityLevel::Warning,
+            unused_function: SeverityLevel::Warning,
+            dead_code: SeverityLevel::Warning,
+            unreachable_code: SeverityLevel::Warning,
+            missing_return_type: SeverityLevel::Off,
+        }
+    }
+}
+
+impl Settings {
+    /// Parse settings from `initializationOptions` or `workspace/didChangeConfiguration`.
+    /// Clients may send them either bare or nested under a `pain` section.
+    pub fn from_value(value: &serde_json::Value) -> Option<Self> {
+        let section = value.get("pain").unwrap_or(value);
+        if !section.is_object() {
+            return None;
+        }
+        serde_json::from_value(section.clone()).ok()
+    }
+
+    /// The configured trigger characters the server supports, in their
+    /// canonical order
+    pub fn trigger_characters(&self) -> Vec<String> {
+        COMPLETION_TRIGGER_CHARACTERS
+            .iter()
+            .filter(|supported| self.completion_trigger_characters.iter().any(|c| c == *supported))
+            .map(|c| c.to_string())
+            .collect()
+    }
+}
@@ -0,0 +1,261 @@
+// Constant folding for hover - evaluates literal-only expressions
+//
+// Works on lexer tokens so it can fold any initializer the lexer can see.
+// Anything that is not a literal or an operator on literals (variables,
+// calls, member access, ...) makes the whole expression non-constant.
+
+use crate::lexer::{tokenize, Token, TokenKind};
+use std::fmt;
+
+/// Value of a folded constant expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConstValue {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+}
+
+impl fmt::Display for ConstValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConstValue::Int(value) => write!(f, "{}", value),
+            // Debug keeps the fractional part (`2.0`, not `2`)
+            ConstValue::Float(value) => write!(f, "{:?}", value),
+            ConstValue::Bool(value) => write!(f, "{}", value),
+            ConstValue::Str(value) => write!(f, "{:?}", value),
+        }
+    }
+}
+
+/// Fold a single-line expression given as source text
+pub fn fold_expression(source: &str) -> Option<ConstValue> {
+    let tokens = tokenize(source);
+    let tokens: Vec<&Token> = tokens.iter().collect();
+    fold_tokens(&tokens)
+}
+
+/// Fold an expression spanning exactly the given tokens. Comments are ignored.
+pub fn fold_tokens(tokens: &[&Token]) -> Option<ConstValue> {
+    let tokens: Vec<&Token> = tokens
+        .iter()
+        .copied()
+        .filter(|t| !matches!(t.kind, TokenKind::Comment | TokenKind::DocComment))
+        .collect();
+    let mut folder = Folder { tokens: &tokens, pos: 0 };
+    let value = folder.or_expr()?;
+    // Trailing tokens mean we did not understand the whole expression
+    (folder.pos == tokens.len()).then_some(value)
+}
+
+struct Folder<'t> {
+    tokens: &'t [&'t Token],
+    pos: usize,
+}
+
+impl Folder<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    // Consume the next token if it is the given operator or keyword
+    fn eat(&mut self, op: &str) -> bool {
+        let matched = self
+            .peek()
+            .is_some_and(|t| t.text == op && matches!(t.kind, TokenKind::Punct | TokenKind::Keyword));
+        if matched {
+            self.pos += 1;
+        }
+        matched
+    }
+
+    fn or_expr(&mut self) -> Option<ConstValue> {
+        let mut left = self.and_expr()?;
+        while self.eat("or") {
+            let right = self.and_expr()?;
+            left = ConstValue::Bool(as_bool(&left)? || as_bool(&right)?);
+        }
+        Some(left)
+    }
+
+    fn and_expr(&mut self) -> Option<ConstValue> {
+        let mut left = self.not_expr()?;
+        while self.eat("and") {
+            let right = self.not_expr()?;
+            left = ConstValue::Bool(as_bool(&left)? && as_bool(&right)?);
+        }
+        Some(left)
+    }
+
+    fn not_expr(&mut self) -> Option<ConstValue> {
+        if self.eat("not") {
+            let value = self.not_expr()?;
+            return Some(ConstValue::Bool(!as_bool(&value)?));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Option<ConstValue> {
+        let left = self.additive()?;
+        for op in ["==", "!=", "<=", ">=", "<", ">"] {
+            if self.eat(op) {
+                let right = self.additive()?;
+                return compare(op, &left, &right).map(ConstValue::Bool);
+            }
+        }
+        Some(left)
+    }
+
+    fn additive(&mut self) -> Option<ConstValue> {
+        let mut left = self.multiplicative()?;
+        loop {
+            let op = if self.eat("+") {
+                "+"
+            } else if self.eat("-") {
+                "-"
+            } else {
+                return Some(left);
+            };
+            let right = self.multiplicative()?;
+            left = arithmetic(op, left, right)?;
+        }
+    }
+
+    fn multiplicative(&mut self) -> Option<ConstValue> {
+        let mut left = self.unary()?;
+        loop {
+            let op = if self.eat("*") {
+                "*"
+            } else if self.eat("/") {
+                "/"
+            } else if self.eat("%") {
+                "%"
+            } else {
+                return Some(left);
+            };
+            let right = self.unary()?;
+            left = arithmetic(op, left, right)?;
+        }
+    }
+
+    fn unary(&mut self) -> Option<ConstValue> {
+        if self.eat("-") {
+            return match self.unary()? {
+                ConstValue::Int(value) => value.checked_neg().map(ConstValue::Int),
+                ConstValue::Float(value) => Some(ConstValue::Float(-value)),
+                _ => None,
+            };
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Option<ConstValue> {
+        if self.eat("(") {
+            let value = self.or_expr()?;
+            return self.eat(")").then_some(value);
+        }
+
+        let token = self.peek()?;
+        let value = match token.kind {
+            TokenKind::Number => parse_number(&token.text)?,
+            TokenKind::String => ConstValue::Str(parse_string(&token.text)?),
+            TokenKind::Keyword if token.text == "true" => ConstValue::Bool(true),
+            TokenKind::Keyword if token.text == "false" => ConstValue::Bool(false),
+            // Variables, calls and everything else are not constant
+            _ => return None,
+        };
+        self.pos += 1;
+        Some(value)
+    }
+}
+
+fn as_bool(value: &ConstValue) -> Option<bool> {
+    match value {
+        ConstValue::Bool(value) => Some(*value),
+        _ => None,
+    }
+}
+
+fn parse_number(text: &str) -> Option<ConstValue> {
+    let digits = text.replace('_', "");
+    if digits.contains('.') {
+        digits.parse().ok().map(ConstValue::Float)
+    } else {
+        digits.parse().ok().map(ConstValue::Int)
+    }
+}
+
+// Literal contents without quotes, with the common escapes resolved
+fn parse_string(text: &str) -> Option<String> {
+    let quote = text.chars().next()?;
+    let inner = text.get(1..)?.strip_suffix(quote)?;
+    let mut value = String::new();
+    let mut chars = inner.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            value.push(ch);
+            continue;
+        }
+        match chars.next()? {
+            'n' => value.push('\n'),
+            't' => value.push('\t'),
+            'r' => value.push('\r'),
+            other @ ('\\' | '"' | '\'') => value.push(other),
+            _ => return None,
+        }
+    }
+    Some(value)
+}
+
+fn arithmetic(op: &str, left: ConstValue, right: ConstValue) -> Option<ConstValue> {
+    match (left, right) {
+        (ConstValue::Int(a), ConstValue::Int(b)) => match op {
+            "+" => a.checked_add(b),
+            "-" => a.checked_sub(b),
+            "*" => a.checked_mul(b),
+            "/" => a.checked_div(b),
+            "%" => a.checked_rem(b),
+            _ => None,
+        }
+        .map(ConstValue::Int),
+        (ConstValue::Str(a), ConstValue::Str(b)) if op == "+" => Some(ConstValue::Str(a + &b)),
+        (left, right) => {
+            let (a, b) = (as_float(&left)?, as_float(&right)?);
+            let value = match op {
+                "+" => a + b,
+                "-" => a - b,
+                "*" => a * b,
+                "/" if b != 0.0 => a / b,
+                _ => return None,
+            };
+            Some(ConstValue::Float(value))
+        }
+    }
+}
+
+// Numbers widen to float when mixed with a float operand
+fn as_float(value: &ConstValue) -> Option<f64> {
+    match value {
+        ConstValue::Int(value) => Some(*value as f64),
+        ConstValue::Float(value) => Some(*value),
+        _ => None,
+    }
+}
+
+fn compare(op: &str, left: &ConstValue, right: &ConstValue) -> Option<bool> {
+    let ordering = match (left, right) {
+        (ConstValue::Int(a), ConstValue::Int(b)) => a.partial_cmp(b),
+        (ConstValue::Str(a), ConstValue::Str(b)) => a.partial_cmp(b),
+        (ConstValue::Bool(a), ConstValue::Bool(b)) => a.partial_cmp(b),
+        (left, right) => as_float(left)?.partial_cmp(&as_float(right)?),
+    }?;
+    Some(match op {
+        "==" => ordering.is_eq(),
+        "!=" => ordering.is_ne(),
+        "<" => ordering.is_lt(),
+        "<=" => ordering.is_le(),
+        ">" => ordering.is_gt(),
+        ">=" => ordering.is_ge(),
+        _ => return None,
+    })
+}
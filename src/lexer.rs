@@ -0,0 +1,143 @@
+// Lightweight lexer for position-based LSP features
+//
+// The compiler AST does not carry spans for every node, so features that need
+// exact positions (declaration sites, token under cursor) scan the source text
+// with this lexer instead. It only needs to be good enough to locate tokens -
+// it never rejects input.
+
+/// Pain keywords recognized by the lexer
+pub const KEYWORDS: &[&str] = &[
+    "fn", "class", "let", "var", "if", "else", "for", "in", "while", "break", "continue",
+    "return", "pass", "true", "false", "and", "or", "not",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Ident,
+    Keyword,
+    Number,
+    String,
+    Comment,
+    DocComment,
+    Punct,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub text: String,
+    /// 0-based line index
+    pub line: usize,
+    /// Byte offset of the first character within the line
+    pub start: usize,
+    /// Byte offset one past the last character within the line
+    pub end: usize,
+}
+
+impl Token {
+    pub fn is_ident(&self, name: &str) -> bool {
+        self.kind == TokenKind::Ident && self.text == name
+    }
+
+    pub fn is_keyword(&self, keyword: &str) -> bool {
+        self.kind == TokenKind::Keyword && self.text == keyword
+    }
+
+    pub fn is_punct(&self, punct: &str) -> bool {
+        self.kind == TokenKind::Punct && self.text == punct
+    }
+}
+
+pub fn is_keyword(word: &str) -> bool {
+    KEYWORDS.contains(&word)
+}
+
+/// Tokenize the whole document. Strings and comments never span lines.
+pub fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for (line_idx, line) in text.split('\n').enumerate() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        tokenize_line(line, line_idx, &mut tokens);
+    }
+    tokens
+}
+
+fn tokenize_line(line: &str, line_idx: usize, tokens: &mut Vec<Token>) {
+    let bytes = line.as_bytes();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let c = bytes[i];
+        let start = i;
+
+        let kind = if c.is_ascii_whitespace() {
+            i += 1;
+            continue;
+        } else if line[i..].starts_with("///") {
+            i = bytes.len();
+            TokenKind::DocComment
+        } else if c == b'#' {
+            i = bytes.len();
+            TokenKind::Comment
+        } else if c == b'"' || c == b'\'' {
+            i += 1;
+            while i < bytes.len() && bytes[i] != c {
+                // Skip escaped characters
+                if bytes[i] == b'\\' {
+                    i += 1;
+                }
+                i += 1;
+            }
+            i = (i + 1).min(bytes.len());
+            TokenKind::String
+        } else if c.is_ascii_digit() {
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            // Fractional part, but not a member access like `1.method`
+            if i + 1 < bytes.len() && bytes[i] == b'.' && bytes[i + 1].is_ascii_digit() {
+                i += 1;
+                while i < bytes.len() && bytes[i].is_ascii_digit() {
+                    i += 1;
+                }
+            }
+            TokenKind::Number
+        } else if c.is_ascii_alphabetic() || c == b'_' || !c.is_ascii() {
+            while i < bytes.len()
+                && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_' || !bytes[i].is_ascii())
+            {
+                i += 1;
+            }
+            if is_keyword(&line[start..i]) {
+                TokenKind::Keyword
+            } else {
+                TokenKind::Ident
+            }
+        } else {
+            let two = line.get(i..i + 2).unwrap_or("");
+            if matches!(two, "->" | "==" | "!=" | "<=" | ">=") {
+                i += 2;
+            } else {
+                i += 1;
+            }
+            TokenKind::Punct
+        };
+
+        tokens.push(Token {
+            kind,
+            text: line[start..i].to_string(),
+            line: line_idx,
+            start,
+            end: i,
+        });
+    }
+}
+
+/// Find the token covering the given 0-based line and byte column.
+/// A cursor placed right after the last character of a token still counts as on it.
+pub fn token_at(tokens: &[Token], line: usize, column: usize) -> Option<&Token> {
+    tokens
+        .iter()
+        .filter(|t| t.line == line && t.start <= column && column <= t.end)
+        .find(|t| !matches!(t.kind, TokenKind::Punct) || column < t.end)
+}
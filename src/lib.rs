@@ -1,6 +1,13 @@
 // Pain LSP library - exports for testing
 
+pub mod analysis;
+pub mod analyze;
+pub mod config;
+pub mod consteval;
+pub mod lexer;
 pub mod lsp;
+pub mod position;
+pub mod workspace;
+pub use analyze::{analyze, AnalysisResult, Problem, TextPosition, WarningCategory};
 pub use lsp::*;
-
-
+pub use position::{lsp_position_to_offset, offset_to_lsp_position};
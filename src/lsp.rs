@@ -1,23 +1,355 @@
 // Pain LSP server implementation
 
 use pain_compiler::{
-    ast::*, error::ErrorFormatter, parse_with_recovery, stdlib::get_stdlib_functions,
+    ast::*, parse_with_recovery, stdlib::get_stdlib_functions,
     type_check_program_with_context, type_checker::TypeContext, warnings::WarningCollector,
 };
-use std::collections::{HashMap, HashSet};
-use std::sync::Arc;
+use crate::analyze::{describe_warning, format_type_error, type_error_span, WarningCategory};
+use crate::analysis::{declaration_sites, parameter_sites, token_range, DocumentIndex, NodeKind, ResolvedSymbol};
+use crate::config::{PathResolutionBase, Settings};
+use crate::lexer::{is_keyword, token_at, tokenize, Token, TokenKind};
+use crate::position::{
+    clamp_to_u32, from_byte_position, from_byte_range, lsp_position_to_offset, negotiate_position_encoding, normalize_line_endings,
+    offset_to_position, to_byte_position,
+};
+use crate::workspace::{
+    class_outlines, find_document_links, find_pain_files, index_document, module_name, url_to_path, ClassOutline,
+    IndexedSymbol,
+};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
-// Note: timeout and Duration imports removed - simplified implementation
-// Timeout protection is handled at the VS Code extension level
+use tracing::{debug, error, info, warn};
 use tower_lsp::lsp_types::*;
 
+// Stdlib functions offered, in this order, when completion falls back to the basic list
+const FALLBACK_STDLIB_FUNCTIONS: &[&str] = &["print", "len", "range", "str", "int", "float", "input"];
+
+/// Cached parse of a document, valid while the text hashes to `text_hash`
+#[derive(Debug, Clone)]
+pub struct ParsedDocument {
+    pub text_hash: u64,
+    pub program: Option<Arc<Program>>,
+    pub parse_errors: Vec<pain_compiler::error::ParseError>,
+    /// When `program` is `None`: the items that still parse with the broken
+    /// ones left out. Good enough for completion and hover, not diagnostics.
+    pub recovered: Option<Arc<Program>>,
+}
+
+/// Type context of a document after type checking, with what the checker found
+pub struct CheckedContext {
+    pub ctx: TypeContext,
+    /// `None` when the checker panicked
+    pub outcome: Option<Result<(), pain_compiler::TypeError>>,
+}
+
+/// Parse the document with every top-level item that has a parse error blanked
+/// out, keeping line numbers intact. Blanking can expose errors in other
+/// items, so this retries a few times before giving up.
+pub fn recover_program(text: &str, parse_errors: &[pain_compiler::error::ParseError]) -> Option<Program> {
+    let mut lines: Vec<String> = text.lines().map(str::to_string).collect();
+    // Item that each line belongs to; doc comments and attributes stay with
+    // the header below them
+    let mut item_of_line = Vec::with_capacity(lines.len());
+    let mut item = 0;
+    let mut prefix = false;
+    for line in &lines {
+        let top_level = !line.trim().is_empty() && !line.starts_with(char::is_whitespace);
+        if top_level && !prefix {
+            item += 1;
+        }
+        prefix = top_level && (line.starts_with("///") || line.starts_with('@'));
+        item_of_line.push(item);
+    }
+
+    let mut error_lines: Vec<usize> = parse_errors.iter().map(|err| err.span.line().saturating_sub(1)).collect();
+    for _ in 0..3 {
+        let broken: HashSet<usize> = error_lines
+            .iter()
+            .filter_map(|line| item_of_line.get(*line).or(item_of_line.last()).copied())
+            .collect();
+        for (line, item) in lines.iter_mut().zip(&item_of_line) {
+            if broken.contains(item) {
+                line.clear();
+            }
+        }
+        let (parse_result, parse_errors) = parse_with_recovery(&lines.join("\n"));
+        match parse_result {
+            Ok(program) => return Some(program),
+            Err(_) if !parse_errors.is_empty() => {
+                error_lines = parse_errors.iter().map(|err| err.span.line().saturating_sub(1)).collect();
+            }
+            Err(_) => return None,
+        }
+    }
+    None
+}
+
+/// Log a panic swallowed by `catch_unwind`, with the document and cursor that
+/// triggered it so the failure can be reproduced from the log
+fn log_panic(
+    context: &str,
+    uri: Option<&url::Url>,
+    position: Option<Position>,
+    payload: &(dyn std::any::Any + Send),
+) {
+    let message = payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("<non-string panic payload>");
+    let uri = uri.map_or_else(|| "<unknown document>".to_string(), |uri| uri.to_string());
+    match position {
+        Some(position) => error!(
+            "{} panicked: {} (uri={}, position={}:{})",
+            context, message, uri, position.line, position.character
+        ),
+        None => error!("{} panicked: {} (uri={})", context, message, uri),
+    }
+}
+
+/// Environment variable that, when set, lets panics in completion, diagnostics
+/// and hover propagate with their backtrace instead of being caught
+pub const NO_CATCH_ENV: &str = "PAIN_LSP_NO_CATCH";
+
+/// `catch_unwind` for the request paths above, unless `PAIN_LSP_NO_CATCH` is
+/// set (and not `0`) - then the closure runs unguarded
+pub fn catch_panics<T>(f: impl FnOnce() -> T) -> std::thread::Result<T> {
+    if panics_propagate() {
+        return Ok(f());
+    }
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(f))
+}
+
+// Whether `PAIN_LSP_NO_CATCH` is set (and not `0`)
+fn panics_propagate() -> bool {
+    std::env::var_os(NO_CATCH_ENV).is_some_and(|value| !value.is_empty() && value != "0")
+}
+
+fn hash_text(text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Convert diagnostics computed with byte columns (including related
+/// information) into the given position encoding
+pub fn diagnostics_to_encoding(
+    text: &str,
+    diagnostics: Vec<Diagnostic>,
+    encoding: &PositionEncodingKind,
+) -> Vec<Diagnostic> {
+    diagnostics
+        .into_iter()
+        .map(|mut diagnostic| {
+            diagnostic.range = from_byte_range(text, diagnostic.range, encoding);
+            for related in diagnostic.related_information.iter_mut().flatten() {
+                related.location.range = from_byte_range(text, related.location.range, encoding);
+            }
+            diagnostic
+        })
+        .collect()
+}
+
+// `$/progress` carrying a partial `workspace/diagnostic` result
+enum WorkspaceDiagnosticProgress {}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct WorkspaceDiagnosticProgressParams {
+    token: ProgressToken,
+    value: WorkspaceDiagnosticReportPartialResult,
+}
+
+impl notification::Notification for WorkspaceDiagnosticProgress {
+    type Params = WorkspaceDiagnosticProgressParams;
+    const METHOD: &'static str = "$/progress";
+}
+
+// `$/progress` carrying a partial `textDocument/completion` result
+enum CompletionProgress {}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct CompletionProgressParams {
+    token: ProgressToken,
+    value: Vec<CompletionItem>,
+}
+
+impl notification::Notification for CompletionProgress {
+    type Params = CompletionProgressParams;
+    const METHOD: &'static str = "$/progress";
+}
+
+/// Result id for pull diagnostics - changes whenever the text or the
+/// settings that shape diagnostics change
+pub fn diagnostic_result_id(text: &str, settings: &Settings) -> String {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    format!("{:?}", settings).hash(&mut hasher);
+    format!("{:x}", hasher.finish())
+}
+
+// Convert a document symbol and its children from byte columns to the client's encoding
+fn symbol_to_encoding(text: &str, symbol: DocumentSymbol, encoding: &PositionEncodingKind) -> DocumentSymbol {
+    DocumentSymbol {
+        range: from_byte_range(text, symbol.range, encoding),
+        selection_range: from_byte_range(text, symbol.selection_range, encoding),
+        children: symbol.children.map(|children| {
+            children.into_iter().map(|child| symbol_to_encoding(text, child, encoding)).collect()
+        }),
+        ..symbol
+    }
+}
+
+// Convert the text edits of completion items from byte columns to the client's encoding
+fn completion_edits_to_encoding(text: &str, items: &mut [CompletionItem], encoding: &PositionEncodingKind) {
+    for item in items {
+        match item.text_edit.as_mut() {
+            Some(CompletionTextEdit::Edit(edit)) => edit.range = from_byte_range(text, edit.range, encoding),
+            Some(CompletionTextEdit::InsertAndReplace(edit)) => {
+                edit.insert = from_byte_range(text, edit.insert, encoding);
+                edit.replace = from_byte_range(text, edit.replace, encoding);
+            }
+            None => {}
+        }
+    }
+}
+
+// Bound on the classes scanned (and returned) by one type hierarchy request
+const MAX_TYPE_HIERARCHY_ITEMS: usize = 200;
+
+// Dynamic completion registration, replaced when the trigger characters change
+const COMPLETION_REGISTRATION_ID: &str = "pain-completion";
+
+/// Type hierarchy entry for a class, with ranges in the given encoding
+pub fn type_hierarchy_item(
+    uri: &url::Url,
+    text: &str,
+    outline: &ClassOutline,
+    encoding: &PositionEncodingKind,
+) -> TypeHierarchyItem {
+    TypeHierarchyItem {
+        name: outline.name.clone(),
+        kind: SymbolKind::CLASS,
+        tags: None,
+        detail: Some(format!("class {}", outline.name)),
+        uri: uri.clone(),
+        range: from_byte_range(text, outline.range, encoding),
+        selection_range: from_byte_range(text, outline.selection_range, encoding),
+        data: None,
+    }
+}
+
+/// Command returning the parsed AST of a document, for bug reports against the compiler
+pub const DUMP_AST_COMMAND: &str = "pain.dumpAst";
+
+/// Command returning a snapshot of the server's state, for the extension's status panel
+pub const STATUS_COMMAND: &str = "pain.status";
+
+/// Command checking a document and returning its diagnostics as JSON, so CI
+/// scripts can lint without a full LSP client. Takes one argument: a URI
+/// string, `{"uri": "file:///..."}`, or `{"text": "..."}` for inline source.
+/// URIs of documents that are not open are read from disk.
+pub const LINT_COMMAND: &str = "pain.lint";
+
+/// Client-side command re-opening the completion widget, attached to class
+/// completions so members are suggested as soon as `.` is typed
+pub const TRIGGER_SUGGEST_COMMAND: &str = "editor.action.triggerSuggest";
+
+/// Source of the current time for cache eviction - tests substitute a manual clock
+pub trait Clock: std::fmt::Debug + Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+#[derive(Debug)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Environment variable that, when set, logs hover and completion requests
+/// slower than `SLOW_REQUEST_THRESHOLD`
+pub const TRACE_TIMING_ENV: &str = "PAIN_LSP_TRACE_TIMING";
+
+pub const SLOW_REQUEST_THRESHOLD: Duration = Duration::from_millis(50);
+
+fn slow_request_threshold_from_env() -> Option<Duration> {
+    std::env::var_os(TRACE_TIMING_ENV)
+        .is_some_and(|value| !value.is_empty() && value != "0")
+        .then_some(SLOW_REQUEST_THRESHOLD)
+}
+
+/// Times one request from creation until dropped, then logs a warning with
+/// the document and position if it took longer than the threshold
+pub struct RequestTiming {
+    request: &'static str,
+    uri: url::Url,
+    position: Position,
+    clock: Arc<dyn Clock>,
+    started: Instant,
+    threshold: Option<Duration>,
+}
+
+impl Drop for RequestTiming {
+    fn drop(&mut self) {
+        let Some(threshold) = self.threshold else { return };
+        let elapsed = self.clock.now().saturating_duration_since(self.started);
+        if elapsed > threshold {
+            warn!(
+                "slow {}: {}ms (uri={}, position={}:{})",
+                self.request,
+                elapsed.as_millis(),
+                self.uri,
+                self.position.line,
+                self.position.character
+            );
+        }
+    }
+}
+
+fn read_clock(clock: &std::sync::RwLock<Arc<dyn Clock>>) -> Instant {
+    clock.read().map_or_else(|_| Instant::now(), |clock| clock.now())
+}
+
+// Configured idle time, or `None` once the backend is gone
+fn settings_cache_idle(settings: &Weak<std::sync::RwLock<Settings>>) -> Option<Duration> {
+    let settings = settings.upgrade()?;
+    let seconds = settings.read().map_or(300, |settings| settings.cache_idle_seconds);
+    // A zero interval would spin
+    Some(Duration::from_secs(seconds.max(1)))
+}
+
+// Drop cache entries not accessed within `max_idle`, returning how many went
+fn evict_idle_entries(
+    cache: &mut HashMap<url::Url, ParsedDocument>,
+    access: &mut HashMap<url::Url, Instant>,
+    now: Instant,
+    max_idle: Duration,
+) -> usize {
+    let before = cache.len();
+    cache.retain(|uri, _| {
+        access
+            .get(uri)
+            .is_some_and(|last| now.saturating_duration_since(*last) < max_idle)
+    });
+    access.retain(|uri, _| cache.contains_key(uri));
+    before - cache.len()
+}
+
 #[derive(Debug, Clone)]
 pub struct HoverInfo {
     pub signature: String,
     pub doc: Option<String>,
 }
 
-#[derive(Debug)]
+// Clones share all state - used to move the backend into background tasks
+#[derive(Debug, Clone)]
 pub struct Backend {
     pub client: tower_lsp::Client,
     pub documents: Arc<RwLock<HashMap<url::Url, String>>>,
@@ -25,24 +357,619 @@ pub struct Backend {
     pub max_document_size: usize, // Maximum document size in bytes (default: 10MB)
     // Cache for parsed programs to avoid re-parsing on every completion/hover
     // Note: This is a simple cache - in production, consider using LRU cache
-    pub parsed_cache: Arc<RwLock<HashMap<url::Url, (String, Program)>>>, // (text_hash, program)
+    pub parsed_cache: Arc<RwLock<HashMap<url::Url, ParsedDocument>>>,
+    // Parse results by text hash, shared by every document with that content.
+    // Entries no `parsed_cache` entry refers to are dropped on the next parse.
+    pub parsed_by_content: Arc<RwLock<HashMap<u64, ParsedDocument>>>,
+    // Number of parses done by the cache layer - cache hits do not count
+    pub parse_count: Arc<AtomicUsize>,
+    // Client settings - read synchronously from diagnostics, so a std lock is used
+    pub settings: Arc<std::sync::RwLock<Settings>>,
+    // Capabilities the client announced in `initialize`
+    pub client_capabilities: Arc<std::sync::RwLock<ClientCapabilities>>,
+    // Position encoding negotiated in `initialize` (UTF-16 unless the client offers UTF-8)
+    pub position_encoding: Arc<std::sync::RwLock<PositionEncodingKind>>,
+    // Workspace folders to index, captured in `initialize`
+    pub workspace_roots: Arc<RwLock<Vec<url::Url>>>,
+    // Top-level symbols per workspace file, for cross-file features
+    pub workspace_index: Arc<RwLock<HashMap<url::Url, Vec<IndexedSymbol>>>>,
+    // Latest version seen per document - diagnostics for older versions are dropped
+    pub document_versions: Arc<RwLock<HashMap<url::Url, i32>>>,
+    // Diagnostics most recently computed per document, as sent to the client
+    pub published_diagnostics: Arc<RwLock<HashMap<url::Url, Vec<Diagnostic>>>>,
+    // Text and diagnostics of each document's last version that parsed, shown
+    // again while it does not parse when `keepStaleDiagnosticsWhileTyping` is set
+    pub last_good_diagnostics: Arc<RwLock<HashMap<url::Url, (String, Vec<Diagnostic>)>>>,
+    // Identifiers used by recent completion and hover requests, per document -
+    // read while ranking completions, so a std lock is used
+    pub recent_identifiers: Arc<std::sync::RwLock<HashMap<url::Url, RecentIdentifiers>>>,
+    // Last time each `parsed_cache` entry was used - idle entries are evicted
+    pub cache_access: Arc<std::sync::RwLock<HashMap<url::Url, Instant>>>,
+    // Type context of each document after type checking, valid while the text
+    // hashes to the stored hash. Read from synchronous diagnostics, so a std
+    // lock is used. The checker runs once per text, never on a copy.
+    pub type_contexts: Arc<std::sync::RwLock<HashMap<url::Url, (u64, Arc<CheckedContext>)>>>,
+    // Number of documents type checked - cache hits do not count
+    pub type_context_builds: Arc<AtomicUsize>,
+    // Semantic tokens last sent per document - the base for delta requests
+    pub semantic_tokens: Arc<RwLock<HashMap<url::Url, SemanticTokens>>>,
+    // Long-running background tasks (indexing, refreshes, cache eviction),
+    // aborted on shutdown so they never touch cleared state
+    pub background_tasks: Arc<std::sync::Mutex<Vec<tokio::task::JoinHandle<()>>>>,
+    // Time source shared with the eviction task - see `set_clock`
+    clock: Arc<std::sync::RwLock<Arc<dyn Clock>>>,
+    // Hover and completion requests slower than this are logged, from
+    // `PAIN_LSP_TRACE_TIMING`
+    pub slow_request_threshold: Option<Duration>,
 }
 
 impl Backend {
     pub fn new(client: tower_lsp::Client) -> Self {
-        Self {
+        let backend = Self {
             client,
             documents: Arc::new(RwLock::new(HashMap::new())),
             max_document_size: 10 * 1024 * 1024, // 10MB default
             parsed_cache: Arc::new(RwLock::new(HashMap::new())),
+            parsed_by_content: Arc::new(RwLock::new(HashMap::new())),
+            parse_count: Arc::new(AtomicUsize::new(0)),
+            settings: Arc::new(std::sync::RwLock::new(Settings::default())),
+            client_capabilities: Arc::new(std::sync::RwLock::new(ClientCapabilities::default())),
+            position_encoding: Arc::new(std::sync::RwLock::new(PositionEncodingKind::UTF16)),
+            workspace_roots: Arc::new(RwLock::new(Vec::new())),
+            workspace_index: Arc::new(RwLock::new(HashMap::new())),
+            document_versions: Arc::new(RwLock::new(HashMap::new())),
+            published_diagnostics: Arc::new(RwLock::new(HashMap::new())),
+            last_good_diagnostics: Arc::new(RwLock::new(HashMap::new())),
+            recent_identifiers: Arc::new(std::sync::RwLock::new(HashMap::new())),
+            cache_access: Arc::new(std::sync::RwLock::new(HashMap::new())),
+            type_contexts: Arc::new(std::sync::RwLock::new(HashMap::new())),
+            type_context_builds: Arc::new(AtomicUsize::new(0)),
+            semantic_tokens: Arc::new(RwLock::new(HashMap::new())),
+            background_tasks: Arc::new(std::sync::Mutex::new(Vec::new())),
+            clock: Arc::new(std::sync::RwLock::new(Arc::new(SystemClock))),
+            slow_request_threshold: slow_request_threshold_from_env(),
+        };
+
+        // Evict idle parse results in the background. The task only holds weak
+        // references to the caches and ends once the backend is gone.
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let cache = Arc::downgrade(&backend.parsed_cache);
+            let access = Arc::downgrade(&backend.cache_access);
+            let settings = Arc::downgrade(&backend.settings);
+            let clock = Arc::downgrade(&backend.clock);
+            let task = runtime.spawn(async move {
+                loop {
+                    let Some(idle) = settings_cache_idle(&settings) else { break };
+                    tokio::time::sleep(idle).await;
+                    let (Some(cache), Some(access), Some(clock)) = (cache.upgrade(), access.upgrade(), clock.upgrade()) else {
+                        break;
+                    };
+                    let now = read_clock(&clock);
+                    let mut cache = cache.write().await;
+                    let evicted = match access.write() {
+                        Ok(mut access) => evict_idle_entries(&mut cache, &mut access, now, idle),
+                        Err(_) => 0,
+                    };
+                    if evicted > 0 {
+                        debug!("evicted {} idle parse cache entries", evicted);
+                    }
+                }
+            });
+            backend.track_task(task);
+        }
+        backend
+    }
+
+    /// Abort the tracked background tasks and wait briefly for them to wind
+    /// down, so none outlives the state it works on. Called on `shutdown` and
+    /// again once the client sends `exit`. Returns how many were still running.
+    pub async fn abort_background_tasks(&self) -> usize {
+        let tasks = match self.background_tasks.lock() {
+            Ok(mut tasks) => std::mem::take(&mut *tasks),
+            Err(_) => Vec::new(),
+        };
+        let running = tasks.iter().filter(|task| !task.is_finished()).count();
+        for task in &tasks {
+            task.abort();
+        }
+        let joined = tokio::time::timeout(Duration::from_millis(500), async {
+            for task in tasks {
+                let _ = task.await;
+            }
+        });
+        if joined.await.is_err() {
+            warn!("background tasks still running after abort");
+        }
+        running
+    }
+
+    // Keep a background task's handle so shutdown can abort it. Finished
+    // tasks are dropped from the list as new ones arrive.
+    fn track_task(&self, task: tokio::task::JoinHandle<()>) {
+        if let Ok(mut tasks) = self.background_tasks.lock() {
+            tasks.retain(|task| !task.is_finished());
+            tasks.push(task);
+        }
+    }
+
+    /// A backend whose client is not connected to an editor, for benchmarks
+    /// and tools that only call the analysis methods. Notifications sent
+    /// through its client are dropped.
+    pub fn detached() -> Self {
+        // The service keeps its own clone - both share the same state
+        let mut captured = None;
+        let (_service, _socket) = tower_lsp::LspService::new(|client| {
+            let backend = Backend::new(client);
+            captured = Some(backend.clone());
+            backend
+        });
+        captured.expect("LspService always calls the init closure")
+    }
+
+    /// Replace the time source, here and in the background eviction task
+    pub fn set_clock(&self, clock: Arc<dyn Clock>) {
+        if let Ok(mut current) = self.clock.write() {
+            *current = clock;
+        }
+    }
+
+    fn current_clock(&self) -> Arc<dyn Clock> {
+        self.clock.read().map_or_else(|_| Arc::new(SystemClock) as Arc<dyn Clock>, |clock| clock.clone())
+    }
+
+    fn now(&self) -> Instant {
+        read_clock(&self.clock)
+    }
+
+    /// Drop parse results not used within the configured idle time. Open
+    /// documents keep their text - only the cached parse is dropped.
+    pub async fn evict_idle_cache_entries(&self) -> usize {
+        let max_idle = Duration::from_secs(self.settings().cache_idle_seconds);
+        let mut cache = self.parsed_cache.write().await;
+        match self.cache_access.write() {
+            Ok(mut access) => evict_idle_entries(&mut cache, &mut access, self.now(), max_idle),
+            Err(_) => 0,
+        }
+    }
+
+    // Record a use of the document's cache entry
+    fn touch_cache_entry(&self, uri: &url::Url) {
+        if let Ok(mut access) = self.cache_access.write() {
+            access.insert(uri.clone(), self.now());
+        }
+    }
+
+    // Remember identifiers near the cursor of a completion or hover request:
+    // the one under it, or those before it on the line
+    fn note_identifier_use(&self, uri: &url::Url, text: &str, position: Position) {
+        let tokens = tokenize(text);
+        let line = position.line as usize;
+        let column = position.character as usize;
+        let used: Vec<&str> = match token_at(&tokens, line, column) {
+            Some(token) if token.kind == TokenKind::Ident && token.end > column => vec![token.text.as_str()],
+            _ => tokens
+                .iter()
+                .filter(|t| t.line == line && t.end < column && t.kind == TokenKind::Ident)
+                .map(|t| t.text.as_str())
+                .collect(),
+        };
+        if used.is_empty() {
+            return;
+        }
+        if let Ok(mut recent) = self.recent_identifiers.write() {
+            let recent = recent.entry(uri.clone()).or_default();
+            for name in used {
+                recent.touch(name);
+            }
+        }
+    }
+
+    /// Server state reported by `pain.status`. Each lock is held just long
+    /// enough to read a length.
+    pub async fn status(&self) -> serde_json::Value {
+        let open_documents = self.documents.read().await.len();
+        let cached_documents = self.parsed_cache.read().await.len();
+        let (indexed_files, indexed_symbols) = {
+            let index = self.workspace_index.read().await;
+            (index.len(), index.values().map(Vec::len).sum::<usize>())
+        }; // Lock released here
+        serde_json::json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "openDocuments": open_documents,
+            "parseCacheEntries": cached_documents,
+            "workspaceIndex": {
+                "files": indexed_files,
+                "symbols": indexed_symbols,
+            },
+            "warningsEnabled": self.settings().enable_warnings,
+        })
+    }
+
+    /// Diagnostics of a document as `{uri, diagnostics: [{severity, range, code, message}]}`
+    pub async fn lint(&self, argument: Option<&serde_json::Value>) -> Result<serde_json::Value, tower_lsp::jsonrpc::Error> {
+        let invalid = |message: String| tower_lsp::jsonrpc::Error::invalid_params(message);
+        let uri_arg = argument.and_then(|arg| arg.as_str().or_else(|| arg.get("uri").and_then(|uri| uri.as_str())));
+        let inline_text = argument.and_then(|arg| arg.get("text")).and_then(|text| text.as_str());
+
+        let (uri, text) = match (uri_arg, inline_text) {
+            (_, Some(text)) => (None, text.to_string()),
+            (Some(uri), None) => {
+                let uri = url::Url::parse(uri).map_err(|e| invalid(format!("invalid document URI `{}`: {}", uri, e)))?;
+                let text = self
+                    .document_or_file_text(&uri)
+                    .await
+                    .ok_or_else(|| invalid(format!("cannot read document {}", uri)))?;
+                (Some(uri), text)
+            }
+            (None, None) => {
+                return Err(invalid(format!("{} expects a document URI or `{{\"text\": ...}}` argument", LINT_COMMAND)));
+            }
+        };
+
+        let diagnostics = match &uri {
+            Some(uri) => self.check_document_with_uri(uri, &text),
+            None => self.check_document(&text),
+        };
+        let diagnostics: Vec<serde_json::Value> = diagnostics_to_encoding(&text, diagnostics, &self.position_encoding())
+            .into_iter()
+            .map(|diag| {
+                let severity = match diag.severity {
+                    Some(DiagnosticSeverity::ERROR) => "error",
+                    Some(DiagnosticSeverity::WARNING) => "warning",
+                    Some(DiagnosticSeverity::INFORMATION) => "information",
+                    _ => "hint",
+                };
+                serde_json::json!({
+                    "severity": severity,
+                    "range": diag.range,
+                    "code": diag.code,
+                    "message": diag.message,
+                })
+            })
+            .collect();
+        Ok(serde_json::json!({
+            "uri": uri.map(|uri| uri.to_string()),
+            "diagnostics": diagnostics,
+        }))
+    }
+
+    /// Text of an open document, or of the file on disk for closed ones
+    pub async fn document_or_file_text(&self, uri: &url::Url) -> Option<String> {
+        let open_text = {
+            let docs = self.documents.read().await;
+            docs.get(uri).cloned()
+        }; // Lock released here
+        open_text.or_else(|| url_to_path(uri).and_then(|path| std::fs::read_to_string(path).ok()))
+    }
+
+    // Classes of every indexed document that declares one, at most
+    // MAX_TYPE_HIERARCHY_ITEMS in total
+    async fn workspace_class_outlines(&self) -> Vec<(url::Url, String, ClassOutline)> {
+        let mut uris: Vec<url::Url> = {
+            let index = self.workspace_index.read().await;
+            index
+                .iter()
+                .filter(|(_, symbols)| symbols.iter().any(|symbol| symbol.kind == SymbolKind::CLASS))
+                .map(|(uri, _)| uri.clone())
+                .collect()
+        }; // Lock released here
+        uris.sort();
+
+        let mut classes = Vec::new();
+        for uri in uris {
+            let Some(text) = self.document_or_file_text(&uri).await else { continue };
+            for outline in class_outlines(&text) {
+                if classes.len() == MAX_TYPE_HIERARCHY_ITEMS {
+                    return classes;
+                }
+                classes.push((uri.clone(), text.clone(), outline));
+            }
+        }
+        classes
+    }
+
+    /// Snapshot of the capabilities the client announced
+    pub fn client_capabilities(&self) -> ClientCapabilities {
+        self.client_capabilities
+            .read()
+            .map(|capabilities| capabilities.clone())
+            .unwrap_or_default()
+    }
+
+    /// Position encoding used for all positions exchanged with the client
+    pub fn position_encoding(&self) -> PositionEncodingKind {
+        self.position_encoding
+            .read()
+            .map(|encoding| encoding.clone())
+            .unwrap_or(PositionEncodingKind::UTF16)
+    }
+
+    /// Whether the client renders Markdown in completion item documentation
+    pub fn supports_markdown_completion_docs(&self) -> bool {
+        self.client_capabilities()
+            .text_document
+            .and_then(|text_document| text_document.completion)
+            .and_then(|completion| completion.completion_item)
+            .and_then(|item| item.documentation_format)
+            .is_some_and(|formats| formats.contains(&MarkupKind::Markdown))
+    }
+
+    /// Whether completion items may carry an `InsertReplaceEdit`
+    pub fn supports_insert_replace_completion(&self) -> bool {
+        self.client_capabilities()
+            .text_document
+            .and_then(|text_document| text_document.completion)
+            .and_then(|completion| completion.completion_item)
+            .and_then(|item| item.insert_replace_support)
+            .unwrap_or(false)
+    }
+
+    /// Whether completion items may carry `commitCharacters`
+    pub fn supports_commit_characters(&self) -> bool {
+        self.client_capabilities()
+            .text_document
+            .and_then(|text_document| text_document.completion)
+            .and_then(|completion| completion.completion_item)
+            .and_then(|item| item.commit_characters_support)
+            .unwrap_or(false)
+    }
+
+    /// Wrap completion items in the response shape the client expects
+    ///
+    /// Incomplete lists need a `CompletionList` so the client asks again as
+    /// the user types; complete ones go out as a bare array.
+    pub fn completion_response(&self, list: CompletionList) -> CompletionResponse {
+        if list.is_incomplete {
+            CompletionResponse::List(list)
+        } else {
+            CompletionResponse::Array(list.items)
+        }
+    }
+
+    /// Whether the client requests diagnostics with `textDocument/diagnostic`
+    pub fn supports_pull_diagnostics(&self) -> bool {
+        self.client_capabilities()
+            .text_document
+            .is_some_and(|text_document| text_document.diagnostic.is_some())
+    }
+
+    fn supports_work_done_progress(&self) -> bool {
+        self.client_capabilities()
+            .window
+            .and_then(|window| window.work_done_progress)
+            .unwrap_or(false)
+    }
+
+    fn supports_completion_registration(&self) -> bool {
+        self.client_capabilities()
+            .text_document
+            .and_then(|text_document| text_document.completion)
+            .and_then(|completion| completion.dynamic_registration)
+            .unwrap_or(false)
+    }
+
+    /// Completion capability for the current settings
+    pub fn completion_options(&self) -> CompletionOptions {
+        CompletionOptions {
+            resolve_provider: Some(true),
+            trigger_characters: Some(self.settings().trigger_characters()),
+            ..Default::default()
+        }
+    }
+
+    // (Re-)register completion with the current trigger characters, dropping
+    // an earlier registration first
+    fn register_completion(&self, replace: bool) {
+        let client = self.client.clone();
+        let options = CompletionRegistrationOptions {
+            text_document_registration_options: TextDocumentRegistrationOptions {
+                document_selector: Some(vec![DocumentFilter {
+                    language: Some("pain".to_string()),
+                    scheme: None,
+                    pattern: None,
+                }]),
+            },
+            completion_options: self.completion_options(),
+        };
+        let task = tokio::spawn(async move {
+            if replace {
+                let unregistration = Unregistration {
+                    id: COMPLETION_REGISTRATION_ID.to_string(),
+                    method: "textDocument/completion".to_string(),
+                };
+                if let Err(err) = client.unregister_capability(vec![unregistration]).await {
+                    warn!("completion unregistration failed: {}", err);
+                }
+            }
+            let registration = Registration {
+                id: COMPLETION_REGISTRATION_ID.to_string(),
+                method: "textDocument/completion".to_string(),
+                register_options: serde_json::to_value(options).ok(),
+            };
+            if let Err(err) = client.register_capability(vec![registration]).await {
+                warn!("completion registration failed: {}", err);
+            }
+        });
+        self.track_task(task);
+    }
+
+    /// Directory relative paths in `uri` resolve against, per the
+    /// `pathResolutionBase` setting. `None` for documents not on disk outside
+    /// any workspace folder.
+    pub async fn path_base_dir(&self, uri: &url::Url) -> Option<std::path::PathBuf> {
+        let document = url_to_path(uri);
+        let document_dir = document.as_ref().and_then(|path| path.parent().map(|dir| dir.to_path_buf()));
+        if self.settings().path_resolution_base == PathResolutionBase::Document {
+            return document_dir;
+        }
+
+        // The innermost folder containing the document, else the first one
+        let roots: Vec<std::path::PathBuf> = self.workspace_roots.read().await.iter().filter_map(url_to_path).collect();
+        let containing = roots
+            .iter()
+            .filter(|root| document.as_ref().is_some_and(|path| path.starts_with(root)))
+            .max_by_key(|root| root.components().count());
+        containing.or(roots.first()).cloned().or(document_dir)
+    }
+
+    /// Start timing a request for the slow request log
+    pub fn time_request(&self, request: &'static str, uri: &url::Url, position: Position) -> RequestTiming {
+        RequestTiming {
+            request,
+            uri: uri.clone(),
+            position,
+            clock: self.current_clock(),
+            started: self.now(),
+            threshold: self.slow_request_threshold,
+        }
+    }
+
+    /// Snapshot of the current client settings
+    pub fn settings(&self) -> Settings {
+        self.settings
+            .read()
+            .map(|settings| settings.clone())
+            .unwrap_or_default()
+    }
+
+    /// Size limit for analysis - the `maxDocumentSize` setting, else `max_document_size`
+    pub fn document_size_limit(&self) -> usize {
+        self.settings().max_document_size.unwrap_or(self.max_document_size)
+    }
+
+    /// Publish diagnostics computed for `version`, unless a newer version of
+    /// the document has been seen since. Returns whether they were published.
+    pub async fn publish_diagnostics_if_current(
+        &self,
+        uri: url::Url,
+        diagnostics: Vec<Diagnostic>,
+        version: Option<i32>,
+    ) -> bool {
+        publish_if_current(self.client.clone(), self.document_versions.clone(), uri, diagnostics, version).await
+    }
+
+    pub fn update_settings(&self, settings: Settings) {
+        if let Ok(mut current) = self.settings.write() {
+            *current = settings;
         }
     }
     
-    // Get or parse program - simplified without caching to avoid panics
-    async fn get_or_parse_program(&self, _uri: &url::Url, text: &str) -> Option<Program> {
-        // Parse directly without caching to avoid clone panics
-        let (parse_result, _) = parse_with_recovery(text);
-        parse_result.ok()
+    // Get or parse program through the cache - cache hits share the AST instead of cloning it.
+    // Documents with syntax errors yield whatever items recovered.
+    async fn get_or_parse_program(&self, uri: &url::Url, text: &str) -> Option<Arc<Program>> {
+        let parsed = self.get_or_parse(uri, text).await;
+        parsed.program.or(parsed.recovered)
+    }
+
+    /// Semantic tokens of an open document, remembered as the base for the
+    /// next delta request. The result id is the text hash, so an unchanged
+    /// document keeps its id.
+    pub async fn semantic_tokens_for(&self, uri: &url::Url) -> Option<SemanticTokens> {
+        let text = {
+            let docs = self.documents.read().await;
+            docs.get(uri).cloned()
+        }?; // Lock released here
+        let program = self.get_or_parse_program(uri, &text).await?;
+        let encoding = self.position_encoding();
+
+        let data = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            semantic_tokens(&program, &text, &encoding)
+        })).unwrap_or_else(|e| {
+            log_panic("semantic_tokens", Some(uri), None, &*e);
+            Vec::new()
+        });
+        let tokens = SemanticTokens {
+            result_id: Some(format!("{:x}", hash_text(&text))),
+            data,
+        };
+        self.semantic_tokens.write().await.insert(uri.clone(), tokens.clone());
+        Some(tokens)
+    }
+
+    /// Type context with the document's functions and classes, type checked,
+    /// reusing the cached one while the text is unchanged. Documents without
+    /// a URI are not cached. Later definitions replace earlier ones, as in the
+    /// compiler.
+    pub fn type_context(&self, uri: Option<&url::Url>, text: &str, program: &Program) -> Arc<CheckedContext> {
+        let text_hash = hash_text(text);
+        let cached = uri.and_then(|uri| {
+            let contexts = self.type_contexts.read().ok()?;
+            let (hash, checked) = contexts.get(uri)?;
+            (*hash == text_hash).then(|| checked.clone())
+        });
+        if let Some(checked) = cached {
+            return checked;
+        }
+
+        self.type_context_builds.fetch_add(1, Ordering::Relaxed);
+        let mut ctx = program_type_context(program);
+        // Type check - wrap in catch_unwind to prevent panics
+        let outcome = catch_panics(|| {
+            type_check_program_with_context(program, &mut ctx).map(|_| ())
+        })
+        .map_err(|e| log_panic("type_check_program_with_context", uri, None, &*e))
+        .ok();
+        let checked = Arc::new(CheckedContext { ctx, outcome });
+        if let (Some(uri), Ok(mut contexts)) = (uri, self.type_contexts.write()) {
+            contexts.insert(uri.clone(), (text_hash, checked.clone()));
+        }
+        checked
+    }
+
+    // Drop the cached type context along with the document's parse
+    fn invalidate_type_context(&self, uri: &url::Url) {
+        if let Ok(mut contexts) = self.type_contexts.write() {
+            contexts.remove(uri);
+        }
+    }
+
+    /// Parse result for the document, reusing the cached one while the text is unchanged
+    pub async fn get_or_parse(&self, uri: &url::Url, text: &str) -> ParsedDocument {
+        let text_hash = hash_text(text);
+        let cached = {
+            let cache = self.parsed_cache.read().await;
+            cache.get(uri).filter(|parsed| parsed.text_hash == text_hash).cloned()
+        }; // Lock released here
+        self.touch_cache_entry(uri);
+        if let Some(parsed) = cached {
+            return parsed;
+        }
+        // Another document (or an earlier version of this one) may have the same text
+        let shared = self.parsed_by_content.read().await.get(&text_hash).cloned();
+        if let Some(parsed) = shared {
+            self.parsed_cache.write().await.insert(uri.clone(), parsed.clone());
+            return parsed;
+        }
+
+        self.parse_count.fetch_add(1, Ordering::Relaxed);
+        let normalized = normalize_line_endings(text);
+        let text = normalized.as_ref();
+        let (parse_result, parse_errors) = parse_with_recovery(text);
+        let program = parse_result.ok().map(Arc::new);
+        let recovered = match program {
+            Some(_) => None,
+            None => std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| recover_program(text, &parse_errors)))
+                .unwrap_or_else(|e| {
+                    log_panic("recover_program", Some(uri), None, &*e);
+                    None
+                })
+                .map(Arc::new),
+        };
+        let parsed = ParsedDocument {
+            text_hash,
+            program,
+            parse_errors,
+            recovered,
+        };
+        let live_hashes: HashSet<u64> = {
+            let mut cache = self.parsed_cache.write().await;
+            cache.insert(uri.clone(), parsed.clone());
+            cache.values().map(|parsed| parsed.text_hash).collect()
+        }; // Lock released here
+        let mut by_content = self.parsed_by_content.write().await;
+        by_content.retain(|hash, _| live_hashes.contains(hash));
+        by_content.insert(text_hash, parsed.clone());
+        parsed
     }
 }
 
@@ -50,70 +977,218 @@ impl Backend {
 impl tower_lsp::LanguageServer for Backend {
     async fn initialize(
         &self,
-        _: InitializeParams,
+        params: InitializeParams,
     ) -> Result<InitializeResult, tower_lsp::jsonrpc::Error> {
-        eprintln!("LSP: initialize START");
+        debug!("initialize START");
+        if let Some(settings) = params
+            .initialization_options
+            .as_ref()
+            .and_then(Settings::from_value)
+        {
+            self.update_settings(settings);
+        }
+        if let Ok(mut capabilities) = self.client_capabilities.write() {
+            *capabilities = params.capabilities.clone();
+        }
+        let encoding = negotiate_position_encoding(
+            params
+                .capabilities
+                .general
+                .as_ref()
+                .and_then(|general| general.position_encodings.as_deref()),
+        );
+        info!("negotiated position encoding {}", encoding.as_str());
+        if let Ok(mut current) = self.position_encoding.write() {
+            *current = encoding.clone();
+        }
+        {
+            let mut roots = self.workspace_roots.write().await;
+            *roots = match (&params.workspace_folders, &params.root_uri) {
+                (Some(folders), _) => folders.iter().map(|folder| folder.uri.clone()).collect(),
+                (None, Some(root)) => vec![root.clone()],
+                (None, None) => Vec::new(),
+            };
+        }
         let result = InitializeResult {
             capabilities: ServerCapabilities {
+                position_encoding: Some(encoding),
                 text_document_sync: Some(TextDocumentSyncCapability::Kind(
                     TextDocumentSyncKind::FULL,
                 )),
-                completion_provider: Some(CompletionOptions {
-                    resolve_provider: Some(false),
-                    trigger_characters: Some(vec![".".to_string()]),
+                // Registered in `initialized` instead when the client allows it,
+                // so trigger characters can follow the settings
+                completion_provider: (!self.supports_completion_registration()).then(|| self.completion_options()),
+                hover_provider: Some(HoverProviderCapability::Simple(true)),
+                definition_provider: Some(OneOf::Left(true)),
+                declaration_provider: Some(DeclarationCapability::Simple(true)),
+                implementation_provider: Some(ImplementationProviderCapability::Simple(true)),
+                inlay_hint_provider: Some(OneOf::Left(true)),
+                document_symbol_provider: Some(OneOf::Left(true)),
+                rename_provider: Some(OneOf::Left(true)),
+                moniker_provider: Some(OneOf::Left(true)),
+                document_formatting_provider: Some(OneOf::Left(true)),
+                // Answered only while `formatOnType` is enabled
+                document_on_type_formatting_provider: Some(DocumentOnTypeFormattingOptions {
+                    first_trigger_character: "\n".to_string(),
+                    more_trigger_character: None,
+                }),
+                semantic_tokens_provider: Some(SemanticTokensServerCapabilities::SemanticTokensOptions(SemanticTokensOptions {
+                    legend: SemanticTokensLegend {
+                        token_types: SEMANTIC_TOKEN_TYPES.to_vec(),
+                        token_modifiers: SEMANTIC_TOKEN_MODIFIERS.to_vec(),
+                    },
+                    full: Some(SemanticTokensFullOptions::Delta { delta: Some(true) }),
+                    range: None,
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                })),
+                diagnostic_provider: Some(DiagnosticServerCapabilities::Options(DiagnosticOptions {
+                    identifier: Some("pain".to_string()),
+                    // Each document is checked on its own
+                    inter_file_dependencies: false,
+                    workspace_diagnostics: true,
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                })),
+                code_action_provider: Some(CodeActionProviderCapability::Options(CodeActionOptions {
+                    code_action_kinds: Some(vec![CodeActionKind::QUICKFIX]),
                     ..Default::default()
+                })),
+                document_link_provider: Some(DocumentLinkOptions {
+                    resolve_provider: Some(false),
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
                 }),
-                hover_provider: Some(HoverProviderCapability::Simple(true)),
+                execute_command_provider: Some(ExecuteCommandOptions {
+                    commands: vec![
+                        DUMP_AST_COMMAND.to_string(),
+                        STATUS_COMMAND.to_string(),
+                        LINT_COMMAND.to_string(),
+                    ],
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                }),
+                workspace_symbol_provider: Some(OneOf::Right(WorkspaceSymbolOptions {
+                    work_done_progress_options: WorkDoneProgressOptions {
+                        work_done_progress: Some(true),
+                    },
+                    resolve_provider: None,
+                })),
                 ..Default::default()
             },
             ..Default::default()
         };
-        eprintln!("LSP: initialize END");
+        info!("initialize END");
         Ok(result)
     }
 
     async fn initialized(&self, _: InitializedParams) {
-        eprintln!("LSP: initialized START");
+        debug!("initialized START");
         // Log initialization - ignore errors to prevent crashes
         let _ = self.client
             .log_message(MessageType::INFO, "Pain LSP server initialized")
             .await;
-        eprintln!("LSP: initialized END - server is ready");
-    }
 
-    async fn did_open(&self, params: DidOpenTextDocumentParams) {
-        eprintln!("LSP: did_open START");
-        
-        // Wrap entire handler in catch_unwind to prevent any panics from crashing LSP
-        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-            params.text_document.uri.clone()
-        }));
-        
-        let uri = match result {
-            Ok(u) => u,
+        // Index the workspace in the background so the server stays responsive
+        let roots = self.workspace_roots.read().await.clone();
+        let client = self.client.clone();
+        let index = self.workspace_index.clone();
+        let report_progress = self.supports_work_done_progress();
+        let task = tokio::spawn(async move {
+            index_workspace(client, roots, index, report_progress).await;
+        });
+        self.track_task(task);
+
+        // This lsp-types version has no static `typeHierarchyProvider` capability,
+        // so the type hierarchy is registered dynamically
+        let supports_type_hierarchy_registration = self
+            .client_capabilities()
+            .text_document
+            .and_then(|text_document| text_document.type_hierarchy)
+            .and_then(|type_hierarchy| type_hierarchy.dynamic_registration)
+            .unwrap_or(false);
+        if supports_type_hierarchy_registration {
+            let client = self.client.clone();
+            let task = tokio::spawn(async move {
+                let options = TypeHierarchyRegistrationOptions {
+                    text_document_registration_options: TextDocumentRegistrationOptions {
+                        document_selector: Some(vec![DocumentFilter {
+                            language: Some("pain".to_string()),
+                            scheme: None,
+                            pattern: None,
+                        }]),
+                    },
+                    ..Default::default()
+                };
+                let registration = Registration {
+                    id: "pain-type-hierarchy".to_string(),
+                    method: "textDocument/prepareTypeHierarchy".to_string(),
+                    register_options: serde_json::to_value(options).ok(),
+                };
+                if let Err(err) = client.register_capability(vec![registration]).await {
+                    warn!("type hierarchy registration failed: {}", err);
+                }
+            });
+            self.track_task(task);
+        }
+        if self.supports_completion_registration() {
+            self.register_completion(false);
+        }
+        // Keep the workspace index in sync with changes made outside the editor
+        let supports_watched_files_registration = self
+            .client_capabilities()
+            .workspace
+            .and_then(|workspace| workspace.did_change_watched_files)
+            .and_then(|watched| watched.dynamic_registration)
+            .unwrap_or(false);
+        if supports_watched_files_registration {
+            let client = self.client.clone();
+            let task = tokio::spawn(async move {
+                let options = DidChangeWatchedFilesRegistrationOptions {
+                    watchers: vec![FileSystemWatcher {
+                        glob_pattern: GlobPattern::String("**/*.pain".to_string()),
+                        kind: None,
+                    }],
+                };
+                let registration = Registration {
+                    id: "pain-watched-files".to_string(),
+                    method: "workspace/didChangeWatchedFiles".to_string(),
+                    register_options: serde_json::to_value(options).ok(),
+                };
+                if let Err(err) = client.register_capability(vec![registration]).await {
+                    warn!("file watcher registration failed: {}", err);
+                }
+            });
+            self.track_task(task);
+        }
+        info!("initialized END - server is ready");
+    }
+
+    async fn did_open(&self, params: DidOpenTextDocumentParams) {
+        debug!("did_open START");
+        
+        // Wrap entire handler in catch_unwind to prevent any panics from crashing LSP
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            params.text_document.uri.clone()
+        }));
+        
+        let uri = match result {
+            Ok(u) => u,
             Err(e) => {
-                eprintln!("LSP: did_open PANICKED extracting URI: {:?}", e);
+                log_panic("did_open extracting URI", None, None, &*e);
                 return;
             }
         };
         
         let text = params.text_document.text.clone();
-        eprintln!("LSP: did_open uri={}, text_len={}", uri, text.len());
+        let version = params.text_document.version;
+        debug!("did_open uri={}, version={}, text_len={}", uri, version, text.len());
+        self.document_versions.write().await.insert(uri.clone(), version);
         
-        // Check document size to prevent memory issues
-        if text.len() > self.max_document_size {
-            eprintln!("LSP: did_open document too large, skipping");
-            let _ = self.client
-                .log_message(
-                    MessageType::WARNING,
-                    format!("Document {} is too large ({} bytes), skipping", uri, text.len()),
-                )
-                .await;
-            return;
+        // Oversized documents are stored but not analyzed - on_change reports why
+        let limit = self.document_size_limit();
+        if text.len() > limit {
+            warn!("did_open document too large ({} bytes, limit {})", text.len(), limit);
         }
         
         // Store document - release lock quickly - wrap in catch_unwind
-        eprintln!("LSP: did_open storing document");
+        debug!("did_open storing document");
         let store_result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
             let docs_arc = self.documents.clone();
             let uri_clone = uri.clone();
@@ -125,13 +1200,13 @@ impl tower_lsp::LanguageServer for Backend {
             let mut docs = docs_arc.write().await;
             docs.insert(uri_clone, text_clone);
         } else {
-            eprintln!("LSP: did_open PANICKED storing document");
+            error!("did_open PANICKED storing document (uri={})", uri);
             return;
         }
-        eprintln!("LSP: did_open document stored");
+        debug!("did_open document stored");
         
         // Clear cache for this document - wrap in catch_unwind
-        eprintln!("LSP: did_open clearing cache");
+        debug!("did_open clearing cache");
         let cache_result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
             (self.parsed_cache.clone(), uri.clone())
         }));
@@ -139,56 +1214,61 @@ impl tower_lsp::LanguageServer for Backend {
         if let Ok((cache_arc, uri_clone)) = cache_result {
             let mut cache = cache_arc.write().await;
             cache.remove(&uri_clone);
-            eprintln!("LSP: did_open cache cleared");
+            self.invalidate_type_context(&uri_clone);
+            debug!("did_open cache cleared");
         } else {
-            eprintln!("LSP: did_open PANICKED clearing cache (continuing anyway)");
+            error!("did_open PANICKED clearing cache, continuing anyway (uri={})", uri);
         }
         
         // Call on_change after releasing lock
-        eprintln!("LSP: did_open calling on_change");
-        self.on_change(uri, text).await;
-        eprintln!("LSP: did_open END");
+        debug!("did_open calling on_change");
+        self.on_change(uri, text, Some(version)).await;
+        debug!("did_open END");
     }
 
+    #[tracing::instrument(skip_all, fields(uri = %params.text_document.uri))]
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
-        eprintln!("LSP: did_change START");
+        debug!("did_change START");
         
-        // Wrap extraction in catch_unwind
-        let extract_result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-            let uri = params.text_document.uri.clone();
-            let text = params
-                .content_changes
-                .into_iter()
-                .next()
-                .map(|change| change.text)
-                .unwrap_or_default();
-            (uri, text)
+        let uri = params.text_document.uri.clone();
+        let version = params.text_document.version;
+        self.document_versions.write().await.insert(uri.clone(), version);
+        if let Ok(mut recent) = self.recent_identifiers.write() {
+            if let Some(recent) = recent.get_mut(&uri) {
+                recent.decay();
+            }
+        }
+
+        // Ranged changes apply on top of the stored text - clone quickly and release lock
+        let current = {
+            let docs = self.documents.read().await;
+            docs.get(&uri).cloned().unwrap_or_default()
+        }; // Lock released here
+
+        // Apply every change event in order - wrap in catch_unwind
+        let encoding = self.position_encoding();
+        let apply_result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            apply_content_changes(&current, &params.content_changes, &encoding)
         }));
-        
-        let (uri, text) = match extract_result {
-            Ok(data) => data,
+
+        let text = match apply_result {
+            Ok(text) => text,
             Err(e) => {
-                eprintln!("LSP: did_change PANICKED extracting params: {:?}", e);
+                log_panic("did_change applying changes", Some(&uri), None, &*e);
                 return;
             }
         };
         
-        eprintln!("LSP: did_change uri={}, text_len={}", uri, text.len());
+        debug!("did_change uri={}, text_len={}", uri, text.len());
         
-        // Check document size
-        if text.len() > self.max_document_size {
-            eprintln!("LSP: did_change document too large, skipping");
-            let _ = self.client
-                .log_message(
-                    MessageType::WARNING,
-                    format!("Document {} is too large ({} bytes), skipping", uri, text.len()),
-                )
-                .await;
-            return;
+        // Oversized documents are stored but not analyzed - on_change reports why
+        let limit = self.document_size_limit();
+        if text.len() > limit {
+            warn!("did_change document too large ({} bytes, limit {})", text.len(), limit);
         }
         
         // Store document - release lock quickly - wrap in catch_unwind
-        eprintln!("LSP: did_change storing document");
+        debug!("did_change storing document");
         let store_result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
             (self.documents.clone(), uri.clone(), text.clone())
         }));
@@ -196,14 +1276,14 @@ impl tower_lsp::LanguageServer for Backend {
         if let Ok((docs_arc, uri_clone, text_clone)) = store_result {
             let mut docs = docs_arc.write().await;
             docs.insert(uri_clone, text_clone);
-            eprintln!("LSP: did_change document stored");
+            debug!("did_change document stored");
         } else {
-            eprintln!("LSP: did_change PANICKED storing document");
+            error!("did_change PANICKED storing document (uri={})", uri);
             return;
         }
         
         // Invalidate cache for this document - wrap in catch_unwind
-        eprintln!("LSP: did_change clearing cache");
+        debug!("did_change clearing cache");
         let cache_result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
             (self.parsed_cache.clone(), uri.clone())
         }));
@@ -211,23 +1291,87 @@ impl tower_lsp::LanguageServer for Backend {
         if let Ok((cache_arc, uri_clone)) = cache_result {
             let mut cache = cache_arc.write().await;
             cache.remove(&uri_clone);
-            eprintln!("LSP: did_change cache cleared");
+            self.invalidate_type_context(&uri_clone);
+            debug!("did_change cache cleared");
         } else {
-            eprintln!("LSP: did_change PANICKED clearing cache (continuing anyway)");
+            error!("did_change PANICKED clearing cache, continuing anyway (uri={})", uri);
         }
         
         // Call on_change after releasing lock
-        eprintln!("LSP: did_change calling on_change");
-        self.on_change(uri, text).await;
-        eprintln!("LSP: did_change END");
+        debug!("did_change calling on_change");
+        self.on_change(uri, text, Some(version)).await;
+        debug!("did_change END");
+    }
+
+    async fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
+        let Some(settings) = Settings::from_value(&params.settings) else { return };
+        info!("did_change_configuration {:?}", settings);
+        let previous = self.settings();
+        if settings == previous {
+            return;
+        }
+        let triggers_changed = settings.trigger_characters() != previous.trigger_characters();
+        self.update_settings(settings);
+        if triggers_changed && self.supports_completion_registration() {
+            self.register_completion(true);
+        }
+
+        // Severities or enabled warnings changed - refresh open documents in the
+        // background so the notification returns immediately
+        let backend = self.clone();
+        let task = tokio::spawn(async move {
+            backend.refresh_open_documents().await;
+        });
+        self.track_task(task);
+    }
+
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+        for change in params.changes {
+            let uri = change.uri;
+            if !uri.path().ends_with(".pain") {
+                continue;
+            }
+            debug!("did_change_watched_files {:?} {}", change.typ, uri);
+
+            // Open documents are analyzed from the editor buffer, even once
+            // the file behind them is gone
+            if self.documents.read().await.contains_key(&uri) {
+                continue;
+            }
+
+            if change.typ == FileChangeType::DELETED {
+                self.workspace_index.write().await.remove(&uri);
+                self.parsed_cache.write().await.remove(&uri);
+                self.invalidate_type_context(&uri);
+                self.last_good_diagnostics.write().await.remove(&uri);
+                let had_diagnostics = self.published_diagnostics.write().await.remove(&uri).is_some();
+                if had_diagnostics {
+                    self.client.publish_diagnostics(uri, Vec::new(), None).await;
+                }
+                continue;
+            }
+
+            let Some(text) = url_to_path(&uri).and_then(|path| std::fs::read_to_string(path).ok()) else {
+                continue;
+            };
+            let symbols = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                index_document(&text)
+            })).unwrap_or_else(|e| {
+                log_panic("index_document", Some(&uri), None, &*e);
+                Vec::new()
+            });
+            self.workspace_index.write().await.insert(uri, symbols);
+        }
     }
 
+    #[tracing::instrument(skip_all, fields(uri = %params.text_document_position.text_document.uri))]
     async fn completion(
         &self,
         params: CompletionParams,
     ) -> Result<Option<CompletionResponse>, tower_lsp::jsonrpc::Error> {
         let uri = params.text_document_position.text_document.uri.clone();
         let position = params.text_document_position.position;
+        let _timing = self.time_request("completion", &uri, position);
 
         // Get document text - clone quickly and release lock
         let text = {
@@ -236,31 +1380,119 @@ impl tower_lsp::LanguageServer for Backend {
         }; // Lock released here
         
         if let Some(text) = text {
-            // Use cached parsing for better performance
-            let program = self.get_or_parse_program(&uri, &text).await;
+            let encoding = self.position_encoding();
+            let position = to_byte_position(&text, position, &encoding);
+            self.note_identifier_use(&uri, &text, position);
+
+            // A half-typed `fn `/`class ` header rarely parses - offer the skeleton first
+            if let Some(item) = declaration_snippet(&text, position) {
+                let mut items = vec![item];
+                completion_edits_to_encoding(&text, &mut items, &encoding);
+                return Ok(Some(self.completion_response(complete_list(items))));
+            }
+            // Annotations being typed rarely parse either, and only take types
+            if let Some(mut items) = type_annotation_completions(&text, position).or_else(|| attribute_completions(&text, position)) {
+                self.attach_insert_replace_edits(&text, position, &mut items);
+                completion_edits_to_encoding(&text, &mut items, &encoding);
+                return Ok(Some(self.completion_response(complete_list(items))));
+            }
+            // An open string index rarely parses either
+            if let Some(items) = map_key_completions(&text, position) {
+                return Ok(Some(self.completion_response(complete_list(items))));
+            }
+
+            // Use cached parsing for better performance. A dangling `receiver.`
+            // breaks the parse, so member completion parses around it.
+            let parsed = self.get_or_parse(&uri, &text).await;
+            let program = parsed.program.or_else(|| {
+                catch_panics(|| parse_without_dangling_dot(&text, position))
+                    .unwrap_or(None)
+                    .map(Arc::new)
+                    .or(parsed.recovered)
+            });
             if let Some(program) = program {
+                // Functions and classes of other workspace files
+                let workspace = {
+                    let roots = self.workspace_roots.read().await.clone();
+                    let index = self.workspace_index.read().await;
+                    workspace_completions(&index, &roots, &uri, &program)
+                };
+                // With a partial result token, each group of items goes out as
+                // `$/progress` as soon as it is computed, and the response only
+                // says whether the list is complete
+                if let Some(token) = params.partial_result_params.partial_result_token {
+                    let (sender, mut groups) = tokio::sync::mpsc::unbounded_channel();
+                    let backend = self.clone();
+                    let (task_uri, task_text) = (uri.clone(), text.clone());
+                    let task = tokio::task::spawn_blocking(move || {
+                        let mut emit = |group: Vec<CompletionItem>| {
+                            let _ = sender.send(group);
+                        };
+                        backend.get_completions_for_document(&task_uri, &program, &task_text, position, workspace, Some(&mut emit))
+                    });
+                    while let Some(mut group) = groups.recv().await {
+                        completion_edits_to_encoding(&text, &mut group, &encoding);
+                        self.client
+                            .send_notification::<CompletionProgress>(CompletionProgressParams {
+                                token: token.clone(),
+                                value: group,
+                            })
+                            .await;
+                    }
+                    let mut list = task.await.unwrap_or_else(|e| {
+                        if let Ok(payload) = e.try_into_panic() {
+                            // The blocking task caught it - re-raise under `PAIN_LSP_NO_CATCH`
+                            if panics_propagate() {
+                                std::panic::resume_unwind(payload);
+                            }
+                            log_panic("completion", Some(&uri), Some(position), &*payload);
+                        }
+                        complete_list(self.get_basic_completions())
+                    });
+                    completion_edits_to_encoding(&text, &mut list.items, &encoding);
+                    return Ok(Some(CompletionResponse::List(list)));
+                }
+
                 // Wrap get_completions in catch_unwind to prevent panics
                 // Note: Timeout protection is handled at the VS Code extension level
-                let items = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-                    self.get_completions(&program, &text, position)
-                })).unwrap_or_else(|_| {
+                let mut list = catch_panics(|| {
+                    self.get_completions_for_document(&uri, &program, &text, position, workspace, None)
+                }).unwrap_or_else(|e| {
                     // If get_completions panics, return basic completions
-                    self.get_basic_completions()
+                    log_panic("completion", Some(&uri), Some(position), &*e);
+                    complete_list(self.get_basic_completions())
                 });
-                
-                return Ok(Some(CompletionResponse::Array(items)));
+                completion_edits_to_encoding(&text, &mut list.items, &encoding);
+                return Ok(Some(self.completion_response(list)));
             }
         }
 
         // Fallback to basic completions if parsing fails
-        Ok(Some(CompletionResponse::Array(
-            self.get_basic_completions(),
-        )))
+        Ok(Some(self.completion_response(complete_list(self.get_basic_completions()))))
+    }
+
+    async fn completion_resolve(&self, params: CompletionItem) -> Result<CompletionItem, tower_lsp::jsonrpc::Error> {
+        let mut item = params;
+        let Some(data) = item.data.as_ref() else { return Ok(item) };
+        let document = data.get("document").and_then(|uri| uri.as_str()).and_then(|uri| url::Url::parse(uri).ok());
+        let Some(from) = data.get("from").and_then(|from| from.as_str()).map(str::to_string) else { return Ok(item) };
+        let Some(document) = document else { return Ok(item) };
+
+        let text = {
+            let docs = self.documents.read().await;
+            docs.get(&document).cloned()
+        }; // Lock released here
+        if let Some(edit) = text.and_then(|text| requires_comment_edit(&text, &from)) {
+            item.additional_text_edits = Some(vec![edit]);
+        }
+        Ok(item)
     }
 
+    #[tracing::instrument(skip_all, fields(uri = %params.text_document_position_params.text_document.uri))]
     async fn hover(&self, params: HoverParams) -> Result<Option<Hover>, tower_lsp::jsonrpc::Error> {
         let uri = params.text_document_position_params.text_document.uri.clone();
         let position = params.text_document_position_params.position;
+        let _timing = self.time_request("hover", &uri, position);
 
         // Get document text from cache - clone quickly and release lock
         let text = {
@@ -269,19 +1501,51 @@ impl tower_lsp::LanguageServer for Backend {
         }; // Lock released here
         
         if let Some(text) = text {
-            // Use parse_with_recovery instead of parse to avoid panics
-            let (parse_result, _) = parse_with_recovery(&text);
-            if let Ok(program) = parse_result {
-                // Wrap find_function_at_position in catch_unwind to prevent panics
-                let hover_info = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-                    find_function_at_position(
-                        &program,
-                        position.line as usize + 1,
-                        position.character as usize + 1,
-                    )
-                }));
+            let encoding = self.position_encoding();
+            let position = to_byte_position(&text, position, &encoding);
+            self.note_identifier_use(&uri, &text, position);
+
+            // Keywords are documented from a static table - no parsing needed
+            if let Some(mut hover) = keyword_hover(&text, position) {
+                hover.range = hover.range.map(|range| from_byte_range(&text, range, &encoding));
+                return Ok(Some(hover));
+            }
 
-                if let Ok(Some(hover_info)) = hover_info {
+            // Use cached parsing - diagnostics already parsed this text
+            if let Some(program) = self.get_or_parse_program(&uri, &text).await {
+                // Locals show their type, and their value when it is a constant
+                let variable = catch_panics(|| {
+                    variable_hover(&program, &text, position)
+                }).unwrap_or_else(|e| {
+                    log_panic("variable_hover", Some(&uri), Some(position), &*e);
+                    None
+                });
+                if let Some(mut hover) = variable {
+                    hover.range = hover.range.map(|range| from_byte_range(&text, range, &encoding));
+                    return Ok(Some(hover));
+                }
+
+                let literal = catch_panics(|| {
+                    collection_literal_hover(&program, &text, position)
+                }).unwrap_or_else(|e| {
+                    log_panic("collection_literal_hover", Some(&uri), Some(position), &*e);
+                    None
+                });
+                if let Some(mut hover) = literal {
+                    hover.range = hover.range.map(|range| from_byte_range(&text, range, &encoding));
+                    return Ok(Some(hover));
+                }
+
+                // Functions of the program shadow stdlib functions of the same name
+                let hover_info = catch_panics(|| {
+                    find_stdlib_function_at_position(&program, &text, position)
+                        .or_else(|| function_hover(&program, &text, position))
+                }).unwrap_or_else(|e| {
+                    log_panic("function hover", Some(&uri), Some(position), &*e);
+                    None
+                });
+
+                if let Some(hover_info) = hover_info {
                     let mut contents = Vec::new();
 
                     // Add function signature
@@ -289,7 +1553,7 @@ impl tower_lsp::LanguageServer for Backend {
 
                     // Add doc comment if present
                     if let Some(doc) = hover_info.doc {
-                        contents.push(MarkedString::String(format!("---\n{}", doc)));
+                        contents.push(MarkedString::String(format!("---\n{}", format_doc_markdown(&doc))));
                     }
 
                     return Ok(Some(Hover {
@@ -303,654 +1567,4533 @@ impl tower_lsp::LanguageServer for Backend {
         Ok(None)
     }
 
-    async fn shutdown(&self) -> Result<(), tower_lsp::jsonrpc::Error> {
-        eprintln!("LSP: shutdown START");
-        // Clear documents and cache on shutdown to free memory
-        {
-            let mut docs = self.documents.write().await;
-            docs.clear();
-        }
-        {
-            let mut cache = self.parsed_cache.write().await;
-            cache.clear();
-        }
-        eprintln!("LSP: shutdown END");
-        Ok(())
+    async fn goto_definition(
+        &self,
+        params: GotoDefinitionParams,
+    ) -> Result<Option<GotoDefinitionResponse>, tower_lsp::jsonrpc::Error> {
+        let uri = params.text_document_position_params.text_document.uri.clone();
+        let position = params.text_document_position_params.position;
+
+        let text = {
+            let docs = self.documents.read().await;
+            docs.get(&uri).cloned()
+        }; // Lock released here
+
+        let Some(text) = text else { return Ok(None) };
+        let Some(program) = self.get_or_parse_program(&uri, &text).await else { return Ok(None) };
+        let encoding = self.position_encoding();
+        let position = to_byte_position(&text, position, &encoding);
+
+        let range = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            DocumentIndex::new(&program, &text).find_definition(position)
+        })).unwrap_or_else(|e| {
+            log_panic("goto_definition", Some(&uri), Some(position), &*e);
+            None
+        });
+
+        Ok(range.map(|range| {
+            let range = from_byte_range(&text, range, &encoding);
+            GotoDefinitionResponse::Scalar(Location { uri, range })
+        }))
     }
-}
 
-impl Backend {
-    /// Get context-aware completions
-    pub fn get_completions(
+    async fn goto_declaration(
         &self,
-        program: &Program,
-        text: &str,
-        position: Position,
-    ) -> Vec<CompletionItem> {
-        // Wrap in catch_unwind to prevent panics
-        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-            self.get_completions_internal(program, text, position)
-        })).unwrap_or_else(|_| {
-            // If anything panics, return basic completions
-            eprintln!("LSP: get_completions panicked, returning basic completions");
-            self.get_basic_completions()
-        })
+        params: request::GotoDeclarationParams,
+    ) -> Result<Option<request::GotoDeclarationResponse>, tower_lsp::jsonrpc::Error> {
+        let uri = params.text_document_position_params.text_document.uri.clone();
+        let position = params.text_document_position_params.position;
+
+        let text = {
+            let docs = self.documents.read().await;
+            docs.get(&uri).cloned()
+        }; // Lock released here
+
+        let Some(text) = text else { return Ok(None) };
+        let Some(program) = self.get_or_parse_program(&uri, &text).await else { return Ok(None) };
+        let encoding = self.position_encoding();
+        let position = to_byte_position(&text, position, &encoding);
+
+        let range = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            DocumentIndex::new(&program, &text).find_declaration(position)
+        })).unwrap_or_else(|e| {
+            log_panic("goto_declaration", Some(&uri), Some(position), &*e);
+            None
+        });
+
+        Ok(range.map(|range| {
+            let range = from_byte_range(&text, range, &encoding);
+            request::GotoDeclarationResponse::Scalar(Location { uri, range })
+        }))
     }
 
-    fn get_completions_internal(
+    async fn goto_implementation(
         &self,
-        program: &Program,
-        text: &str,
-        position: Position,
-    ) -> Vec<CompletionItem> {
-        let mut items = Vec::new();
-        let line = position.line as usize;
-        let column = position.character as usize;
+        params: request::GotoImplementationParams,
+    ) -> Result<Option<request::GotoImplementationResponse>, tower_lsp::jsonrpc::Error> {
+        let uri = params.text_document_position_params.text_document.uri.clone();
+        let position = params.text_document_position_params.position;
 
-        // Get text before cursor on current line - safe indexing
-        let lines: Vec<&str> = text.lines().collect();
-        let current_line = if line < lines.len() {
-            lines[line]
-        } else {
-            return self.get_basic_completions();
-        };
+        let text = {
+            let docs = self.documents.read().await;
+            docs.get(&uri).cloned()
+        }; // Lock released here
 
-        let text_before_cursor = if column <= current_line.len() {
-            &current_line[..column]
-        } else {
-            current_line
-        };
+        let Some(text) = text else { return Ok(None) };
+        let Some(program) = self.get_or_parse_program(&uri, &text).await else { return Ok(None) };
+        let encoding = self.position_encoding();
+        let position = to_byte_position(&text, position, &encoding);
 
-        // Check if we're after a dot (member access)
-        let is_member_access = text_before_cursor.trim_end().ends_with('.');
+        let found = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            method_implementations(&program, &text, position)
+        })).unwrap_or_else(|e| {
+            log_panic("goto_implementation", Some(&uri), Some(position), &*e);
+            None
+        });
+        let Some((name, ranges)) = found else { return Ok(None) };
 
-        // Extract functions from program - optimize by limiting detail formatting
-        // Format full signatures only for first N items to improve performance
-        let mut function_names = HashSet::new();
-        let max_detailed_items = 50; // Limit detailed formatting for performance
-        let mut detailed_count = 0;
-        
-        for item in &program.items {
-            match item {
-                Item::Function(func) => {
-                    function_names.insert(func.name.clone());
-                    // Only format full signature for first N items
-                    let detail = if detailed_count < max_detailed_items {
-                        detailed_count += 1;
-                        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-                            format_function_signature(func)
-                        })).unwrap_or_else(|_| format!("fn {}", func.name))
-                    } else {
-                        format!("fn {}", func.name)
-                    };
-                    
-                    items.push(CompletionItem {
-                        label: func.name.clone(),
-                        kind: Some(CompletionItemKind::FUNCTION),
-                        detail: Some(detail),
-                        documentation: func.doc.clone().map(Documentation::String),
-                        ..Default::default()
-                    });
-                }
-                Item::Class(class) => {
-                    // Add class name
-                    items.push(CompletionItem {
-                        label: class.name.clone(),
-                        kind: Some(CompletionItemKind::CLASS),
-                        detail: Some(format!("class {}", class.name)),
-                        documentation: class.doc.clone().map(Documentation::String),
-                        ..Default::default()
-                    });
+        let mut locations: Vec<Location> = ranges
+            .into_iter()
+            .map(|range| Location { uri: uri.clone(), range: from_byte_range(&text, range, &encoding) })
+            .collect();
 
-                    // Add class methods - optimize formatting
-                    for method in &class.methods {
-                        function_names.insert(method.name.clone());
-                        let detail = if detailed_count < max_detailed_items {
-                            detailed_count += 1;
-                            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-                                format_function_signature(method)
-                            })).unwrap_or_else(|_| format!("fn {}", method.name))
-                        } else {
-                            format!("fn {}", method.name)
-                        };
-                        
-                        items.push(CompletionItem {
-                            label: format!("{}.{}", class.name, method.name),
-                            kind: Some(CompletionItemKind::METHOD),
-                            detail: Some(detail),
-                            documentation: method.doc.clone().map(Documentation::String),
-                            ..Default::default()
-                        });
-                    }
-                }
+        // Methods of the same name in the rest of the workspace
+        let mut others: Vec<(url::Url, Vec<Range>)> = {
+            let index = self.workspace_index.read().await;
+            index
+                .iter()
+                .filter(|(other, _)| **other != uri)
+                .map(|(other, symbols)| {
+                    let ranges: Vec<Range> = symbols
+                        .iter()
+                        .filter(|symbol| symbol.kind == SymbolKind::METHOD && symbol.name == name)
+                        .map(|symbol| symbol.range)
+                        .collect();
+                    (other.clone(), ranges)
+                })
+                .filter(|(_, ranges)| !ranges.is_empty())
+                .collect()
+        }; // Lock released here
+        others.sort_by(|a, b| a.0.cmp(&b.0));
+        for (other, ranges) in others {
+            let Some(other_text) = self.document_or_file_text(&other).await else { continue };
+            for range in ranges {
+                locations.push(Location { uri: other.clone(), range: from_byte_range(&other_text, range, &encoding) });
             }
         }
 
-        // Extract variables from current scope - wrap in catch_unwind
-        let vars = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-            extract_variables_in_scope(program, line + 1, column + 1)
-        })).unwrap_or(None);
-        
-        if let Some(vars) = vars {
-            for var_name in vars {
-                if !function_names.contains(&var_name) {
-                    items.push(CompletionItem {
-                        label: var_name.clone(),
-                        kind: Some(CompletionItemKind::VARIABLE),
-                        detail: Some("Variable".to_string()),
-                        ..Default::default()
-                    });
-                }
-            }
-        }
+        // A single implementation behaves like go to definition
+        Ok(match locations.len() {
+            0 => None,
+            1 => locations.pop().map(request::GotoImplementationResponse::Scalar),
+            _ => Some(request::GotoImplementationResponse::Array(locations)),
+        })
+    }
 
-        // Add stdlib functions - optimize by caching formatted signatures
-        // Only format signatures if we're actually going to use them
-        // This avoids expensive formatting for functions that won't be shown
-        let stdlib_funcs = get_stdlib_functions();
-        let max_stdlib_items = 100; // Limit stdlib completions to prevent UI lag
-        
-        for stdlib_func in stdlib_funcs.iter().take(max_stdlib_items) {
-            // Avoid duplicates
-            if !function_names.contains(&stdlib_func.name) {
-                // Only format signature if we have space (performance optimization)
-                let signature = if items.len() < 200 {
-                    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-                        let params_str: Vec<String> = stdlib_func
-                            .params
-                            .iter()
-                            .map(|(name, ty)| format!("{}: {}", name, format_type(ty)))
-                            .collect();
-                        format!(
-                            "{}({}) -> {}",
-                            stdlib_func.name,
-                            params_str.join(", "),
-                            format_type(&stdlib_func.return_type)
-                        )
-                    })).unwrap_or_else(|_| format!("{}()", stdlib_func.name))
-                } else {
-                    // For large lists, use simple format to save time
-                    format!("{}()", stdlib_func.name)
-                };
+    async fn inlay_hint(&self, params: InlayHintParams) -> Result<Option<Vec<InlayHint>>, tower_lsp::jsonrpc::Error> {
+        let uri = params.text_document.uri;
 
-                items.push(CompletionItem {
-                    label: stdlib_func.name.clone(),
-                    kind: Some(CompletionItemKind::FUNCTION),
-                    detail: Some(signature),
-                    documentation: Some(Documentation::String(stdlib_func.description.clone())),
-                    ..Default::default()
-                });
-            }
-        }
+        let text = {
+            let docs = self.documents.read().await;
+            docs.get(&uri).cloned()
+        }; // Lock released here
 
-        // Add keywords (only if not in member access context)
-        if !is_member_access {
-            items.extend(self.get_keyword_completions());
-        }
+        let Some(text) = text else { return Ok(None) };
+        let Some(program) = self.get_or_parse_program(&uri, &text).await else { return Ok(None) };
+        let encoding = self.position_encoding();
+        let range = Range {
+            start: to_byte_position(&text, params.range.start, &encoding),
+            end: to_byte_position(&text, params.range.end, &encoding),
+        };
 
-        items
+        let hints = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            parameter_inlay_hints(&program, &text, range)
+        })).unwrap_or_else(|e| {
+            log_panic("inlay_hint", Some(&uri), Some(range.start), &*e);
+            Vec::new()
+        });
+
+        Ok(Some(
+            hints
+                .into_iter()
+                .map(|hint| InlayHint { position: from_byte_position(&text, hint.position, &encoding), ..hint })
+                .collect(),
+        ))
     }
 
-    /// Get basic keyword completions
-    pub fn get_keyword_completions(&self) -> Vec<CompletionItem> {
-        vec![
-            CompletionItem {
-                label: "fn".to_string(),
-                kind: Some(CompletionItemKind::KEYWORD),
-                detail: Some("Function definition".to_string()),
-                ..Default::default()
-            },
-            CompletionItem {
-                label: "let".to_string(),
-                kind: Some(CompletionItemKind::KEYWORD),
-                detail: Some("Immutable variable".to_string()),
-                ..Default::default()
-            },
-            CompletionItem {
-                label: "var".to_string(),
-                kind: Some(CompletionItemKind::KEYWORD),
-                detail: Some("Mutable variable".to_string()),
-                ..Default::default()
-            },
-            CompletionItem {
-                label: "if".to_string(),
-                kind: Some(CompletionItemKind::KEYWORD),
-                detail: Some("Conditional statement".to_string()),
-                ..Default::default()
-            },
-            CompletionItem {
-                label: "else".to_string(),
-                kind: Some(CompletionItemKind::KEYWORD),
-                detail: Some("Else branch".to_string()),
-                ..Default::default()
-            },
-            CompletionItem {
-                label: "for".to_string(),
-                kind: Some(CompletionItemKind::KEYWORD),
-                detail: Some("For loop".to_string()),
-                ..Default::default()
-            },
-            CompletionItem {
-                label: "while".to_string(),
-                kind: Some(CompletionItemKind::KEYWORD),
-                detail: Some("While loop".to_string()),
-                ..Default::default()
-            },
-            CompletionItem {
-                label: "break".to_string(),
-                kind: Some(CompletionItemKind::KEYWORD),
-                detail: Some("Break out of loop".to_string()),
-                ..Default::default()
-            },
-            CompletionItem {
-                label: "continue".to_string(),
-                kind: Some(CompletionItemKind::KEYWORD),
-                detail: Some("Continue to next loop iteration".to_string()),
-                ..Default::default()
-            },
-            CompletionItem {
-                label: "return".to_string(),
-                kind: Some(CompletionItemKind::KEYWORD),
-                detail: Some("Return from function".to_string()),
-                ..Default::default()
-            },
-        ]
-    }
+    async fn document_symbol(
+        &self,
+        params: DocumentSymbolParams,
+    ) -> Result<Option<DocumentSymbolResponse>, tower_lsp::jsonrpc::Error> {
+        let uri = params.text_document.uri;
 
-    /// Get basic completions (fallback)
-    pub fn get_basic_completions(&self) -> Vec<CompletionItem> {
-        let mut items = self.get_keyword_completions();
+        let text = {
+            let docs = self.documents.read().await;
+            docs.get(&uri).cloned()
+        }; // Lock released here
 
-        // Add basic stdlib functions
-        items.push(CompletionItem {
-            label: "print".to_string(),
-            kind: Some(CompletionItemKind::FUNCTION),
-            detail: Some("print(value: dynamic) -> void".to_string()),
-            ..Default::default()
+        let Some(text) = text else { return Ok(None) };
+        let Some(program) = self.get_or_parse_program(&uri, &text).await else { return Ok(None) };
+        let encoding = self.position_encoding();
+
+        let symbols = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            document_symbols(&program, &text)
+        })).unwrap_or_else(|e| {
+            log_panic("document_symbol", Some(&uri), None, &*e);
+            Vec::new()
         });
+        Ok(Some(DocumentSymbolResponse::Nested(
+            symbols.into_iter().map(|symbol| symbol_to_encoding(&text, symbol, &encoding)).collect(),
+        )))
+    }
 
-        items
+    async fn semantic_tokens_full(
+        &self,
+        params: SemanticTokensParams,
+    ) -> Result<Option<SemanticTokensResult>, tower_lsp::jsonrpc::Error> {
+        let tokens = self.semantic_tokens_for(&params.text_document.uri).await;
+        Ok(tokens.map(SemanticTokensResult::Tokens))
     }
 
-    async fn on_change(&self, uri: url::Url, text: String) {
-        eprintln!("LSP: on_change START uri={}, text_len={}", uri, text.len());
-        
-        // Wrap check_document in catch_unwind to prevent panics from crashing LSP
-        // Note: We compute diagnostics synchronously here, but the lock is already released
-        // so this won't block other operations. For very large files, this could still be slow,
-        // but it's better than blocking the document cache.
-        eprintln!("LSP: on_change calling check_document");
-        let diagnostics = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-            self.check_document(&text)
-        })).unwrap_or_else(|e| {
-            // If check_document panics, return empty diagnostics
-            // Log the panic for debugging
-            eprintln!("LSP: check_document PANICKED!");
-            eprintln!("LSP: panic info: {:?}", e);
-            eprintln!("LSP: text length: {}, uri: {}", text.len(), uri);
-            vec![]
-        });
-        eprintln!("LSP: on_change check_document returned {} diagnostics", diagnostics.len());
-        
-        // Publish diagnostics - wrap in catch_unwind to prevent panics
-        eprintln!("LSP: on_change preparing to publish diagnostics");
-        let publish_result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-            (self.client.clone(), uri.clone(), diagnostics)
-        }));
-        
-        if let Ok((client, uri_clone, diags)) = publish_result {
-            eprintln!("LSP: on_change spawning publish task");
-            // Use spawn to avoid blocking
-            tokio::spawn(async move {
-                eprintln!("LSP: publish task START");
-                client.publish_diagnostics(uri_clone, diags, None).await;
-                eprintln!("LSP: publish task END");
-            });
-        } else {
-            eprintln!("LSP: on_change panicked before publishing diagnostics");
-        }
-        eprintln!("LSP: on_change END");
+    async fn semantic_tokens_full_delta(
+        &self,
+        params: SemanticTokensDeltaParams,
+    ) -> Result<Option<SemanticTokensFullDeltaResult>, tower_lsp::jsonrpc::Error> {
+        let uri = params.text_document.uri;
+        let previous = self.semantic_tokens.read().await.get(&uri).cloned();
+        let Some(tokens) = self.semantic_tokens_for(&uri).await else { return Ok(None) };
+
+        // A result id we no longer have (or never sent) gets the full tokens
+        Ok(Some(match previous {
+            Some(previous) if previous.result_id.as_deref() == Some(params.previous_result_id.as_str()) => {
+                SemanticTokensFullDeltaResult::TokensDelta(SemanticTokensDelta {
+                    edits: semantic_token_edits(&previous.data, &tokens.data),
+                    result_id: tokens.result_id,
+                })
+            }
+            _ => SemanticTokensFullDeltaResult::Tokens(tokens),
+        }))
     }
 
-    pub fn check_document(&self, text: &str) -> Vec<Diagnostic> {
-        eprintln!("LSP: check_document START text_len={}", text.len());
-        // Wrap entire function in catch_unwind to prevent any panics
-        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-            eprintln!("LSP: check_document calling check_document_internal");
-            let diags = self.check_document_internal(text);
-            eprintln!("LSP: check_document_internal returned {} diagnostics", diags.len());
-            diags
-        })).unwrap_or_else(|_| {
-            eprintln!("LSP: check_document_internal panicked");
-            // If anything panics, return empty diagnostics
-            vec![]
+    async fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>, tower_lsp::jsonrpc::Error> {
+        let uri = params.text_document_position.text_document.uri.clone();
+        let position = params.text_document_position.position;
+        if !is_identifier(&params.new_name) {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params(format!(
+                "`{}` is not a valid identifier",
+                params.new_name
+            )));
+        }
+
+        let text = {
+            let docs = self.documents.read().await;
+            docs.get(&uri).cloned()
+        }; // Lock released here
+
+        let Some(text) = text else { return Ok(None) };
+        let Some(program) = self.get_or_parse_program(&uri, &text).await else { return Ok(None) };
+        let encoding = self.position_encoding();
+        let position = to_byte_position(&text, position, &encoding);
+        let update_doc_comments = self.settings().rename_updates_doc_comments;
+
+        let edits = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            rename_edits(&program, &text, position, &params.new_name, update_doc_comments)
+        })).unwrap_or_else(|e| {
+            log_panic("rename", Some(&uri), Some(position), &*e);
+            None
         });
-        eprintln!("LSP: check_document END");
-        result
+
+        Ok(edits.map(|edits| {
+            let edits = edits
+                .into_iter()
+                .map(|edit| TextEdit {
+                    range: from_byte_range(&text, edit.range, &encoding),
+                    ..edit
+                })
+                .collect();
+            WorkspaceEdit {
+                changes: Some(HashMap::from([(uri, edits)])),
+                ..Default::default()
+            }
+        }))
     }
 
-    fn check_document_internal(&self, text: &str) -> Vec<Diagnostic> {
-        eprintln!("LSP: check_document_internal START text_len={}", text.len());
-        
-        // Handle empty files gracefully
-        if text.trim().is_empty() {
-            eprintln!("LSP: check_document_internal file is empty, returning no diagnostics");
-            return Vec::new();
-        }
-        
-        let mut diagnostics = Vec::new();
+    async fn code_action(
+        &self,
+        params: CodeActionParams,
+    ) -> Result<Option<CodeActionResponse>, tower_lsp::jsonrpc::Error> {
+        let uri = params.text_document.uri.clone();
+        let text = {
+            let docs = self.documents.read().await;
+            docs.get(&uri).cloned()
+        }; // Lock released here
 
-        // Parse with error recovery for better IDE experience
-        eprintln!("LSP: check_document_internal calling parse_with_recovery");
-        let (parse_result, parse_errors) = parse_with_recovery(text);
-        eprintln!("LSP: check_document_internal parse_with_recovery returned {} errors", parse_errors.len());
+        let Some(text) = text else { return Ok(None) };
+        let Some(program) = self.get_or_parse_program(&uri, &text).await else { return Ok(None) };
+        let encoding = self.position_encoding();
 
-        // Add parse errors as diagnostics
-        for parse_err in &parse_errors {
-            diagnostics.push(self.parse_error_to_diagnostic(parse_err));
-        }
+        let assignments = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            find_immutable_assignments(&program, &text)
+        })).unwrap_or_else(|e| {
+            log_panic("code_action", Some(&uri), Some(params.range.start), &*e);
+            Vec::new()
+        });
 
-        // If parsing succeeded (even partially), try type checking
-        if let Ok(program) = parse_result {
-            // Build type context for better error messages
-            let mut ctx = TypeContext::new();
-            for item in &program.items {
-                match item {
-                    Item::Function(func) => {
-                        ctx.add_function(func.name.clone(), func.clone());
-                    }
-                    Item::Class(class) => {
-                        ctx.add_class(class.name.clone(), class.clone());
-                    }
-                }
+        // One fix per declaration, however many assignments it has
+        let mut fixed = HashSet::new();
+        let mut actions = Vec::new();
+        for assignment in assignments {
+            let assignment = ImmutableAssignment {
+                range: from_byte_range(&text, assignment.range, &encoding),
+                declaration: from_byte_range(&text, assignment.declaration, &encoding),
+                ..assignment
+            };
+            let requested = ranges_overlap(&assignment.range, &params.range)
+                || ranges_overlap(&assignment.declaration, &params.range);
+            if !requested || !fixed.insert((assignment.declaration.start.line, assignment.declaration.start.character)) {
+                continue;
             }
 
-            // Type check - wrap in catch_unwind to prevent panics
-            let type_check_result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-                type_check_program_with_context(&program, &mut ctx)
-            }));
+            let mut action = make_mutable_action(&uri, &assignment);
+            let diagnostics: Vec<Diagnostic> = params
+                .context
+                .diagnostics
+                .iter()
+                .filter(|d| d.code == Some(NumberOrString::String(IMMUTABLE_ASSIGNMENT_CODE.to_string())))
+                .filter(|d| d.range == assignment.range)
+                .cloned()
+                .collect();
+            if !diagnostics.is_empty() {
+                action.diagnostics = Some(diagnostics);
+            }
+            actions.push(CodeActionOrCommand::CodeAction(action));
+        }
 
-            match type_check_result {
-                Ok(Ok(_)) => {
-                    // Collect warnings - wrap in catch_unwind
-                    let warnings_result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-                        WarningCollector::collect_warnings(&program, &ctx)
-                    }));
-                    
-                    if let Ok(warnings) = warnings_result {
-                        for warning in warnings {
-                            diagnostics.push(self.warning_to_diagnostic(&warning, text));
-                        }
-                    }
-                }
-                Ok(Err(err)) => {
-                    // Type error - format safely
-                    let error_msg = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-                        let formatter = ErrorFormatter::new(text).with_context(&ctx);
-                        formatter.format_error(&err)
-                    })).unwrap_or_else(|_| format!("Type error: {:?}", err));
-                    
-                    diagnostics.push(self.type_error_to_diagnostic(&err, &error_msg));
+        // Return type annotations, offered along with their hint
+        if self.settings().warning_severity.missing_return_type.to_lsp().is_some() {
+            let missing_types = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                find_missing_return_types(&program, &text)
+            })).unwrap_or_else(|e| {
+                log_panic("code_action", Some(&uri), Some(params.range.start), &*e);
+                Vec::new()
+            });
+            for missing in missing_types {
+                let missing = MissingReturnType {
+                    range: from_byte_range(&text, missing.range, &encoding),
+                    insert_at: from_byte_position(&text, missing.insert_at, &encoding),
+                    ..missing
+                };
+                if !ranges_overlap(&missing.range, &params.range) {
+                    continue;
                 }
-                Err(_) => {
-                    // Type checking panicked - skip type checking diagnostics
+                let mut action = add_return_type_action(&uri, &missing);
+                let diagnostics: Vec<Diagnostic> = params
+                    .context
+                    .diagnostics
+                    .iter()
+                    .filter(|d| d.code == Some(NumberOrString::String(MISSING_RETURN_TYPE_CODE.to_string())))
+                    .filter(|d| d.range == missing.range)
+                    .cloned()
+                    .collect();
+                if !diagnostics.is_empty() {
+                    action.diagnostics = Some(diagnostics);
                 }
+                actions.push(CodeActionOrCommand::CodeAction(action));
             }
         }
 
-        diagnostics
+        Ok(Some(actions))
     }
 
-    pub fn parse_error_to_diagnostic(&self, err: &pain_compiler::error::ParseError) -> Diagnostic {
-        Diagnostic {
-            range: Range {
-                start: Position {
-                    line: (err.span.line().saturating_sub(1)) as u32,
-                    character: (err.span.column().saturating_sub(1)) as u32,
+    async fn diagnostic(
+        &self,
+        params: DocumentDiagnosticParams,
+    ) -> Result<DocumentDiagnosticReportResult, tower_lsp::jsonrpc::Error> {
+        let uri = params.text_document.uri;
+        let text = {
+            let docs = self.documents.read().await;
+            docs.get(&uri).cloned()
+        }; // Lock released here
+        let text = text.ok_or_else(|| {
+            tower_lsp::jsonrpc::Error::invalid_params(format!("document {} is not open", uri))
+        })?;
+
+        // Same text and settings give the same diagnostics
+        let result_id = diagnostic_result_id(&text, &self.settings());
+        if params.previous_result_id.as_deref() == Some(result_id.as_str()) {
+            return Ok(DocumentDiagnosticReportResult::Report(DocumentDiagnosticReport::Unchanged(
+                RelatedUnchangedDocumentDiagnosticReport {
+                    related_documents: None,
+                    unchanged_document_diagnostic_report: UnchangedDocumentDiagnosticReport { result_id },
                 },
-                end: Position {
-                    line: (err.span.line().saturating_sub(1)) as u32,
-                    character: (err.span.column().saturating_sub(1) + 1) as u32,
+            )));
+        }
+
+        let items = self.compute_diagnostics(&uri, &text).await;
+        Ok(DocumentDiagnosticReportResult::Report(DocumentDiagnosticReport::Full(
+            RelatedFullDocumentDiagnosticReport {
+                related_documents: None,
+                full_document_diagnostic_report: FullDocumentDiagnosticReport {
+                    result_id: Some(result_id),
+                    items,
                 },
             },
-            severity: Some(DiagnosticSeverity::ERROR),
-            code: None,
-            code_description: None,
-            source: Some("pain".to_string()),
-            message: err.message.clone(),
-            related_information: None,
-            tags: None,
-            data: None,
+        )))
+    }
+
+    async fn workspace_diagnostic(
+        &self,
+        params: WorkspaceDiagnosticParams,
+    ) -> Result<WorkspaceDiagnosticReportResult, tower_lsp::jsonrpc::Error> {
+        let previous: HashMap<url::Url, String> = params
+            .previous_result_ids
+            .into_iter()
+            .map(|previous| (previous.uri, previous.value))
+            .collect();
+        let mut uris: Vec<url::Url> = self.workspace_index.read().await.keys().cloned().collect();
+        uris.sort();
+
+        let settings = self.settings();
+        let encoding = self.position_encoding();
+        let partial_token = params.partial_result_params.partial_result_token;
+        let mut items = Vec::new();
+
+        for uri in uris {
+            let is_open = self.documents.read().await.contains_key(&uri);
+            let Some(text) = self.document_or_file_text(&uri).await else { continue };
+            let version = if is_open {
+                self.document_versions.read().await.get(&uri).map(|version| *version as i64)
+            } else {
+                None
+            };
+
+            let result_id = diagnostic_result_id(&text, &settings);
+            let report = if previous.get(&uri) == Some(&result_id) {
+                WorkspaceDocumentDiagnosticReport::Unchanged(WorkspaceUnchangedDocumentDiagnosticReport {
+                    uri,
+                    version,
+                    unchanged_document_diagnostic_report: UnchangedDocumentDiagnosticReport { result_id },
+                })
+            } else {
+                // Closed files are checked without going through the parse cache
+                let diagnostics = if is_open {
+                    self.compute_diagnostics(&uri, &text).await
+                } else {
+                    diagnostics_to_encoding(&text, self.check_document_with_uri(&uri, &text), &encoding)
+                };
+                WorkspaceDocumentDiagnosticReport::Full(WorkspaceFullDocumentDiagnosticReport {
+                    uri,
+                    version,
+                    full_document_diagnostic_report: FullDocumentDiagnosticReport {
+                        result_id: Some(result_id),
+                        items: diagnostics,
+                    },
+                })
+            };
+
+            // With a partial result token, stream each file as soon as it is checked
+            match &partial_token {
+                Some(token) => {
+                    self.client
+                        .send_notification::<WorkspaceDiagnosticProgress>(WorkspaceDiagnosticProgressParams {
+                            token: token.clone(),
+                            value: WorkspaceDiagnosticReportPartialResult { items: vec![report] },
+                        })
+                        .await;
+                }
+                None => items.push(report),
+            }
         }
+
+        // Streamed results leave the final response empty
+        Ok(WorkspaceDiagnosticReportResult::Report(WorkspaceDiagnosticReport { items }))
     }
 
-    pub fn type_error_to_diagnostic(
+    async fn document_link(
         &self,
-        err: &pain_compiler::TypeError,
-        formatted_msg: &str,
-    ) -> Diagnostic {
-        let span = match err {
-            pain_compiler::TypeError::UndefinedVariable { span, .. } => *span,
-            pain_compiler::TypeError::TypeMismatch { span, .. } => *span,
-            pain_compiler::TypeError::CannotInferType { span, .. } => *span,
-            pain_compiler::TypeError::InvalidOperation { span, .. } => *span,
+        params: DocumentLinkParams,
+    ) -> Result<Option<Vec<DocumentLink>>, tower_lsp::jsonrpc::Error> {
+        let uri = params.text_document.uri;
+        let text = {
+            let docs = self.documents.read().await;
+            docs.get(&uri).cloned()
+        }; // Lock released here
+
+        let Some(text) = text else { return Ok(None) };
+        let Some(base_dir) = self.path_base_dir(&uri).await else { return Ok(None) };
+
+        let encoding = self.position_encoding();
+        let links = find_document_links(&text, &base_dir)
+            .into_iter()
+            .map(|mut link| {
+                link.range = from_byte_range(&text, link.range, &encoding);
+                link
+            })
+            .collect();
+        Ok(Some(links))
+    }
+
+    async fn prepare_type_hierarchy(
+        &self,
+        params: TypeHierarchyPrepareParams,
+    ) -> Result<Option<Vec<TypeHierarchyItem>>, tower_lsp::jsonrpc::Error> {
+        let uri = params.text_document_position_params.text_document.uri.clone();
+        let position = params.text_document_position_params.position;
+        let text = {
+            let docs = self.documents.read().await;
+            docs.get(&uri).cloned()
+        }; // Lock released here
+
+        let Some(text) = text else { return Ok(None) };
+        let encoding = self.position_encoding();
+        let position = to_byte_position(&text, position, &encoding);
+        let tokens = tokenize(&text);
+        let Some(token) = token_at(&tokens, position.line as usize, position.character as usize) else {
+            return Ok(None);
         };
+        if token.kind != TokenKind::Ident {
+            return Ok(None);
+        }
 
-        Diagnostic {
-            range: Range {
-                start: Position {
-                    line: (span.line().saturating_sub(1)) as u32,
-                    character: (span.column().saturating_sub(1)) as u32,
-                },
-                end: Position {
-                    line: (span.line().saturating_sub(1)) as u32,
-                    character: (span.column().saturating_sub(1) + 1) as u32,
-                },
-            },
-            severity: Some(DiagnosticSeverity::ERROR),
-            code: None,
-            code_description: None,
-            source: Some("pain".to_string()),
-            message: formatted_msg
-                .lines()
-                .next()
-                .unwrap_or(formatted_msg)
-                .to_string(),
-            related_information: None,
-            tags: None,
-            data: None,
+        // Classes declared in this document win over same-named ones elsewhere
+        if let Some(outline) = class_outlines(&text).into_iter().find(|outline| outline.name == token.text) {
+            return Ok(Some(vec![type_hierarchy_item(&uri, &text, &outline, &encoding)]));
+        }
+        let item = self
+            .workspace_class_outlines()
+            .await
+            .into_iter()
+            .find(|(_, _, outline)| outline.name == token.text)
+            .map(|(uri, text, outline)| type_hierarchy_item(&uri, &text, &outline, &encoding));
+        Ok(item.map(|item| vec![item]))
+    }
+
+    // Without inheritance, a class's "supertypes" are the classes its fields
+    // and methods use, and its "subtypes" the classes that use it
+    async fn supertypes(
+        &self,
+        params: TypeHierarchySupertypesParams,
+    ) -> Result<Option<Vec<TypeHierarchyItem>>, tower_lsp::jsonrpc::Error> {
+        let item = params.item;
+        let Some(text) = self.document_or_file_text(&item.uri).await else { return Ok(None) };
+        let Some(outline) = class_outlines(&text).into_iter().find(|outline| outline.name == item.name) else {
+            return Ok(None);
+        };
+
+        let encoding = self.position_encoding();
+        let items = self
+            .workspace_class_outlines()
+            .await
+            .into_iter()
+            .filter(|(_, _, class)| outline.references.contains(&class.name))
+            .map(|(uri, text, class)| type_hierarchy_item(&uri, &text, &class, &encoding))
+            .collect();
+        Ok(Some(items))
+    }
+
+    async fn subtypes(
+        &self,
+        params: TypeHierarchySubtypesParams,
+    ) -> Result<Option<Vec<TypeHierarchyItem>>, tower_lsp::jsonrpc::Error> {
+        let item = params.item;
+        let encoding = self.position_encoding();
+        let items = self
+            .workspace_class_outlines()
+            .await
+            .into_iter()
+            .filter(|(_, _, class)| class.references.contains(&item.name))
+            .map(|(uri, text, class)| type_hierarchy_item(&uri, &text, &class, &encoding))
+            .collect();
+        Ok(Some(items))
+    }
+
+    async fn formatting(&self, params: DocumentFormattingParams) -> Result<Option<Vec<TextEdit>>, tower_lsp::jsonrpc::Error> {
+        let uri = params.text_document.uri;
+        let text = {
+            let docs = self.documents.read().await;
+            docs.get(&uri).cloned()
+        }; // Lock released here
+
+        let Some(text) = text else { return Ok(None) };
+        let encoding = self.position_encoding();
+        let edits = formatting_edits(&text)
+            .into_iter()
+            .map(|edit| TextEdit { range: from_byte_range(&text, edit.range, &encoding), ..edit })
+            .collect();
+        Ok(Some(edits))
+    }
+
+    async fn on_type_formatting(
+        &self,
+        params: DocumentOnTypeFormattingParams,
+    ) -> Result<Option<Vec<TextEdit>>, tower_lsp::jsonrpc::Error> {
+        if !self.settings().format_on_type || params.ch != "\n" {
+            return Ok(None);
+        }
+        let uri = params.text_document_position.text_document.uri;
+        let text = {
+            let docs = self.documents.read().await;
+            docs.get(&uri).cloned()
+        }; // Lock released here
+
+        let Some(text) = text else { return Ok(None) };
+        let Some(finished) = params.text_document_position.position.line.checked_sub(1) else { return Ok(None) };
+        let Some(line) = text.lines().nth(finished as usize) else { return Ok(None) };
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let trimmed = line.trim_end().len();
+        if trimmed == line.len() {
+            return Ok(Some(Vec::new()));
+        }
+
+        // Drop the trailing whitespace of the line just finished
+        let range = Range {
+            start: Position { line: finished, character: clamp_to_u32(trimmed) },
+            end: Position { line: finished, character: clamp_to_u32(line.len()) },
+        };
+        let range = from_byte_range(&text, range, &self.position_encoding());
+        Ok(Some(vec![TextEdit { range, new_text: String::new() }]))
+    }
+
+    async fn moniker(&self, params: MonikerParams) -> Result<Option<Vec<Moniker>>, tower_lsp::jsonrpc::Error> {
+        let uri = params.text_document_position_params.text_document.uri.clone();
+        let position = params.text_document_position_params.position;
+
+        let text = {
+            let docs = self.documents.read().await;
+            docs.get(&uri).cloned()
+        }; // Lock released here
+
+        let Some(text) = text else { return Ok(None) };
+        let Some(program) = self.get_or_parse_program(&uri, &text).await else { return Ok(None) };
+        let position = to_byte_position(&text, position, &self.position_encoding());
+
+        let symbol = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            DocumentIndex::new(&program, &text).resolve_symbol(position)
+        })).unwrap_or_else(|e| {
+            log_panic("moniker", Some(&uri), Some(position), &*e);
+            None
+        });
+        let Some(symbol) = symbol else { return Ok(None) };
+
+        let module = module_name(&uri, &self.workspace_roots.read().await);
+        Ok(Some(vec![symbol_moniker(&module, &symbol)]))
+    }
+
+    async fn symbol(
+        &self,
+        params: WorkspaceSymbolParams,
+    ) -> Result<Option<Vec<SymbolInformation>>, tower_lsp::jsonrpc::Error> {
+        let query = params.query.to_lowercase();
+        let mut matches: Vec<(url::Url, Vec<IndexedSymbol>)> = {
+            let index = self.workspace_index.read().await;
+            index
+                .iter()
+                .map(|(uri, symbols)| {
+                    let matching = symbols
+                        .iter()
+                        .filter(|symbol| symbol.name.to_lowercase().contains(&query))
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    (uri.clone(), matching)
+                })
+                .filter(|(_, matching)| !matching.is_empty())
+                .collect()
+        }; // Lock released here
+        matches.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        // The index keeps byte columns - convert them with each file's text
+        let encoding = self.position_encoding();
+        let mut symbols = Vec::new();
+        for (uri, matching) in matches {
+            let text = if encoding == PositionEncodingKind::UTF8 {
+                None
+            } else {
+                self.document_or_file_text(&uri).await
+            };
+            for symbol in matching {
+                let range = match &text {
+                    Some(text) => from_byte_range(text, symbol.range, &encoding),
+                    None => symbol.range,
+                };
+                #[allow(deprecated)] // SymbolInformation::deprecated must still be initialized
+                symbols.push(SymbolInformation {
+                    name: symbol.name,
+                    kind: symbol.kind,
+                    tags: None,
+                    deprecated: None,
+                    location: Location { uri: uri.clone(), range },
+                    container_name: symbol.container,
+                });
+            }
+        }
+
+        Ok(Some(symbols))
+    }
+
+    async fn execute_command(
+        &self,
+        params: ExecuteCommandParams,
+    ) -> Result<Option<serde_json::Value>, tower_lsp::jsonrpc::Error> {
+        info!("execute_command {}", params.command);
+        match params.command.as_str() {
+            DUMP_AST_COMMAND => {
+                let uri = params
+                    .arguments
+                    .first()
+                    .and_then(|arg| arg.as_str())
+                    .and_then(|arg| url::Url::parse(arg).ok())
+                    .ok_or_else(|| tower_lsp::jsonrpc::Error::invalid_params(format!("{} expects a document URI argument", DUMP_AST_COMMAND)))?;
+
+                let text = {
+                    let docs = self.documents.read().await;
+                    docs.get(&uri).cloned()
+                }; // Lock released here
+                let text = text.ok_or_else(|| tower_lsp::jsonrpc::Error::invalid_params(format!("document {} is not open", uri)))?;
+
+                let (parse_result, parse_errors) = parse_with_recovery(&text);
+                let dump = match parse_result {
+                    Ok(program) => format!("{:#?}", program),
+                    Err(_) => {
+                        let messages: Vec<String> = parse_errors
+                            .iter()
+                            .map(|err| format!("{}:{}: {}", err.span.line(), err.span.column(), err.message))
+                            .collect();
+                        return Err(tower_lsp::jsonrpc::Error::invalid_params(format!("document does not parse:\n{}", messages.join("\n"))));
+                    }
+                };
+                Ok(Some(serde_json::Value::String(dump)))
+            }
+            STATUS_COMMAND => Ok(Some(self.status().await)),
+            LINT_COMMAND => self.lint(params.arguments.first()).await.map(Some),
+            command => Err(tower_lsp::jsonrpc::Error::invalid_params(format!("unknown command `{}`", command))),
+        }
+    }
+
+    async fn shutdown(&self) -> Result<(), tower_lsp::jsonrpc::Error> {
+        info!("shutdown START");
+        // Stop background work first so nothing repopulates the maps cleared below
+        let aborted = self.abort_background_tasks().await;
+
+        // Clear documents and cache on shutdown to free memory
+        {
+            let mut docs = self.documents.write().await;
+            docs.clear();
+        }
+        {
+            let mut cache = self.parsed_cache.write().await;
+            cache.clear();
+        }
+        self.parsed_by_content.write().await.clear();
+        if let Ok(mut contexts) = self.type_contexts.write() {
+            contexts.clear();
+        }
+        self.semantic_tokens.write().await.clear();
+        // The debug log is written unbuffered, so this record is on disk once logged
+        info!(aborted_tasks = aborted, "server shutting down cleanly");
+        info!("shutdown END");
+        Ok(())
+    }
+}
+
+impl Backend {
+    /// Get context-aware completions
+    pub fn get_completions(
+        &self,
+        program: &Program,
+        text: &str,
+        position: Position,
+    ) -> Vec<CompletionItem> {
+        self.get_completion_list(program, text, position).items
+    }
+
+    /// Like `get_completions`, but marked incomplete when the list was cut
+    /// short, so the client asks again as the user keeps typing
+    pub fn get_completion_list(
+        &self,
+        program: &Program,
+        text: &str,
+        position: Position,
+    ) -> CompletionList {
+        self.guarded_completion_list(None, program, text, position, Vec::new(), None)
+    }
+
+    // Completions of the document at `uri`, falling back to the basic ones
+    // when computing them panics
+    fn guarded_completion_list(
+        &self,
+        uri: Option<&url::Url>,
+        program: &Program,
+        text: &str,
+        position: Position,
+        workspace: Vec<CompletionItem>,
+        emit: Option<&mut dyn FnMut(Vec<CompletionItem>)>,
+    ) -> CompletionList {
+        // Wrap in catch_unwind to prevent panics
+        catch_panics(|| {
+            self.get_completions_internal(program, text, position, workspace, emit)
+        }).unwrap_or_else(|e| {
+            // If anything panics, return basic completions
+            log_panic("get_completions", uri, Some(position), &*e);
+            complete_list(self.get_basic_completions())
+        })
+    }
+
+    /// Like `get_completion_list`, but recently used variables of the document
+    /// rank first, and the `workspace` items of other files are offered too.
+    /// With an `emit` sink, each group of items (the document's declarations,
+    /// variables, then the rest) is handed to it as soon as it is ready, and
+    /// the returned list only tells whether the result is complete.
+    pub fn get_completions_for_document(
+        &self,
+        uri: &url::Url,
+        program: &Program,
+        text: &str,
+        position: Position,
+        workspace: Vec<CompletionItem>,
+        emit: Option<&mut dyn FnMut(Vec<CompletionItem>)>,
+    ) -> CompletionList {
+        let recent = self
+            .recent_identifiers
+            .read()
+            .ok()
+            .and_then(|recent| recent.get(uri).cloned())
+            .unwrap_or_default();
+        let mut list = match emit {
+            Some(emit) => {
+                let mut ranked = |mut group: Vec<CompletionItem>| {
+                    rank_recent_variables(&mut group, &recent);
+                    emit(group);
+                };
+                self.guarded_completion_list(Some(uri), program, text, position, workspace, Some(&mut ranked))
+            }
+            None => self.guarded_completion_list(Some(uri), program, text, position, workspace, None),
+        };
+        rank_recent_variables(&mut list.items, &recent);
+        list
+    }
+
+    fn get_completions_internal(
+        &self,
+        program: &Program,
+        text: &str,
+        position: Position,
+        workspace: Vec<CompletionItem>,
+        mut emit: Option<&mut dyn FnMut(Vec<CompletionItem>)>,
+    ) -> CompletionList {
+        let mut items = Vec::new();
+        let line = position.line as usize;
+        let column = position.character as usize;
+
+        // Get text before cursor on current line - safe indexing. Unlike
+        // `lines()`, splitting keeps the empty line after a final newline.
+        let Some(current_line) = text.split('\n').nth(line).map(|line| line.strip_suffix('\r').unwrap_or(line)) else {
+            return complete_list(self.get_basic_completions());
+        };
+
+        // Positions are byte columns here, so the UTF-8 conversion finds the cursor
+        let line_start = lsp_position_to_offset(text, Position { line: position.line, character: 0 }, PositionEncodingKind::UTF8)
+            .unwrap_or(0);
+        let text_before_cursor = lsp_position_to_offset(text, position, PositionEncodingKind::UTF8)
+            .and_then(|offset| text.get(line_start..offset))
+            .unwrap_or(current_line);
+
+        // Declaration skeletons replace the whole list after a top-level `fn `/`class `
+        if let Some(item) = declaration_snippet(text, position) {
+            return complete_list(vec![item]);
+        }
+        if let Some(mut items) = type_annotation_completions(text, position).or_else(|| attribute_completions(text, position)) {
+            self.attach_insert_replace_edits(text, position, &mut items);
+            return complete_list(items);
+        }
+        if let Some(items) = map_key_completions(text, position) {
+            return complete_list(items);
+        }
+        if let Some(items) = member_completions(program, text, position) {
+            return complete_list(items);
+        }
+
+        // Check if we're after a dot (member access)
+        let is_member_access = text_before_cursor.trim_end().ends_with('.');
+
+        let markdown = self.supports_markdown_completion_docs();
+
+        // Only what matches the word typed so far is offered - the client
+        // re-queries on each keystroke because the list is then incomplete
+        let (typed, _) = identifier_ranges(text, position);
+        let prefix = current_line
+            .get(typed.start.character as usize..typed.end.character as usize)
+            .unwrap_or("")
+            .to_lowercase();
+        let matches_prefix = |label: &str| label.to_lowercase().starts_with(&prefix);
+
+        // Inside `name[`, rank variables of the index type first - lists and
+        // arrays take ints, maps take their key type. Nothing is filtered out.
+        // Likewise, conditions rank `bool` variables and `true`/`false` first,
+        // and annotated initializers or `return` their declared type.
+        let expected_type = catch_panics(|| {
+            bracket_index_type(program, text, position).or_else(|| expected_type_at(program, text, position))
+        }).unwrap_or(None);
+        let wanted = expected_type.as_ref().map(format_type);
+
+        // Right after `return `, a literal of the return type is a likely start
+        let returning = text_before_cursor.trim_start().strip_prefix("return").is_some_and(|rest| rest.trim().is_empty());
+        let return_literal = wanted.as_deref().filter(|_| returning && !is_member_access).and_then(|wanted| {
+            let literal = match wanted {
+                "int" => "0",
+                "float32" | "float64" => "0.0",
+                "str" => "\"\"",
+                "bool" => "true",
+                _ => return None,
+            };
+            Some(CompletionItem {
+                label: literal.to_string(),
+                kind: Some(CompletionItemKind::VALUE),
+                detail: Some(wanted.to_string()),
+                sort_text: Some(format!("0{}", literal)),
+                ..Default::default()
+            })
+        });
+
+        // Functions and methods start with a short `fn name` detail - full
+        // signatures are formatted once the list is filtered and ranked
+        let mut function_names = HashSet::new();
+        let mut signature_sources: HashMap<String, &Function> = HashMap::new();
+
+        // The list is built in groups - the document's functions and classes,
+        // then variables, then the rest (stdlib, keywords, snippets, other
+        // files) - each filtered and ranked on its own, so a partial result
+        // sink gets every group as soon as it is ready
+        let mut declarations = Vec::new();
+        for item in &program.items {
+            match item {
+                Item::Function(func) => {
+                    function_names.insert(func.name.clone());
+                    signature_sources.insert(func.name.clone(), func);
+                    let detail = format!("fn {}", func.name);
+
+                    declarations.push(CompletionItem {
+                        label: func.name.clone(),
+                        kind: Some(CompletionItemKind::FUNCTION),
+                        documentation: completion_documentation(&detail, func.doc.as_deref(), markdown),
+                        detail: Some(detail),
+                        ..Default::default()
+                    });
+                }
+                Item::Class(class) => {
+                    // Add class name
+                    declarations.push(CompletionItem {
+                        label: class.name.clone(),
+                        kind: Some(CompletionItemKind::CLASS),
+                        detail: Some(format!("class {}", class.name)),
+                        documentation: class.doc.clone().map(Documentation::String),
+                        command: Some(Command {
+                            title: "Trigger suggest".to_string(),
+                            command: TRIGGER_SUGGEST_COMMAND.to_string(),
+                            arguments: None,
+                        }),
+                        ..Default::default()
+                    });
+
+                    // Methods are labeled `Class.method`, so they never shadow
+                    // free functions or stdlib names
+                    for method in &class.methods {
+                        let label = format!("{}.{}", class.name, method.name);
+                        signature_sources.insert(label.clone(), method);
+                        let detail = format!("fn {}", method.name);
+
+                        declarations.push(CompletionItem {
+                            label,
+                            kind: Some(CompletionItemKind::METHOD),
+                            documentation: completion_documentation(&detail, method.doc.as_deref(), markdown),
+                            detail: Some(detail),
+                            ..Default::default()
+                        });
+                    }
+                }
+            }
+        }
+
+        let max_items = self.settings().completion_max_items;
+        let mut filtered = false;
+        let mut truncated = false;
+        let mut items = Vec::new();
+        let mut streamed = 0;
+        // Filter, rank and decorate one group, then stream or collect it
+        let mut finish = |mut group: Vec<CompletionItem>, variable_types: &HashMap<String, Type>, literal: Option<CompletionItem>| {
+            let before_filter = group.len();
+            group.retain(|item| matches_prefix(&item.label));
+            filtered |= group.len() < before_filter;
+
+            if let (Some(expected_type), Some(wanted)) = (&expected_type, &wanted) {
+                for item in &mut group {
+                    let literal = item.kind == Some(CompletionItemKind::KEYWORD)
+                        && matches!(item.label.as_str(), "true" | "false");
+                    let preferred = (literal && matches!(expected_type, Type::Bool))
+                        || variable_types
+                            .get(&item.label)
+                            .is_some_and(|ty| format_type(ty) == *wanted);
+                    item.sort_text = Some(format!("{}{}", if preferred { "0" } else { "1" }, item.label));
+                }
+            }
+            if let Some(literal) = literal {
+                // `true` is also a keyword completion; keep a single entry
+                group.retain(|item| item.label != literal.label);
+                group.push(literal);
+            }
+
+            // Streamed groups count against the limit in turn
+            if emit.is_some() && streamed + group.len() > max_items {
+                cut_to_ranked(&mut group, max_items - streamed);
+                truncated = true;
+            }
+
+            // Format full signatures for the first MAX_DETAILED_COMPLETIONS declared
+            // functions in display order, so the ones matching what is typed get them
+            let mut declared: Vec<usize> = (0..group.len())
+                .filter(|&i| {
+                    matches!(group[i].kind, Some(CompletionItemKind::FUNCTION) | Some(CompletionItemKind::METHOD))
+                        && signature_sources.contains_key(&group[i].label)
+                })
+                .collect();
+            declared.sort_by_key(|&i| group[i].sort_text.clone().unwrap_or_else(|| group[i].label.clone()));
+            for i in declared.into_iter().take(MAX_DETAILED_COMPLETIONS) {
+                let func = signature_sources[&group[i].label];
+                let Ok(detail) = catch_panics(|| format_function_signature(func)) else {
+                    continue;
+                };
+                group[i].documentation = completion_documentation(&detail, func.doc.as_deref(), markdown);
+                group[i].detail = Some(detail);
+            }
+
+            // Typing `(` after a function or `.` after a class accepts the item
+            if self.supports_commit_characters() {
+                for item in &mut group {
+                    let commit = match item.kind {
+                        Some(CompletionItemKind::FUNCTION) | Some(CompletionItemKind::METHOD) => "(",
+                        Some(CompletionItemKind::CLASS) => ".",
+                        _ => continue,
+                    };
+                    item.commit_characters = Some(vec![commit.to_string()]);
+                }
+            }
+
+            self.attach_insert_replace_edits(text, position, &mut group);
+
+            match emit.as_mut() {
+                Some(emit) if !group.is_empty() => {
+                    streamed += group.len();
+                    emit(group);
+                }
+                Some(_) => {}
+                None => items.extend(group),
+            }
+        };
+        finish(declarations, &HashMap::new(), None);
+
+        // Extract variables from current scope - wrap in catch_unwind
+        let vars = catch_panics(|| {
+            extract_variables_in_scope(program, line + 1, column + 1)
+        }).unwrap_or(None);
+        // Program functions and locals offered so far - a stdlib function with the
+        // same name is shadowed and never offered
+        let mut shadowing_names = function_names.clone();
+        // Types of the variables offered, for context-aware ranking
+        let mut variable_types: HashMap<String, Type> = HashMap::new();
+        let mut variables = Vec::new();
+
+        if let Some(vars) = vars {
+            let index = DocumentIndex::new(program, text);
+            let enclosing = index.enclosing_function(line);
+            // `let myVar = my|` must not offer `myVar` itself
+            let being_declared = name_being_declared(&index, line, column);
+
+            for var_name in vars {
+                if being_declared.as_ref() == Some(&var_name) {
+                    continue;
+                }
+                if !function_names.contains(&var_name) {
+                    shadowing_names.insert(var_name.clone());
+                    // Show the declared or inferred type - wrap in catch_unwind
+                    let var_type = enclosing.and_then(|(func, class)| {
+                        catch_panics(|| {
+                            index.variable_type(func, class, &var_name, line)
+                        }).unwrap_or(None)
+                    });
+                    let detail = match &var_type {
+                        Some(ty) => format!("{}: {}", var_name, format_type(ty)),
+                        None => "Variable".to_string(),
+                    };
+                    if let Some(ty) = var_type {
+                        variable_types.insert(var_name.clone(), ty);
+                    }
+
+                    variables.push(CompletionItem {
+                        label: var_name.clone(),
+                        kind: Some(CompletionItemKind::VARIABLE),
+                        detail: Some(detail),
+                        ..Default::default()
+                    });
+                }
+            }
+        }
+        finish(variables, &variable_types, None);
+
+        // Add stdlib functions - signatures are only formatted for functions
+        // that match, as formatting the whole stdlib is the expensive part
+        let mut rest = Vec::new();
+        let stdlib_funcs = get_stdlib_functions();
+        let mut skipped_stdlib = false;
+        for stdlib_func in &stdlib_funcs {
+            if !matches_prefix(&stdlib_func.name) {
+                skipped_stdlib = true;
+                continue;
+            }
+            // User-defined symbols take precedence over stdlib functions
+            if !shadowing_names.contains(&stdlib_func.name) {
+                let signature = catch_panics(|| {
+                    format_stdlib_signature(&stdlib_func.name, &stdlib_func.params, &stdlib_func.return_type)
+                }).unwrap_or_else(|_| format!("{}()", stdlib_func.name));
+
+                rest.push(CompletionItem {
+                    label: stdlib_func.name.clone(),
+                    kind: Some(CompletionItemKind::FUNCTION),
+                    documentation: completion_documentation(&signature, Some(&stdlib_func.description), markdown),
+                    detail: Some(signature),
+                    ..Default::default()
+                });
+            }
+        }
+
+        // Add keywords (only if not in member access context)
+        if !is_member_access {
+            rest.extend(self.get_keyword_completions());
+            // Idioms for the kind of line being started: declarations at top
+            // level, statements in a body
+            let statement_start = text_before_cursor.trim().chars().all(|c| c.is_alphanumeric() || c == '_');
+            if statement_start {
+                let top_level = !text_before_cursor.starts_with(char::is_whitespace);
+                rest.extend(idiom_snippets(top_level));
+            }
+            // Other files' symbols rank and truncate with the rest, below locals
+            for item in workspace {
+                if !shadowing_names.contains(&item.label) && !rest.iter().any(|existing| existing.label == item.label) {
+                    rest.push(item);
+                }
+            }
+        }
+        finish(rest, &variable_types, return_literal);
+        let streaming = emit.is_some();
+        filtered |= skipped_stdlib;
+
+        // Cut to the configured limit, keeping the ranked-first items
+        if !streaming && items.len() > max_items {
+            cut_to_ranked(&mut items, max_items);
+            truncated = true;
+        }
+
+        CompletionList { is_incomplete: filtered || truncated, items }
+    }
+
+    // Mid-word, inserting the label alone would keep the rest of the word -
+    // items without an edit of their own replace the whole word instead
+    fn attach_insert_replace_edits(&self, text: &str, position: Position, items: &mut [CompletionItem]) {
+        if !self.supports_insert_replace_completion() {
+            return;
+        }
+        let (insert, replace) = identifier_ranges(text, position);
+        for item in items {
+            if item.text_edit.is_none() {
+                item.text_edit = Some(CompletionTextEdit::InsertAndReplace(InsertReplaceEdit {
+                    new_text: item.insert_text.take().unwrap_or_else(|| item.label.clone()),
+                    insert,
+                    replace,
+                }));
+            }
+        }
+    }
+
+    /// Get basic keyword completions
+    pub fn get_keyword_completions(&self) -> Vec<CompletionItem> {
+        KEYWORD_DOCS
+            .iter()
+            .filter(|doc| !HOVER_ONLY_KEYWORDS.contains(&doc.keyword))
+            .map(|doc| CompletionItem {
+                label: doc.keyword.to_string(),
+                kind: Some(CompletionItemKind::KEYWORD),
+                detail: Some(doc.detail.to_string()),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    /// Get basic completions (fallback)
+    pub fn get_basic_completions(&self) -> Vec<CompletionItem> {
+        let mut items = self.get_keyword_completions();
+
+        // Add the most common stdlib functions, with signatures taken from the
+        // stdlib itself so they cannot go stale
+        let basic = std::panic::catch_unwind(|| {
+            let stdlib_funcs = get_stdlib_functions();
+            FALLBACK_STDLIB_FUNCTIONS
+                .iter()
+                .filter_map(|name| stdlib_funcs.iter().find(|func| func.name == *name))
+                .map(|func| CompletionItem {
+                    label: func.name.clone(),
+                    kind: Some(CompletionItemKind::FUNCTION),
+                    detail: Some(format_stdlib_signature(&func.name, &func.params, &func.return_type)),
+                    ..Default::default()
+                })
+                .collect::<Vec<_>>()
+        });
+        match basic {
+            Ok(basic) => items.extend(basic),
+            Err(e) => {
+                log_panic("get_basic_completions", None, None, &*e);
+                // Last resort when the stdlib itself is broken
+                items.push(CompletionItem {
+                    label: "print".to_string(),
+                    kind: Some(CompletionItemKind::FUNCTION),
+                    ..Default::default()
+                });
+            }
+        }
+
+        items
+    }
+
+    /// Re-check every open document and publish the results
+    pub async fn refresh_open_documents(&self) {
+        let open_documents: Vec<(url::Url, String)> = {
+            let docs = self.documents.read().await;
+            docs.iter().map(|(uri, text)| (uri.clone(), text.clone())).collect()
+        }; // Lock released here
+        info!("refreshing diagnostics for {} open documents", open_documents.len());
+        for (uri, text) in open_documents {
+            let version = self.document_versions.read().await.get(&uri).copied();
+            self.on_change(uri, text, version).await;
+        }
+        // Pulling clients only see the new results once they ask again
+        if self.supports_pull_diagnostics() {
+            let _ = self.client.workspace_diagnostic_refresh().await;
+        }
+    }
+
+    /// Diagnostics for the document in the negotiated position encoding
+    pub async fn compute_diagnostics(&self, uri: &url::Url, text: &str) -> Vec<Diagnostic> {
+        // Wrap check_document in catch_unwind to prevent panics from crashing LSP
+        // Note: We compute diagnostics synchronously here, but the lock is already released
+        // so this won't block other operations. For very large files, this could still be slow,
+        // but it's better than blocking the document cache.
+        debug!("compute_diagnostics calling check_document");
+        // Parse through the cache so hover and completion reuse the result
+        let parsed = if text.len() > self.document_size_limit() {
+            None
+        } else {
+            Some(self.get_or_parse(uri, text).await)
+        };
+        let diagnostics = catch_panics(|| {
+            self.check_document_for(Some(uri), text, parsed.as_ref())
+        }).unwrap_or_else(|e| {
+            // If check_document panics, return empty diagnostics
+            log_panic("check_document", Some(uri), None, &*e);
+            error!("text length: {}", text.len());
+            vec![]
+        });
+        debug!("compute_diagnostics check_document returned {} diagnostics", diagnostics.len());
+
+        // Diagnostics are computed with byte columns - convert to the negotiated encoding
+        let mut diagnostics = diagnostics_to_encoding(text, diagnostics, &self.position_encoding());
+
+        // Mid-edit parse failures hide the type errors and warnings - bring back
+        // the last parsed version's ones on lines that are unchanged since and
+        // have no live parse error
+        let parses = parsed.as_ref().is_some_and(|parsed| parsed.program.is_some());
+        if parses {
+            self.last_good_diagnostics.write().await.insert(uri.clone(), (text.to_string(), diagnostics.clone()));
+        } else if self.settings().keep_stale_diagnostics_while_typing {
+            let live_lines: HashSet<u32> = diagnostics.iter().map(|d| d.range.start.line).collect();
+            let stale: Vec<Diagnostic> = match self.last_good_diagnostics.read().await.get(uri) {
+                Some((last_text, stale)) => stale
+                    .iter()
+                    .filter(|d| !live_lines.contains(&d.range.start.line))
+                    .filter(|d| lines_unchanged(last_text, text, d.range))
+                    .filter(|d| !diagnostics.contains(d))
+                    .cloned()
+                    .collect(),
+                None => Vec::new(),
+            };
+            diagnostics.extend(stale);
+        }
+        diagnostics
+    }
+
+    async fn on_change(&self, uri: url::Url, text: String, version: Option<i32>) {
+        debug!("on_change START uri={}, text_len={}", uri, text.len());
+        
+        let diagnostics = self.compute_diagnostics(&uri, &text).await;
+        self.published_diagnostics.write().await.insert(uri.clone(), diagnostics.clone());
+        
+        // Clients pulling diagnostics ask for them - pushing as well would duplicate them
+        if self.supports_pull_diagnostics() {
+            debug!("on_change skipping publish, client pulls diagnostics");
+        } else {
+            // Publish diagnostics - wrap in catch_unwind to prevent panics
+            debug!("on_change preparing to publish diagnostics");
+            let publish_result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                (self.client.clone(), self.document_versions.clone(), uri.clone(), diagnostics)
+            }));
+
+            if let Ok((client, versions, uri_clone, diags)) = publish_result {
+                debug!("on_change spawning publish task");
+                // Use spawn to avoid blocking - the version check runs in the task,
+                // so results overtaken by a newer edit are dropped
+                let task = tokio::spawn(async move {
+                    debug!("publish task START");
+                    publish_if_current(client, versions, uri_clone, diags, version).await;
+                    debug!("publish task END");
+                });
+                self.track_task(task);
+            } else {
+                error!("on_change panicked before publishing diagnostics (uri={})", uri);
+            }
+        }
+        // Keep the workspace index in sync with the edited document
+        let symbols = if text.len() > self.document_size_limit() {
+            Vec::new()
+        } else {
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                index_document(&text)
+            })).unwrap_or_else(|e| {
+                log_panic("index_document", Some(&uri), None, &*e);
+                Vec::new()
+            })
+        };
+        self.workspace_index.write().await.insert(uri.clone(), symbols);
+        debug!("on_change END");
+    }
+
+    pub fn check_document(&self, text: &str) -> Vec<Diagnostic> {
+        self.check_document_for(None, text, None)
+    }
+
+    /// Like `check_document`, but diagnostics can carry related information
+    /// pointing back into the document
+    pub fn check_document_with_uri(&self, uri: &url::Url, text: &str) -> Vec<Diagnostic> {
+        self.check_document_for(Some(uri), text, None)
+    }
+
+    fn check_document_for(
+        &self,
+        uri: Option<&url::Url>,
+        text: &str,
+        parsed: Option<&ParsedDocument>,
+    ) -> Vec<Diagnostic> {
+        debug!("check_document START text_len={}", text.len());
+        let limit = self.document_size_limit();
+        if text.len() > limit {
+            return vec![self.document_too_large_diagnostic(text.len(), limit)];
+        }
+        // Wrap entire function in catch_unwind to prevent any panics
+        let result = catch_panics(|| {
+            debug!("check_document calling check_document_internal");
+            let mut diags = self.check_document_internal(uri, text, parsed);
+            debug!("check_document_internal returned {} diagnostics", diags.len());
+            // Commented-out code and string contents are not code - drop anything reported there
+            let tokens = tokenize(text);
+            diags.retain(|diag| !is_in_comment_or_string(&tokens, diag));
+            // Parse errors, type errors and warnings arrive in pass order - present them by position
+            diags.sort_by_key(|diag| (diag.range.start.line, diag.range.start.character, diag.severity));
+            diags
+        }).unwrap_or_else(|e| {
+            log_panic("check_document_internal", uri, None, &*e);
+            // If anything panics, return empty diagnostics
+            vec![]
+        });
+        debug!("check_document END");
+        result
+    }
+
+    fn check_document_internal(
+        &self,
+        uri: Option<&url::Url>,
+        text: &str,
+        parsed: Option<&ParsedDocument>,
+    ) -> Vec<Diagnostic> {
+        debug!("check_document_internal START text_len={}", text.len());
+        // The compiler sees `\n` line endings only - positions are unaffected
+        let normalized = normalize_line_endings(text);
+        let text = normalized.as_ref();
+        
+        // Handle empty files gracefully
+        if text.trim().is_empty() {
+            debug!("check_document_internal file is empty, returning no diagnostics");
+            return Vec::new();
+        }
+        
+        let settings = self.settings();
+        let mut diagnostics = Vec::new();
+
+        // Parse with error recovery for better IDE experience, unless the
+        // caller already has a (cached) parse of this text
+        let (parse_result, parse_errors) = match parsed {
+            Some(parsed) => (parsed.program.clone().ok_or(()), parsed.parse_errors.clone()),
+            None => {
+                debug!("check_document_internal calling parse_with_recovery");
+                let (parse_result, parse_errors) = parse_with_recovery(text);
+                (parse_result.map(Arc::new).map_err(|_| ()), parse_errors)
+            }
+        };
+        debug!("check_document_internal parse returned {} errors", parse_errors.len());
+
+        // Add parse errors as diagnostics - recovery often reports the same
+        // token several times, so stacked errors are merged
+        let parse_diagnostics = parse_errors
+            .iter()
+            .map(|parse_err| self.parse_error_to_diagnostic(parse_err, text))
+            .collect();
+        diagnostics.extend(merge_parse_diagnostics(text, parse_diagnostics));
+
+        // If parsing succeeded (even partially), try type checking
+        if let Ok(program) = parse_result {
+            // Later definitions silently replace earlier ones in the context,
+            // so report them.
+            let checked = self.type_context(uri, text, &program);
+            let ctx = &checked.ctx;
+            let index = DocumentIndex::new(&program, text);
+            let mut first_definitions: HashMap<String, Option<Range>> = HashMap::new();
+            let mut classes_seen: HashMap<String, usize> = HashMap::new();
+            for item in &program.items {
+                let (name, range) = match item {
+                    Item::Function(func) => (&func.name, index.function_name_range(func)),
+                    Item::Class(class) => {
+                        // Classes carry no span - match them to headers in order
+                        let nth = classes_seen.entry(class.name.clone()).or_insert(0);
+                        let range = index.class_name_ranges(&class.name).get(*nth).copied();
+                        *nth += 1;
+                        (&class.name, range)
+                    }
+                };
+                match first_definitions.get(name) {
+                    Some(first) => {
+                        if let Some(range) = range {
+                            diagnostics.push(self.redefinition_to_diagnostic(name, range, *first, uri));
+                        }
+                    }
+                    None => {
+                        first_definitions.insert(name.clone(), range);
+                    }
+                }
+            }
+
+            match &checked.outcome {
+                Some(Ok(())) if !settings.enable_warnings => {}
+                Some(Ok(())) => {
+                    // Collect warnings - wrap in catch_unwind
+                    let warnings_result = catch_panics(|| {
+                        WarningCollector::collect_warnings(&program, ctx)
+                    });
+                    
+                    if let Ok(warnings) = warnings_result {
+                        for warning in warnings {
+                            diagnostics.extend(self.warning_to_diagnostic(&warning, text));
+                        }
+                    }
+                }
+                Some(Err(err)) => {
+                    // Type error - format safely
+                    let error_msg = format_type_error(text, ctx, err);
+
+                    diagnostics.push(self.type_error_to_diagnostic(err, &error_msg));
+                }
+                // Type checking panicked (logged when checked) - skip type
+                // checking diagnostics
+                None => {}
+            }
+
+            let immutable_assignments = catch_panics(|| {
+                find_immutable_assignments(&program, text)
+            }).unwrap_or_else(|e| {
+                log_panic("find_immutable_assignments", uri, None, &*e);
+                Vec::new()
+            });
+            for assignment in &immutable_assignments {
+                diagnostics.push(self.immutable_assignment_to_diagnostic(assignment, uri));
+            }
+
+            let duplicate_parameters = catch_panics(|| {
+                find_duplicate_parameters(&program, text)
+            }).unwrap_or_else(|e| {
+                log_panic("find_duplicate_parameters", uri, None, &*e);
+                Vec::new()
+            });
+            for duplicate in &duplicate_parameters {
+                diagnostics.push(self.duplicate_parameter_to_diagnostic(duplicate, uri));
+            }
+
+            let deprecated_calls = catch_panics(|| {
+                find_deprecated_calls(&program, text)
+            }).unwrap_or_else(|e| {
+                log_panic("find_deprecated_calls", uri, None, &*e);
+                Vec::new()
+            });
+            for call in &deprecated_calls {
+                diagnostics.push(self.deprecated_call_to_diagnostic(call, uri));
+            }
+
+            let arity_mismatches = catch_panics(|| {
+                find_arity_mismatches(&program, text)
+            }).unwrap_or_else(|e| {
+                log_panic("find_arity_mismatches", uri, None, &*e);
+                Vec::new()
+            });
+            for mismatch in &arity_mismatches {
+                diagnostics.push(self.arity_mismatch_to_diagnostic(mismatch, uri));
+            }
+
+            // The type checker may already report the name as undefined -
+            // the more specific message replaces it
+            let early_uses = catch_panics(|| {
+                find_uses_before_declaration(&program, text)
+            }).unwrap_or_else(|e| {
+                log_panic("find_uses_before_declaration", uri, None, &*e);
+                Vec::new()
+            });
+            for early in &early_uses {
+                diagnostics.retain(|d| d.range.start != early.range.start || d.severity != Some(DiagnosticSeverity::ERROR));
+                diagnostics.push(self.use_before_declaration_to_diagnostic(early, uri));
+            }
+
+            let missing_returns = catch_panics(|| {
+                find_missing_returns(&program, text)
+            }).unwrap_or_else(|e| {
+                log_panic("find_missing_returns", uri, None, &*e);
+                Vec::new()
+            });
+            for missing in &missing_returns {
+                diagnostics.push(self.missing_return_to_diagnostic(missing));
+            }
+
+            if let Some(severity) = settings.warning_severity.missing_return_type.to_lsp() {
+                let missing_types = catch_panics(|| {
+                    find_missing_return_types(&program, text)
+                }).unwrap_or_else(|e| {
+                    log_panic("find_missing_return_types", uri, None, &*e);
+                    Vec::new()
+                });
+                for missing in &missing_types {
+                    diagnostics.push(self.missing_return_type_to_diagnostic(missing, severity));
+                }
+            }
+
+            // Method bodies are checked here with `self` bound to the class -
+            // errors the whole-program check already reported are skipped
+            let method_errors = catch_panics(|| {
+                find_method_type_errors(&program, text)
+            }).unwrap_or_else(|e| {
+                log_panic("find_method_type_errors", uri, None, &*e);
+                Vec::new()
+            });
+            for method_error in &method_errors {
+                let diagnostic = self.type_error_to_diagnostic(&method_error.error, &method_error.message);
+                let reported = diagnostics
+                    .iter()
+                    .any(|d| d.range == diagnostic.range && d.message == diagnostic.message);
+                if !reported {
+                    diagnostics.push(diagnostic);
+                }
+            }
+
+            if settings.enable_warnings {
+                let shadowed = catch_panics(|| {
+                    find_shadowed_bindings(&program, text)
+                }).unwrap_or_else(|e| {
+                    log_panic("find_shadowed_bindings", uri, None, &*e);
+                    Vec::new()
+                });
+                for binding in &shadowed {
+                    diagnostics.push(self.shadowed_binding_to_diagnostic(binding, uri));
+                }
+
+                let function_assignments = catch_panics(|| {
+                    find_function_name_assignments(&program, text)
+                }).unwrap_or_else(|e| {
+                    log_panic("find_function_name_assignments", uri, None, &*e);
+                    Vec::new()
+                });
+                for assignment in &function_assignments {
+                    diagnostics.push(self.function_name_assignment_to_diagnostic(assignment, uri));
+                }
+            }
+        }
+
+        diagnostics
+    }
+
+    pub fn redefinition_to_diagnostic(
+        &self,
+        name: &str,
+        range: Range,
+        first: Option<Range>,
+        uri: Option<&url::Url>,
+    ) -> Diagnostic {
+        let related_information = uri.zip(first).map(|(uri, first)| {
+            vec![DiagnosticRelatedInformation {
+                location: Location {
+                    uri: uri.clone(),
+                    range: first,
+                },
+                message: format!("`{}` is first defined here", name),
+            }]
+        });
+
+        Diagnostic {
+            range,
+            severity: Some(DiagnosticSeverity::ERROR),
+            code: None,
+            code_description: None,
+            source: Some("pain".to_string()),
+            message: format!("`{}` is defined multiple times", name),
+            related_information,
+            tags: None,
+            data: None,
+        }
+    }
+
+    pub fn immutable_assignment_to_diagnostic(
+        &self,
+        assignment: &ImmutableAssignment,
+        uri: Option<&url::Url>,
+    ) -> Diagnostic {
+        let related_information = uri.map(|uri| {
+            vec![DiagnosticRelatedInformation {
+                location: Location {
+                    uri: uri.clone(),
+                    range: assignment.declaration,
+                },
+                message: format!("`{}` is declared with `let` here", assignment.name),
+            }]
+        });
+
+        Diagnostic {
+            range: assignment.range,
+            severity: Some(DiagnosticSeverity::ERROR),
+            code: Some(NumberOrString::String(IMMUTABLE_ASSIGNMENT_CODE.to_string())),
+            code_description: None,
+            source: Some("pain".to_string()),
+            message: format!("cannot assign to immutable variable `{}`", assignment.name),
+            related_information,
+            tags: None,
+            data: None,
+        }
+    }
+
+    pub fn shadowed_binding_to_diagnostic(
+        &self,
+        binding: &ShadowedBinding,
+        uri: Option<&url::Url>,
+    ) -> Diagnostic {
+        let related_information = uri.map(|uri| {
+            vec![DiagnosticRelatedInformation {
+                location: Location {
+                    uri: uri.clone(),
+                    range: binding.original,
+                },
+                message: format!("`{}` is first bound here", binding.name),
+            }]
+        });
+
+        Diagnostic {
+            range: binding.range,
+            severity: Some(DiagnosticSeverity::WARNING),
+            code: None,
+            code_description: None,
+            source: Some("pain".to_string()),
+            message: format!("variable `{}` shadows an earlier binding", binding.name),
+            related_information,
+            tags: None,
+            data: None,
+        }
+    }
+
+    pub fn duplicate_parameter_to_diagnostic(
+        &self,
+        duplicate: &DuplicateParameter,
+        uri: Option<&url::Url>,
+    ) -> Diagnostic {
+        let related_information = uri.map(|uri| {
+            vec![DiagnosticRelatedInformation {
+                location: Location {
+                    uri: uri.clone(),
+                    range: duplicate.first,
+                },
+                message: format!("`{}` is first declared here", duplicate.name),
+            }]
+        });
+
+        Diagnostic {
+            range: duplicate.range,
+            severity: Some(DiagnosticSeverity::ERROR),
+            code: None,
+            code_description: None,
+            source: Some("pain".to_string()),
+            message: format!("duplicate parameter `{}`", duplicate.name),
+            related_information,
+            tags: None,
+            data: None,
+        }
+    }
+
+    pub fn use_before_declaration_to_diagnostic(
+        &self,
+        early: &UseBeforeDeclaration,
+        uri: Option<&url::Url>,
+    ) -> Diagnostic {
+        let related_information = uri.map(|uri| {
+            vec![DiagnosticRelatedInformation {
+                location: Location {
+                    uri: uri.clone(),
+                    range: early.declaration,
+                },
+                message: format!("`{}` is declared here", early.name),
+            }]
+        });
+
+        Diagnostic {
+            range: early.range,
+            severity: Some(DiagnosticSeverity::ERROR),
+            code: None,
+            code_description: None,
+            source: Some("pain".to_string()),
+            message: format!("use of `{}` before its declaration", early.name),
+            related_information,
+            tags: None,
+            data: None,
+        }
+    }
+
+    pub fn deprecated_call_to_diagnostic(&self, call: &DeprecatedCall, uri: Option<&url::Url>) -> Diagnostic {
+        let related_information = uri.zip(call.function).map(|(uri, function)| {
+            vec![DiagnosticRelatedInformation {
+                location: Location {
+                    uri: uri.clone(),
+                    range: function,
+                },
+                message: format!("`{}` is marked `@deprecated` here", call.name),
+            }]
+        });
+
+        Diagnostic {
+            range: call.range,
+            severity: Some(DiagnosticSeverity::HINT),
+            code: None,
+            code_description: None,
+            source: Some("pain".to_string()),
+            message: format!("`{}` is deprecated", call.name),
+            related_information,
+            tags: Some(vec![DiagnosticTag::DEPRECATED]),
+            data: None,
+        }
+    }
+
+    pub fn arity_mismatch_to_diagnostic(&self, mismatch: &ArityMismatch, uri: Option<&url::Url>) -> Diagnostic {
+        let related_information = uri.zip(mismatch.function).map(|(uri, function)| {
+            vec![DiagnosticRelatedInformation {
+                location: Location {
+                    uri: uri.clone(),
+                    range: function,
+                },
+                message: format!("`{}` is declared here", mismatch.name),
+            }]
+        });
+        let plural = |count: usize| if count == 1 { "" } else { "s" };
+
+        Diagnostic {
+            range: mismatch.range,
+            severity: Some(DiagnosticSeverity::ERROR),
+            code: None,
+            code_description: None,
+            source: Some("pain".to_string()),
+            message: format!(
+                "`{}` expects {} argument{}, found {}",
+                mismatch.name,
+                mismatch.expected,
+                plural(mismatch.expected),
+                mismatch.found
+            ),
+            related_information,
+            tags: None,
+            data: None,
+        }
+    }
+
+    pub fn function_name_assignment_to_diagnostic(
+        &self,
+        assignment: &FunctionNameAssignment,
+        uri: Option<&url::Url>,
+    ) -> Diagnostic {
+        let related_information = uri.zip(assignment.function).map(|(uri, function)| {
+            vec![DiagnosticRelatedInformation {
+                location: Location {
+                    uri: uri.clone(),
+                    range: function,
+                },
+                message: format!("function `{}` is defined here", assignment.name),
+            }]
+        });
+        let kind = if assignment.function.is_some() { "function" } else { "built-in function" };
+
+        Diagnostic {
+            range: assignment.range,
+            severity: Some(DiagnosticSeverity::WARNING),
+            code: None,
+            code_description: None,
+            source: Some("pain".to_string()),
+            message: format!(
+                "assignment to `{}`, which is a {} - consider renaming the variable",
+                assignment.name, kind
+            ),
+            related_information,
+            tags: None,
+            data: None,
+        }
+    }
+
+    pub fn missing_return_to_diagnostic(&self, missing: &MissingReturn) -> Diagnostic {
+        Diagnostic {
+            range: missing.range,
+            severity: Some(DiagnosticSeverity::ERROR),
+            code: None,
+            code_description: None,
+            source: Some("pain".to_string()),
+            message: format!(
+                "function `{}` may reach the end without returning a value of type `{}`",
+                missing.name,
+                format_type(&missing.return_type)
+            ),
+            related_information: None,
+            tags: None,
+            data: None,
+        }
+    }
+
+    pub fn missing_return_type_to_diagnostic(&self, missing: &MissingReturnType, severity: DiagnosticSeverity) -> Diagnostic {
+        Diagnostic {
+            range: missing.range,
+            severity: Some(severity),
+            code: Some(NumberOrString::String(MISSING_RETURN_TYPE_CODE.to_string())),
+            code_description: None,
+            source: Some("pain".to_string()),
+            message: format!(
+                "function `{}` returns `{}` - consider annotating it with `-> {}`",
+                missing.name,
+                format_type(&missing.inferred),
+                format_type(&missing.inferred)
+            ),
+            related_information: None,
+            tags: None,
+            data: None,
+        }
+    }
+
+    // Single diagnostic at the top of a document too large to analyze
+    pub fn document_too_large_diagnostic(&self, size: usize, limit: usize) -> Diagnostic {
+        Diagnostic {
+            range: Range {
+                start: Position { line: 0, character: 0 },
+                end: Position { line: 0, character: 0 },
+            },
+            severity: Some(DiagnosticSeverity::INFORMATION),
+            code: None,
+            code_description: None,
+            source: Some("pain".to_string()),
+            message: format!("File too large for Pain analysis ({} bytes, limit {})", size, limit),
+            related_information: None,
+            tags: None,
+            data: None,
+        }
+    }
+
+    pub fn parse_error_to_diagnostic(&self, err: &pain_compiler::error::ParseError, text: &str) -> Diagnostic {
+        Diagnostic {
+            range: parse_error_range(err, text),
+            severity: Some(DiagnosticSeverity::ERROR),
+            code: Some(NumberOrString::String(PARSE_ERROR_CODE.to_string())),
+            code_description: None,
+            source: Some("pain".to_string()),
+            message: err.message.clone(),
+            related_information: None,
+            tags: None,
+            data: None,
+        }
+    }
+
+    /// Type error diagnostic. The message is the first line of the formatted
+    /// error; the full text, with its explanation and hints, goes in
+    /// `data.fullMessage` for clients that render diagnostic details.
+    pub fn type_error_to_diagnostic(
+        &self,
+        err: &pain_compiler::TypeError,
+        formatted_msg: &str,
+    ) -> Diagnostic {
+        let span = type_error_span(err);
+
+        Diagnostic {
+            range: Range {
+                start: Position {
+                    line: clamp_to_u32(span.line().saturating_sub(1)),
+                    character: clamp_to_u32(span.column().saturating_sub(1)),
+                },
+                end: Position {
+                    line: clamp_to_u32(span.line().saturating_sub(1)),
+                    character: clamp_to_u32(span.column().saturating_sub(1) + 1),
+                },
+            },
+            severity: Some(DiagnosticSeverity::ERROR),
+            code: None,
+            code_description: None,
+            source: Some("pain".to_string()),
+            message: formatted_msg
+                .lines()
+                .next()
+                .unwrap_or(formatted_msg)
+                .to_string(),
+            related_information: None,
+            tags: None,
+            data: Some(serde_json::json!({ "fullMessage": formatted_msg })),
+        }
+    }
+
+    /// Convert a compiler warning, using the severity configured for its category.
+    /// Returns `None` when the category is turned off.
+    pub fn warning_to_diagnostic(&self, warning: &pain_compiler::Warning, text: &str) -> Option<Diagnostic> {
+        let severities = self.settings().warning_severity;
+        let (category, message, span) = describe_warning(warning);
+        let level = match category {
+            WarningCategory::UnusedVariable => severities.unused_variable,
+            WarningCategory::UnusedFunction => severities.unused_function,
+            WarningCategory::DeadCode => severities.dead_code,
+            WarningCategory::UnreachableCode => severities.unreachable_code,
+        };
+        let severity = level.to_lsp()?;
+
+        Some(Diagnostic {
+            range: span_to_range(&span, text),
+            severity: Some(severity),
+            code: None,
+            code_description: None,
+            source: Some("pain".to_string()),
+            message,
+            related_information: None,
+            tags: None,
+            data: None,
+        })
+    }
+}
+
+/// Where to show a parse error: the character it points at, or - when that
+/// is past the end of its line or of the document, as for a statement cut
+/// off at EOF - the last token before it. Always inside the document.
+pub fn parse_error_range(err: &pain_compiler::error::ParseError, text: &str) -> Range {
+    let line = err.span.line().saturating_sub(1);
+    let column = err.span.column().saturating_sub(1);
+    let lines: Vec<&str> = text.split('\n').map(|line| line.strip_suffix('\r').unwrap_or(line)).collect();
+
+    if let Some(content) = lines.get(line) {
+        if content.get(column..).is_some_and(|rest| !rest.trim().is_empty()) {
+            let end = content[column..].chars().next().map_or(column, |ch| column + ch.len_utf8());
+            return Range {
+                start: Position { line: clamp_to_u32(line), character: clamp_to_u32(column) },
+                end: Position { line: clamp_to_u32(line), character: clamp_to_u32(end) },
+            };
+        }
+    }
+
+    let tokens = tokenize(text);
+    let before = tokens
+        .iter()
+        .rev()
+        .filter(|token| !matches!(token.kind, TokenKind::Comment | TokenKind::DocComment))
+        .find(|token| (token.line, token.start) < (line, column));
+    if let Some(token) = before {
+        return token_range(token);
+    }
+    // Nothing but whitespace before the error - the start of the document
+    Range::default()
+}
+
+/// Diagnostic code for errors reported by the parser
+pub const PARSE_ERROR_CODE: &str = "parse-error";
+
+/// Whether the diagnostic lies entirely inside the body of a comment or string
+/// literal. Diagnostics starting at the `#` or quote are about what follows the
+/// code or the literal itself and are kept, as are parse errors, which are the
+/// only ones that can be about a literal's contents (bad escapes and the like).
+pub fn is_in_comment_or_string(tokens: &[Token], diag: &Diagnostic) -> bool {
+    if diag.code == Some(NumberOrString::String(PARSE_ERROR_CODE.to_string())) {
+        return false;
+    }
+    let Range { start, end } = diag.range;
+    tokens.iter().any(|token| {
+        matches!(token.kind, TokenKind::Comment | TokenKind::DocComment | TokenKind::String)
+            && token.line == start.line as usize
+            && token.line == end.line as usize
+            && start.character as usize > token.start
+            && end.character as usize <= token.end
+    })
+}
+
+/// Whether every line the range touches reads the same in both versions of a
+/// document. Lines past the end of either version count as changed.
+fn lines_unchanged(before: &str, after: &str, range: Range) -> bool {
+    let start = range.start.line as usize;
+    let count = (range.end.line as usize).saturating_sub(start) + 1;
+    let mut before = before.lines().skip(start);
+    let mut after = after.lines().skip(start);
+    (0..count).all(|_| match (before.next(), after.next()) {
+        (Some(before), Some(after)) => before == after,
+        _ => false,
+    })
+}
+
+/// Range covered by a compiler span, which may run over several lines (dead
+/// code). Spans without a usable end cover the token at their start instead.
+pub fn span_to_range(span: &pain_compiler::span::Span, text: &str) -> Range {
+    let start = Position {
+        line: clamp_to_u32(span.line().saturating_sub(1)),
+        character: clamp_to_u32(span.column().saturating_sub(1)),
+    };
+    let end = Position {
+        line: clamp_to_u32(span.end.line.saturating_sub(1)),
+        character: clamp_to_u32(span.end.column.saturating_sub(1)),
+    };
+    if end > start {
+        return Range { start, end };
+    }
+
+    let tokens = tokenize(text);
+    match token_at(&tokens, start.line as usize, start.character as usize) {
+        Some(token) if token.start == start.character as usize => token_range(token),
+        _ => Range {
+            start,
+            end: Position { line: start.line, character: start.character.saturating_add(1) },
+        },
+    }
+}
+
+// Scan all workspace folders and index their `.pain` files, reporting progress
+// through `window/workDoneProgress/create` + `$/progress` when the client supports it
+async fn index_workspace(
+    client: tower_lsp::Client,
+    roots: Vec<url::Url>,
+    index: Arc<RwLock<HashMap<url::Url, Vec<IndexedSymbol>>>>,
+    report_progress: bool,
+) {
+    let files: Vec<_> = roots
+        .iter()
+        .filter_map(url_to_path)
+        .flat_map(|root| find_pain_files(&root))
+        .collect();
+    if files.is_empty() {
+        return;
+    }
+    info!("indexing {} workspace files", files.len());
+
+    // The token must be created by the client before any `$/progress` is sent
+    let token = NumberOrString::String("pain/indexWorkspace".to_string());
+    let report_progress = report_progress
+        && client
+            .send_request::<request::WorkDoneProgressCreate>(WorkDoneProgressCreateParams {
+                token: token.clone(),
+            })
+            .await
+            .is_ok();
+    let send_progress = |progress: WorkDoneProgress| {
+        let client = client.clone();
+        let token = token.clone();
+        async move {
+            if report_progress {
+                client
+                    .send_notification::<notification::Progress>(ProgressParams {
+                        token,
+                        value: ProgressParamsValue::WorkDone(progress),
+                    })
+                    .await;
+            }
+        }
+    };
+
+    let total = files.len();
+    send_progress(WorkDoneProgress::Begin(WorkDoneProgressBegin {
+        title: "Indexing Pain workspace".to_string(),
+        cancellable: Some(false),
+        message: Some(format!("0/{} files", total)),
+        percentage: Some(0),
+    }))
+    .await;
+
+    for (done, path) in files.iter().enumerate() {
+        let (Ok(uri), Ok(text)) = (url::Url::from_file_path(path), std::fs::read_to_string(path)) else {
+            continue;
+        };
+        let symbols = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            index_document(&text)
+        })).unwrap_or_else(|e| {
+            log_panic("index_document", Some(&uri), None, &*e);
+            Vec::new()
+        });
+        // Open documents are indexed from the editor buffer instead
+        index.write().await.entry(uri).or_insert(symbols);
+
+        send_progress(WorkDoneProgress::Report(WorkDoneProgressReport {
+            cancellable: Some(false),
+            message: Some(format!("{}/{} files", done + 1, total)),
+            percentage: Some(((done + 1) * 100 / total) as u32),
+        }))
+        .await;
+    }
+
+    send_progress(WorkDoneProgress::End(WorkDoneProgressEnd {
+        message: Some(format!("Indexed {} files", total)),
+    }))
+    .await;
+}
+
+/// Apply `didChange` content changes in order. Changes without a range replace
+/// the whole document; ranged changes splice into the result of the previous one.
+pub fn apply_content_changes(
+    text: &str,
+    changes: &[TextDocumentContentChangeEvent],
+    encoding: &PositionEncodingKind,
+) -> String {
+    let mut text = text.to_string();
+    for change in changes {
+        match change.range {
+            None => text = change.text.clone(),
+            Some(range) => {
+                let start = lsp_position_to_offset(&text, range.start, encoding.clone()).unwrap_or(text.len());
+                let end = lsp_position_to_offset(&text, range.end, encoding.clone())
+                    .unwrap_or(text.len())
+                    .max(start);
+                text.replace_range(start..end, &change.text);
+            }
+        }
+    }
+    text
+}
+
+/// Documentation for a language keyword, shared by completion and hover
+pub struct KeywordDoc {
+    pub keyword: &'static str,
+    /// Short description used as completion detail
+    pub detail: &'static str,
+    pub description: &'static str,
+    pub example: &'static str,
+}
+
+// Documented for hover, but not offered as keyword completions
+const HOVER_ONLY_KEYWORDS: &[&str] = &["class", "in", "pass"];
+
+pub const KEYWORD_DOCS: &[KeywordDoc] = &[
+    KeywordDoc {
+        keyword: "fn",
+        detail: "Function definition",
+        description: "Defines a function. Parameters are annotated with their types and the return type follows `->`; omit it for functions that return nothing.",
+        example: "fn add(a: int, b: int) -> int:\n    return a + b",
+    },
+    KeywordDoc {
+        keyword: "class",
+        detail: "Class definition",
+        description: "Defines a class with typed fields declared by `let` and methods declared by `fn`.",
+        example: "class Point:\n    let x: int\n    let y: int",
+    },
+    KeywordDoc {
+        keyword: "let",
+        detail: "Immutable variable",
+        description: "Declares an immutable variable. The type is inferred from the initializer unless annotated.",
+        example: "let name: str = \"Pain\"",
+    },
+    KeywordDoc {
+        keyword: "var",
+        detail: "Mutable variable",
+        description: "Declares a mutable variable that can be reassigned later.",
+        example: "var count = 0\ncount = count + 1",
+    },
+    KeywordDoc {
+        keyword: "if",
+        detail: "Conditional statement",
+        description: "Runs the indented block only when the condition is true. May be followed by an `else` branch.",
+        example: "if x > 0:\n    print(\"positive\")",
+    },
+    KeywordDoc {
+        keyword: "else",
+        detail: "Else branch",
+        description: "Block that runs when the preceding `if` condition is false.",
+        example: "if x > 0:\n    print(\"positive\")\nelse:\n    print(\"not positive\")",
+    },
+    KeywordDoc {
+        keyword: "for",
+        detail: "For loop",
+        description: "Loops over every element of a list, array or map, binding each element to the loop variable.",
+        example: "for item in [1, 2, 3]:\n    print(item)",
+    },
+    KeywordDoc {
+        keyword: "in",
+        detail: "Loop iterable",
+        description: "Separates the loop variable from the collection iterated by a `for` loop.",
+        example: "for item in items:\n    print(item)",
+    },
+    KeywordDoc {
+        keyword: "while",
+        detail: "While loop",
+        description: "Repeats the indented block as a loop for as long as the condition stays true.",
+        example: "var i = 0\nwhile i < 10:\n    i = i + 1",
+    },
+    KeywordDoc {
+        keyword: "break",
+        detail: "Break out of loop",
+        description: "Exits the innermost `for` or `while` loop immediately.",
+        example: "while true:\n    break",
+    },
+    KeywordDoc {
+        keyword: "continue",
+        detail: "Continue to next loop iteration",
+        description: "Skips the rest of the current iteration and continues with the next one of the innermost loop.",
+        example: "for i in items:\n    if i < 0:\n        continue\n    print(i)",
+    },
+    KeywordDoc {
+        keyword: "return",
+        detail: "Return from function",
+        description: "Leaves the current function, optionally producing a value of the declared return type.",
+        example: "fn square(x: int) -> int:\n    return x * x",
+    },
+    KeywordDoc {
+        keyword: "pass",
+        detail: "Empty statement",
+        description: "Does nothing. Used where a block is required but no code is needed yet.",
+        example: "fn todo():\n    pass",
+    },
+    KeywordDoc {
+        keyword: "true",
+        detail: "Boolean literal",
+        description: "The `bool` value true.",
+        example: "var running = true",
+    },
+    KeywordDoc {
+        keyword: "false",
+        detail: "Boolean literal",
+        description: "The `bool` value false.",
+        example: "var done = false",
+    },
+];
+
+// Skeletons offered after `fn `/`class ` at the start of a top-level line
+const DECLARATION_SNIPPETS: &[(&str, &str, &str)] = &[
+    ("fn", "fn ${1:name}(${2}) -> ${3:int}:\n    ${0}", "Function declaration"),
+    ("class", "class ${1:Name}:\n    ${0}", "Class declaration"),
+];
+
+/// Parse of the document with the `.` just before the cursor blanked out, so
+/// `receiver.` being typed - possibly as the last character of the file -
+/// still yields a program. Blanking keeps every position unchanged. `None`
+/// when the cursor does not follow a dot or the document still fails to parse.
+pub fn parse_without_dangling_dot(text: &str, position: Position) -> Option<Program> {
+    let offset = lsp_position_to_offset(text, position, PositionEncodingKind::UTF8)?;
+    let before = text.get(..offset)?.trim_end_matches([' ', '\t']);
+    let dot = before.strip_suffix('.').map(str::len)?;
+
+    let mut patched = String::with_capacity(text.len() + 1);
+    patched.push_str(&text[..dot]);
+    patched.push(' ');
+    patched.push_str(&text[dot + 1..]);
+    // The last statement needs its newline to end
+    if !patched.ends_with('\n') {
+        patched.push('\n');
+    }
+    let (parse_result, _) = parse_with_recovery(&normalize_line_endings(&patched));
+    parse_result.ok()
+}
+
+// Idiom skeletons: label, snippet, detail, and whether they belong at top
+// level (otherwise in a function body)
+const IDIOM_SNIPPETS: &[(&str, &str, &str, bool)] = &[
+    ("main", "fn main():\n    ${0:pass}", "Main function", true),
+    (
+        "class with constructor",
+        "class ${1:Name}:\n    let ${2:value}: ${3:int}\n\n    fn new(${2:value}: ${3:int}) -> ${1:Name}:\n        let instance = ${1:Name}()\n        instance.${2:value} = ${2:value}\n        return instance\n${0}",
+        "Class with a `new` constructor",
+        true,
+    ),
+    (
+        "while counter",
+        "var ${1:i} = 0\nwhile ${1:i} < ${2:count}:\n    ${0:pass}\n    ${1:i} = ${1:i} + 1",
+        "Counting while loop",
+        false,
+    ),
+];
+
+/// Idiom snippets for a line being started at top level or in a function body
+pub fn idiom_snippets(top_level: bool) -> Vec<CompletionItem> {
+    IDIOM_SNIPPETS
+        .iter()
+        .filter(|(_, _, _, at_top)| *at_top == top_level)
+        .map(|(label, snippet, detail, _)| CompletionItem {
+            label: label.to_string(),
+            kind: Some(CompletionItemKind::SNIPPET),
+            detail: Some(detail.to_string()),
+            insert_text: Some(snippet.to_string()),
+            insert_text_format: Some(InsertTextFormat::SNIPPET),
+            ..Default::default()
+        })
+        .collect()
+}
+
+// Pain has no import statement - a file relying on another records it in a
+// `# requires <file>` comment at its top
+const REQUIRES_COMMENT: &str = "# requires ";
+
+/// Completions for the functions and classes that other workspace files
+/// declare and this document does not. Each item names its file in `detail`,
+/// and its `data` lets `completionItem/resolve` add the `# requires` comment.
+pub fn workspace_completions(
+    index: &HashMap<url::Url, Vec<IndexedSymbol>>,
+    roots: &[url::Url],
+    uri: &url::Url,
+    program: &Program,
+) -> Vec<CompletionItem> {
+    let local: HashSet<&str> = program
+        .items
+        .iter()
+        .map(|item| match item {
+            Item::Function(func) => func.name.as_str(),
+            Item::Class(class) => class.name.as_str(),
+        })
+        .collect();
+
+    let mut items: Vec<CompletionItem> = Vec::new();
+    for (other, symbols) in index.iter().filter(|(other, _)| *other != uri) {
+        let from = format!("{}.pain", module_name(other, roots));
+        for symbol in symbols {
+            let kind = match symbol.kind {
+                SymbolKind::FUNCTION => CompletionItemKind::FUNCTION,
+                SymbolKind::CLASS => CompletionItemKind::CLASS,
+                _ => continue,
+            };
+            if local.contains(symbol.name.as_str()) || items.iter().any(|item| item.label == symbol.name) {
+                continue;
+            }
+            items.push(CompletionItem {
+                label: symbol.name.clone(),
+                kind: Some(kind),
+                detail: Some(format!("from {}", from)),
+                // Local symbols sort first
+                sort_text: Some(format!("~{}", symbol.name)),
+                data: Some(serde_json::json!({ "document": uri.as_str(), "from": from })),
+                ..Default::default()
+            });
+        }
+    }
+    items
+}
+
+/// Edit adding `# requires <from>` after the document's leading `# requires`
+/// comments, or at its top. `None` when the comment is already there.
+pub fn requires_comment_edit(text: &str, from: &str) -> Option<TextEdit> {
+    let comment = format!("{}{}", REQUIRES_COMMENT, from);
+    if text.lines().any(|content| content.trim_end() == comment) {
+        return None;
+    }
+    let line = text.lines().take_while(|content| content.starts_with(REQUIRES_COMMENT)).count();
+
+    let position = Position { line: clamp_to_u32(line), character: 0 };
+    Some(TextEdit {
+        range: Range { start: position, end: position },
+        new_text: format!("{}\n", comment),
+    })
+}
+
+/// Snippet completion for a declaration header being typed at top level
+/// (`fn `, `fn na`, `class `, ...). Only the name may follow the cursor, so
+/// existing headers are left alone. The edit replaces the typed header and
+/// name, so `position` and the returned range use byte columns.
+pub fn declaration_snippet(text: &str, position: Position) -> Option<CompletionItem> {
+    let is_name_char = |c: char| c.is_alphanumeric() || c == '_';
+    let line = text.lines().nth(position.line as usize)?;
+    let before = line.get(..position.character as usize)?;
+    let (keyword, rest) = before.split_once(' ')?;
+    if !rest.chars().all(is_name_char) {
+        return None;
+    }
+    let after = &line[before.len()..];
+    let name_rest = after.len() - after.trim_start_matches(is_name_char).len();
+    if !after[name_rest..].trim().is_empty() {
+        return None;
+    }
+    let (label, snippet, detail) = DECLARATION_SNIPPETS.iter().find(|(kw, _, _)| *kw == keyword)?;
+
+    Some(CompletionItem {
+        label: label.to_string(),
+        kind: Some(CompletionItemKind::SNIPPET),
+        detail: Some(detail.to_string()),
+        // Filter against the typed header so clients keep the item while typing the name
+        filter_text: Some(before.to_string()),
+        sort_text: Some("0".to_string()),
+        preselect: Some(true),
+        insert_text_format: Some(InsertTextFormat::SNIPPET),
+        text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+            range: Range {
+                start: Position { line: position.line, character: 0 },
+                end: Position { line: position.line, character: clamp_to_u32(before.len() + name_rest) },
+            },
+            new_text: snippet.to_string(),
+        })),
+        ..Default::default()
+    })
+}
+
+/// Collapse parse-error diagnostics that land on the same token into one
+/// diagnostic covering the token. Identical messages are reported once;
+/// distinct ones are joined line by line in their original order.
+pub fn merge_parse_diagnostics(text: &str, diagnostics: Vec<Diagnostic>) -> Vec<Diagnostic> {
+    let tokens = tokenize(text);
+    let mut merged: Vec<Diagnostic> = Vec::new();
+
+    for mut diagnostic in diagnostics {
+        let start = diagnostic.range.start;
+        if let Some(token) = tokens.iter().find(|t| {
+            t.line == start.line as usize && t.start <= start.character as usize && (start.character as usize) < t.end
+        }) {
+            diagnostic.range = token_range(token);
+        }
+
+        match merged.iter_mut().find(|existing| existing.range == diagnostic.range) {
+            Some(existing) => {
+                if !existing.message.lines().any(|line| line == diagnostic.message) {
+                    existing.message.push('\n');
+                    existing.message.push_str(&diagnostic.message);
+                }
+            }
+            None => merged.push(diagnostic),
+        }
+    }
+
+    merged
+}
+
+// Publish unless a newer version of the document has superseded `version`
+async fn publish_if_current(
+    client: tower_lsp::Client,
+    versions: Arc<RwLock<HashMap<url::Url, i32>>>,
+    uri: url::Url,
+    diagnostics: Vec<Diagnostic>,
+    version: Option<i32>,
+) -> bool {
+    if let Some(version) = version {
+        let latest = versions.read().await.get(&uri).copied();
+        if latest.is_some_and(|latest| latest > version) {
+            warn!("dropping diagnostics for {} version {} (latest {:?})", uri, version, latest);
+            return false;
+        }
+    }
+    client.publish_diagnostics(uri, diagnostics, version).await;
+    true
+}
+
+/// Moniker for a resolved symbol: `<module>::<path>` in the `pain` scheme.
+/// Top-level declarations and members are exported, locals stay in their document.
+pub fn symbol_moniker(module: &str, symbol: &ResolvedSymbol) -> Moniker {
+    let (unique, kind) = if symbol.local {
+        (UniquenessLevel::Document, MonikerKind::Local)
+    } else {
+        (UniquenessLevel::Project, MonikerKind::Export)
+    };
+    Moniker {
+        scheme: "pain".to_string(),
+        identifier: format!("{}::{}", module, symbol.path.join("::")),
+        unique,
+        kind: Some(kind),
+    }
+}
+
+pub fn keyword_doc(keyword: &str) -> Option<&'static KeywordDoc> {
+    KEYWORD_DOCS.iter().find(|doc| doc.keyword == keyword)
+}
+
+// Hover for the keyword under the cursor, rendered as Markdown
+pub fn keyword_hover(text: &str, position: Position) -> Option<Hover> {
+    let tokens = tokenize(text);
+    let token = token_at(&tokens, position.line as usize, position.character as usize)?;
+    if token.kind != TokenKind::Keyword {
+        return None;
+    }
+    let doc = keyword_doc(&token.text)?;
+
+    Some(Hover {
+        contents: HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: format!(
+                "**{}** - {}\n\n{}\n\n```pain\n{}\n```",
+                doc.keyword, doc.detail, doc.description, doc.example
+            ),
+        }),
+        range: Some(token_range(token)),
+    })
+}
+
+// Completion items given a fully formatted signature - formatting every
+// declaration of a large document is slow
+const MAX_DETAILED_COMPLETIONS: usize = 50;
+
+// Identifiers remembered per document, and how many edits an entry survives
+const MAX_RECENT_IDENTIFIERS: usize = 16;
+const RECENT_IDENTIFIER_EDITS: u32 = 200;
+
+/// Identifiers used by recent requests in a document, most recent first.
+/// Entries age with every edit and are forgotten once they get too old.
+#[derive(Debug, Clone, Default)]
+pub struct RecentIdentifiers {
+    // (name, edits since last use)
+    entries: VecDeque<(String, u32)>,
+}
+
+impl RecentIdentifiers {
+    /// Mark `name` as the most recently used identifier
+    pub fn touch(&mut self, name: &str) {
+        self.entries.retain(|(entry, _)| entry != name);
+        self.entries.push_front((name.to_string(), 0));
+        self.entries.truncate(MAX_RECENT_IDENTIFIERS);
+    }
+
+    /// Age every entry by one edit, dropping those unused for too long
+    pub fn decay(&mut self) {
+        for (_, age) in &mut self.entries {
+            *age += 1;
+        }
+        self.entries.retain(|(_, age)| *age <= RECENT_IDENTIFIER_EDITS);
+    }
+
+    /// Position of `name` in recency order, 0 being the most recent
+    pub fn rank(&self, name: &str) -> Option<usize> {
+        self.entries.iter().position(|(entry, _)| entry == name)
+    }
+}
+
+/// Rank recently used variables first, by recency. This is a secondary key:
+/// a group prefix already in `sort_text` (like the `name[` ranking) still wins.
+pub fn rank_recent_variables(items: &mut [CompletionItem], recent: &RecentIdentifiers) {
+    let is_recent = |item: &CompletionItem| {
+        item.kind == Some(CompletionItemKind::VARIABLE) && recent.rank(&item.label).is_some()
+    };
+    if !items.iter().any(is_recent) {
+        return;
+    }
+    for item in items.iter_mut() {
+        let key = match recent.rank(&item.label) {
+            Some(rank) if item.kind == Some(CompletionItemKind::VARIABLE) => format!("0{:02}", rank),
+            _ => "1".to_string(),
+        };
+        let sort_text = item.sort_text.take().unwrap_or_else(|| item.label.clone());
+        let group = sort_text.strip_suffix(item.label.as_str()).unwrap_or("");
+        item.sort_text = Some(format!("{}{}{}", group, key, item.label));
+    }
+}
+
+// Keep the first `limit` items, ranked-first ones ahead of the rest
+fn cut_to_ranked(items: &mut Vec<CompletionItem>, limit: usize) {
+    items.sort_by_key(|item| item.sort_text.as_deref().is_some_and(|text| text.starts_with('1')));
+    items.truncate(limit);
+}
+
+// A completion list holding every candidate
+fn complete_list(items: Vec<CompletionItem>) -> CompletionList {
+    CompletionList { is_incomplete: false, items }
+}
+
+/// Ranges of the identifier around the cursor, in byte columns: the insert
+/// range ends at the cursor, the replace range covers the whole identifier.
+/// Both are empty at the cursor when it is not touching an identifier.
+pub fn identifier_ranges(text: &str, position: Position) -> (Range, Range) {
+    let line = text.lines().nth(position.line as usize).unwrap_or("");
+    let bytes = line.as_bytes();
+    let column = (position.character as usize).min(bytes.len());
+    let is_ident = |b: u8| b.is_ascii_alphanumeric() || b == b'_';
+
+    let mut start = column;
+    while start > 0 && is_ident(bytes[start - 1]) {
+        start -= 1;
+    }
+    let mut end = column;
+    while end < bytes.len() && is_ident(bytes[end]) {
+        end += 1;
+    }
+
+    let at = |character: usize| Position { line: position.line, character: clamp_to_u32(character) };
+    (
+        Range { start: at(start), end: at(column) },
+        Range { start: at(start), end: at(end) },
+    )
+}
+
+// Function attributes the compiler recognizes, with a short description
+const KNOWN_ATTRIBUTES: &[(&str, &str)] = &[
+    ("inline", "Hint that calls to the function should be inlined"),
+    ("noinline", "Never inline calls to the function"),
+    ("test", "Mark the function as a test"),
+    ("deprecated", "Warn when the function is called"),
+    ("export", "Export the function from the compiled module"),
+];
+
+/// Attribute names when the cursor follows an `@` that starts a line,
+/// optionally with part of the name typed. Attributes already used in the
+/// document are offered after the known ones. `None` elsewhere.
+pub fn attribute_completions(text: &str, position: Position) -> Option<Vec<CompletionItem>> {
+    let line = text.lines().nth(position.line as usize).unwrap_or("");
+    let before = line.get(..position.character as usize)?;
+    let typed = before.trim_start().strip_prefix('@')?;
+    if !typed.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return None;
+    }
+
+    let mut seen = HashSet::new();
+    let mut items = Vec::new();
+    for (name, description) in KNOWN_ATTRIBUTES {
+        seen.insert(name.to_string());
+        items.push(CompletionItem {
+            label: name.to_string(),
+            kind: Some(CompletionItemKind::PROPERTY),
+            detail: Some(format!("@{}", name)),
+            documentation: Some(Documentation::String(description.to_string())),
+            ..Default::default()
+        });
+    }
+    let tokens = tokenize(text);
+    for pair in tokens.windows(2) {
+        let (at, name) = (&pair[0], &pair[1]);
+        let used = at.is_punct("@") && name.kind == TokenKind::Ident && name.start == at.end;
+        if used && name.line != position.line as usize && seen.insert(name.text.clone()) {
+            items.push(CompletionItem {
+                label: name.text.clone(),
+                kind: Some(CompletionItemKind::PROPERTY),
+                detail: Some(format!("@{}", name.text)),
+                ..Default::default()
+            });
+        }
+    }
+    Some(items)
+}
+
+/// Members after `receiver.`, optionally with part of the name typed. On a
+/// class name (`Point.`) these are its associated functions - methods
+/// returning the class, like constructors - and constant fields. On an
+/// instance (`p.`) they are its fields and all of its methods. `None` when
+/// the cursor is not after a `.` or the receiver's class is unknown.
+pub fn member_completions(program: &Program, text: &str, position: Position) -> Option<Vec<CompletionItem>> {
+    let index = DocumentIndex::new(program, text);
+    let line = position.line as usize;
+    let column = position.character as usize;
+    let last = index
+        .tokens
+        .iter()
+        .rposition(|token| token.line == line && token.end <= column)?;
+    let dot = if index.tokens[last].kind == TokenKind::Ident && index.tokens[last].end == column {
+        last.checked_sub(1)?
+    } else {
+        last
+    };
+    if !index.tokens[dot].is_punct(".") || dot == 0 || index.tokens[dot - 1].line != line {
+        return None;
+    }
+    let receiver = dot - 1;
+
+    let enclosing = index.enclosing_function(line);
+    let func = enclosing.map(|(func, _)| func);
+    let class = enclosing.and_then(|(_, class)| class);
+    let target = index.receiver_class(receiver, func, class)?;
+    let chained = receiver > 0 && index.tokens[receiver - 1].is_punct(".");
+    let on_type = !chained && index.tokens[receiver].text == target.name;
+
+    // An instance built in this function with `let p = Class()` is being
+    // initialized: its fields come first, those not yet assigned at the top
+    let assigned = func
+        .filter(|_| !chained && !on_type)
+        .and_then(|func| constructed_fields(&index, func, receiver, target));
+
+    let associated = |method: &Function| method.return_type.as_ref().is_some_and(|ty| format_type(ty) == target.name);
+    let mut items = Vec::new();
+    for field in index.class_fields(target) {
+        if on_type && !field.constant {
+            continue;
+        }
+        let mut detail = field.ty.as_ref().map(|ty| format!("{}: {}", field.name, format_type(ty)));
+        let mut sort_text = None;
+        if let Some(assigned) = &assigned {
+            let pending = !assigned.contains(&field.name);
+            if pending {
+                detail = Some(format!("{} (not yet assigned)", detail.unwrap_or_else(|| field.name.clone())));
+            }
+            sort_text = Some(format!("{}{}", if pending { "0" } else { "1" }, field.name));
+        }
+        items.push(CompletionItem {
+            label: field.name.clone(),
+            kind: Some(if on_type { CompletionItemKind::CONSTANT } else { CompletionItemKind::FIELD }),
+            detail,
+            sort_text,
+            ..Default::default()
+        });
+    }
+    for method in target.methods.iter().filter(|method| !on_type || associated(method)) {
+        let detail = format_function_signature(method);
+        items.push(CompletionItem {
+            label: method.name.clone(),
+            kind: Some(if on_type { CompletionItemKind::FUNCTION } else { CompletionItemKind::METHOD }),
+            documentation: method.doc.clone().map(Documentation::String),
+            detail: Some(detail),
+            sort_text: assigned.as_ref().map(|_| format!("2{}", method.name)),
+            ..Default::default()
+        });
+    }
+    Some(items)
+}
+
+// Fields already assigned with `p.field = ...` when the receiver token is a
+// local declared as `let p = Class()` in `func` - `None` for other receivers
+fn constructed_fields(index: &DocumentIndex, func: &Function, receiver: usize, class: &Class) -> Option<HashSet<String>> {
+    let tokens = &index.tokens;
+    let name = &tokens[receiver].text;
+    let declaration = index.local_declaration(func, name, tokens[receiver].line)?;
+    let initializer = tokens.get(declaration + 1..declaration + 5)?;
+    let constructs = initializer[0].is_punct("=")
+        && initializer[1].is_ident(&class.name)
+        && initializer[2].is_punct("(")
+        && initializer[3].is_punct(")");
+    if !constructs {
+        return None;
+    }
+
+    let assigned = tokens
+        .get(declaration..receiver)?
+        .windows(4)
+        .filter(|window| {
+            window[0].is_ident(name) && window[1].is_punct(".") && window[2].kind == TokenKind::Ident && window[3].is_punct("=")
+        })
+        .map(|window| window[2].text.clone())
+        .collect();
+    Some(assigned)
+}
+
+/// Keys of a map literal when the cursor is inside a string index `m["...`
+/// and `m` was initialized with `{"key": ...}` earlier in the same function.
+/// `None` elsewhere, and when the map is built dynamically: keys that are not
+/// string literals, or entries assigned after the declaration.
+pub fn map_key_completions(text: &str, position: Position) -> Option<Vec<CompletionItem>> {
+    let tokens = tokenize(text);
+    let line = position.line as usize;
+    let column = position.character as usize;
+
+    let string = tokens.iter().position(|t| {
+        let quote = t.text.chars().next();
+        let closed = t.text.len() > 1 && t.text.chars().last() == quote;
+        t.line == line && t.kind == TokenKind::String && t.start < column && (column < t.end || !closed)
+    })?;
+    let open = string.checked_sub(1).filter(|&i| tokens[i].is_punct("[") && tokens[i].line == line)?;
+    let map = open.checked_sub(1).filter(|&i| tokens[i].kind == TokenKind::Ident && tokens[i].line == line)?;
+    let name = tokens[map].text.as_str();
+    let typed = tokens[string].text.get(1..column - tokens[string].start).unwrap_or("");
+
+    // The function holding the cursor runs from its `fn` to the next one
+    let header = tokens[..map].iter().rposition(|t| t.is_keyword("fn"))?;
+    let end = tokens[map..].iter().position(|t| t.is_keyword("fn")).map_or(tokens.len(), |offset| map + offset);
+    let declaration = (header + 1..map).rev().find(|&i| {
+        tokens[i].is_ident(name)
+            && tokens[i - 1].kind == TokenKind::Keyword
+            && matches!(tokens[i - 1].text.as_str(), "let" | "var")
+    })?;
+    if !(tokens.get(declaration + 1)?.is_punct("=") && tokens.get(declaration + 2)?.is_punct("{")) {
+        return None;
+    }
+
+    let mut keys = Vec::new();
+    let mut depth = 0;
+    let mut expect_key = true;
+    let mut literal_end = None;
+    for (i, token) in tokens.iter().enumerate().skip(declaration + 2) {
+        if ["{", "[", "("].iter().any(|p| token.is_punct(p)) {
+            depth += 1;
+        } else if ["}", "]", ")"].iter().any(|p| token.is_punct(p)) {
+            depth -= 1;
+            if depth == 0 {
+                literal_end = Some(i);
+                break;
+            }
+        } else if depth == 1 && token.is_punct(",") {
+            expect_key = true;
+        } else if depth == 1 && expect_key {
+            if token.kind != TokenKind::String {
+                return None;
+            }
+            // Unterminated keys are still being typed - they are not offered
+            let quote = token.text.get(..1).unwrap_or("");
+            if let Some(key) = token.text.strip_prefix(quote).and_then(|rest| rest.strip_suffix(quote)) {
+                keys.push(key.to_string());
+            }
+            expect_key = false;
+        }
+    }
+    let literal_end = literal_end?;
+
+    // `m[key] = ...` or `m = ...` after the literal adds keys it cannot show
+    let rebuilt = (literal_end + 1..end).any(|i| {
+        if !tokens[i].is_ident(name) || tokens[i - 1].is_punct(".") {
+            return false;
+        }
+        let next = tokens.get(i + 1);
+        if next.is_some_and(|t| t.is_punct("=")) {
+            return true;
+        }
+        if !next.is_some_and(|t| t.is_punct("[")) {
+            return false;
+        }
+        let close = tokens[i + 1..end].iter().position(|t| t.is_punct("]") && t.line == tokens[i].line);
+        close.is_some_and(|offset| tokens.get(i + 2 + offset).is_some_and(|t| t.is_punct("=")))
+    });
+    if rebuilt {
+        return None;
+    }
+
+    Some(
+        keys.into_iter()
+            .filter(|key| key.starts_with(typed))
+            .map(|key| CompletionItem {
+                label: key,
+                kind: Some(CompletionItemKind::VALUE),
+                detail: Some(format!("key of {}", name)),
+                ..Default::default()
+            })
+            .collect(),
+    )
+}
+
+// Builtin types offered in annotations, with the generic containers
+const BUILTIN_TYPES: &[&str] = &[
+    "int", "str", "float32", "float64", "bool", "dynamic", "list", "array", "map", "Tensor",
+];
+
+/// Type candidates when the cursor is in a type annotation: after `let x:`,
+/// `var x:`, a parameter's `name:` in a `fn` header, or a return type `->`.
+/// `None` outside annotations. Classes come from the document's `class` headers
+/// so this works while the annotation is still incomplete.
+pub fn type_annotation_completions(text: &str, position: Position) -> Option<Vec<CompletionItem>> {
+    let tokens = tokenize(text);
+    let line = position.line as usize;
+    let column = position.character as usize;
+    let before: Vec<&Token> = tokens
+        .iter()
+        .filter(|t| t.line == line && t.end <= column && !matches!(t.kind, TokenKind::Comment))
+        .collect();
+
+    // Allow a partially typed type name: `let x: P`
+    let mut last = before.len().checked_sub(1)?;
+    if before[last].kind == TokenKind::Ident && before[last].end == column {
+        last = last.checked_sub(1)?;
+    }
+    let in_annotation = if before[last].is_punct("->") {
+        true
+    } else if before[last].is_punct(":") && last >= 2 && before[last - 1].kind == TokenKind::Ident {
+        let introducer = before[last - 2];
+        introducer.is_keyword("let")
+            || introducer.is_keyword("var")
+            || ((introducer.is_punct("(") || introducer.is_punct(",")) && before[0].is_keyword("fn"))
+    } else {
+        false
+    };
+    if !in_annotation {
+        return None;
+    }
+
+    let mut items: Vec<CompletionItem> = BUILTIN_TYPES
+        .iter()
+        .map(|name| CompletionItem {
+            label: name.to_string(),
+            kind: Some(CompletionItemKind::TYPE_PARAMETER),
+            detail: Some("builtin type".to_string()),
+            ..Default::default()
+        })
+        .collect();
+    let mut classes = HashSet::new();
+    for pair in tokens.windows(2) {
+        if pair[0].is_keyword("class") && pair[1].kind == TokenKind::Ident && classes.insert(pair[1].text.clone()) {
+            items.push(CompletionItem {
+                label: pair[1].text.clone(),
+                kind: Some(CompletionItemKind::CLASS),
+                detail: Some(format!("class {}", pair[1].text)),
+                ..Default::default()
+            });
+        }
+    }
+    Some(items)
+}
+
+/// Documentation for a function completion: the signature in a fenced
+/// ```pain block followed by the doc prose when the client renders Markdown,
+/// otherwise just the plain doc
+pub fn completion_documentation(signature: &str, doc: Option<&str>, markdown: bool) -> Option<Documentation> {
+    if !markdown {
+        return doc.map(|doc| Documentation::String(doc.to_string()));
+    }
+    let mut value = format!("```pain\n{}\n```", signature);
+    if let Some(doc) = doc {
+        value.push_str("\n\n");
+        value.push_str(&format_doc_markdown(doc));
+    }
+    Some(Documentation::MarkupContent(MarkupContent {
+        kind: MarkupKind::Markdown,
+        value,
+    }))
+}
+
+/// A doc comment split along the `Args:`/`Returns:` convention
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ParsedDoc {
+    /// Text before the first section
+    pub summary: String,
+    /// `name: description` entries of the `Args:` section, in order
+    pub args: Vec<(String, String)>,
+    /// Text of the `Returns:` section
+    pub returns: Option<String>,
+}
+
+/// Split a doc comment into its summary, `Args:` entries and `Returns:` text.
+/// Argument descriptions may continue on following lines. `None` when the
+/// doc has neither section.
+pub fn parse_doc_comment(doc: &str) -> Option<ParsedDoc> {
+    enum Section {
+        Summary,
+        Args,
+        Returns,
+    }
+    let mut parsed = ParsedDoc::default();
+    let mut summary = Vec::new();
+    let mut returns = Vec::new();
+    let mut section = Section::Summary;
+    let mut has_sections = false;
+
+    for line in doc.lines().map(str::trim) {
+        match line {
+            "Args:" => {
+                section = Section::Args;
+                has_sections = true;
+                continue;
+            }
+            "Returns:" => {
+                section = Section::Returns;
+                has_sections = true;
+                continue;
+            }
+            _ => {}
+        }
+        match section {
+            Section::Summary => summary.push(line),
+            Section::Args if line.is_empty() => {}
+            Section::Args => match line.split_once(':') {
+                Some((name, description)) if is_identifier(name.trim()) => {
+                    parsed.args.push((name.trim().to_string(), description.trim().to_string()));
+                }
+                // Continuation of the previous argument's description
+                _ => {
+                    if let Some((_, description)) = parsed.args.last_mut() {
+                        description.push(' ');
+                        description.push_str(line);
+                    }
+                }
+            },
+            Section::Returns if line.is_empty() => {}
+            Section::Returns => returns.push(line),
+        }
+    }
+    if !has_sections {
+        return None;
+    }
+    parsed.summary = summary.join("\n").trim().to_string();
+    parsed.returns = (!returns.is_empty()).then(|| returns.join(" "));
+    Some(parsed)
+}
+
+/// Markdown for a doc comment: the summary, a bullet per argument and the
+/// return description. Docs not following the `Args:`/`Returns:` convention
+/// are shown verbatim.
+pub fn format_doc_markdown(doc: &str) -> String {
+    let Some(parsed) = parse_doc_comment(doc) else { return doc.to_string() };
+    let mut sections = Vec::new();
+    if !parsed.summary.is_empty() {
+        sections.push(parsed.summary);
+    }
+    if !parsed.args.is_empty() {
+        let args: Vec<String> = parsed
+            .args
+            .iter()
+            .map(|(name, description)| format!("- `{}`: {}", name, description))
+            .collect();
+        sections.push(format!("**Parameters**\n{}", args.join("\n")));
+    }
+    if let Some(returns) = parsed.returns {
+        sections.push(format!("**Returns**: {}", returns));
+    }
+    sections.join("\n\n")
+}
+
+/// Type expected inside `collection[` at the cursor: `int` for lists and
+/// arrays, the key type for maps. `None` outside an index expression.
+pub fn bracket_index_type(program: &Program, text: &str, position: Position) -> Option<Type> {
+    let index = DocumentIndex::new(program, text);
+    let line = position.line as usize;
+    let column = position.character as usize;
+    let before: Vec<&Token> = index
+        .tokens
+        .iter()
+        .filter(|t| t.line == line && t.end <= column && !matches!(t.kind, TokenKind::Comment))
+        .collect();
+
+    // Allow a partially typed index: `numbers[` or `numbers[i`
+    let mut open = before.len().checked_sub(1)?;
+    if before[open].kind == TokenKind::Ident && before[open].end == column {
+        open = open.checked_sub(1)?;
+    }
+    if !before[open].is_punct("[") {
+        return None;
+    }
+    let collection = before.get(open.checked_sub(1)?)?;
+    if collection.kind != TokenKind::Ident {
+        return None;
+    }
+
+    let (func, class) = index.enclosing_function(line)?;
+    match index.variable_type(func, class, &collection.text, line)? {
+        Type::List(_) | Type::Array(_) => Some(Type::Int),
+        Type::Map(key, _) => Some(*key),
+        _ => None,
+    }
+}
+
+// Name declared by `let`/`var`/`for` earlier on the cursor's line, unless an
+// earlier declaration or a parameter of the same name is already in scope
+// (`let total = total + 1` may use the old `total`)
+fn name_being_declared(index: &DocumentIndex, line: usize, column: usize) -> Option<String> {
+    let name = index
+        .tokens
+        .windows(2)
+        .find(|pair| {
+            pair[0].line == line
+                && matches!(pair[0].text.as_str(), "let" | "var" | "for")
+                && pair[0].kind == TokenKind::Keyword
+                && pair[1].kind == TokenKind::Ident
+                && pair[1].end <= column
+        })
+        .map(|pair| pair[1].text.clone())?;
+
+    if let Some((func, _)) = index.enclosing_function(line) {
+        let header_line = func.span.start.line.saturating_sub(1);
+        let mut earlier = declaration_sites(&index.tokens, header_line, line.saturating_sub(1))
+            .into_iter()
+            .chain(parameter_sites(&index.tokens, header_line, &func.name));
+        if line > header_line && earlier.any(|(earlier, _)| earlier == name) {
+            return None;
+        }
+    }
+    Some(name)
+}
+
+/// Type the expression at the cursor should have, from the syntax around it:
+/// `bool` in `if`/`while` conditions and after `and`/`or`/`not`, the active
+/// parameter's type inside a call's arguments, the declared type after
+/// `let x: T =`, and the return type after `return`. `position` uses byte
+/// columns.
+pub fn expected_type_at(program: &Program, text: &str, position: Position) -> Option<Type> {
+    let index = DocumentIndex::new(program, text);
+    let line = position.line as usize;
+    let column = position.character as usize;
+    let before: Vec<&Token> = index
+        .tokens
+        .iter()
+        .filter(|t| t.line == line && t.end <= column && !matches!(t.kind, TokenKind::Comment))
+        .collect();
+
+    // Allow a partially typed expression: `while fl`
+    let mut last = before.len().checked_sub(1)?;
+    if before[last].kind == TokenKind::Ident && before[last].end == column {
+        last = last.checked_sub(1)?;
+    }
+    let previous = before[last];
+    if ["and", "or", "not"].iter().any(|op| previous.is_keyword(op)) {
+        return Some(Type::Bool);
+    }
+    if let Some(ty) = call_argument_type(&index, line, column) {
+        return Some(ty);
+    }
+    // Conditions run until the `:` closing the header
+    let first = before[0];
+    if (first.is_keyword("if") || first.is_keyword("while")) && !before[1..=last].iter().any(|t| t.is_punct(":")) {
+        return Some(Type::Bool);
+    }
+    if first.is_keyword("else") && before.get(1).is_some_and(|t| t.is_keyword("if")) && !before[..=last].iter().any(|t| t.is_punct(":")) {
+        return Some(Type::Bool);
+    }
+
+    let (func, class) = index.enclosing_function(line)?;
+    if previous.is_keyword("return") && last == 0 {
+        return func.return_type.clone();
+    }
+    // `let name: T = ` - only annotated declarations, the initializer is still being typed
+    let annotated = last >= 4
+        && previous.is_punct("=")
+        && (first.is_keyword("let") || first.is_keyword("var"))
+        && before[1].kind == TokenKind::Ident
+        && before[2].is_punct(":");
+    if annotated && last == before.iter().position(|t| t.is_punct("="))? {
+        return index.variable_type(func, class, &before[1].text, line);
+    }
+    None
+}
+
+// Type of the argument being typed in `callee(a, |`: the parameter at the
+// active position of the program function, method or stdlib function called
+fn call_argument_type(index: &DocumentIndex, line: usize, column: usize) -> Option<Type> {
+    let tokens = &index.tokens;
+    let first = tokens.iter().position(|t| t.line == line)?;
+    let end = tokens.iter().rposition(|t| t.line == line && t.end <= column)?;
+
+    // Walk back to the innermost unclosed `(`, counting the commas before the cursor
+    let mut depth = 0;
+    let mut commas = 0;
+    let mut open = None;
+    for i in (first..=end).rev() {
+        let token = &tokens[i];
+        if [")", "]", "}"].iter().any(|p| token.is_punct(p)) {
+            depth += 1;
+        } else if ["(", "[", "{"].iter().any(|p| token.is_punct(p)) {
+            if depth == 0 {
+                open = Some(i).filter(|_| token.is_punct("("));
+                break;
+            }
+            depth -= 1;
+        } else if depth == 0 && token.is_punct(",") {
+            commas += 1;
+        }
+    }
+    // A `fn` header's parameter list is not a call
+    if tokens[first].is_keyword("fn") {
+        return None;
+    }
+    let callee = open?.checked_sub(1).filter(|&i| i >= first && tokens[i].kind == TokenKind::Ident)?;
+    callee_parameters(index, callee)?.into_iter().nth(commas).map(|(_, ty)| ty)
+}
+
+// Parameters of the function called by the identifier token at `callee` -
+// a method when it follows `receiver.`, else a program or stdlib function
+fn callee_parameters(index: &DocumentIndex, callee: usize) -> Option<Vec<(String, Type)>> {
+    let tokens = &index.tokens;
+    let name = &tokens[callee].text;
+
+    if callee >= 2 && tokens[callee - 1].is_punct(".") {
+        let enclosing = index.enclosing_function(tokens[callee].line);
+        let func = enclosing.map(|(func, _)| func);
+        let class = enclosing.and_then(|(_, class)| class);
+        let receiver = index.receiver_class(callee - 2, func, class)?;
+        let method = receiver.methods.iter().find(|method| method.name == *name)?;
+        return Some(method.params.iter().map(|param| (param.name.clone(), param.ty.clone())).collect());
+    }
+    if let Some(func) = index.find_function(name) {
+        return Some(func.params.iter().map(|param| (param.name.clone(), param.ty.clone())).collect());
+    }
+    let stdlib_func = get_stdlib_functions().into_iter().find(|func| func.name == *name)?;
+    Some(stdlib_func.params)
+}
+
+// The arguments of the call whose name is `tokens[callee]`, split at
+// top-level commas, and the index of its closing paren. `f()` has one empty
+// argument; an unclosed call stops at the last complete argument.
+fn call_arguments(tokens: &[Token], callee: usize) -> (Vec<&[Token]>, Option<usize>) {
+    let mut arguments: Vec<&[Token]> = Vec::new();
+    let mut depth = 0;
+    let mut argument_start = callee + 2;
+    for i in callee + 2..tokens.len() {
+        let t = &tokens[i];
+        if ["(", "[", "{"].iter().any(|p| t.is_punct(p)) {
+            depth += 1;
+        } else if [")", "]", "}"].iter().any(|p| t.is_punct(p)) {
+            if depth == 0 {
+                arguments.push(&tokens[argument_start..i]);
+                return (arguments, Some(i));
+            }
+            depth -= 1;
+        } else if depth == 0 && t.is_punct(",") {
+            arguments.push(&tokens[argument_start..i]);
+            argument_start = i + 1;
+        }
+    }
+    (arguments, None)
+}
+
+/// `name:` hints before each argument of the calls that start inside
+/// `range`. An argument that is just a variable named like its parameter
+/// gets no hint. Positions use byte columns.
+pub fn parameter_inlay_hints(program: &Program, text: &str, range: Range) -> Vec<InlayHint> {
+    let index = DocumentIndex::new(program, text);
+    let tokens = &index.tokens;
+    let mut hints = Vec::new();
+
+    for (callee, token) in tokens.iter().enumerate() {
+        let Some(open) = tokens.get(callee + 1) else { break };
+        let start = token_range(token).start;
+        if token.kind != TokenKind::Ident || !open.is_punct("(") || start < range.start || start > range.end {
+            continue;
+        }
+        // A `fn` header's parameter list is not a call
+        if callee > 0 && tokens[callee - 1].is_keyword("fn") {
+            continue;
+        }
+        let Some(params) = callee_parameters(&index, callee) else { continue };
+        let (arguments, _) = call_arguments(tokens, callee);
+
+        for (argument, (name, _)) in arguments.iter().zip(&params) {
+            let Some(first) = argument.first() else { continue };
+            if argument.len() == 1 && first.is_ident(name) {
+                continue;
+            }
+            hints.push(InlayHint {
+                position: token_range(first).start,
+                label: InlayHintLabel::String(format!("{}:", name)),
+                kind: Some(InlayHintKind::PARAMETER),
+                text_edits: None,
+                tooltip: None,
+                padding_left: None,
+                padding_right: Some(true),
+                data: None,
+            });
+        }
+    }
+    hints
+}
+
+/// Hover for a local variable or parameter: `name: type`, plus `= value`
+/// when the initializer folds to a constant
+pub fn variable_hover(program: &Program, text: &str, position: Position) -> Option<Hover> {
+    let index = DocumentIndex::new(program, text);
+    let symbol = index.resolve_symbol(position)?;
+    if !symbol.local {
+        return None;
+    }
+    let token = &index.tokens[index.token_index_at(position)?];
+    let (func, class) = index.enclosing_function(token.line)?;
+
+    let mut signature = match index.variable_type(func, class, &token.text, token.line) {
+        Some(ty) => format!("{}: {}", token.text, format_type(&ty)),
+        None => token.text.clone(),
+    };
+    if let Some(value) = index.constant_value(func, &token.text, token.line) {
+        signature.push_str(&format!(" = {}", value));
+    }
+
+    Some(Hover {
+        contents: HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: format!("```pain\n{}\n```", signature),
+        }),
+        range: Some(token_range(token)),
+    })
+}
+
+/// Hover for a `[...]` or `{...}` literal with the cursor on its opening
+/// bracket: `list[T]` or `map[K, V]`. Elements of different types give
+/// `dynamic`, as does an empty literal.
+pub fn collection_literal_hover(program: &Program, text: &str, position: Position) -> Option<Hover> {
+    let index = DocumentIndex::new(program, text);
+    let open = index.token_index_at(position)?;
+    let token = &index.tokens[open];
+    if !(token.is_punct("[") || token.is_punct("{")) {
+        return None;
+    }
+    // `items[0]` indexes rather than builds a list
+    let indexing = open > 0 && {
+        let prev = &index.tokens[open - 1];
+        prev.line == token.line
+            && (matches!(prev.kind, TokenKind::Ident | TokenKind::String) || prev.is_punct(")") || prev.is_punct("]"))
+    };
+    if indexing {
+        return None;
+    }
+    let (func, class) = index.enclosing_function(token.line)?;
+
+    // Start of each element (and of each value, for maps) at the literal's own depth
+    let mut elements = Vec::new();
+    let mut values = Vec::new();
+    let mut depth = 0;
+    let mut close = None;
+    for (i, t) in index.tokens.iter().enumerate().skip(open) {
+        if t.line != token.line {
+            break;
+        }
+        if t.kind == TokenKind::Punct && matches!(t.text.as_str(), "(" | "[" | "{") {
+            depth += 1;
+            if depth == 1 {
+                elements.push(i + 1);
+            }
+            continue;
+        }
+        if t.kind == TokenKind::Punct && matches!(t.text.as_str(), ")" | "]" | "}") {
+            depth -= 1;
+            if depth == 0 {
+                close = Some(i);
+                break;
+            }
+            continue;
+        }
+        if depth == 1 && t.is_punct(",") {
+            elements.push(i + 1);
+        } else if depth == 1 && t.is_punct(":") {
+            values.push(i + 1);
+        }
+    }
+    let close = close?;
+    elements.retain(|&start| start < close);
+
+    let infer = |starts: &[usize]| -> Type {
+        let types: Vec<Option<Type>> = starts
+            .iter()
+            .map(|&start| index.infer_expression_type(start, func, class, token.line))
+            .collect();
+        match types.first() {
+            Some(Some(first)) if types.iter().all(|ty| ty.as_ref().map(format_type) == Some(format_type(first))) => {
+                first.clone()
+            }
+            _ => Type::Dynamic,
+        }
+    };
+    let ty = if token.is_punct("[") {
+        Type::List(Box::new(infer(&elements)))
+    } else {
+        Type::Map(Box::new(infer(&elements)), Box::new(infer(&values)))
+    };
+
+    Some(Hover {
+        contents: HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: format!("```pain\n{}\n```", format_type(&ty)),
+        }),
+        range: Some(Range {
+            start: token_range(token).start,
+            end: token_range(&index.tokens[close]).end,
+        }),
+    })
+}
+
+/// Hover info for a call to a stdlib function under the cursor. Program
+/// functions with the same name shadow the stdlib one.
+pub fn find_stdlib_function_at_position(program: &Program, text: &str, position: Position) -> Option<HoverInfo> {
+    let tokens = tokenize(text);
+    let token = token_at(&tokens, position.line as usize, position.character as usize)?;
+    if token.kind != TokenKind::Ident {
+        return None;
+    }
+    // `obj.len` is a member, not the stdlib function
+    let idx = tokens.iter().position(|t| std::ptr::eq(t, token))?;
+    if idx > 0 && tokens[idx - 1].is_punct(".") && tokens[idx - 1].line == token.line {
+        return None;
+    }
+    let shadowed = program
+        .items
+        .iter()
+        .any(|item| matches!(item, Item::Function(func) if func.name == token.text));
+    if shadowed {
+        return None;
+    }
+
+    let stdlib_func = get_stdlib_functions().into_iter().find(|func| func.name == token.text)?;
+    Some(HoverInfo {
+        signature: format_stdlib_signature(&stdlib_func.name, &stdlib_func.params, &stdlib_func.return_type),
+        doc: Some(stdlib_func.description.clone()),
+    })
+}
+
+// Format a stdlib function signature for completion details and hover
+pub fn format_stdlib_signature(name: &str, params: &[(String, Type)], return_type: &Type) -> String {
+    let params_str: Vec<String> = params
+        .iter()
+        .map(|(name, ty)| format!("{}: {}", name, format_type(ty)))
+        .collect();
+    format!("{}({}) -> {}", name, params_str.join(", "), format_type(return_type))
+}
+
+/// Hover info for the function or method named under the cursor: in its
+/// `fn` header, in a call, or as `receiver.method`
+pub fn function_hover(program: &Program, text: &str, position: Position) -> Option<HoverInfo> {
+    let index = DocumentIndex::new(program, text);
+    let node = index.node_at(position)?;
+    let func = match node.kind {
+        // Not inside the body - only the name in the header
+        NodeKind::Function if index.token_index_at(position).is_some_and(|i| index.tokens[i].is_ident(&node.name)) => {
+            node.function.filter(|func| func.name == node.name)?
+        }
+        NodeKind::Call | NodeKind::Identifier => index.find_function(&node.name)?,
+        NodeKind::Member => {
+            let i = index.token_index_at(position)?;
+            let receiver = index.receiver_class(i.checked_sub(2)?, node.function, node.class)?;
+            receiver.methods.iter().find(|method| method.name == node.name)?
+        }
+        _ => return None,
+    };
+    Some(HoverInfo {
+        signature: format_function_signature(func),
+        doc: func.doc.clone(),
+    })
+}
+
+/// Hover info by line alone: the function declared on or just after the
+/// 1-based line. Prefer `function_hover` when the document text is at hand.
+pub fn find_function_at_position(program: &Program, line: usize, _column: usize) -> Option<HoverInfo> {
+    for item in &program.items {
+        let Item::Function(func) = item else { continue };
+        // Simple heuristic: check if position is on function name line
+        // In a real implementation, we'd use proper span information
+        let func_line = func.span.start.line;
+
+        // Check if we're on the function definition line
+        if line == func_line || line == func_line + 1 {
+            let signature = format_function_signature(func);
+            return Some(HoverInfo {
+                signature,
+                doc: func.doc.clone(),
+            });
+        }
+    }
+    None
+}
+
+// Format function signature for hover display
+pub fn format_function_signature(func: &Function) -> String {
+    // Wrap in catch_unwind to prevent panics from format_type recursion
+    catch_panics(|| {
+        format_function_signature_internal(func)
+    }).unwrap_or_else(|_| {
+        // Fallback to simple signature if formatting panics
+        format!("fn {}()", func.name)
+    })
+}
+
+fn format_function_signature_internal(func: &Function) -> String {
+    let mut sig = String::new();
+
+    // Attributes
+    if !func.attrs.is_empty() {
+        for attr in &func.attrs {
+            sig.push_str(&format!(
+                "@{}{} ",
+                attr.name,
+                if attr.args.is_empty() {
+                    String::new()
+                } else {
+                    format!("({})", attr.args.len())
+                }
+            ));
+        }
+    }
+
+    // Function name and parameters
+    sig.push_str("fn ");
+    sig.push_str(&func.name);
+    sig.push('(');
+
+    let params: Vec<String> = func
+        .params
+        .iter()
+        .map(|p| format!("{}: {}", p.name, format_type(&p.ty)))
+        .collect();
+    sig.push_str(&params.join(", "));
+    sig.push(')');
+
+    // Return type
+    if let Some(ref ret_ty) = func.return_type {
+        sig.push_str(" -> ");
+        sig.push_str(&format_type(ret_ty));
+    }
+
+    sig
+}
+
+// Format type for display with recursion limit to prevent stack overflow
+pub fn format_type(ty: &Type) -> String {
+    format_type_with_depth(ty, 0)
+}
+
+fn format_type_with_depth(ty: &Type, depth: usize) -> String {
+    // Limit recursion depth to prevent stack overflow
+    if depth > 10 {
+        return "...".to_string();
+    }
+
+    match ty {
+        Type::Int => "int".to_string(),
+        Type::Str => "str".to_string(),
+        Type::Float32 => "float32".to_string(),
+        Type::Float64 => "float64".to_string(),
+        Type::Bool => "bool".to_string(),
+        Type::Dynamic => "dynamic".to_string(),
+        Type::List(inner) => format!("list[{}]", format_type_with_depth(inner, depth + 1)),
+        Type::Array(inner) => format!("array[{}]", format_type_with_depth(inner, depth + 1)),
+        Type::Map(k, v) => format!("map[{}, {}]", format_type_with_depth(k, depth + 1), format_type_with_depth(v, depth + 1)),
+        Type::Tensor(inner, dims) => format!("Tensor[{}, {:?}]", format_type_with_depth(inner, depth + 1), dims),
+        Type::Named(name) => name.clone(),
+    }
+}
+
+// Extract variables visible at given position (simplified implementation)
+pub fn extract_variables_in_scope(
+    program: &Program,
+    line: usize,
+    _column: usize,
+) -> Option<HashSet<String>> {
+    let mut variables = HashSet::new();
+
+    // Find function containing this line
+    for item in &program.items {
+        if let Item::Function(func) = item {
+            let func_start = func.span.start.line;
+            let func_end = func.span.end.line;
+
+            // Check if position is within this function
+            if line >= func_start && line <= func_end {
+                // Add function parameters
+                for param in &func.params {
+                    variables.insert(param.name.clone());
+                }
+
+                // Extract variables from function body (simplified - just from let/var statements)
+                extract_variables_from_statements(&func.body, &mut variables);
+
+                return Some(variables);
+            }
+        }
+    }
+
+    None
+}
+
+// Block nesting the statement walkers follow - deeper blocks are skipped
+// rather than risking a stack overflow on pathological input
+const MAX_STATEMENT_DEPTH: usize = 100;
+
+// Extract variable names from statements
+pub fn extract_variables_from_statements(statements: &[Statement], variables: &mut HashSet<String>) {
+    extract_variables_with_depth(statements, variables, 0)
+}
+
+fn extract_variables_with_depth(statements: &[Statement], variables: &mut HashSet<String>, depth: usize) {
+    // Limit recursion depth to prevent stack overflow
+    if depth > MAX_STATEMENT_DEPTH {
+        return;
+    }
+
+    for stmt in statements {
+        match stmt {
+            Statement::Let { name, .. } => {
+                variables.insert(name.clone());
+            }
+            Statement::For { var, body, .. } => {
+                variables.insert(var.clone());
+                extract_variables_with_depth(body, variables, depth + 1);
+            }
+            Statement::If { then, else_, .. } => {
+                extract_variables_with_depth(then, variables, depth + 1);
+                if let Some(else_stmts) = else_ {
+                    extract_variables_with_depth(else_stmts, variables, depth + 1);
+                }
+            }
+            Statement::While { body, .. } => {
+                extract_variables_with_depth(body, variables, depth + 1);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Diagnostic code for assignments to `let` bindings
+pub const IMMUTABLE_ASSIGNMENT_CODE: &str = "immutable-assignment";
+
+/// An assignment to a variable declared with `let`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImmutableAssignment {
+    pub name: String,
+    /// Range of the assigned name
+    pub range: Range,
+    /// Range of the `let` keyword of the declaration
+    pub declaration: Range,
+}
+
+// Find `name = ...` (and `name += ...`) statements whose nearest earlier
+// declaration of `name` in the same function is a `let`
+pub fn find_immutable_assignments(program: &Program, text: &str) -> Vec<ImmutableAssignment> {
+    let tokens = tokenize(text);
+    let mut assignments = Vec::new();
+
+    for item in &program.items {
+        match item {
+            Item::Function(func) => check_function_assignments(func, &tokens, &mut assignments),
+            Item::Class(class) => {
+                for method in &class.methods {
+                    check_function_assignments(method, &tokens, &mut assignments);
+                }
+            }
+        }
+    }
+
+    assignments
+}
+
+fn check_function_assignments(func: &Function, tokens: &[Token], assignments: &mut Vec<ImmutableAssignment>) {
+    let first_line = func.span.start.line.saturating_sub(1);
+    let last_line = func.span.end.line.saturating_sub(1);
+    let in_function = |t: &Token| t.line >= first_line && t.line <= last_line;
+
+    for (i, target) in tokens.iter().enumerate() {
+        if target.kind != TokenKind::Ident || !in_function(target) {
+            continue;
+        }
+        // Only statements starting with the name: `x = 1`, not `f(x = 1)` or `a.x = 1`
+        let starts_line = i == 0 || tokens[i - 1].line != target.line;
+        if !starts_line || !is_assignment_operator(&tokens[i + 1..], target.line) {
+            continue;
+        }
+
+        let declaration = tokens[..i]
+            .windows(2)
+            .filter(|pair| in_function(&pair[0]) && pair[1].is_ident(&target.text))
+            .filter(|pair| matches!(pair[0].text.as_str(), "let" | "var" | "for") && pair[0].kind == TokenKind::Keyword)
+            .last();
+        if let Some([keyword, _]) = declaration {
+            if keyword.text == "let" {
+                assignments.push(ImmutableAssignment {
+                    name: target.text.clone(),
+                    range: token_range(target),
+                    declaration: token_range(keyword),
+                });
+            }
+        }
+    }
+}
+
+// `=` or a compound operator like `+=` at the start of `rest`, on the given line
+fn is_assignment_operator(rest: &[Token], line: usize) -> bool {
+    match rest {
+        [eq, ..] if eq.is_punct("=") && eq.line == line => true,
+        [op, eq, ..] => {
+            matches!(op.text.as_str(), "+" | "-" | "*" | "/" | "%")
+                && op.kind == TokenKind::Punct
+                && eq.is_punct("=")
+                && eq.line == line
+                && eq.start == op.end
+        }
+        _ => false,
+    }
+}
+
+/// A parameter whose name an earlier parameter of the same signature already uses
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicateParameter {
+    pub name: String,
+    /// Range of the repeated name
+    pub range: Range,
+    /// Range of the first parameter with this name
+    pub first: Range,
+}
+
+// Find repeated parameter names in the headers of functions and methods
+pub fn find_duplicate_parameters(program: &Program, text: &str) -> Vec<DuplicateParameter> {
+    let index = DocumentIndex::new(program, text);
+    let functions = program.items.iter().flat_map(|item| match item {
+        Item::Function(func) => std::slice::from_ref(func),
+        Item::Class(class) => class.methods.as_slice(),
+    });
+
+    let mut duplicates = Vec::new();
+    for func in functions {
+        let header_line = func.span.start.line.saturating_sub(1);
+        let mut seen: HashMap<String, Range> = HashMap::new();
+        for (name, range) in parameter_sites(&index.tokens, header_line, &func.name) {
+            match seen.get(&name) {
+                Some(&first) => duplicates.push(DuplicateParameter { name, range, first }),
+                None => {
+                    seen.insert(name, range);
+                }
+            }
+        }
+    }
+    duplicates
+}
+
+/// A reference to a local that only a later `let`, `var` or `for` of the same function declares
+#[derive(Debug, Clone, PartialEq)]
+pub struct UseBeforeDeclaration {
+    pub name: String,
+    /// Range of the reference
+    pub range: Range,
+    /// Range of the name in the later declaration
+    pub declaration: Range,
+}
+
+// Find identifiers used in a function body before the statement that binds
+// them - bindings take effect in order, so a later declaration does not cover
+// earlier lines
+pub fn find_uses_before_declaration(program: &Program, text: &str) -> Vec<UseBeforeDeclaration> {
+    let index = DocumentIndex::new(program, text);
+    let stdlib: HashSet<String> = get_stdlib_functions().into_iter().map(|func| func.name).collect();
+    let functions = program.items.iter().flat_map(|item| match item {
+        Item::Function(func) => std::slice::from_ref(func),
+        Item::Class(class) => class.methods.as_slice(),
+    });
+
+    let mut uses = Vec::new();
+    for func in functions {
+        let first_line = func.span.start.line.saturating_sub(1);
+        let last_line = func.span.end.line.saturating_sub(1);
+        let sites = declaration_sites(&index.tokens, first_line, last_line);
+        let tokens = &index.tokens;
+
+        for (i, token) in tokens.iter().enumerate() {
+            if token.kind != TokenKind::Ident || token.line <= first_line || token.line > last_line {
+                continue;
+            }
+            let after_dot = i > 0 && tokens[i - 1].is_punct(".");
+            let is_declaration = i > 0
+                && tokens[i - 1].kind == TokenKind::Keyword
+                && matches!(tokens[i - 1].text.as_str(), "let" | "var" | "for");
+            if after_dot || is_declaration || token.text == "self" {
+                continue;
+            }
+            if func.params.iter().any(|param| param.name == token.text)
+                || index.find_function(&token.text).is_some()
+                || index.find_class(&token.text).is_some()
+                || stdlib.contains(&token.text)
+            {
+                continue;
+            }
+
+            let range = token_range(token);
+            let mut matching = sites.iter().filter(|(name, _)| *name == token.text);
+            if matching.clone().any(|(_, site)| site.start < range.start) {
+                continue;
+            }
+            if let Some((_, declaration)) = matching.next() {
+                uses.push(UseBeforeDeclaration { name: token.text.clone(), range, declaration: *declaration });
+            }
+        }
+    }
+    uses
+}
+
+/// An assignment whose target is the name of a function rather than a variable
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionNameAssignment {
+    pub name: String,
+    /// Range of the assigned name
+    pub range: Range,
+    /// Name in the `fn` header, for functions of this document - `None` for stdlib functions
+    pub function: Option<Range>,
+}
+
+// Find `name = ...` statements where `name` is a function of the program or
+// the stdlib and no parameter or earlier declaration in the function binds it
+pub fn find_function_name_assignments(program: &Program, text: &str) -> Vec<FunctionNameAssignment> {
+    let index = DocumentIndex::new(program, text);
+    let stdlib: HashSet<String> = get_stdlib_functions().into_iter().map(|func| func.name).collect();
+    let mut assignments = Vec::new();
+
+    let functions = program.items.iter().flat_map(|item| match item {
+        Item::Function(func) => std::slice::from_ref(func).iter(),
+        Item::Class(class) => class.methods.iter(),
+    });
+    for func in functions {
+        let first_line = func.span.start.line.saturating_sub(1);
+        let last_line = func.span.end.line.saturating_sub(1);
+        let in_function = |t: &Token| t.line >= first_line && t.line <= last_line;
+        let tokens = &index.tokens;
+
+        for (i, target) in tokens.iter().enumerate() {
+            if target.kind != TokenKind::Ident || !in_function(target) {
+                continue;
+            }
+            let starts_line = i == 0 || tokens[i - 1].line != target.line;
+            if !starts_line || !is_assignment_operator(&tokens[i + 1..], target.line) {
+                continue;
+            }
+            let declared = func.params.iter().any(|param| param.name == target.text)
+                || tokens[..i].windows(2).any(|pair| {
+                    in_function(&pair[0])
+                        && pair[0].kind == TokenKind::Keyword
+                        && matches!(pair[0].text.as_str(), "let" | "var" | "for")
+                        && pair[1].is_ident(&target.text)
+                });
+            if declared {
+                continue;
+            }
+
+            let function = index.find_function(&target.text);
+            if function.is_none() && !stdlib.contains(&target.text) {
+                continue;
+            }
+            assignments.push(FunctionNameAssignment {
+                name: target.text.clone(),
+                range: token_range(target),
+                function: function.and_then(|func| index.function_name_range(func)),
+            });
+        }
+    }
+
+    assignments
+}
+
+/// Whether the function carries a `@deprecated` attribute
+pub fn is_deprecated(func: &Function) -> bool {
+    func.attrs.iter().any(|attr| attr.name == "deprecated")
+}
+
+/// Outline of the document: functions, and classes with their methods.
+/// `@deprecated` functions are tagged. Ranges use byte columns.
+pub fn document_symbols(program: &Program, text: &str) -> Vec<DocumentSymbol> {
+    let index = DocumentIndex::new(program, text);
+    let outlines = class_outlines(text);
+    let mut classes_seen: HashMap<&str, usize> = HashMap::new();
+
+    #[allow(deprecated)] // DocumentSymbol::deprecated must still be initialized
+    let function_symbol = |func: &Function, kind: SymbolKind| -> Option<DocumentSymbol> {
+        let selection_range = index.function_name_range(func)?;
+        let span = span_to_range(&func.span, text);
+        Some(DocumentSymbol {
+            name: func.name.clone(),
+            detail: Some(format_function_signature(func)),
+            kind,
+            tags: is_deprecated(func).then(|| vec![SymbolTag::DEPRECATED]),
+            deprecated: None,
+            range: Range { start: span.start.min(selection_range.start), end: span.end.max(selection_range.end) },
+            selection_range,
+            children: None,
+        })
+    };
+
+    let mut symbols = Vec::new();
+    for item in &program.items {
+        match item {
+            Item::Function(func) => symbols.extend(function_symbol(func, SymbolKind::FUNCTION)),
+            Item::Class(class) => {
+                // Classes carry no span - match them to headers in order
+                let nth = classes_seen.entry(class.name.as_str()).or_insert(0);
+                let outline = outlines.iter().filter(|outline| outline.name == class.name).nth(*nth);
+                *nth += 1;
+                let Some(outline) = outline else { continue };
+                let methods = class
+                    .methods
+                    .iter()
+                    .filter_map(|method| function_symbol(method, SymbolKind::METHOD))
+                    .collect();
+                #[allow(deprecated)]
+                symbols.push(DocumentSymbol {
+                    name: class.name.clone(),
+                    detail: Some(format!("class {}", class.name)),
+                    kind: SymbolKind::CLASS,
+                    tags: None,
+                    deprecated: None,
+                    range: outline.range,
+                    selection_range: outline.selection_range,
+                    children: Some(methods),
+                });
+            }
         }
     }
+    symbols
+}
 
-    pub fn warning_to_diagnostic(&self, warning: &pain_compiler::Warning, _text: &str) -> Diagnostic {
-        let (message, span) = match warning {
-            pain_compiler::Warning::UnusedVariable { name, span } => {
-                (format!("unused variable `{}`", name), *span)
-            }
-            pain_compiler::Warning::UnusedFunction { name, span } => {
-                (format!("unused function `{}`", name), *span)
-            }
-            pain_compiler::Warning::DeadCode { span, reason } => {
-                (format!("dead code: {}", reason), *span)
-            }
-            pain_compiler::Warning::UnreachableCode { span } => {
-                ("unreachable code".to_string(), *span)
-            }
+/// The method under `position`, in its `fn` header or a `.name(` call, and
+/// the name range of every class method of this document with that name.
+/// Byte columns.
+pub fn method_implementations(program: &Program, text: &str, position: Position) -> Option<(String, Vec<Range>)> {
+    let index = DocumentIndex::new(program, text);
+    let tokens = &index.tokens;
+    let i = index.token_index_at(position)?;
+    let token = &tokens[i];
+    if token.kind != TokenKind::Ident || i == 0 {
+        return None;
+    }
+    let header = tokens[i - 1].is_keyword("fn") && tokens[i - 1].start > 0;
+    let call = tokens[i - 1].is_punct(".") && tokens.get(i + 1).is_some_and(|next| next.is_punct("("));
+    if !header && !call {
+        return None;
+    }
+
+    let ranges = program
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Class(class) => Some(class),
+            Item::Function(_) => None,
+        })
+        .flat_map(|class| class.methods.iter())
+        .filter(|method| method.name == token.text)
+        .filter_map(|method| index.function_name_range(method))
+        .collect();
+    Some((token.text.clone(), ranges))
+}
+
+/// A call to a function marked `@deprecated`
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeprecatedCall {
+    pub name: String,
+    /// Range of the called name
+    pub range: Range,
+    /// Name in the deprecated function's `fn` header
+    pub function: Option<Range>,
+}
+
+// Find calls of `@deprecated` functions and methods of this document
+pub fn find_deprecated_calls(program: &Program, text: &str) -> Vec<DeprecatedCall> {
+    let index = DocumentIndex::new(program, text);
+    let tokens = &index.tokens;
+    let mut calls = Vec::new();
+
+    for (i, token) in tokens.iter().enumerate() {
+        if token.kind != TokenKind::Ident || !tokens.get(i + 1).is_some_and(|next| next.is_punct("(")) {
+            continue;
+        }
+        if i > 0 && tokens[i - 1].is_keyword("fn") {
+            continue;
+        }
+        let callee = if i >= 2 && tokens[i - 1].is_punct(".") {
+            let enclosing = index.enclosing_function(token.line);
+            let func = enclosing.map(|(func, _)| func);
+            let class = enclosing.and_then(|(_, class)| class);
+            index
+                .receiver_class(i - 2, func, class)
+                .and_then(|receiver| receiver.methods.iter().find(|method| method.name == token.text))
+        } else {
+            index.find_function(&token.text)
         };
+        let Some(callee) = callee.filter(|callee| is_deprecated(callee)) else { continue };
+        calls.push(DeprecatedCall {
+            name: token.text.clone(),
+            range: token_range(token),
+            function: index.function_name_range(callee),
+        });
+    }
+    calls
+}
 
-        Diagnostic {
+/// A call of a function or method of this document with the wrong number
+/// of arguments
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArityMismatch {
+    pub name: String,
+    pub expected: usize,
+    pub found: usize,
+    /// From the called name through the closing paren
+    pub range: Range,
+    /// Name in the callee's `fn` header
+    pub function: Option<Range>,
+}
+
+// Find calls whose argument count differs from the callee's parameters.
+// Stdlib functions are left to the type checker.
+pub fn find_arity_mismatches(program: &Program, text: &str) -> Vec<ArityMismatch> {
+    let index = DocumentIndex::new(program, text);
+    let tokens = &index.tokens;
+    let mut mismatches = Vec::new();
+
+    for (i, token) in tokens.iter().enumerate() {
+        if token.kind != TokenKind::Ident || !tokens.get(i + 1).is_some_and(|next| next.is_punct("(")) {
+            continue;
+        }
+        if i > 0 && tokens[i - 1].is_keyword("fn") {
+            continue;
+        }
+        let callee = if i >= 2 && tokens[i - 1].is_punct(".") {
+            let enclosing = index.enclosing_function(token.line);
+            let func = enclosing.map(|(func, _)| func);
+            let class = enclosing.and_then(|(_, class)| class);
+            index
+                .receiver_class(i - 2, func, class)
+                .and_then(|receiver| receiver.methods.iter().find(|method| method.name == token.text))
+        } else {
+            index.find_function(&token.text)
+        };
+        let Some(callee) = callee else { continue };
+        let (arguments, Some(close)) = call_arguments(tokens, i) else { continue };
+        let found = if arguments.len() == 1 && arguments[0].is_empty() { 0 } else { arguments.len() };
+        if found == callee.params.len() {
+            continue;
+        }
+        mismatches.push(ArityMismatch {
+            name: token.text.clone(),
+            expected: callee.params.len(),
+            found,
             range: Range {
-                start: Position {
-                    line: (span.line().saturating_sub(1)) as u32,
-                    character: (span.column().saturating_sub(1)) as u32,
-                },
-                end: Position {
-                    line: (span.line().saturating_sub(1)) as u32,
-                    character: (span.column().saturating_sub(1) + 1) as u32,
-                },
+                start: token_range(token).start,
+                end: token_range(&tokens[close]).end,
             },
-            severity: Some(DiagnosticSeverity::WARNING),
-            code: None,
-            code_description: None,
-            source: Some("pain".to_string()),
-            message,
-            related_information: None,
-            tags: None,
-            data: None,
-        }
+            function: index.function_name_range(callee),
+        });
     }
+    mismatches
 }
 
-// Find function at given line and column position
-pub fn find_function_at_position(program: &Program, line: usize, _column: usize) -> Option<HoverInfo> {
-    for item in &program.items {
-        let Item::Function(func) = item else { continue };
-        // Simple heuristic: check if position is on function name line
-        // In a real implementation, we'd use proper span information
-        let func_line = func.span.start.line;
+/// Whether `name` can be used as a Pain identifier
+pub fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|first| first.is_alphabetic() || first == '_')
+        && chars.all(|ch| ch.is_alphanumeric() || ch == '_')
+        && !is_keyword(name)
+}
 
-        // Check if we're on the function definition line
-        if line == func_line || line == func_line + 1 {
-            let signature = format_function_signature(func);
-            return Some(HoverInfo {
-                signature,
-                doc: func.doc.clone(),
-            });
+/// The document with trailing whitespace stripped, blank-line runs capped at
+/// two, and exactly one newline at the end. Blank lines after a `///` doc
+/// comment are dropped so it stays attached to the item below. Formatting
+/// the result again changes nothing.
+pub fn format_text(text: &str) -> String {
+    let newline = if text.contains("\r\n") { "\r\n" } else { "\n" };
+    let mut lines: Vec<&str> = Vec::new();
+    let mut blank_run = 0;
+    for line in text.lines() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            if lines.last().is_some_and(|previous| previous.trim_start().starts_with("///")) {
+                continue;
+            }
+            blank_run += 1;
+            if blank_run > 2 {
+                continue;
+            }
+        } else {
+            blank_run = 0;
         }
+        lines.push(line);
     }
-    None
+    while lines.last() == Some(&"") {
+        lines.pop();
+    }
+    if lines.is_empty() {
+        return String::new();
+    }
+    let mut formatted = lines.join(newline);
+    formatted.push_str(newline);
+    formatted
 }
 
-// Format function signature for hover display
-pub fn format_function_signature(func: &Function) -> String {
-    // Wrap in catch_unwind to prevent panics from format_type recursion
-    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-        format_function_signature_internal(func)
-    })).unwrap_or_else(|_| {
-        // Fallback to simple signature if formatting panics
-        format!("fn {}()", func.name)
-    })
+/// Edits formatting the document: none when it is already formatted,
+/// otherwise one edit replacing the whole text. Ranges use byte columns.
+pub fn formatting_edits(text: &str) -> Vec<TextEdit> {
+    let formatted = format_text(text);
+    if formatted == text {
+        return Vec::new();
+    }
+    let end = offset_to_position(text, text.len(), &PositionEncodingKind::UTF8);
+    vec![TextEdit {
+        range: Range { start: Position { line: 0, character: 0 }, end },
+        new_text: formatted,
+    }]
 }
 
-fn format_function_signature_internal(func: &Function) -> String {
-    let mut sig = String::new();
+/// Token types of the semantic tokens legend - `token_type` indexes this
+pub const SEMANTIC_TOKEN_TYPES: &[SemanticTokenType] = &[
+    SemanticTokenType::KEYWORD,
+    SemanticTokenType::FUNCTION,
+    SemanticTokenType::CLASS,
+    SemanticTokenType::VARIABLE,
+    SemanticTokenType::NUMBER,
+    SemanticTokenType::STRING,
+    SemanticTokenType::COMMENT,
+];
 
-    // Attributes
-    if !func.attrs.is_empty() {
-        for attr in &func.attrs {
-            sig.push_str(&format!(
-                "@{}{} ",
-                attr.name,
-                if attr.args.is_empty() {
-                    String::new()
+/// Token modifiers of the semantic tokens legend - bit `i` of
+/// `token_modifiers_bitset` is modifier `i`
+pub const SEMANTIC_TOKEN_MODIFIERS: &[SemanticTokenModifier] = &[
+    SemanticTokenModifier::DECLARATION,
+    SemanticTokenModifier::DEFINITION,
+    SemanticTokenModifier::READONLY,
+];
+
+fn semantic_token_modifiers(modifiers: &[SemanticTokenModifier]) -> u32 {
+    modifiers
+        .iter()
+        .filter_map(|modifier| SEMANTIC_TOKEN_MODIFIERS.iter().position(|known| known == modifier))
+        .fold(0, |bits, i| bits | (1 << i))
+}
+
+fn semantic_token_type(token_type: &SemanticTokenType) -> u32 {
+    SEMANTIC_TOKEN_TYPES.iter().position(|known| known == token_type).unwrap_or(0) as u32
+}
+
+/// Semantic tokens of the document, relative-encoded with columns in the
+/// client's position encoding. Identifiers are functions when called or
+/// declared as one, classes when the program declares them, and variables
+/// otherwise. Punctuation is left to the grammar. Function names in `fn`
+/// headers are declarations and definitions; variables bound by `let` and
+/// parameters are readonly, `var` bindings are not.
+pub fn semantic_tokens(program: &Program, text: &str, encoding: &PositionEncodingKind) -> Vec<SemanticToken> {
+    let index = DocumentIndex::new(program, text);
+    let stdlib: HashSet<String> = get_stdlib_functions().into_iter().map(|func| func.name).collect();
+    let mut data = Vec::new();
+    let mut previous = Position { line: 0, character: 0 };
+
+    for (i, token) in index.tokens.iter().enumerate() {
+        let mut modifiers = Vec::new();
+        let token_type = match token.kind {
+            TokenKind::Keyword => SemanticTokenType::KEYWORD,
+            TokenKind::Number => SemanticTokenType::NUMBER,
+            TokenKind::String => SemanticTokenType::STRING,
+            TokenKind::Comment | TokenKind::DocComment => SemanticTokenType::COMMENT,
+            TokenKind::Punct => continue,
+            TokenKind::Ident => {
+                let called = index.tokens.get(i + 1).is_some_and(|next| next.is_punct("(") && next.line == token.line);
+                let declared = i > 0 && index.tokens[i - 1].is_keyword("fn");
+                if declared || called || index.find_function(&token.text).is_some() || stdlib.contains(&token.text) {
+                    if declared {
+                        modifiers.extend([SemanticTokenModifier::DECLARATION, SemanticTokenModifier::DEFINITION]);
+                    }
+                    SemanticTokenType::FUNCTION
+                } else if index.find_class(&token.text).is_some() {
+                    SemanticTokenType::CLASS
                 } else {
-                    format!("({})", attr.args.len())
+                    if is_readonly_binding(&index, token) {
+                        modifiers.push(SemanticTokenModifier::READONLY);
+                    }
+                    SemanticTokenType::VARIABLE
                 }
-            ));
-        }
+            }
+        };
+
+        let range = from_byte_range(text, token_range(token), encoding);
+        let delta_line = range.start.line - previous.line;
+        let delta_start = if delta_line == 0 {
+            range.start.character - previous.character
+        } else {
+            range.start.character
+        };
+        data.push(SemanticToken {
+            delta_line,
+            delta_start,
+            length: range.end.character - range.start.character,
+            token_type: semantic_token_type(&token_type),
+            token_modifiers_bitset: semantic_token_modifiers(&modifiers),
+        });
+        previous = range.start;
     }
+    data
+}
 
-    // Function name and parameters
-    sig.push_str("fn ");
-    sig.push_str(&func.name);
-    sig.push('(');
+// Whether the identifier is a local bound by `let` or a parameter. `var` and
+// `for` bindings can change, and anything that does not resolve to a local is
+// left alone.
+fn is_readonly_binding(index: &DocumentIndex, token: &Token) -> bool {
+    let position = Position { line: clamp_to_u32(token.line), character: clamp_to_u32(token.start) };
+    let Some(symbol) = index.resolve_symbol(position).filter(|symbol| symbol.local) else { return false };
+    let Some(declaration) = index.token_index_at(symbol.range.start) else { return false };
+    match declaration.checked_sub(1).map(|i| &index.tokens[i]) {
+        Some(binder) if binder.kind == TokenKind::Keyword => binder.is_keyword("let"),
+        // Parameters follow `(` or `,` in the header
+        _ => true,
+    }
+}
 
-    let params: Vec<String> = func
-        .params
+/// Edits turning `previous` into `current`: one edit replacing whatever lies
+/// between their common prefix and suffix, or none when they are equal.
+/// Offsets count integers of the flattened array, five per token.
+pub fn semantic_token_edits(previous: &[SemanticToken], current: &[SemanticToken]) -> Vec<SemanticTokensEdit> {
+    let prefix = previous.iter().zip(current).take_while(|(old, new)| old == new).count();
+    let suffix = previous[prefix..]
         .iter()
-        .map(|p| format!("{}: {}", p.name, format_type(&p.ty)))
-        .collect();
-    sig.push_str(&params.join(", "));
-    sig.push(')');
+        .rev()
+        .zip(current[prefix..].iter().rev())
+        .take_while(|(old, new)| old == new)
+        .count();
+    let deleted = previous.len() - prefix - suffix;
+    let inserted = &current[prefix..current.len() - suffix];
+    if deleted == 0 && inserted.is_empty() {
+        return Vec::new();
+    }
+    vec![SemanticTokensEdit {
+        start: (prefix * 5) as u32,
+        delete_count: (deleted * 5) as u32,
+        data: Some(inserted.to_vec()),
+    }]
+}
 
-    // Return type
-    if let Some(ref ret_ty) = func.return_type {
-        sig.push_str(" -> ");
-        sig.push_str(&format_type(ret_ty));
+/// Edits renaming the symbol under the cursor: its declaration and every
+/// identifier resolving to it. With `update_doc_comments`, whole-word
+/// mentions of functions and classes in `///` comments are renamed too.
+/// Ranges use byte columns.
+pub fn rename_edits(
+    program: &Program,
+    text: &str,
+    position: Position,
+    new_name: &str,
+    update_doc_comments: bool,
+) -> Option<Vec<TextEdit>> {
+    let index = DocumentIndex::new(program, text);
+    let old_name = index.tokens.get(index.token_index_at(position)?)?.text.clone();
+    let symbol = index.resolve_symbol(position)?;
+
+    let mut ranges = vec![symbol.range];
+    for token in &index.tokens {
+        if !token.is_ident(&old_name) {
+            continue;
+        }
+        let range = token_range(token);
+        if range != symbol.range && index.resolve_symbol(range.start).is_some_and(|other| other.range == symbol.range) {
+            ranges.push(range);
+        }
     }
 
-    sig
-}
+    if update_doc_comments && !symbol.local && symbol.path.len() == 1 {
+        for token in index.tokens.iter().filter(|token| token.kind == TokenKind::DocComment) {
+            for (offset, _) in token.text.match_indices(old_name.as_str()) {
+                let before = token.text[..offset].chars().next_back();
+                let after = token.text[offset + old_name.len()..].chars().next();
+                let word_char = |ch: char| ch.is_alphanumeric() || ch == '_';
+                if before.is_some_and(word_char) || after.is_some_and(word_char) {
+                    continue;
+                }
+                let start = token.start + offset;
+                ranges.push(Range {
+                    start: Position { line: clamp_to_u32(token.line), character: clamp_to_u32(start) },
+                    end: Position { line: clamp_to_u32(token.line), character: clamp_to_u32(start + old_name.len()) },
+                });
+            }
+        }
+    }
 
-// Format type for display with recursion limit to prevent stack overflow
-pub fn format_type(ty: &Type) -> String {
-    format_type_with_depth(ty, 0)
+    ranges.sort_by_key(|range| (range.start.line, range.start.character));
+    ranges.dedup();
+    Some(
+        ranges
+            .into_iter()
+            .map(|range| TextEdit { range, new_text: new_name.to_string() })
+            .collect(),
+    )
 }
 
-fn format_type_with_depth(ty: &Type, depth: usize) -> String {
-    // Limit recursion depth to prevent stack overflow
-    if depth > 10 {
-        return "...".to_string();
-    }
+/// Quick fix turning the `let` of an assigned binding into `var`
+pub fn make_mutable_action(uri: &url::Url, assignment: &ImmutableAssignment) -> CodeAction {
+    let mut changes = HashMap::new();
+    changes.insert(
+        uri.clone(),
+        vec![TextEdit {
+            range: assignment.declaration,
+            new_text: "var".to_string(),
+        }],
+    );
 
-    match ty {
-        Type::Int => "int".to_string(),
-        Type::Str => "str".to_string(),
-        Type::Float32 => "float32".to_string(),
-        Type::Float64 => "float64".to_string(),
-        Type::Bool => "bool".to_string(),
-        Type::Dynamic => "dynamic".to_string(),
-        Type::List(inner) => format!("list[{}]", format_type_with_depth(inner, depth + 1)),
-        Type::Array(inner) => format!("array[{}]", format_type_with_depth(inner, depth + 1)),
-        Type::Map(k, v) => format!("map[{}, {}]", format_type_with_depth(k, depth + 1), format_type_with_depth(v, depth + 1)),
-        Type::Tensor(inner, dims) => format!("Tensor[{}, {:?}]", format_type_with_depth(inner, depth + 1), dims),
-        Type::Named(name) => name.clone(),
+    CodeAction {
+        title: format!("Make `{}` mutable (var)", assignment.name),
+        kind: Some(CodeActionKind::QUICKFIX),
+        edit: Some(WorkspaceEdit {
+            changes: Some(changes),
+            ..Default::default()
+        }),
+        is_preferred: Some(true),
+        ..Default::default()
     }
 }
 
-// Extract variables visible at given position (simplified implementation)
-pub fn extract_variables_in_scope(
-    program: &Program,
-    line: usize,
-    _column: usize,
-) -> Option<HashSet<String>> {
-    let mut variables = HashSet::new();
+pub fn add_return_type_action(uri: &url::Url, missing: &MissingReturnType) -> CodeAction {
+    let mut changes = HashMap::new();
+    changes.insert(
+        uri.clone(),
+        vec![TextEdit {
+            range: Range {
+                start: missing.insert_at,
+                end: missing.insert_at,
+            },
+            new_text: format!(" -> {}", format_type(&missing.inferred)),
+        }],
+    );
 
-    // Find function containing this line
-    for item in &program.items {
-        if let Item::Function(func) = item {
-            let func_start = func.span.start.line;
-            let func_end = func.span.end.line;
+    CodeAction {
+        title: format!("Annotate `{}` with `-> {}`", missing.name, format_type(&missing.inferred)),
+        kind: Some(CodeActionKind::QUICKFIX),
+        edit: Some(WorkspaceEdit {
+            changes: Some(changes),
+            ..Default::default()
+        }),
+        is_preferred: Some(true),
+        ..Default::default()
+    }
+}
 
-            // Check if position is within this function
-            if line >= func_start && line <= func_end {
-                // Add function parameters
-                for param in &func.params {
-                    variables.insert(param.name.clone());
-                }
+fn ranges_overlap(a: &Range, b: &Range) -> bool {
+    a.start <= b.end && b.start <= a.end
+}
 
-                // Extract variables from function body (simplified - just from let/var statements)
-                extract_variables_from_statements(&func.body, &mut variables);
+/// A `let`/`var` that re-binds a name already visible in the same function
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShadowedBinding {
+    pub name: String,
+    /// Range of the new declaration's name
+    pub range: Range,
+    /// Range of the binding being shadowed
+    pub original: Range,
+}
 
-                return Some(variables);
+// Find bindings that shadow a parameter or an earlier `let`/`var` in an enclosing block
+pub fn find_shadowed_bindings(program: &Program, text: &str) -> Vec<ShadowedBinding> {
+    let tokens = tokenize(text);
+    let mut shadowed = Vec::new();
+
+    for item in &program.items {
+        match item {
+            Item::Function(func) => check_function_shadowing(func, &tokens, &mut shadowed),
+            Item::Class(class) => {
+                for method in &class.methods {
+                    check_function_shadowing(method, &tokens, &mut shadowed);
+                }
             }
         }
     }
 
-    None
+    shadowed
 }
 
-// Extract variable names from statements
-pub fn extract_variables_from_statements(statements: &[Statement], variables: &mut HashSet<String>) {
+fn check_function_shadowing(func: &Function, tokens: &[Token], shadowed: &mut Vec<ShadowedBinding>) {
+    let first_line = func.span.start.line.saturating_sub(1);
+    let last_line = func.span.end.line.saturating_sub(1);
+
+    let mut scope = parameter_sites(tokens, first_line, &func.name);
+    let sites = declaration_sites(tokens, first_line, last_line);
+    let mut cursor = 0;
+    check_block_shadowing(&func.body, &mut scope, &sites, &mut cursor, shadowed, 0);
+}
+
+fn check_block_shadowing(
+    statements: &[Statement],
+    scope: &mut Vec<(String, Range)>,
+    sites: &[(String, Range)],
+    cursor: &mut usize,
+    shadowed: &mut Vec<ShadowedBinding>,
+    nesting: usize,
+) {
+    if nesting > MAX_STATEMENT_DEPTH {
+        return;
+    }
+    let depth = scope.len();
+
     for stmt in statements {
         match stmt {
             Statement::Let { name, .. } => {
-                variables.insert(name.clone());
+                let Some(range) = next_declaration_site(sites, cursor, name) else { continue };
+                if let Some((_, original)) = scope.iter().rev().find(|(bound, _)| bound == name) {
+                    shadowed.push(ShadowedBinding {
+                        name: name.clone(),
+                        range,
+                        original: *original,
+                    });
+                }
+                scope.push((name.clone(), range));
             }
             Statement::For { var, body, .. } => {
-                variables.insert(var.clone());
-                extract_variables_from_statements(body, variables);
+                let loop_depth = scope.len();
+                if let Some(range) = next_declaration_site(sites, cursor, var) {
+                    scope.push((var.clone(), range));
+                }
+                check_block_shadowing(body, scope, sites, cursor, shadowed, nesting + 1);
+                scope.truncate(loop_depth);
             }
             Statement::If { then, else_, .. } => {
-                extract_variables_from_statements(then, variables);
+                check_block_shadowing(then, scope, sites, cursor, shadowed, nesting + 1);
                 if let Some(else_stmts) = else_ {
-                    extract_variables_from_statements(else_stmts, variables);
+                    check_block_shadowing(else_stmts, scope, sites, cursor, shadowed, nesting + 1);
                 }
             }
             Statement::While { body, .. } => {
-                extract_variables_from_statements(body, variables);
+                check_block_shadowing(body, scope, sites, cursor, shadowed, nesting + 1);
             }
             _ => {}
         }
     }
+
+    // Bindings introduced in this block are not visible after it
+    scope.truncate(depth);
+}
+
+// Declaration sites are matched to AST statements in source order
+fn next_declaration_site(sites: &[(String, Range)], cursor: &mut usize, name: &str) -> Option<Range> {
+    let offset = sites[*cursor..].iter().position(|(site, _)| site == name)?;
+    *cursor += offset + 1;
+    Some(sites[*cursor - 1].1)
+}
+
+/// A function with a declared return type that can fall off its end
+#[derive(Debug, Clone, PartialEq)]
+pub struct MissingReturn {
+    pub name: String,
+    /// Range of the function name in its header
+    pub range: Range,
+    pub return_type: Type,
+}
+
+// Find functions and methods declaring a return type where some path doesn't return
+pub fn find_missing_returns(program: &Program, text: &str) -> Vec<MissingReturn> {
+    let index = DocumentIndex::new(program, text);
+    let mut missing = Vec::new();
+
+    let functions = program.items.iter().flat_map(|item| match item {
+        Item::Function(func) => std::slice::from_ref(func).iter(),
+        Item::Class(class) => class.methods.iter(),
+    });
+    for func in functions {
+        let Some(return_type) = &func.return_type else { continue };
+        if matches!(return_type, Type::Named(name) if name == "void") || always_returns(&func.body, 0) {
+            continue;
+        }
+        if let Some(range) = index.function_name_range(func) {
+            missing.push(MissingReturn {
+                name: func.name.clone(),
+                range,
+                return_type: return_type.clone(),
+            });
+        }
+    }
+
+    missing
+}
+
+/// Diagnostic code for top-level functions that could declare their return type
+pub const MISSING_RETURN_TYPE_CODE: &str = "missing-return-type";
+
+/// A top-level function without a return type whose `return` values all
+/// have the same inferred type
+#[derive(Debug, Clone, PartialEq)]
+pub struct MissingReturnType {
+    pub name: String,
+    /// Range of the function name in its header
+    pub range: Range,
+    pub inferred: Type,
+    /// Just after the parameter list's closing paren, where `-> T` goes
+    pub insert_at: Position,
+}
+
+// Find top-level functions that return values without declaring their type.
+// Any return whose type cannot be inferred, or that disagrees with the
+// others, leaves the function alone.
+pub fn find_missing_return_types(program: &Program, text: &str) -> Vec<MissingReturnType> {
+    let index = DocumentIndex::new(program, text);
+    let tokens = &index.tokens;
+    let mut missing = Vec::new();
+
+    for item in &program.items {
+        let Item::Function(func) = item else { continue };
+        if func.return_type.is_some() {
+            continue;
+        }
+        let first_line = func.span.start.line.saturating_sub(1);
+        let last_line = func.span.end.line.saturating_sub(1);
+
+        let mut inferred: Option<Type> = None;
+        let mut consistent = true;
+        for (i, token) in tokens.iter().enumerate() {
+            if token.line < first_line || token.line > last_line || !token.is_keyword("return") {
+                continue;
+            }
+            let has_value = tokens
+                .get(i + 1)
+                .is_some_and(|next| next.line == token.line && !matches!(next.kind, TokenKind::Comment));
+            if !has_value {
+                continue;
+            }
+            match (index.infer_expression_type(i + 1, func, None, token.line), &inferred) {
+                (Some(found), None) => inferred = Some(found),
+                (Some(found), Some(previous)) if format_type(&found) == format_type(previous) => {}
+                _ => consistent = false,
+            }
+        }
+        let (true, Some(inferred)) = (consistent, inferred) else { continue };
+
+        let Some(range) = index.function_name_range(func) else { continue };
+        let Some(name) = index.token_index_at(range.start) else { continue };
+        let (_, Some(close)) = call_arguments(tokens, name) else { continue };
+        missing.push(MissingReturnType {
+            name: func.name.clone(),
+            range,
+            inferred,
+            insert_at: token_range(&tokens[close]).end,
+        });
+    }
+
+    missing
+}
+
+/// Type context with the program's functions and classes, before type checking.
+/// Later definitions replace earlier ones, as in the compiler.
+fn program_type_context(program: &Program) -> TypeContext {
+    let mut ctx = TypeContext::new();
+    for item in &program.items {
+        match item {
+            Item::Function(func) => ctx.add_function(func.name.clone(), func.clone()),
+            Item::Class(class) => ctx.add_class(class.name.clone(), class.clone()),
+        }
+    }
+    ctx
+}
+
+/// Type error inside a class method, with its formatted message
+#[derive(Debug)]
+pub struct MethodTypeError {
+    pub class: String,
+    pub method: String,
+    pub error: pain_compiler::TypeError,
+    pub message: String,
+}
+
+// Type check each class method as a function taking `self` of the class type,
+// against the program's functions and classes. The checker stops at the first
+// error, so a method reports at most one.
+pub fn find_method_type_errors(program: &Program, text: &str) -> Vec<MethodTypeError> {
+    let mut errors = Vec::new();
+
+    for item in &program.items {
+        let Item::Class(class) = item else { continue };
+        for method in &class.methods {
+            let mut params = method.params.clone();
+            if !params.iter().any(|param| param.name == "self") {
+                params.insert(0, Parameter { name: "self".to_string(), ty: Type::Named(class.name.clone()) });
+            }
+            // Qualified, so the method never replaces a function of the same name
+            let function = Function {
+                name: format!("{}.{}", class.name, method.name),
+                params,
+                ..method.clone()
+            };
+            let scoped = Program { items: vec![Item::Function(function)] };
+
+            let mut ctx = program_type_context(program);
+            if let Err(error) = type_check_program_with_context(&scoped, &mut ctx) {
+                errors.push(MethodTypeError {
+                    class: class.name.clone(),
+                    method: method.name.clone(),
+                    message: format_type_error(text, &ctx, &error),
+                    error,
+                });
+            }
+        }
+    }
+
+    errors
+}
+
+// Whether every path through the block ends in a `return`. Blocks nested
+// too deeply to walk are assumed to return, so they are never reported.
+fn always_returns(statements: &[Statement], depth: usize) -> bool {
+    if depth > MAX_STATEMENT_DEPTH {
+        return true;
+    }
+    statements.iter().any(|stmt| match stmt {
+        Statement::Return { .. } => true,
+        Statement::If { then, else_, .. } => {
+            always_returns(then, depth + 1)
+                && else_.as_ref().is_some_and(|else_stmts| always_returns(else_stmts, depth + 1))
+        }
+        _ => false,
+    })
 }
@@ -3,17 +3,25 @@
 use pain_lsp::Backend;
 use tower_lsp::{LspService, Server};
 use std::fs::OpenOptions;
-use std::io::Write;
+use std::path::Path;
+use tracing_subscriber::{fmt::format::FmtSpan, EnvFilter};
 
-// Helper function to log to file (in temp directory for visibility)
-fn log_to_file(msg: &str) {
-    let log_path = std::env::temp_dir().join("pain_lsp_debug.log");
-    if let Ok(mut file) = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(&log_path)
-    {
-        let _ = writeln!(file, "[{}] {}", chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f"), msg);
+// Environment variable holding the log filter, e.g. `PAIN_LSP_LOG=debug`
+const LOG_ENV: &str = "PAIN_LSP_LOG";
+
+// Route tracing events to the debug log (stderr if it cannot be opened).
+// stdout carries the protocol, so nothing may be logged there.
+fn init_logging(log_path: &Path) {
+    let filter = EnvFilter::try_from_env(LOG_ENV).unwrap_or_else(|_| EnvFilter::new("info"));
+    // Span close events carry the elapsed time of each request
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_ansi(false);
+
+    match OpenOptions::new().create(true).append(true).open(log_path) {
+        Ok(file) => builder.with_writer(std::sync::Mutex::new(file)).init(),
+        Err(_) => builder.with_writer(std::io::stderr).init(),
     }
 }
 
@@ -21,46 +29,46 @@ fn log_to_file(msg: &str) {
 async fn main() {
     let log_path = std::env::temp_dir().join("pain_lsp_debug.log");
     eprintln!("=== Pain LSP starting, log file: {:?} ===", log_path);
-    
-    log_to_file("=== LSP MAIN START ===");
-    log_to_file(&format!("Log file location: {:?}", log_path));
-    log_to_file(&format!("Current working directory: {:?}", std::env::current_dir()));
+    init_logging(&log_path);
+
+    tracing::info!("=== LSP MAIN START ===");
+    tracing::info!("Log file location: {:?}", log_path);
+    tracing::info!("Current working directory: {:?}", std::env::current_dir());
     
     // Set panic hook to log panics before they crash the server
     std::panic::set_hook(Box::new(|panic_info| {
-        let msg = format!("LSP PANIC: {:?}", panic_info);
-        eprintln!("{}", msg);
-        log_to_file(&msg);
-        
-        let loc_msg = format!("LSP PANIC location: {:?}", panic_info.location());
-        eprintln!("{}", loc_msg);
-        log_to_file(&loc_msg);
-        
-        if let Some(s) = panic_info.payload().downcast_ref::<&str>() {
-            let panic_msg = format!("LSP PANIC message: {}", s);
-            eprintln!("{}", panic_msg);
-            log_to_file(&panic_msg);
-        } else if let Some(s) = panic_info.payload().downcast_ref::<String>() {
-            let panic_msg = format!("LSP PANIC message: {}", s);
-            eprintln!("{}", panic_msg);
-            log_to_file(&panic_msg);
-        }
+        eprintln!("LSP PANIC: {:?}", panic_info);
+        let message = panic_info
+            .payload()
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| panic_info.payload().downcast_ref::<String>().cloned());
+        tracing::error!(
+            location = ?panic_info.location(),
+            message = message.as_deref().unwrap_or("<non-string payload>"),
+            "LSP PANIC"
+        );
     }));
-    log_to_file("Panic hook set");
+    tracing::debug!("Panic hook set");
 
-    log_to_file("Creating stdin/stdout");
     let stdin = tokio::io::stdin();
     let stdout = tokio::io::stdout();
-    log_to_file("stdin/stdout created");
 
-    log_to_file("Creating LspService");
+    // Clones share all state, so this handle sees the tasks the server spawns
+    let mut backend_handle = None;
     let (service, socket) = LspService::new(|client| {
-        log_to_file("Backend::new called");
-        Backend::new(client)
+        tracing::debug!("Backend::new called");
+        let backend = Backend::new(client);
+        backend_handle = Some(backend.clone());
+        backend
     });
-    log_to_file("LspService created");
     
-    log_to_file("Starting server");
+    tracing::info!("Starting server");
     Server::new(stdin, stdout, socket).serve(service).await;
-    log_to_file("Server stopped");
+    // `serve` returns on `exit` - stop whatever was spawned after `shutdown`
+    if let Some(backend) = backend_handle {
+        let aborted = backend.abort_background_tasks().await;
+        tracing::info!(aborted_tasks = aborted, "Background tasks stopped");
+    }
+    tracing::info!("Server stopped");
 }
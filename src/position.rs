@@ -0,0 +1,148 @@
+// Conversions between LSP positions and byte offsets
+//
+// LSP columns count code units of the negotiated position encoding (UTF-16
+// unless the client agreed on something else). The server itself works with
+// byte offsets into the UTF-8 text, so positions are converted at the
+// protocol boundary. A tab is a single code unit in every encoding, however
+// wide the editor draws it, so tab-indented lines need no special casing.
+
+use std::borrow::Cow;
+use tower_lsp::lsp_types::{Position, PositionEncodingKind, Range};
+
+/// Pick the position encoding from the encodings the client offers.
+/// UTF-8 matches the server's byte offsets, so it wins when offered;
+/// otherwise UTF-16, which every client must support.
+pub fn negotiate_position_encoding(offered: Option<&[PositionEncodingKind]>) -> PositionEncodingKind {
+    match offered {
+        Some(encodings) if encodings.contains(&PositionEncodingKind::UTF8) => PositionEncodingKind::UTF8,
+        _ => PositionEncodingKind::UTF16,
+    }
+}
+
+/// Text with `\r\n` line endings turned into `\n`, for the compiler. Lines and
+/// columns are the same in both forms, so compiler positions apply to the
+/// original text unchanged.
+pub fn normalize_line_endings(text: &str) -> Cow<'_, str> {
+    if text.contains("\r\n") {
+        Cow::Owned(text.replace("\r\n", "\n"))
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+/// A line or column as an LSP `u32`, saturating instead of wrapping when a
+/// span is out of range
+pub fn clamp_to_u32(value: usize) -> u32 {
+    u32::try_from(value).unwrap_or(u32::MAX)
+}
+
+// Number of code units a character occupies in the given encoding
+fn code_units(ch: char, encoding: &PositionEncodingKind) -> usize {
+    if *encoding == PositionEncodingKind::UTF8 {
+        ch.len_utf8()
+    } else if *encoding == PositionEncodingKind::UTF32 {
+        1
+    } else {
+        ch.len_utf16()
+    }
+}
+
+// Byte offset where the 0-based line starts, or `None` past the last line
+fn line_start(text: &str, line: u32) -> Option<usize> {
+    let mut start = 0;
+    for _ in 0..line {
+        start += text[start..].find('\n')? + 1;
+    }
+    Some(start)
+}
+
+// Content of the line starting at `start`, without its terminator
+fn line_content(text: &str, start: usize) -> &str {
+    let rest = &text[start..];
+    let line = rest.find('\n').map_or(rest, |newline| &rest[..newline]);
+    line.strip_suffix('\r').unwrap_or(line)
+}
+
+/// Byte offset of an LSP position. Columns past the end of the line clamp to it.
+pub fn position_to_offset(text: &str, position: Position, encoding: &PositionEncodingKind) -> Option<usize> {
+    let start = line_start(text, position.line)?;
+    let mut units = 0;
+    for (offset, ch) in line_content(text, start).char_indices() {
+        if units >= position.character as usize {
+            return Some(start + offset);
+        }
+        units += code_units(ch, encoding);
+    }
+    Some(start + line_content(text, start).len())
+}
+
+/// LSP position of a byte offset. Offsets inside a character round down to its start.
+pub fn offset_to_position(text: &str, offset: usize, encoding: &PositionEncodingKind) -> Position {
+    let mut offset = offset.min(text.len());
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let line = text[..offset].matches('\n').count();
+    let start = text[..offset].rfind('\n').map_or(0, |newline| newline + 1);
+
+    let character = line_content(text, start)
+        .char_indices()
+        .take_while(|(byte, ch)| byte + ch.len_utf8() <= offset - start)
+        .map(|(_, ch)| code_units(ch, encoding))
+        .sum::<usize>();
+
+    Position {
+        line: clamp_to_u32(line),
+        character: clamp_to_u32(character),
+    }
+}
+
+/// Byte offset of an LSP position in `text`, for the given position encoding.
+///
+/// This is the conversion the server applies to every incoming position, so
+/// companion tooling using it gets exactly the server's offsets. Lines end at
+/// `\n` with an optional `\r` before it, and columns past the end of a line
+/// clamp to the end of its content. `None` when the line does not exist.
+pub fn lsp_position_to_offset(text: &str, pos: Position, encoding: PositionEncodingKind) -> Option<usize> {
+    position_to_offset(text, pos, &encoding)
+}
+
+/// LSP position of a byte offset in `text`, for the given position encoding.
+/// The inverse of [`lsp_position_to_offset`]: offsets inside a character round
+/// down to its start. `None` when the offset is past the end of the text.
+pub fn offset_to_lsp_position(text: &str, offset: usize, encoding: PositionEncodingKind) -> Option<Position> {
+    (offset <= text.len()).then(|| offset_to_position(text, offset, &encoding))
+}
+
+/// Convert a client position into the server's (line, byte column) form
+pub fn to_byte_position(text: &str, position: Position, encoding: &PositionEncodingKind) -> Position {
+    let Some(start) = line_start(text, position.line) else { return position };
+    let offset = position_to_offset(text, position, encoding).unwrap_or(start);
+    Position {
+        line: position.line,
+        character: clamp_to_u32(offset - start),
+    }
+}
+
+/// Convert a (line, byte column) position into the client's encoding
+pub fn from_byte_position(text: &str, position: Position, encoding: &PositionEncodingKind) -> Position {
+    let Some(start) = line_start(text, position.line) else { return position };
+    let content = line_content(text, start);
+    let mut column = (position.character as usize).min(content.len());
+    while !content.is_char_boundary(column) {
+        column -= 1;
+    }
+    let character = content[..column].chars().map(|ch| code_units(ch, encoding)).sum::<usize>();
+    Position {
+        line: position.line,
+        character: clamp_to_u32(character),
+    }
+}
+
+/// Convert a range with byte columns into the client's encoding
+pub fn from_byte_range(text: &str, range: Range, encoding: &PositionEncodingKind) -> Range {
+    Range {
+        start: from_byte_position(text, range.start, encoding),
+        end: from_byte_position(text, range.end, encoding),
+    }
+}
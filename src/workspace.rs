@@ -0,0 +1,272 @@
+// Workspace index - top-level symbols of every `.pain` file in the workspace
+
+use crate::analysis::{token_range, DocumentIndex};
+use crate::lexer::{tokenize, TokenKind};
+use crate::position::clamp_to_u32;
+use pain_compiler::{ast::*, parse_with_recovery, stdlib::get_stdlib_functions};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use tower_lsp::lsp_types::{DocumentLink, Position, Range, SymbolKind, Url};
+
+// Directories never worth scanning for sources
+const SKIPPED_DIRS: &[&str] = &["target", "node_modules"];
+
+// Stdlib functions whose first argument is a file path, beyond those
+// recognized by parameter name
+const FILE_ARGUMENT_FUNCTIONS: &[&str] = &["pml_load_file"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexedSymbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// Range of the symbol's name
+    pub range: Range,
+    /// Enclosing class for methods
+    pub container: Option<String>,
+}
+
+/// Functions, classes and methods declared in a document
+pub fn index_document(text: &str) -> Vec<IndexedSymbol> {
+    let (parse_result, _) = parse_with_recovery(text);
+    let Ok(program) = parse_result else { return Vec::new() };
+    let index = DocumentIndex::new(&program, text);
+    let mut symbols = Vec::new();
+
+    for item in &program.items {
+        match item {
+            Item::Function(func) => {
+                if let Some(range) = index.function_name_range(func) {
+                    symbols.push(IndexedSymbol {
+                        name: func.name.clone(),
+                        kind: SymbolKind::FUNCTION,
+                        range,
+                        container: None,
+                    });
+                }
+            }
+            Item::Class(class) => {
+                if let Some(range) = index.class_name_range(class) {
+                    symbols.push(IndexedSymbol {
+                        name: class.name.clone(),
+                        kind: SymbolKind::CLASS,
+                        range,
+                        container: None,
+                    });
+                }
+                for method in &class.methods {
+                    if let Some(range) = index.function_name_range(method) {
+                        symbols.push(IndexedSymbol {
+                            name: method.name.clone(),
+                            kind: SymbolKind::METHOD,
+                            range,
+                            container: Some(class.name.clone()),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    symbols
+}
+
+/// Recursively collect `.pain` files, skipping hidden directories and build output
+pub fn find_pain_files(root: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else { continue };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if path.is_dir() {
+                if !name.starts_with('.') && !SKIPPED_DIRS.contains(&name.as_ref()) {
+                    pending.push(path);
+                }
+            } else if path.extension().is_some_and(|ext| ext == "pain") {
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+    files
+}
+
+/// Local path of a `file:` URI. Other schemes (`untitled:`, virtual file
+/// systems) have no file on disk, even when their path looks like one.
+/// Windows URIs (`file:///c%3A/dir/main.pain`) are decoded to drive paths
+/// on every platform, with `\` separators on Windows.
+pub fn url_to_path(uri: &Url) -> Option<PathBuf> {
+    if uri.scheme() != "file" {
+        return None;
+    }
+    let decoded = percent_decode(uri.path())?;
+    let bytes = decoded.as_bytes();
+    let has_drive = bytes.len() >= 3 && bytes[0] == b'/' && bytes[1].is_ascii_alphabetic() && bytes[2] == b':';
+    if !has_drive {
+        return uri.to_file_path().ok();
+    }
+    let path = &decoded[1..];
+    if cfg!(windows) {
+        Some(PathBuf::from(path.replace('/', "\\")))
+    } else {
+        Some(PathBuf::from(path))
+    }
+}
+
+// `%XX` escapes of a URI path decoded, `None` when the result is not UTF-8
+fn percent_decode(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| path.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Module path of a document: its path relative to the containing workspace root
+/// without the `.pain` extension (`geometry/point`), or just the file stem
+/// for documents outside every root
+pub fn module_name(uri: &Url, roots: &[Url]) -> String {
+    let path = uri.path();
+    let relative = roots
+        .iter()
+        .find_map(|root| {
+            let root = root.path().trim_end_matches('/');
+            path.strip_prefix(root)?.strip_prefix('/')
+        })
+        .unwrap_or_else(|| path.rsplit('/').next().unwrap_or(path));
+    relative.strip_suffix(".pain").unwrap_or(relative).to_string()
+}
+
+// Stdlib functions taking a path as their first argument
+fn file_argument_functions() -> Vec<String> {
+    let mut names: Vec<String> = FILE_ARGUMENT_FUNCTIONS.iter().map(|name| name.to_string()).collect();
+    for func in get_stdlib_functions() {
+        let takes_path = func.params.first().is_some_and(|(name, ty)| {
+            matches!(ty, Type::Str) && (name.contains("path") || name.contains("file"))
+        });
+        if takes_path && !names.contains(&func.name) {
+            names.push(func.name.clone());
+        }
+    }
+    names
+}
+
+/// Links for string literals passed to file-taking stdlib functions, e.g.
+/// `pml_load_file("config.pml")`. Paths resolve relative to `base_dir` (the
+/// document's directory or its workspace folder, per `pathResolutionBase`);
+/// literals naming files that do not exist get no link.
+/// Ranges cover the literal's contents and use byte columns.
+pub fn find_document_links(text: &str, base_dir: &Path) -> Vec<DocumentLink> {
+    let functions = file_argument_functions();
+    let tokens = tokenize(text);
+    let mut links = Vec::new();
+
+    for window in tokens.windows(3) {
+        let [callee, open, literal] = window else { continue };
+        if callee.kind != TokenKind::Ident
+            || !functions.contains(&callee.text)
+            || !open.is_punct("(")
+            || literal.kind != TokenKind::String
+        {
+            continue;
+        }
+        // Only terminated, plain literals - escapes would make the path ambiguous
+        let quote = &literal.text[..1];
+        let Some(path) = literal.text.strip_prefix(quote).and_then(|rest| rest.strip_suffix(quote)) else { continue };
+        if path.is_empty() || path.contains('\\') {
+            continue;
+        }
+
+        let target = base_dir.join(path);
+        if !target.is_file() {
+            continue;
+        }
+        let Ok(target) = Url::from_file_path(&target) else { continue };
+        links.push(DocumentLink {
+            range: Range {
+                start: Position { line: clamp_to_u32(literal.line), character: clamp_to_u32(literal.start + 1) },
+                end: Position { line: clamp_to_u32(literal.line), character: clamp_to_u32(literal.end - 1) },
+            },
+            target: Some(target),
+            tooltip: None,
+            data: None,
+        });
+    }
+
+    links
+}
+
+/// A top-level class and the names mentioned in its body
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassOutline {
+    pub name: String,
+    /// From the `class` keyword to the end of the body
+    pub range: Range,
+    /// Range of the class name
+    pub selection_range: Range,
+    /// Identifiers used in fields and methods, other than the class itself
+    pub references: BTreeSet<String>,
+}
+
+/// Top-level classes of a document, found with the lexer so it works on
+/// documents that do not parse. Ranges use byte columns.
+pub fn class_outlines(text: &str) -> Vec<ClassOutline> {
+    let tokens = tokenize(text);
+    let lines: Vec<&str> = text.lines().collect();
+    let mut outlines = Vec::new();
+
+    for pair in tokens.windows(2) {
+        let (keyword, name) = (&pair[0], &pair[1]);
+        if !keyword.is_keyword("class") || keyword.start != 0 || name.kind != TokenKind::Ident {
+            continue;
+        }
+        // The body runs until the next non-indented line
+        let mut end_line = keyword.line;
+        for (line, content) in lines.iter().enumerate().skip(keyword.line + 1) {
+            if content.trim().is_empty() {
+                continue;
+            }
+            if !content.starts_with(char::is_whitespace) {
+                break;
+            }
+            end_line = line;
+        }
+
+        let references = tokens
+            .iter()
+            .filter(|t| t.line > keyword.line && t.line <= end_line)
+            .filter(|t| t.kind == TokenKind::Ident && t.text != name.text)
+            .map(|t| t.text.clone())
+            .collect();
+        outlines.push(ClassOutline {
+            name: name.text.clone(),
+            range: Range {
+                start: Position { line: clamp_to_u32(keyword.line), character: 0 },
+                end: Position { line: clamp_to_u32(end_line), character: clamp_to_u32(lines[end_line].len()) },
+            },
+            selection_range: token_range(name),
+            references,
+        });
+    }
+
+    outlines
+}
@@ -0,0 +1,40 @@
+// Headless analysis tests - test the structured results of `analyze`
+
+use pain_lsp::{analyze, AnalysisResult};
+
+#[test]
+fn test_analyze_reports_type_mismatch() {
+    let result = analyze("fn main():\n    let n: int = \"five\"\n    print(n)\n");
+
+    assert!(result.parse_errors.is_empty(), "The snippet parses: {:?}", result.parse_errors);
+    assert_eq!(result.type_errors.len(), 1, "Expected one type error: {:?}", result);
+    assert!(!result.is_ok());
+    let error = &result.type_errors[0];
+    assert_eq!(error.start.line, 1, "The mismatch is on the `let` line");
+    assert!(error.start < error.end);
+    assert!(error.category.is_none(), "Only warnings have a category");
+    assert!(!error.message.contains('\n'), "The message is a single line");
+}
+
+#[test]
+fn test_analyze_result_round_trips_through_json() {
+    let result = analyze("fn main():\n    let n: int = \"five\"\n    print(n)\n");
+    let json = serde_json::to_value(&result).unwrap();
+
+    assert!(json["parseErrors"].as_array().is_some_and(|errors| errors.is_empty()));
+    assert_eq!(json["typeErrors"][0]["start"]["line"], 1);
+    assert!(json["typeErrors"][0]["message"].is_string());
+    let back: AnalysisResult = serde_json::from_value(json).unwrap();
+    assert_eq!(back, result);
+}
+
+#[test]
+fn test_analyze_reports_parse_errors_and_clean_code() {
+    let broken = analyze("fn main(:\n    pass\n");
+    assert!(!broken.parse_errors.is_empty(), "A broken header should not parse");
+    assert!(broken.type_errors.is_empty(), "Type checking needs a program");
+
+    let clean = analyze("fn main():\n    print(1)\n");
+    assert!(clean.is_ok(), "{:?}", clean);
+    assert_eq!(analyze(""), AnalysisResult::default());
+}
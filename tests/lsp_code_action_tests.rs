@@ -0,0 +1,128 @@
+// LSP code action tests - test quick fixes offered for diagnostics
+
+use pain_lsp::{Backend, IMMUTABLE_ASSIGNMENT_CODE, MISSING_RETURN_TYPE_CODE};
+use tower_lsp::lsp_types::*;
+use tower_lsp::LanguageServer;
+
+const REASSIGNED_LET: &str = "fn main():\n    let x = 0\n    x = 1\n    print(x)\n";
+
+#[tokio::test]
+async fn test_reassigned_let_is_diagnosed() {
+    let backend = Backend::detached();
+    let diagnostics = backend.check_document(REASSIGNED_LET);
+
+    let diagnostic = diagnostics
+        .iter()
+        .find(|d| d.code == Some(NumberOrString::String(IMMUTABLE_ASSIGNMENT_CODE.to_string())))
+        .expect("Assigning to a `let` binding should be reported");
+    assert_eq!(diagnostic.severity, Some(DiagnosticSeverity::ERROR));
+    assert_eq!(diagnostic.range.start, Position { line: 2, character: 4 });
+}
+
+#[tokio::test]
+async fn test_var_reassignment_is_allowed() {
+    let backend = Backend::detached();
+    let code = "fn main():\n    var x = 0\n    x += 1\n    print(x)\n";
+    let diagnostics = backend.check_document(code);
+    assert!(!diagnostics
+        .iter()
+        .any(|d| d.code == Some(NumberOrString::String(IMMUTABLE_ASSIGNMENT_CODE.to_string()))));
+}
+
+#[tokio::test]
+async fn test_make_mutable_quick_fix() {
+    let backend = Backend::detached();
+    let uri = Url::parse("file:///mutable.pain").unwrap();
+    backend
+        .did_open(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "pain".to_string(),
+                version: 1,
+                text: REASSIGNED_LET.to_string(),
+            },
+        })
+        .await;
+
+    let response = backend
+        .code_action(CodeActionParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            range: Range {
+                start: Position { line: 2, character: 4 },
+                end: Position { line: 2, character: 5 },
+            },
+            context: CodeActionContext::default(),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        })
+        .await
+        .unwrap()
+        .expect("Should return code actions");
+
+    let action = match response.as_slice() {
+        [CodeActionOrCommand::CodeAction(action)] => action,
+        other => panic!("Expected a single quick fix, got {:?}", other),
+    };
+    assert_eq!(action.title, "Make `x` mutable (var)");
+    let edits = &action.edit.as_ref().unwrap().changes.as_ref().unwrap()[&uri];
+    assert_eq!(
+        edits,
+        &vec![TextEdit {
+            range: Range {
+                start: Position { line: 1, character: 4 },
+                end: Position { line: 1, character: 7 },
+            },
+            new_text: "var".to_string(),
+        }]
+    );
+}
+
+#[tokio::test]
+async fn test_missing_return_type_hint_and_fix() {
+    use pain_lsp::config::{SeverityLevel, Settings};
+
+    let backend = Backend::detached();
+    let uri = Url::parse("file:///returns.pain").unwrap();
+    let code = "fn double(x: int):\n    return x * 2\n\nfn main():\n    print(double(2))\n";
+    let is_hint = |d: &Diagnostic| d.code == Some(NumberOrString::String(MISSING_RETURN_TYPE_CODE.to_string()));
+    assert!(!backend.check_document(code).iter().any(is_hint), "The hint is off by default");
+
+    let mut settings = Settings::default();
+    settings.warning_severity.missing_return_type = SeverityLevel::Hint;
+    backend.update_settings(settings);
+    let diagnostics = backend.check_document(code);
+    let hint = diagnostics.iter().find(|d| is_hint(d)).expect("`double` has no return type");
+    assert_eq!(hint.severity, Some(DiagnosticSeverity::HINT));
+    assert_eq!(hint.range.start, Position { line: 0, character: 3 });
+    assert_eq!(diagnostics.iter().filter(|d| is_hint(d)).count(), 1, "`main` returns nothing: {:?}", diagnostics);
+
+    backend
+        .did_open(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "pain".to_string(),
+                version: 1,
+                text: code.to_string(),
+            },
+        })
+        .await;
+    let response = backend
+        .code_action(CodeActionParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            range: hint.range,
+            context: CodeActionContext::default(),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        })
+        .await
+        .unwrap()
+        .expect("Should return code actions");
+    let action = match response.as_slice() {
+        [CodeActionOrCommand::CodeAction(action)] => action,
+        other => panic!("Expected a single quick fix, got {:?}", other),
+    };
+    assert_eq!(action.title, "Annotate `double` with `-> int`");
+    let edits = &action.edit.as_ref().unwrap().changes.as_ref().unwrap()[&uri];
+    let end_of_params = Position { line: 0, character: 17 };
+    assert_eq!(edits, &vec![TextEdit { range: Range { start: end_of_params, end: end_of_params }, new_text: " -> int".to_string() }]);
+}
@@ -0,0 +1,134 @@
+// LSP execute-command tests - test workspace/executeCommand handlers
+
+use pain_lsp::{Backend, DUMP_AST_COMMAND};
+use tower_lsp::jsonrpc::ErrorCode;
+use tower_lsp::lsp_types::*;
+use tower_lsp::LanguageServer;
+
+fn command(name: &str, arguments: Vec<serde_json::Value>) -> ExecuteCommandParams {
+    ExecuteCommandParams {
+        command: name.to_string(),
+        arguments,
+        work_done_progress_params: WorkDoneProgressParams::default(),
+    }
+}
+
+#[tokio::test]
+async fn test_dump_ast_returns_program() {
+    let backend = Backend::detached();
+    let uri = Url::parse("file:///dump.pain").unwrap();
+    backend
+        .did_open(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "pain".to_string(),
+                version: 1,
+                text: "fn answer() -> int:\n    return 42\n".to_string(),
+            },
+        })
+        .await;
+
+    let result = backend
+        .execute_command(command(DUMP_AST_COMMAND, vec![serde_json::json!(uri.as_str())]))
+        .await
+        .expect("dumpAst should succeed");
+    let dump = result.and_then(|value| value.as_str().map(str::to_string)).expect("dump should be a string");
+    assert!(dump.contains("answer"), "Dump should include the function: {}", dump);
+}
+
+#[tokio::test]
+async fn test_dump_ast_requires_open_document() {
+    let backend = Backend::detached();
+    let error = backend
+        .execute_command(command(DUMP_AST_COMMAND, vec![serde_json::json!("file:///missing.pain")]))
+        .await
+        .expect_err("Unknown documents should be rejected");
+    assert_eq!(error.code, ErrorCode::InvalidParams);
+}
+
+#[tokio::test]
+async fn test_unknown_command_is_an_error() {
+    let backend = Backend::detached();
+    let error = backend
+        .execute_command(command("pain.doesNotExist", Vec::new()))
+        .await
+        .expect_err("Unknown commands should be rejected");
+    assert_eq!(error.code, ErrorCode::InvalidParams);
+}
+
+#[tokio::test]
+async fn test_status_reports_server_state() {
+    let backend = Backend::detached();
+    let uri = Url::parse("file:///status.pain").unwrap();
+    backend
+        .did_open(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri,
+                language_id: "pain".to_string(),
+                version: 1,
+                text: "fn answer() -> int:\n    return 42\n".to_string(),
+            },
+        })
+        .await;
+
+    let status = backend
+        .execute_command(command(pain_lsp::STATUS_COMMAND, vec![]))
+        .await
+        .unwrap()
+        .expect("status should return a value");
+    assert_eq!(status["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(status["openDocuments"], 1);
+    assert_eq!(status["parseCacheEntries"], 1);
+    assert_eq!(status["workspaceIndex"]["files"], 1);
+    assert_eq!(status["workspaceIndex"]["symbols"], 1);
+    assert_eq!(status["warningsEnabled"], true);
+}
+
+#[tokio::test]
+async fn test_lint_reports_diagnostics() {
+    let backend = Backend::detached();
+    let code = "fn main():\n    let x = undefined_variable\n    print(x)\n";
+
+    let result = backend
+        .execute_command(command(pain_lsp::LINT_COMMAND, vec![serde_json::json!({ "text": code })]))
+        .await
+        .unwrap()
+        .expect("lint should return a value");
+    let diagnostics = result["diagnostics"].as_array().expect("diagnostics should be a list");
+    let error = diagnostics
+        .iter()
+        .find(|diag| diag["severity"] == "error")
+        .expect("The undefined variable should be reported");
+    assert_eq!(error["range"]["start"]["line"], 1);
+    assert!(error["message"].as_str().unwrap().to_lowercase().contains("undefined"));
+
+    // Open documents can be linted by URI
+    let uri = Url::parse("file:///lint.pain").unwrap();
+    backend
+        .did_open(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "pain".to_string(),
+                version: 1,
+                text: code.to_string(),
+            },
+        })
+        .await;
+    let by_uri = backend
+        .execute_command(command(pain_lsp::LINT_COMMAND, vec![serde_json::json!(uri.as_str())]))
+        .await
+        .unwrap()
+        .expect("lint should return a value");
+    assert_eq!(by_uri["uri"], uri.as_str());
+    assert_eq!(by_uri["diagnostics"].as_array().map(Vec::len), Some(diagnostics.len()));
+}
+
+#[tokio::test]
+async fn test_lint_requires_an_argument() {
+    let backend = Backend::detached();
+    let error = backend
+        .execute_command(command(pain_lsp::LINT_COMMAND, Vec::new()))
+        .await
+        .expect_err("A document is required");
+    assert_eq!(error.code, ErrorCode::InvalidParams);
+}
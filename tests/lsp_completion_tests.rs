@@ -239,3 +239,1140 @@ fn test_completion_performance() {
     }
 }
 
+
+/// Backend for calling completion helpers directly - the client is never used
+#[tokio::test]
+async fn test_completion_variable_detail_shows_type() {
+    let code = r#"
+fn main(limit: int):
+    let name = "pain"
+    let ratio: float64 = 0.5
+    print(name)
+"#;
+
+    let (parse_result, _) = parse_with_recovery(code);
+    let program = parse_result.expect("code should parse");
+    let backend = pain_lsp::Backend::detached();
+    let items = backend.get_completions(
+        &program,
+        code,
+        tower_lsp::lsp_types::Position { line: 4, character: 4 },
+    );
+
+    let detail_of = |label: &str| {
+        items
+            .iter()
+            .find(|item| item.label == label)
+            .and_then(|item| item.detail.clone())
+    };
+    assert_eq!(detail_of("name").as_deref(), Some("name: str"), "Inferred from the initializer");
+    assert_eq!(detail_of("ratio").as_deref(), Some("ratio: float64"), "Taken from the annotation");
+    assert_eq!(detail_of("limit").as_deref(), Some("limit: int"), "Taken from the parameter");
+}
+
+#[test]
+fn test_completion_fn_snippet_at_top_level() {
+    let code = "fn main():\n    pass\n\nfn \n";
+    let item = pain_lsp::declaration_snippet(code, tower_lsp::lsp_types::Position { line: 3, character: 3 })
+        .expect("Should offer a function skeleton after `fn `");
+
+    assert_eq!(item.insert_text_format, Some(tower_lsp::lsp_types::InsertTextFormat::SNIPPET));
+    match item.text_edit {
+        Some(tower_lsp::lsp_types::CompletionTextEdit::Edit(edit)) => {
+            assert_eq!(edit.new_text, "fn ${1:name}(${2}) -> ${3:int}:\n    ${0}");
+            assert_eq!(edit.range.start.character, 0, "Should replace the typed `fn `");
+        }
+        other => panic!("Expected a text edit, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_completion_class_snippet_while_typing_name() {
+    let code = "class Po\n";
+    let item = pain_lsp::declaration_snippet(code, tower_lsp::lsp_types::Position { line: 0, character: 8 })
+        .expect("Should offer a class skeleton while the name is typed");
+    assert_eq!(item.label, "class");
+}
+
+#[test]
+fn test_completion_no_snippet_inside_existing_header() {
+    // The cursor sits in the name of a header that is already written
+    let code = "fn existing(a: int):\n    pass\n";
+    assert!(pain_lsp::declaration_snippet(code, tower_lsp::lsp_types::Position { line: 0, character: 8 }).is_none());
+
+    // The rest of a name being typed is replaced along with the header
+    let code = "class Point\n";
+    let item = pain_lsp::declaration_snippet(code, tower_lsp::lsp_types::Position { line: 0, character: 8 })
+        .expect("Should offer a class skeleton mid-name");
+    match item.text_edit {
+        Some(tower_lsp::lsp_types::CompletionTextEdit::Edit(edit)) => {
+            assert_eq!(edit.range.end.character, 11, "Should replace the whole name");
+        }
+        other => panic!("Expected a text edit, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_completion_no_snippet_inside_function() {
+    // Indented lines are not top level
+    let code = "fn main():\n    fn \n";
+    assert!(pain_lsp::declaration_snippet(code, tower_lsp::lsp_types::Position { line: 1, character: 7 }).is_none());
+}
+
+#[tokio::test]
+async fn test_completion_fn_snippet_replaces_global_list() {
+    let backend = pain_lsp::Backend::detached();
+    let code = "fn helper() -> int:\n    return 1\n\nfn \n";
+    let (parse_result, _) = parse_with_recovery("fn helper() -> int:\n    return 1\n");
+    let program = parse_result.expect("helper should parse");
+
+    let items = backend.get_completions(&program, code, tower_lsp::lsp_types::Position { line: 3, character: 3 });
+    assert_eq!(items.len(), 1, "Only the skeleton should be offered");
+    assert_eq!(items[0].kind, Some(tower_lsp::lsp_types::CompletionItemKind::SNIPPET));
+}
+
+#[tokio::test]
+async fn test_completion_list_index_ranks_int_variables_first() {
+    let code = r#"
+fn main():
+    let numbers = [1, 2, 3]
+    let label = "first"
+    let count = 0
+    print(numbers[count])
+"#;
+
+    let (parse_result, _) = parse_with_recovery(code);
+    let program = parse_result.expect("code should parse");
+    let backend = pain_lsp::Backend::detached();
+    // Cursor right after `numbers[`
+    let items = backend.get_completions(&program, code, tower_lsp::lsp_types::Position { line: 5, character: 18 });
+
+    let sort_text_of = |label: &str| {
+        items
+            .iter()
+            .find(|item| item.label == label)
+            .and_then(|item| item.sort_text.clone())
+            .unwrap_or_else(|| panic!("`{}` should be offered", label))
+    };
+    assert!(
+        sort_text_of("count") < sort_text_of("label"),
+        "An int variable should outrank a str variable inside list indexing"
+    );
+}
+
+#[tokio::test]
+async fn test_completion_call_argument_ranks_matching_type_first() {
+    let code = r#"
+fn add(a: int, b: int) -> int:
+    return a + b
+
+fn main():
+    let label = "first"
+    let count = 0
+    print(add(count, 1))
+"#;
+
+    let (parse_result, _) = parse_with_recovery(code);
+    let program = parse_result.expect("code should parse");
+    let backend = pain_lsp::Backend::detached();
+    // Cursor right after `add(`
+    let items = backend.get_completions(&program, code, tower_lsp::lsp_types::Position { line: 7, character: 14 });
+
+    let sort_text_of = |label: &str| {
+        items
+            .iter()
+            .find(|item| item.label == label)
+            .and_then(|item| item.sort_text.clone())
+            .unwrap_or_else(|| panic!("`{}` should be offered", label))
+    };
+    assert!(
+        sort_text_of("count") < sort_text_of("label"),
+        "An int variable should outrank a str variable for an int parameter"
+    );
+}
+
+#[tokio::test]
+async fn test_completion_user_len_shadows_stdlib() {
+    let code = r#"
+fn len(items: list[int]) -> int:
+    return 0
+
+class Bag:
+    fn print() -> int:
+        return 1
+
+fn main():
+    let bag = Bag()
+    pass
+"#;
+
+    let (parse_result, _) = parse_with_recovery(code);
+    let program = parse_result.expect("code should parse");
+    let backend = pain_lsp::Backend::detached();
+    let items = backend.get_completions(&program, code, tower_lsp::lsp_types::Position { line: 10, character: 4 });
+
+    let lens: Vec<_> = items.iter().filter(|item| item.label == "len").collect();
+    assert_eq!(lens.len(), 1, "Only the user-defined `len` should be offered");
+    assert_eq!(lens[0].detail.as_deref(), Some(format_function_signature(match &program.items[0] {
+        Item::Function(func) => func,
+        _ => panic!("first item should be `len`"),
+    }).as_str()));
+
+    // `Bag.print` is labeled with its class and must not hide the stdlib `print`
+    assert!(items.iter().any(|item| item.label == "print"), "stdlib `print` should still be offered");
+    assert!(items.iter().any(|item| item.label == "Bag.print"));
+}
+
+#[tokio::test]
+async fn test_completion_documentation_markdown_when_supported() {
+    use tower_lsp::lsp_types::*;
+
+    let code = r#"
+/// Adds one
+fn inc(n: int) -> int:
+    return n + 1
+
+fn main():
+    pass
+"#;
+    let (parse_result, _) = parse_with_recovery(code);
+    let program = parse_result.expect("code should parse");
+    let backend = pain_lsp::Backend::detached();
+    *backend.client_capabilities.write().unwrap() = ClientCapabilities {
+        text_document: Some(TextDocumentClientCapabilities {
+            completion: Some(CompletionClientCapabilities {
+                completion_item: Some(CompletionItemCapability {
+                    documentation_format: Some(vec![MarkupKind::Markdown, MarkupKind::PlainText]),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    let items = backend.get_completions(&program, code, Position { line: 6, character: 4 });
+    for label in ["inc", "print"] {
+        let item = items.iter().find(|item| item.label == label).expect("function should be offered");
+        match &item.documentation {
+            Some(Documentation::MarkupContent(markup)) => {
+                assert_eq!(markup.kind, MarkupKind::Markdown);
+                assert!(
+                    markup.value.starts_with(&format!("```pain\n{}\n```", item.detail.as_deref().unwrap())),
+                    "Docs should open with the fenced signature: {}",
+                    markup.value
+                );
+            }
+            other => panic!("Expected Markdown documentation for `{}`, got {:?}", label, other),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_completion_documentation_plain_by_default() {
+    let code = "fn main():\n    pass\n";
+    let (parse_result, _) = parse_with_recovery(code);
+    let program = parse_result.expect("code should parse");
+    let backend = pain_lsp::Backend::detached();
+
+    let items = backend.get_completions(&program, code, tower_lsp::lsp_types::Position { line: 1, character: 4 });
+    let print = items.iter().find(|item| item.label == "print").expect("print should be offered");
+    assert!(matches!(print.documentation, Some(tower_lsp::lsp_types::Documentation::String(_))));
+}
+
+#[tokio::test]
+async fn test_completion_for_loop_variable_detail() {
+    let code = r#"
+fn main():
+    for i in [1, 2, 3]:
+        print(i)
+"#;
+
+    let (parse_result, _) = parse_with_recovery(code);
+    let program = parse_result.expect("code should parse");
+    let backend = pain_lsp::Backend::detached();
+    let items = backend.get_completions(&program, code, tower_lsp::lsp_types::Position { line: 3, character: 8 });
+
+    let detail = items.iter().find(|item| item.label == "i").and_then(|item| item.detail.clone());
+    assert_eq!(detail.as_deref(), Some("i: int"));
+}
+
+#[test]
+fn test_completion_type_annotation_context() {
+    let code = "class Point:\n    let x: int\n\nfn main():\n    let p: P\n";
+    let items = pain_lsp::type_annotation_completions(code, tower_lsp::lsp_types::Position { line: 4, character: 12 })
+        .expect("Cursor after `let p:` is an annotation");
+
+    let labels: Vec<&str> = items.iter().map(|item| item.label.as_str()).collect();
+    assert!(labels.contains(&"Point"), "Classes should be offered: {:?}", labels);
+    assert!(labels.contains(&"float32") && labels.contains(&"Tensor"), "Builtins should be offered: {:?}", labels);
+    assert!(!labels.contains(&"print"), "Functions should not be offered: {:?}", labels);
+    assert!(!labels.contains(&"main"), "Functions should not be offered: {:?}", labels);
+}
+
+#[test]
+fn test_completion_parameter_and_return_annotations() {
+    let code = "fn scale(factor: , values: list[int]) -> \n";
+    let position = |character| tower_lsp::lsp_types::Position { line: 0, character };
+    assert!(pain_lsp::type_annotation_completions(code, position(17)).is_some(), "Parameter annotation");
+    assert!(pain_lsp::type_annotation_completions(code, position(41)).is_some(), "Return type");
+    assert!(pain_lsp::type_annotation_completions("    print(x)\n", position(11)).is_none());
+}
+
+#[tokio::test]
+async fn test_completion_insert_replace_mid_word() {
+    use tower_lsp::lsp_types::*;
+
+    let code = "fn compute_total() -> int:\n    return 1\n\nfn main():\n    let t = compal\n";
+    let (parse_result, _) = parse_with_recovery(code);
+    let program = parse_result.expect("code should parse");
+    let backend = pain_lsp::Backend::detached();
+    *backend.client_capabilities.write().unwrap() = ClientCapabilities {
+        text_document: Some(TextDocumentClientCapabilities {
+            completion: Some(CompletionClientCapabilities {
+                completion_item: Some(CompletionItemCapability {
+                    insert_replace_support: Some(true),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    // Cursor between `comp` and `al`
+    let items = backend.get_completions(&program, code, Position { line: 4, character: 16 });
+    let item = items.iter().find(|item| item.label == "compute_total").expect("function should be offered");
+    match &item.text_edit {
+        Some(CompletionTextEdit::InsertAndReplace(edit)) => {
+            assert_eq!(edit.new_text, "compute_total");
+            assert_eq!(edit.insert, Range::new(Position::new(4, 12), Position::new(4, 16)));
+            assert_eq!(edit.replace, Range::new(Position::new(4, 12), Position::new(4, 18)));
+        }
+        other => panic!("Expected an insert/replace edit, got {:?}", other),
+    }
+
+    // Without client support items keep the default label insertion
+    let plain = pain_lsp::Backend::detached();
+    let items = plain.get_completions(&program, code, Position { line: 4, character: 16 });
+    assert!(items.iter().all(|item| item.text_edit.is_none()));
+}
+
+#[tokio::test]
+async fn test_completion_insert_replace_in_annotations_and_attributes() {
+    use tower_lsp::lsp_types::*;
+    use tower_lsp::LanguageServer;
+
+    let backend = pain_lsp::Backend::detached();
+    *backend.client_capabilities.write().unwrap() = ClientCapabilities {
+        text_document: Some(TextDocumentClientCapabilities {
+            completion: Some(CompletionClientCapabilities {
+                completion_item: Some(CompletionItemCapability {
+                    insert_replace_support: Some(true),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let uri = Url::parse("file:///annotations.pain").unwrap();
+    let code = "@depcated\nfn main():\n    let ratio: flo64 = 1.0\n";
+    backend
+        .did_open(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "pain".to_string(),
+                version: 1,
+                text: code.to_string(),
+            },
+        })
+        .await;
+    let edit_of = |position: Position, label: &'static str| {
+        let backend = backend.clone();
+        let uri = uri.clone();
+        async move {
+            let response = backend
+                .completion(CompletionParams {
+                    text_document_position: TextDocumentPositionParams {
+                        text_document: TextDocumentIdentifier { uri },
+                        position,
+                    },
+                    work_done_progress_params: WorkDoneProgressParams::default(),
+                    partial_result_params: PartialResultParams::default(),
+                    context: None,
+                })
+                .await
+                .unwrap();
+            let items = match response {
+                Some(CompletionResponse::Array(items)) => items,
+                Some(CompletionResponse::List(list)) => list.items,
+                None => panic!("Expected completion items"),
+            };
+            let item = items.into_iter().find(|item| item.label == label).expect("item should be offered");
+            match item.text_edit {
+                Some(CompletionTextEdit::InsertAndReplace(edit)) => edit,
+                other => panic!("Expected an insert/replace edit, got {:?}", other),
+            }
+        }
+    };
+
+    // Cursor between `flo` and `64`
+    let edit = edit_of(Position::new(2, 18), "float64").await;
+    assert_eq!(edit.insert, Range::new(Position::new(2, 15), Position::new(2, 18)));
+    assert_eq!(edit.replace, Range::new(Position::new(2, 15), Position::new(2, 20)));
+
+    // Cursor between `@dep` and `cated`
+    let edit = edit_of(Position::new(0, 4), "deprecated").await;
+    assert_eq!(edit.insert, Range::new(Position::new(0, 1), Position::new(0, 4)));
+    assert_eq!(edit.replace, Range::new(Position::new(0, 1), Position::new(0, 9)));
+}
+
+#[tokio::test]
+async fn test_completion_uses_recovered_items_after_syntax_error() {
+    let code = "fn helper(x: int) -> int:\n    return x + 1\n\nfn broken(:\n    let = \n";
+    let backend = pain_lsp::Backend::detached();
+    let uri = url::Url::parse("file:///broken.pain").unwrap();
+
+    let parsed = backend.get_or_parse(&uri, code).await;
+    assert!(parsed.program.is_none(), "The whole document should not parse");
+    let program = parsed.recovered.expect("Items before the error should be recovered");
+
+    let items = backend.get_completions(&program, code, tower_lsp::lsp_types::Position { line: 4, character: 4 });
+    assert!(items.iter().any(|item| item.label == "helper"), "Functions that parsed should still be offered");
+}
+
+#[test]
+fn test_completion_attributes_after_at() {
+    let code = "@inline\nfn fast() -> int:\n    return 1\n\n@\nfn main():\n    pass\n";
+    let position = |line, character| tower_lsp::lsp_types::Position { line, character };
+    let items = pain_lsp::attribute_completions(code, position(4, 1)).expect("`@` starts an attribute");
+
+    assert!(items.iter().all(|item| item.kind == Some(tower_lsp::lsp_types::CompletionItemKind::PROPERTY)));
+    let labels: Vec<&str> = items.iter().map(|item| item.label.as_str()).collect();
+    assert!(labels.contains(&"inline"), "Known attributes should be offered: {:?}", labels);
+    assert!(!labels.contains(&"print") && !labels.contains(&"fast"), "Globals should not be offered: {:?}", labels);
+    assert_eq!(labels.iter().filter(|label| **label == "inline").count(), 1, "Used attributes are not repeated");
+
+    // `@` elsewhere is not an attribute
+    assert!(pain_lsp::attribute_completions("fn main():\n    let x = a @\n", position(1, 15)).is_none());
+}
+
+#[tokio::test]
+async fn test_completion_ranks_recently_used_variables_first() {
+    use tower_lsp::lsp_types::*;
+    use tower_lsp::LanguageServer;
+
+    let code = "fn main():\n    let alpha = 1\n    let beta = 2\n    print(alpha)\n    print(beta)\n    \n";
+    let backend = pain_lsp::Backend::detached();
+    let uri = Url::parse("file:///recent.pain").unwrap();
+    backend
+        .did_open(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "pain".to_string(),
+                version: 1,
+                text: code.to_string(),
+            },
+        })
+        .await;
+
+    let hover_at = |line, character| HoverParams {
+        text_document_position_params: TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            position: Position { line, character },
+        },
+        work_done_progress_params: WorkDoneProgressParams::default(),
+    };
+    let sort_texts = || async {
+        let response = backend
+            .completion(CompletionParams {
+                text_document_position: TextDocumentPositionParams {
+                    text_document: TextDocumentIdentifier { uri: uri.clone() },
+                    position: Position { line: 5, character: 4 },
+                },
+                work_done_progress_params: WorkDoneProgressParams::default(),
+                partial_result_params: PartialResultParams::default(),
+                context: None,
+            })
+            .await
+            .unwrap();
+        let Some(CompletionResponse::Array(items)) = response else { panic!("Expected completion items") };
+        let sort_text = |label: &str| {
+            let item = items.iter().find(|item| item.label == label).expect("variable should be offered");
+            item.sort_text.clone().unwrap_or_else(|| item.label.clone())
+        };
+        (sort_text("alpha"), sort_text("beta"))
+    };
+
+    // Looking at `alpha`, then `beta`: `beta` is the most recent
+    backend.hover(hover_at(3, 11)).await.unwrap();
+    backend.hover(hover_at(4, 11)).await.unwrap();
+    let (alpha, beta) = sort_texts().await;
+    assert!(beta < alpha, "Most recently used should sort first: alpha={}, beta={}", alpha, beta);
+
+    // Using `alpha` again puts it back in front
+    backend.hover(hover_at(3, 11)).await.unwrap();
+    let (alpha, beta) = sort_texts().await;
+    assert!(alpha < beta, "Most recently used should sort first: alpha={}, beta={}", alpha, beta);
+}
+
+#[test]
+fn test_recent_identifiers_decay_after_edits() {
+    let mut recent = pain_lsp::RecentIdentifiers::default();
+    recent.touch("alpha");
+    recent.touch("beta");
+    assert_eq!(recent.rank("beta"), Some(0));
+    assert_eq!(recent.rank("alpha"), Some(1));
+
+    for _ in 0..1000 {
+        recent.decay();
+    }
+    assert_eq!(recent.rank("alpha"), None, "Entries are forgotten after many edits");
+}
+
+#[test]
+fn test_completion_context_on_crlf_document() {
+    let code = "class Point:\r\n    let x: int\r\n\r\nfn main():\r\n    let p: P\r\n    @\r\n";
+    let position = |line, character| tower_lsp::lsp_types::Position { line, character };
+    assert!(pain_lsp::type_annotation_completions(code, position(4, 12)).is_some());
+    assert!(pain_lsp::attribute_completions(code, position(5, 5)).is_some());
+}
+
+#[test]
+fn test_completion_ranks_bool_literals_in_while_condition() {
+    let code = "fn main():\n    let flag = true\n    let count = 0\n    while flag:\n        pass\n";
+    let (parse_result, _) = parse_with_recovery(code);
+    let program = parse_result.expect("code should parse");
+    let backend = pain_lsp::Backend::detached();
+
+    // Right after `while `
+    let items = backend.get_completions(&program, code, tower_lsp::lsp_types::Position { line: 3, character: 10 });
+    let sort_text_of = |label: &str| {
+        let item = items.iter().find(|item| item.label == label).unwrap_or_else(|| panic!("{} should be offered", label));
+        item.sort_text.clone().unwrap_or_else(|| item.label.clone())
+    };
+    for literal in ["true", "false"] {
+        assert!(sort_text_of(literal) < sort_text_of("count"), "{} should rank above unrelated variables", literal);
+        assert!(sort_text_of(literal) < sort_text_of("print"), "{} should rank above functions", literal);
+    }
+    assert!(sort_text_of("flag") < sort_text_of("count"), "bool variables fit the condition too");
+}
+
+#[tokio::test]
+async fn test_completion_after_return_ranks_return_type_first() {
+    let code = "fn count_items() -> int:\n    let label = \"items\"\n    let total = 3\n    return total\n";
+    let (parse_result, _) = parse_with_recovery(code);
+    let program = parse_result.expect("code should parse");
+    let backend = pain_lsp::Backend::detached();
+    // Cursor right after `return `
+    let items = backend.get_completions(&program, code, tower_lsp::lsp_types::Position { line: 3, character: 11 });
+
+    let sort_text_of = |label: &str| {
+        items
+            .iter()
+            .find(|item| item.label == label)
+            .and_then(|item| item.sort_text.clone())
+            .unwrap_or_else(|| panic!("`{}` should be offered", label))
+    };
+    assert!(
+        sort_text_of("total") < sort_text_of("label"),
+        "An int variable should outrank a str variable after `return` in an `-> int` function"
+    );
+    assert!(sort_text_of("0") < sort_text_of("label"), "An int literal should be offered first too");
+}
+
+#[tokio::test]
+async fn test_completion_after_return_offers_true_in_bool_function() {
+    let code = "fn ready() -> bool:\n    let label = \"items\"\n    return label\n";
+    let (parse_result, _) = parse_with_recovery(code);
+    let program = parse_result.expect("code should parse");
+    let backend = pain_lsp::Backend::detached();
+    // Cursor right after `return `
+    let items = backend.get_completions(&program, code, tower_lsp::lsp_types::Position { line: 2, character: 11 });
+
+    let literals: Vec<_> = items.iter().filter(|item| item.label == "true").collect();
+    assert_eq!(literals.len(), 1, "`true` should be offered exactly once");
+    assert_eq!(literals[0].kind, Some(tower_lsp::lsp_types::CompletionItemKind::VALUE));
+    let label_sort = items
+        .iter()
+        .find(|item| item.label == "label")
+        .and_then(|item| item.sort_text.clone())
+        .expect("`label` should be offered");
+    assert!(
+        literals[0].sort_text.as_deref().expect("literal should be ranked") < label_sort.as_str(),
+        "A bool literal should outrank a str variable after `return` in an `-> bool` function"
+    );
+}
+
+#[test]
+fn test_expected_type_from_annotation_and_return() {
+    use pain_lsp::expected_type_at;
+    use tower_lsp::lsp_types::Position;
+
+    let code = "fn ready() -> bool:\n    let limit: int = 3\n    return limit > 2\n";
+    let (parse_result, _) = parse_with_recovery(code);
+    let program = parse_result.expect("code should parse");
+    assert!(matches!(expected_type_at(&program, code, Position { line: 1, character: 21 }), Some(Type::Int)));
+    assert!(matches!(expected_type_at(&program, code, Position { line: 2, character: 11 }), Some(Type::Bool)));
+    assert!(expected_type_at(&program, code, Position { line: 2, character: 17 }).is_none());
+}
+
+#[test]
+fn test_completion_excludes_name_being_declared() {
+    let code = "fn main():\n    let myCount = 1\n    let myVar = my\n";
+    let (parse_result, _) = parse_with_recovery(code);
+    let program = parse_result.expect("code should parse");
+    let backend = pain_lsp::Backend::detached();
+
+    let items = backend.get_completions(&program, code, tower_lsp::lsp_types::Position { line: 2, character: 18 });
+    let labels: Vec<&str> = items.iter().map(|item| item.label.as_str()).collect();
+    assert!(!labels.contains(&"myVar"), "The variable being declared should not be offered: {:?}", labels);
+    assert!(labels.contains(&"myCount"), "Other variables are still offered: {:?}", labels);
+}
+
+#[test]
+fn test_completion_keeps_shadowed_name_being_redeclared() {
+    let code = "fn main():\n    let total = 1\n    let total = tot\n";
+    let (parse_result, _) = parse_with_recovery(code);
+    let program = parse_result.expect("code should parse");
+    let backend = pain_lsp::Backend::detached();
+
+    // The earlier `total` is still usable in the new initializer
+    let items = backend.get_completions(&program, code, tower_lsp::lsp_types::Position { line: 2, character: 19 });
+    assert!(items.iter().any(|item| item.label == "total"));
+}
+
+#[tokio::test]
+async fn test_class_completion_retriggers_suggest() {
+    let code = "class Counter:\n    let count: int\n\nfn main():\n    let total = 1\n    \n";
+    let (parse_result, _) = parse_with_recovery(code);
+    let program = parse_result.expect("code should parse");
+    let backend = pain_lsp::Backend::detached();
+
+    let items = backend.get_completions(&program, code, tower_lsp::lsp_types::Position { line: 5, character: 4 });
+    let class = items.iter().find(|item| item.label == "Counter").expect("class should be offered");
+    let command = class.command.as_ref().expect("Accepting a class should re-open completion");
+    assert_eq!(command.command, pain_lsp::TRIGGER_SUGGEST_COMMAND);
+
+    let variable = items.iter().find(|item| item.label == "total").expect("variable should be offered");
+    assert!(variable.command.is_none(), "Variables should not retrigger completion");
+}
+
+#[test]
+fn test_truncated_completion_list_is_incomplete() {
+    let stdlib = get_stdlib_functions();
+    let last = stdlib.last().expect("stdlib should not be empty").name.clone();
+    let typed = &last[..last.len() - 1];
+    let code = format!("fn main():\n    {}\n", typed);
+    let (parse_result, _) = parse_with_recovery("fn main():\n    pass\n");
+    let program = parse_result.expect("code should parse");
+    let backend = pain_lsp::Backend::detached();
+
+    let cursor = tower_lsp::lsp_types::Position { line: 1, character: 4 + typed.len() as u32 };
+    let list = backend.get_completion_list(&program, &code, cursor);
+    assert!(list.is_incomplete, "A list filtered by the typed prefix should be incomplete");
+    assert!(
+        list.items.iter().all(|item| item.label.to_lowercase().starts_with(&typed.to_lowercase())),
+        "Only items matching the typed prefix should be offered"
+    );
+    assert!(list.items.iter().any(|item| item.label == last), "`{}` should be offered", last);
+
+    // Annotation completions always list every candidate
+    let annotation = "fn f(x: ) -> int:\n    return 1\n";
+    let list = backend.get_completion_list(&program, annotation, tower_lsp::lsp_types::Position { line: 0, character: 8 });
+    assert!(!list.is_incomplete);
+}
+
+#[test]
+fn test_member_completion_on_class_and_instance() {
+    let code = r#"
+class Point:
+    let x: int
+    let y: int
+
+    fn new(x: int, y: int) -> Point:
+        let p = Point()
+        p.x = x
+        p.y = y
+        return p
+
+    fn moved(dx: int) -> Point:
+        return Point.new(dx, dx)
+
+    fn distance() -> float64:
+        return 0.0
+
+fn main():
+    let p: Point = Point.new(1, 2)
+    let d = p.distance()
+"#;
+    let (parse_result, _) = parse_with_recovery(code);
+    let program = parse_result.expect("code should parse");
+    let backend = pain_lsp::Backend::detached();
+    let labels = |line: u32, character: u32| -> Vec<(String, Option<tower_lsp::lsp_types::CompletionItemKind>)> {
+        let position = tower_lsp::lsp_types::Position { line, character };
+        backend.get_completion_list(&program, code, position).items.into_iter().map(|item| (item.label, item.kind)).collect()
+    };
+    use tower_lsp::lsp_types::CompletionItemKind;
+
+    // `Point.` on line 18 - associated functions only
+    let on_class = labels(18, 25);
+    assert!(on_class.contains(&("new".to_string(), Some(CompletionItemKind::FUNCTION))), "{:?}", on_class);
+    assert!(on_class.contains(&("moved".to_string(), Some(CompletionItemKind::FUNCTION))), "{:?}", on_class);
+    assert!(!on_class.iter().any(|(label, _)| label == "x" || label == "distance"), "{:?}", on_class);
+
+    // `p.` on line 19 - fields and every method, including those returning a `Point`
+    let on_instance = labels(19, 14);
+    for member in ["x", "y", "distance", "moved", "new"] {
+        assert!(on_instance.iter().any(|(label, _)| label == member), "`{}` missing from {:?}", member, on_instance);
+    }
+    assert!(on_instance.contains(&("moved".to_string(), Some(CompletionItemKind::METHOD))), "{:?}", on_instance);
+}
+
+#[test]
+fn test_completion_max_items_setting() {
+    let code = "fn main():\n    \n";
+    let (parse_result, _) = parse_with_recovery(code);
+    let program = parse_result.expect("code should parse");
+    let backend = pain_lsp::Backend::detached();
+    let cursor = tower_lsp::lsp_types::Position { line: 1, character: 4 };
+
+    let full = backend.get_completion_list(&program, code, cursor);
+    assert!(full.items.len() > 5, "Stdlib and keywords should give more than five items");
+
+    let mut settings = backend.settings();
+    settings.completion_max_items = 5;
+    backend.update_settings(settings);
+    let list = backend.get_completion_list(&program, code, cursor);
+    assert_eq!(list.items.len(), 5, "Completion should stop at completionMaxItems");
+    assert!(list.is_incomplete, "A cut list should be incomplete");
+}
+
+#[tokio::test]
+async fn test_completion_commit_characters() {
+    use tower_lsp::lsp_types::*;
+
+    let code = "class Counter:\n    let count: int\n\nfn add(a: int, b: int) -> int:\n    return a + b\n\nfn main():\n    let total = 1\n    \n";
+    let (parse_result, _) = parse_with_recovery(code);
+    let program = parse_result.expect("code should parse");
+    let backend = pain_lsp::Backend::detached();
+    *backend.client_capabilities.write().unwrap() = ClientCapabilities {
+        text_document: Some(TextDocumentClientCapabilities {
+            completion: Some(CompletionClientCapabilities {
+                completion_item: Some(CompletionItemCapability {
+                    commit_characters_support: Some(true),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    let items = backend.get_completions(&program, code, Position { line: 8, character: 4 });
+    let commit = |label: &str| {
+        items
+            .iter()
+            .find(|item| item.label == label)
+            .unwrap_or_else(|| panic!("`{}` should be offered", label))
+            .commit_characters
+            .clone()
+    };
+    assert_eq!(commit("add"), Some(vec!["(".to_string()]));
+    assert_eq!(commit("print"), Some(vec!["(".to_string()]));
+    assert_eq!(commit("Counter"), Some(vec![".".to_string()]));
+    assert_eq!(commit("total"), None);
+
+    // Not advertised without client support
+    let plain = pain_lsp::Backend::detached();
+    let items = plain.get_completions(&program, code, Position { line: 8, character: 4 });
+    assert!(items.iter().all(|item| item.commit_characters.is_none()));
+}
+
+#[test]
+fn test_fallback_print_matches_stdlib_signature() {
+    let backend = pain_lsp::Backend::detached();
+    let stdlib_print = get_stdlib_functions()
+        .into_iter()
+        .find(|func| func.name == "print")
+        .expect("The stdlib should have print");
+    let expected = pain_lsp::format_stdlib_signature(&stdlib_print.name, &stdlib_print.params, &stdlib_print.return_type);
+
+    let items = backend.get_basic_completions();
+    let print = items.iter().find(|item| item.label == "print").expect("The fallback list should offer print");
+    assert_eq!(print.detail.as_deref(), Some(expected.as_str()));
+}
+
+#[tokio::test]
+async fn test_member_completion_on_trailing_dot_at_end_of_file() {
+    use tower_lsp::lsp_types::*;
+    use tower_lsp::LanguageServer;
+
+    // The last character of the document is the `.` - no newline follows
+    let code = "class Point:\n    let x: int\n    let y: int\n\nfn main():\n    let p: Point = Point()\n    p.";
+    assert!(pain_lsp::parse_without_dangling_dot(code, Position { line: 6, character: 6 }).is_some());
+
+    let backend = pain_lsp::Backend::detached();
+    let uri = Url::parse("file:///trailing_dot.pain").unwrap();
+    backend
+        .did_open(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "pain".to_string(),
+                version: 1,
+                text: code.to_string(),
+            },
+        })
+        .await;
+    let response = backend
+        .completion(CompletionParams {
+            text_document_position: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri },
+                position: Position { line: 6, character: 6 },
+            },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+            context: None,
+        })
+        .await
+        .unwrap();
+    let items = match response {
+        Some(CompletionResponse::Array(items)) => items,
+        Some(CompletionResponse::List(list)) => list.items,
+        None => panic!("Expected completion items"),
+    };
+    let labels: Vec<&str> = items.iter().map(|item| item.label.as_str()).collect();
+    assert!(labels.contains(&"x") && labels.contains(&"y"), "Fields of `p` should be offered: {:?}", labels);
+    assert!(!labels.contains(&"fn"), "Keywords are not members: {:?}", labels);
+}
+
+#[test]
+fn test_idiom_snippets_follow_context() {
+    use tower_lsp::lsp_types::*;
+
+    let code = "fn helper():\n    \n\n";
+    let (parse_result, _) = parse_with_recovery(code);
+    let program = parse_result.expect("code should parse");
+    let backend = pain_lsp::Backend::detached();
+    let snippet = |items: &[CompletionItem], label: &str| {
+        items.iter().find(|item| item.label == label && item.kind == Some(CompletionItemKind::SNIPPET)).cloned()
+    };
+
+    let top_level = backend.get_completions(&program, code, Position { line: 2, character: 0 });
+    let main = snippet(&top_level, "main").expect("The main function idiom should be offered at top level");
+    assert_eq!(main.insert_text_format, Some(InsertTextFormat::SNIPPET));
+    assert!(main.insert_text.as_deref().is_some_and(|text| text.starts_with("fn main():") && text.contains("${0")));
+    assert!(snippet(&top_level, "while counter").is_none(), "Loops belong in a function body");
+
+    let in_body = backend.get_completions(&program, code, Position { line: 1, character: 4 });
+    assert!(snippet(&in_body, "main").is_none(), "No `fn main` inside a function body");
+    assert!(snippet(&in_body, "while counter").is_some());
+}
+
+#[tokio::test]
+async fn test_cross_file_completion_resolves_requires_edit() {
+    use tower_lsp::lsp_types::*;
+    use tower_lsp::LanguageServer;
+
+    let backend = pain_lsp::Backend::detached();
+    let open = |uri: &Url, text: &str| DidOpenTextDocumentParams {
+        text_document: TextDocumentItem {
+            uri: uri.clone(),
+            language_id: "pain".to_string(),
+            version: 1,
+            text: text.to_string(),
+        },
+    };
+    let other = Url::parse("file:///project/geometry.pain").unwrap();
+    let main = Url::parse("file:///project/main.pain").unwrap();
+    backend.did_open(open(&other, "fn area(w: int, h: int) -> int:\n    return w * h\n")).await;
+    backend.did_open(open(&main, "fn main():\n    let size = ar\n")).await;
+
+    let request = || CompletionParams {
+        text_document_position: TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier { uri: main.clone() },
+            position: Position { line: 1, character: 17 },
+        },
+        work_done_progress_params: WorkDoneProgressParams::default(),
+        partial_result_params: PartialResultParams::default(),
+        context: None,
+    };
+    let response = backend.completion(request()).await.unwrap();
+    let Some(CompletionResponse::List(list)) = response else { panic!("Expected a completion list") };
+    // Only what matches `ar` is offered, from this file or another
+    assert!(list.is_incomplete);
+    assert!(list.items.iter().all(|item| item.label.to_lowercase().starts_with("ar")), "{:?}", list.items);
+    let items = list.items;
+    let area = items.iter().find(|item| item.label == "area").expect("`area` from the other file should be offered");
+    assert_eq!(area.detail.as_deref(), Some("from geometry.pain"));
+
+    let resolved = backend.completion_resolve(area.clone()).await.unwrap();
+    let edits = resolved.additional_text_edits.expect("Resolving should add the requires comment");
+    assert_eq!(edits.len(), 1);
+    assert_eq!(edits[0].new_text, "# requires geometry.pain\n");
+    assert_eq!(edits[0].range.start, Position { line: 0, character: 0 });
+
+    assert!(pain_lsp::requires_comment_edit("# requires geometry.pain\nfn main():\n    pass\n", "geometry.pain").is_none());
+
+    // Other files' symbols count toward the configured limit
+    let mut settings = backend.settings();
+    settings.completion_max_items = 1;
+    backend.update_settings(settings);
+    let Some(CompletionResponse::List(list)) = backend.completion(request()).await.unwrap() else {
+        panic!("Expected a completion list")
+    };
+    assert_eq!(list.items.len(), 1, "{:?}", list.items);
+}
+
+// Write one message framed as in the LSP base protocol
+async fn send_message(writer: &mut (impl tokio::io::AsyncWrite + Unpin), message: serde_json::Value) {
+    use tokio::io::AsyncWriteExt;
+
+    let body = message.to_string();
+    let framed = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+    writer.write_all(framed.as_bytes()).await.unwrap();
+}
+
+// Read one message framed as in the LSP base protocol
+async fn receive_message(reader: &mut (impl tokio::io::AsyncBufRead + Unpin)) -> serde_json::Value {
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt};
+
+    let mut length = 0;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).await.unwrap();
+        match header.trim_end() {
+            "" => break,
+            header => {
+                if let Some(value) = header.strip_prefix("Content-Length: ") {
+                    length = value.parse().unwrap();
+                }
+            }
+        }
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await.unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+#[tokio::test]
+async fn test_completion_streams_partial_results_with_token() {
+    use serde_json::json;
+
+    // A real server over in-memory pipes, so the `$/progress` notifications
+    // it sends can be read back
+    let (client_reader, server_writer) = tokio::io::duplex(1 << 16);
+    let (server_reader, mut client_writer) = tokio::io::duplex(1 << 16);
+    let (service, socket) = tower_lsp::LspService::new(pain_lsp::Backend::new);
+    tokio::spawn(tower_lsp::Server::new(server_reader, server_writer, socket).serve(service));
+    let mut client_reader = tokio::io::BufReader::new(client_reader);
+
+    let uri = "file:///partial.pain";
+    let code = "fn add(a: int, b: int) -> int:\n    return a + b\n\nfn main():\n    let total = 1\n    \n";
+    send_message(&mut client_writer, json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": { "capabilities": {} } })).await;
+    while receive_message(&mut client_reader).await["id"] != 1 {}
+    send_message(&mut client_writer, json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} })).await;
+    send_message(
+        &mut client_writer,
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": uri, "languageId": "pain", "version": 1, "text": code } },
+        }),
+    )
+    .await;
+    // Diagnostics are published once the document is stored
+    while receive_message(&mut client_reader).await["method"] != "textDocument/publishDiagnostics" {}
+
+    let position = json!({ "line": 5, "character": 4 });
+    let request = |id: u64| {
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": "textDocument/completion",
+            "params": { "textDocument": { "uri": uri }, "position": position },
+        })
+    };
+
+    send_message(&mut client_writer, request(2)).await;
+    let (result, groups) = receive_response(&mut client_reader, 2).await;
+    assert!(groups.is_empty(), "Without a token the list is sent in one response: {:?}", groups);
+    // A bare array when complete, a list when cut short
+    let incomplete = result.is_object();
+    let items = if incomplete { &result["items"] } else { &result };
+    let labels: Vec<&str> = items.as_array().unwrap().iter().filter_map(|item| item["label"].as_str()).collect();
+    assert!(labels.contains(&"add") && labels.contains(&"total"), "{:?}", labels);
+
+    let mut streaming = request(3);
+    streaming["params"]["partialResultToken"] = json!("partial");
+    send_message(&mut client_writer, streaming).await;
+    let (result, groups) = receive_response(&mut client_reader, 3).await;
+    assert_eq!(result["items"], json!([]), "Streamed items are not repeated in the response: {}", result);
+    assert_eq!(result["isIncomplete"], json!(incomplete));
+
+    // Functions of the document come first, then variables, then the rest
+    let group_of = |label: &str| groups.iter().position(|group| group.iter().any(|item| item.label == label));
+    assert_eq!(groups.len(), 3, "{:?}", groups);
+    assert_eq!(group_of("add"), Some(0), "{:?}", groups);
+    assert_eq!(group_of("total"), Some(1), "{:?}", groups);
+    let streamed: usize = groups.iter().map(Vec::len).sum();
+    assert_eq!(streamed, labels.len(), "Every item should be streamed once");
+}
+
+// Read up to the response to request `id`, returning its result and the
+// completion groups streamed with `$/progress` before it
+async fn receive_response(
+    reader: &mut (impl tokio::io::AsyncBufRead + Unpin),
+    id: u64,
+) -> (serde_json::Value, Vec<Vec<tower_lsp::lsp_types::CompletionItem>>) {
+    let mut groups = Vec::new();
+    loop {
+        let message = receive_message(reader).await;
+        if message["id"] == id {
+            return (message["result"].clone(), groups);
+        }
+        if message["method"] == "$/progress" {
+            groups.push(serde_json::from_value(message["params"]["value"].clone()).unwrap());
+        }
+    }
+}
+
+#[test]
+fn test_member_completion_in_constructor_lists_unassigned_fields_first() {
+    use tower_lsp::lsp_types::{CompletionItemKind, Position};
+
+    let code = r#"
+class Point:
+    let x: int
+    let y: int
+
+    fn new(x: int, y: int) -> Point:
+        let p = Point()
+        p.x = x
+        p.
+        return p
+
+    fn norm() -> int:
+        return 0
+"#;
+    let position = Position { line: 8, character: 10 };
+    let program = pain_lsp::parse_without_dangling_dot(code, position).expect("code should parse without the dot");
+    let backend = pain_lsp::Backend::detached();
+    let mut items = backend.get_completion_list(&program, code, position).items;
+    items.sort_by_key(|item| item.sort_text.clone().unwrap_or_else(|| item.label.clone()));
+
+    let labels: Vec<(&str, Option<CompletionItemKind>)> = items.iter().map(|item| (item.label.as_str(), item.kind)).collect();
+    assert_eq!(
+        labels,
+        vec![
+            ("y", Some(CompletionItemKind::FIELD)),
+            ("x", Some(CompletionItemKind::FIELD)),
+            ("new", Some(CompletionItemKind::METHOD)),
+            ("norm", Some(CompletionItemKind::METHOD)),
+        ],
+        "Unassigned fields first, then assigned ones, then methods"
+    );
+    assert_eq!(items[0].detail.as_deref(), Some("y: int (not yet assigned)"));
+    assert_eq!(items[1].detail.as_deref(), Some("x: int"));
+}
+
+#[test]
+fn test_map_index_completes_literal_keys() {
+    use tower_lsp::lsp_types::{CompletionItemKind, Position};
+
+    let code = "fn main():\n    let m = {\"a\": 1, \"b\": 2}\n    print(m[\"\n";
+    let items = pain_lsp::map_key_completions(code, Position { line: 2, character: 13 }).expect("m[\" should offer keys");
+    let labels: Vec<&str> = items.iter().map(|item| item.label.as_str()).collect();
+    assert_eq!(labels, vec!["a", "b"]);
+    assert!(items.iter().all(|item| item.kind == Some(CompletionItemKind::VALUE)));
+
+    // Entries added later make the literal incomplete
+    let dynamic = "fn main():\n    var m = {\"a\": 1}\n    m[\"c\"] = 3\n    print(m[\"\n";
+    assert!(pain_lsp::map_key_completions(dynamic, Position { line: 3, character: 13 }).is_none());
+
+    // An unterminated key ending in a multibyte character is skipped, not sliced
+    let unterminated = "fn main():\n    let m = {\"é\n    }\n    print(m[\"\n";
+    let items = pain_lsp::map_key_completions(unterminated, Position { line: 3, character: 13 }).unwrap_or_default();
+    assert!(items.is_empty(), "Only terminated keys are offered: {:?}", items);
+}
+
+#[tokio::test]
+async fn test_disabled_trigger_character_is_not_advertised() {
+    use tower_lsp::lsp_types::InitializeParams;
+    use tower_lsp::LanguageServer;
+
+    let default = pain_lsp::Backend::detached().initialize(InitializeParams::default()).await.unwrap();
+    let triggers = default.capabilities.completion_provider.and_then(|options| options.trigger_characters);
+    assert_eq!(triggers, Some(vec![".".to_string(), "@".to_string(), "\"".to_string()]));
+
+    let backend = pain_lsp::Backend::detached();
+    let result = backend
+        .initialize(InitializeParams {
+            initialization_options: Some(serde_json::json!({ "pain": { "completionTriggerCharacters": [".", "@"] } })),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    let triggers = result.capabilities.completion_provider.and_then(|options| options.trigger_characters);
+    assert_eq!(triggers, Some(vec![".".to_string(), "@".to_string()]), "`\"` was disabled");
+}
+
+#[test]
+fn test_prefix_matched_function_beyond_detail_budget_gets_signature() {
+    use tower_lsp::lsp_types::Position;
+
+    let mut code = String::new();
+    for i in 0..80 {
+        code.push_str(&format!("fn func_{}() -> int:\n    return {}\n\n", i, i));
+    }
+    code.push_str("fn target(count: int) -> int:\n    return count\n\n");
+    code.push_str("fn main():\n    tar\n");
+    let (parse_result, _) = parse_with_recovery(&code);
+    let program = parse_result.expect("code should parse");
+    let line = code.lines().count() as u32 - 1;
+
+    let backend = pain_lsp::Backend::detached();
+    let items = backend.get_completions(&program, &code, Position { line, character: 7 });
+    let target = items.iter().find(|item| item.label == "target").expect("`target` matches `tar`");
+    let func = program
+        .items
+        .iter()
+        .find_map(|item| match item {
+            Item::Function(func) if func.name == "target" => Some(func),
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!(target.detail, Some(format_function_signature(func)), "Declared 81st, but the only match");
+}
+
+#[test]
+fn test_completion_panic_falls_back_unless_no_catch_is_set() {
+    if std::env::var_os(pain_lsp::NO_CATCH_ENV).is_some() {
+        return;
+    }
+    let code = "fn main():\n    let total = 1\n    \n";
+    let (parse_result, _) = parse_with_recovery(code);
+    let program = parse_result.expect("code should parse");
+    let uri = url::Url::parse("file:///panics.pain").unwrap();
+
+    // A sink that panics stands in for a bug partway through completion
+    let emit: &mut dyn FnMut(Vec<tower_lsp::lsp_types::CompletionItem>) = &mut |_| panic!("completion bug");
+    let backend = pain_lsp::Backend::detached();
+    let list = backend.get_completions_for_document(
+        &uri,
+        &program,
+        code,
+        tower_lsp::lsp_types::Position { line: 2, character: 4 },
+        Vec::new(),
+        Some(emit),
+    );
+
+    let labels: Vec<&str> = list.items.iter().map(|item| item.label.as_str()).collect();
+    assert!(labels.contains(&"print"), "A caught panic should fall back to basic completions: {:?}", labels);
+    assert!(!labels.contains(&"total"), "The fallback knows nothing of the document: {:?}", labels);
+}
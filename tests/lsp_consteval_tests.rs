@@ -0,0 +1,35 @@
+// Constant folding tests - test the evaluator behind hover values
+
+use pain_lsp::consteval::{fold_expression, ConstValue};
+
+#[test]
+fn test_fold_arithmetic_precedence() {
+    assert_eq!(fold_expression("2 + 3 * 4"), Some(ConstValue::Int(14)));
+    assert_eq!(fold_expression("(2 + 3) * 4"), Some(ConstValue::Int(20)));
+    assert_eq!(fold_expression("-7 % 3"), Some(ConstValue::Int(-1)));
+    assert_eq!(fold_expression("1 + 0.5"), Some(ConstValue::Float(1.5)));
+}
+
+#[test]
+fn test_fold_boolean() {
+    assert_eq!(fold_expression("true and not false"), Some(ConstValue::Bool(true)));
+    assert_eq!(fold_expression("1 + 1 == 2 or false"), Some(ConstValue::Bool(true)));
+    assert_eq!(fold_expression("3 < 2"), Some(ConstValue::Bool(false)));
+}
+
+#[test]
+fn test_fold_string_concatenation() {
+    assert_eq!(
+        fold_expression(r#""hello, " + "world""#),
+        Some(ConstValue::Str("hello, world".to_string()))
+    );
+    assert_eq!(fold_expression(r#""a" + "b""#).unwrap().to_string(), r#""ab""#);
+}
+
+#[test]
+fn test_fold_bails_on_non_constants() {
+    assert_eq!(fold_expression("x + 1"), None, "Variables are not constant");
+    assert_eq!(fold_expression("len([1, 2]) + 1"), None, "Calls are not constant");
+    assert_eq!(fold_expression("1 / 0"), None, "Division by zero has no value");
+    assert_eq!(fold_expression(r#""a" + 1"#), None, "Mismatched operands have no value");
+}
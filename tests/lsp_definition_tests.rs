@@ -0,0 +1,186 @@
+// LSP go-to-definition tests - test symbol resolution
+
+use pain_compiler::parse_with_recovery;
+use pain_lsp::analysis::DocumentIndex;
+use tower_lsp::lsp_types::*;
+
+const POINT_CODE: &str = r#"
+class Point:
+    let x: int
+    let y: int
+
+    fn new(x: int, y: int) -> Point:
+        let p = Point()
+        p.x = x
+        p.y = y
+        return p
+
+    fn distance() -> float64:
+        return 0.0
+
+fn main():
+    let p: Point = Point.new(1, 2)
+    print(p.x)
+    let d = p.distance()
+"#;
+
+fn definition_at(code: &str, line: u32, character: u32) -> Option<Range> {
+    let (parse_result, _) = parse_with_recovery(code);
+    let program = parse_result.ok()?;
+    DocumentIndex::new(&program, code).find_definition(Position { line, character })
+}
+
+#[test]
+fn test_definition_class_field_access() {
+    // Cursor on `x` in `print(p.x)` (line 16)
+    let range = definition_at(POINT_CODE, 16, 12).expect("Should resolve field access");
+    assert_eq!(range.start, Position { line: 2, character: 8 }, "Should jump to `let x: int` in Point");
+}
+
+#[test]
+fn test_definition_class_method_access() {
+    // Cursor on `distance` in `p.distance()` (line 17)
+    let range = definition_at(POINT_CODE, 17, 15).expect("Should resolve method access");
+    assert_eq!(range.start, Position { line: 11, character: 7 }, "Should jump to `fn distance`");
+}
+
+#[test]
+fn test_definition_field_access_on_inferred_local() {
+    // Cursor on `y` in `p.y = y` inside `Point.new` where `p = Point()` (line 8)
+    let range = definition_at(POINT_CODE, 8, 10).expect("Should resolve field on inferred local");
+    assert_eq!(range.start, Position { line: 3, character: 8 }, "Should jump to `let y: int` in Point");
+}
+
+#[test]
+fn test_definition_static_method_access() {
+    // Cursor on `new` in `Point.new(1, 2)` (line 15)
+    let range = definition_at(POINT_CODE, 15, 26).expect("Should resolve static method access");
+    assert_eq!(range.start, Position { line: 5, character: 7 }, "Should jump to `fn new`");
+}
+
+#[test]
+fn test_definition_local_variable() {
+    let code = r#"
+fn main():
+    let total = 1
+    print(total)
+"#;
+
+    let range = definition_at(code, 3, 10).expect("Should resolve local variable");
+    assert_eq!(range.start, Position { line: 2, character: 8 });
+}
+
+#[test]
+fn test_definition_unknown_member() {
+    // Unknown members resolve to nothing rather than a wrong location
+    let code = r#"
+class Point:
+    let x: int
+
+fn main():
+    let p = Point()
+    print(p.z)
+"#;
+
+    assert!(definition_at(code, 6, 12).is_none());
+}
+
+#[test]
+fn test_definition_self_goes_to_enclosing_class() {
+    let code = r#"
+class Counter:
+    let count: int
+
+    fn increment():
+        self.count = self.count + 1
+"#;
+    // Cursor on the first `self` inside `Counter.increment` (line 5)
+    let range = definition_at(code, 5, 9).expect("`self` should resolve to its class");
+    assert_eq!(range.start, Position { line: 1, character: 6 }, "Should jump to `class Counter`");
+
+    // Fields on `self` still resolve to the field
+    let range = definition_at(code, 5, 14).expect("Should resolve field on self");
+    assert_eq!(range.start, Position { line: 2, character: 8 });
+}
+
+#[test]
+fn test_declaration_parameter_usage_goes_to_signature() {
+    let (parse_result, _) = parse_with_recovery(POINT_CODE);
+    let program = parse_result.expect("code should parse");
+    let index = DocumentIndex::new(&program, POINT_CODE);
+
+    // Cursor on `x` in `p.x = x` inside `Point.new` (line 7)
+    let position = Position { line: 7, character: 14 };
+    let range = index.find_declaration(position).expect("Should resolve parameter usage");
+    assert_eq!(range.start, Position { line: 5, character: 11 }, "Should jump to `x: int` in the signature");
+    assert_eq!(Some(range), index.find_definition(position), "Declaration and definition coincide");
+}
+
+fn moniker_at(code: &str, line: u32, character: u32) -> Option<Moniker> {
+    let (parse_result, _) = parse_with_recovery(code);
+    let program = parse_result.ok()?;
+    let symbol = DocumentIndex::new(&program, code).resolve_symbol(Position { line, character })?;
+    Some(pain_lsp::symbol_moniker("geometry/point", &symbol))
+}
+
+#[test]
+fn test_moniker_method_is_exported() {
+    // Cursor on `distance` in `p.distance()` (line 17)
+    let moniker = moniker_at(POINT_CODE, 17, 15).expect("Should resolve method access");
+    assert_eq!(moniker.scheme, "pain");
+    assert_eq!(moniker.identifier, "geometry/point::Point::distance");
+    assert_eq!(moniker.kind, Some(MonikerKind::Export));
+}
+
+#[test]
+fn test_moniker_local_is_local() {
+    // Cursor on `d` in `let d = p.distance()` (line 17)
+    let moniker = moniker_at(POINT_CODE, 17, 8).expect("Should resolve local declaration");
+    assert_eq!(moniker.identifier, "geometry/point::main::d");
+    assert_eq!(moniker.kind, Some(MonikerKind::Local));
+    assert_eq!(moniker.unique, UniquenessLevel::Document);
+}
+
+#[test]
+fn test_module_name_relative_to_root() {
+    let roots = vec![Url::parse("file:///work/project/").unwrap()];
+    let inside = Url::parse("file:///work/project/geometry/point.pain").unwrap();
+    let outside = Url::parse("file:///tmp/scratch.pain").unwrap();
+
+    assert_eq!(pain_lsp::workspace::module_name(&inside, &roots), "geometry/point");
+    assert_eq!(pain_lsp::workspace::module_name(&outside, &roots), "scratch");
+}
+
+#[test]
+fn test_implementations_list_every_class_defining_the_method() {
+    let code = r#"
+class Square:
+    let side: int
+
+    fn area() -> int:
+        return self.side * self.side
+
+class Circle:
+    let radius: int
+
+    fn area() -> int:
+        return 3 * self.radius * self.radius
+
+fn total(s: Square) -> int:
+    return s.area()
+"#;
+    let (parse_result, _) = parse_with_recovery(code);
+    let program = parse_result.expect("code should parse");
+
+    // Cursor on `area` in `s.area()` (line 14)
+    let (name, ranges) = pain_lsp::method_implementations(&program, code, Position { line: 14, character: 14 })
+        .expect("Should resolve the method call");
+    assert_eq!(name, "area");
+    let starts: Vec<Position> = ranges.iter().map(|range| range.start).collect();
+    assert_eq!(starts, vec![Position { line: 4, character: 7 }, Position { line: 10, character: 7 }]);
+
+    // The `fn area` header of either class finds both as well
+    let (_, ranges) = pain_lsp::method_implementations(&program, code, Position { line: 4, character: 8 })
+        .expect("Should resolve the method header");
+    assert_eq!(ranges.len(), 2);
+}
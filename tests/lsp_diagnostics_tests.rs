@@ -1,30 +1,13 @@
 // LSP diagnostics tests - test error and warning detection
 
 use pain_lsp::Backend;
-use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use tower_lsp::lsp_types::*;
 
 /// Create a test backend for testing check_document
 /// Since check_document doesn't use the client, we can create a minimal backend
-fn create_test_backend() -> Backend {
-    // Create a minimal backend - we'll use a dummy client since check_document doesn't need it
-    // In a real implementation, we'd use tower-lsp's test framework
-    let (service, _socket) = tower_lsp::LspService::new(|client| Backend {
-        client,
-        documents: Arc::new(RwLock::new(HashMap::new())),
-    });
-    
-    // We can't easily extract the backend from the service
-    // Instead, let's create a helper that directly tests check_document
-    // by creating a minimal backend structure
-    // For now, we'll test the check_document logic directly
-    todo!("Implement proper test backend - need to refactor Backend to separate check_document logic")
-}
-
 #[tokio::test]
 async fn test_lsp_valid_code_no_diagnostics() {
-    let backend = create_test_backend();
+    let backend = Backend::detached();
     let code = r#"
 fn main():
     print("Hello, Pain!")
@@ -41,7 +24,7 @@ fn main():
 
 #[tokio::test]
 async fn test_lsp_undefined_variable_error() {
-    let backend = create_test_backend();
+    let backend = Backend::detached();
     let code = r#"
 fn main():
     let x = undefined_variable
@@ -59,7 +42,7 @@ fn main():
 
 #[tokio::test]
 async fn test_lsp_type_mismatch_error() {
-    let backend = create_test_backend();
+    let backend = Backend::detached();
     let code = r#"
 fn main():
     let x: int = "string"
@@ -75,7 +58,7 @@ fn main():
 
 #[tokio::test]
 async fn test_lsp_parse_error() {
-    let backend = create_test_backend();
+    let backend = Backend::detached();
     let code = r#"
 fn main():
     let x =  # Incomplete statement
@@ -91,7 +74,7 @@ fn main():
 
 #[tokio::test]
 async fn test_lsp_unused_variable_warning() {
-    let backend = create_test_backend();
+    let backend = Backend::detached();
     let code = r#"
 fn main():
     let unused = 10
@@ -109,7 +92,7 @@ fn main():
 
 #[tokio::test]
 async fn test_lsp_function_with_parameters() {
-    let backend = create_test_backend();
+    let backend = Backend::detached();
     let code = r#"
 fn add(a: int, b: int) -> int:
     return a + b
@@ -128,7 +111,7 @@ fn main():
 
 #[tokio::test]
 async fn test_lsp_classes() {
-    let backend = create_test_backend();
+    let backend = Backend::detached();
     let code = r#"
 class Point:
     let x: int
@@ -154,7 +137,7 @@ fn main():
 
 #[tokio::test]
 async fn test_lsp_control_flow() {
-    let backend = create_test_backend();
+    let backend = Backend::detached();
     let code = r#"
 fn max(a: int, b: int) -> int:
     if a > b:
@@ -181,7 +164,7 @@ fn sum(n: int) -> int:
 
 #[tokio::test]
 async fn test_lsp_lists_and_arrays() {
-    let backend = create_test_backend();
+    let backend = Backend::detached();
     let code = r#"
 fn main() -> int:
     let numbers = [1, 2, 3, 4, 5]
@@ -201,3 +184,806 @@ fn main() -> int:
     assert_eq!(errors.len(), 0, "Valid list/array code should have no errors");
 }
 
+
+#[tokio::test]
+async fn test_lsp_shadowed_variable_warning() {
+    let backend = Backend::detached();
+    let uri = url::Url::parse("file:///shadow.pain").unwrap();
+    let code = r#"
+fn main(x: int):
+    let y = x
+    let x = y + 1
+    print(x)
+"#;
+
+    let diagnostics = backend.check_document_with_uri(&uri, code);
+    let shadowed: Vec<_> = diagnostics
+        .iter()
+        .filter(|d| d.message == "variable `x` shadows an earlier binding")
+        .collect();
+    assert_eq!(shadowed.len(), 1, "Should warn that `x` shadows the parameter");
+    assert_eq!(shadowed[0].severity, Some(DiagnosticSeverity::WARNING));
+    assert_eq!(shadowed[0].range.start, Position { line: 3, character: 8 });
+
+    let related = shadowed[0].related_information.as_ref().expect("Should point at the original binding");
+    assert_eq!(related[0].location.uri, uri);
+    assert_eq!(related[0].location.range.start, Position { line: 1, character: 8 });
+}
+
+#[tokio::test]
+async fn test_lsp_assignment_to_function_name_warning() {
+    let backend = Backend::detached();
+    let uri = url::Url::parse("file:///assign_fn.pain").unwrap();
+    let code = r#"
+fn helper() -> int:
+    return 1
+
+fn main():
+    len = 5
+    helper = 2
+    var total = 0
+    total = 3
+"#;
+
+    let diagnostics = backend.check_document_with_uri(&uri, code);
+    let warnings: Vec<_> = diagnostics
+        .iter()
+        .filter(|d| d.message.starts_with("assignment to `"))
+        .collect();
+    assert_eq!(warnings.len(), 2, "Only `len` and `helper` name functions: {:?}", warnings);
+
+    let len = warnings.iter().find(|d| d.message.contains("`len`")).expect("Should warn about `len`");
+    assert_eq!(len.severity, Some(DiagnosticSeverity::WARNING));
+    assert_eq!(len.range.start, Position { line: 5, character: 4 });
+    assert!(len.message.contains("built-in function"));
+
+    let helper = warnings.iter().find(|d| d.message.contains("`helper`")).expect("Should warn about `helper`");
+    let related = helper.related_information.as_ref().expect("Should point at the function");
+    assert_eq!(related[0].location.range.start, Position { line: 1, character: 3 });
+
+    let mut settings = backend.settings();
+    settings.enable_warnings = false;
+    backend.update_settings(settings);
+    let diagnostics = backend.check_document_with_uri(&uri, code);
+    assert!(!diagnostics.iter().any(|d| d.message.starts_with("assignment to `")));
+}
+
+#[tokio::test]
+async fn test_lsp_shadowing_respects_enable_warnings() {
+    let backend = Backend::detached();
+    let code = r#"
+fn main():
+    let x = 1
+    if x > 0:
+        let x = 2
+        print(x)
+"#;
+
+    let diagnostics = backend.check_document(code);
+    assert!(
+        diagnostics.iter().any(|d| d.message.contains("shadows an earlier binding")),
+        "Should warn about `x` re-declared in a nested block"
+    );
+
+    let mut settings = backend.settings();
+    settings.enable_warnings = false;
+    backend.update_settings(settings);
+
+    let diagnostics = backend.check_document(code);
+    assert!(
+        diagnostics.iter().all(|d| d.severity != Some(DiagnosticSeverity::WARNING)),
+        "No warnings should be reported when enableWarnings is off"
+    );
+}
+
+#[tokio::test]
+async fn test_lsp_sibling_blocks_do_not_shadow() {
+    let backend = Backend::detached();
+    let code = r#"
+fn main(flag: bool):
+    if flag:
+        let value = 1
+        print(value)
+    else:
+        let value = 2
+        print(value)
+"#;
+
+    let diagnostics = backend.check_document(code);
+    assert!(
+        !diagnostics.iter().any(|d| d.message.contains("shadows an earlier binding")),
+        "Bindings in sibling branches should not shadow each other"
+    );
+}
+
+#[tokio::test]
+async fn test_lsp_unused_variable_severity_hint() {
+    let backend = Backend::detached();
+    let settings = pain_lsp::config::Settings::from_value(&serde_json::json!({
+        "pain": { "warningSeverity": { "unusedVariable": "hint" } }
+    }))
+    .expect("Settings should parse");
+    assert_eq!(settings.warning_severity.dead_code, pain_lsp::config::SeverityLevel::Warning);
+    backend.update_settings(settings);
+
+    let code = r#"
+fn main():
+    let unused = 10
+    print("test")
+"#;
+
+    let diagnostics = backend.check_document(code);
+    let unused: Vec<_> = diagnostics
+        .iter()
+        .filter(|d| d.message.contains("unused variable"))
+        .collect();
+    assert!(!unused.is_empty(), "Should still report the unused variable");
+    assert!(
+        unused.iter().all(|d| d.severity == Some(DiagnosticSeverity::HINT)),
+        "Unused variable should be reported as a hint"
+    );
+}
+
+#[tokio::test]
+async fn test_lsp_unused_variable_severity_off() {
+    let backend = Backend::detached();
+    let settings = pain_lsp::config::Settings::from_value(&serde_json::json!({
+        "warningSeverity": { "unusedVariable": "off" }
+    }))
+    .expect("Settings should parse");
+    backend.update_settings(settings);
+
+    let code = r#"
+fn main():
+    let unused = 10
+    print("test")
+"#;
+
+    let diagnostics = backend.check_document(code);
+    assert!(
+        !diagnostics.iter().any(|d| d.message.contains("unused variable")),
+        "Unused variable diagnostics should be skipped when set to off"
+    );
+}
+
+#[tokio::test]
+async fn test_lsp_missing_return_in_else_branch() {
+    let backend = Backend::detached();
+    let code = r#"
+fn sign(n: int) -> int:
+    if n > 0:
+        return 1
+    print("not positive")
+"#;
+
+    let diagnostics = backend.check_document(code);
+    let missing: Vec<_> = diagnostics
+        .iter()
+        .filter(|d| d.message.contains("may reach the end without returning"))
+        .collect();
+    assert_eq!(missing.len(), 1, "Should flag the path falling through the if");
+    assert_eq!(missing[0].severity, Some(DiagnosticSeverity::ERROR));
+    assert_eq!(missing[0].range.start, Position { line: 1, character: 3 }, "Should point at the header");
+}
+
+#[tokio::test]
+async fn test_lsp_missing_return_after_loop() {
+    let backend = Backend::detached();
+    // The loop body may never run, so the function can fall off the end
+    let code = r#"
+fn first(xs: list[int]) -> int:
+    for x in xs:
+        return x
+"#;
+
+    let diagnostics = backend.check_document(code);
+    let missing: Vec<_> = diagnostics
+        .iter()
+        .filter(|d| d.message.contains("may reach the end without returning"))
+        .collect();
+    assert_eq!(missing.len(), 1, "A return inside a loop should not count: {:?}", diagnostics);
+    assert_eq!(missing[0].range.start, Position { line: 1, character: 3 });
+}
+
+#[tokio::test]
+async fn test_lsp_all_paths_return() {
+    let backend = Backend::detached();
+    let code = r#"
+fn max(a: int, b: int) -> int:
+    if a > b:
+        return a
+    else:
+        return b
+
+fn greet():
+    print("hi")
+"#;
+
+    let diagnostics = backend.check_document(code);
+    assert!(
+        !diagnostics.iter().any(|d| d.message.contains("may reach the end without returning")),
+        "Functions returning on every path (or returning nothing) should not be flagged"
+    );
+}
+
+#[tokio::test]
+async fn test_lsp_oversized_document_single_diagnostic() {
+    let backend = Backend::detached();
+    backend.update_settings(pain_lsp::config::Settings {
+        max_document_size: Some(64),
+        ..Default::default()
+    });
+    // Broken code that would otherwise produce parse errors
+    let code = "fn broken(:\n    let x = \n".repeat(10);
+
+    let diagnostics = backend.check_document(&code);
+    assert_eq!(diagnostics.len(), 1, "Oversized documents should only report the size limit");
+    assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::INFORMATION));
+    assert_eq!(diagnostics[0].range.start, Position { line: 0, character: 0 });
+    assert_eq!(
+        diagnostics[0].message,
+        format!("File too large for Pain analysis ({} bytes, limit 64)", code.len())
+    );
+}
+
+fn parse_error_at(line: u32, character: u32, message: &str) -> Diagnostic {
+    Diagnostic {
+        range: Range {
+            start: Position { line, character },
+            end: Position { line, character: character + 1 },
+        },
+        severity: Some(DiagnosticSeverity::ERROR),
+        source: Some("pain".to_string()),
+        message: message.to_string(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_lsp_parse_errors_deduplicated() {
+    let code = "fn main(:\n    let value = 1\n";
+    let diagnostics = vec![
+        parse_error_at(0, 8, "expected parameter name"),
+        parse_error_at(0, 8, "expected parameter name"),
+        parse_error_at(0, 8, "expected ')'"),
+        parse_error_at(1, 8, "unexpected token"),
+        parse_error_at(1, 10, "unexpected token"),
+    ];
+
+    let merged = pain_lsp::merge_parse_diagnostics(code, diagnostics);
+    assert_eq!(merged.len(), 2, "Errors stacked on `:` and on `value` should each collapse");
+    assert_eq!(merged[0].message, "expected parameter name\nexpected ')'");
+    assert_eq!(
+        merged[1].range,
+        Range {
+            start: Position { line: 1, character: 8 },
+            end: Position { line: 1, character: 13 },
+        },
+        "Merged errors should cover the whole token"
+    );
+}
+
+#[tokio::test]
+async fn test_lsp_malformed_input_no_stacked_parse_errors() {
+    let backend = Backend::detached();
+    let code = "fn main(:\n    let = = =\n    if (((\n";
+
+    let diagnostics = backend.check_document(code);
+    for (i, a) in diagnostics.iter().enumerate() {
+        for b in &diagnostics[i + 1..] {
+            assert!(
+                !(a.range == b.range && a.message == b.message),
+                "Duplicate diagnostic {:?}",
+                a
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_lsp_config_change_refreshes_open_documents() {
+    use tower_lsp::LanguageServer;
+
+    let backend = Backend::detached();
+    let uri = Url::parse("file:///refresh.pain").unwrap();
+    let code = "fn main():\n    let unused = 1\n    print(\"hi\")\n";
+
+    backend
+        .did_open(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "pain".to_string(),
+                version: 1,
+                text: code.to_string(),
+            },
+        })
+        .await;
+    let has_warnings = |diagnostics: Option<&Vec<Diagnostic>>| {
+        diagnostics.is_some_and(|diags| diags.iter().any(|d| d.severity == Some(DiagnosticSeverity::WARNING)))
+    };
+    assert!(has_warnings(backend.published_diagnostics.read().await.get(&uri)));
+
+    backend
+        .did_change_configuration(DidChangeConfigurationParams {
+            settings: serde_json::json!({ "pain": { "enableWarnings": false } }),
+        })
+        .await;
+
+    // The refresh runs in a spawned task - let it finish
+    for _ in 0..100 {
+        if !has_warnings(backend.published_diagnostics.read().await.get(&uri)) {
+            break;
+        }
+        tokio::task::yield_now().await;
+    }
+    assert!(
+        !has_warnings(backend.published_diagnostics.read().await.get(&uri)),
+        "Disabling warnings should clear them from already-open documents"
+    );
+}
+
+#[tokio::test]
+async fn test_lsp_pull_diagnostics() {
+    use tower_lsp::LanguageServer;
+
+    let backend = Backend::detached();
+    let uri = Url::parse("file:///pull.pain").unwrap();
+    backend
+        .did_open(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "pain".to_string(),
+                version: 1,
+                text: "fn main():\n    let x = 0\n    x = 1\n".to_string(),
+            },
+        })
+        .await;
+
+    let pull = |previous_result_id: Option<String>| DocumentDiagnosticParams {
+        text_document: TextDocumentIdentifier { uri: uri.clone() },
+        identifier: Some("pain".to_string()),
+        previous_result_id,
+        work_done_progress_params: WorkDoneProgressParams::default(),
+        partial_result_params: PartialResultParams::default(),
+    };
+
+    let result_id = match backend.diagnostic(pull(None)).await.unwrap() {
+        DocumentDiagnosticReportResult::Report(DocumentDiagnosticReport::Full(report)) => {
+            let full = report.full_document_diagnostic_report;
+            assert!(!full.items.is_empty(), "The first pull should report the problems");
+            full.result_id.expect("Full reports should carry a result id")
+        }
+        other => panic!("Expected a full report, got {:?}", other),
+    };
+
+    match backend.diagnostic(pull(Some(result_id))).await.unwrap() {
+        DocumentDiagnosticReportResult::Report(DocumentDiagnosticReport::Unchanged(_)) => {}
+        other => panic!("An unchanged document should return an unchanged report, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_lsp_workspace_diagnostics_for_unopened_files() {
+    use tower_lsp::LanguageServer;
+
+    let dir = std::env::temp_dir().join(format!("pain_lsp_workspace_diagnostics_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("closed.pain");
+    std::fs::write(&path, "fn main():\n    let x = 0\n    x = 1\n").unwrap();
+    let uri = Url::from_file_path(&path).unwrap();
+
+    let backend = Backend::detached();
+    backend.workspace_index.write().await.insert(uri.clone(), Vec::new());
+
+    let request = |previous_result_ids: Vec<PreviousResultId>| WorkspaceDiagnosticParams {
+        identifier: Some("pain".to_string()),
+        previous_result_ids,
+        work_done_progress_params: WorkDoneProgressParams::default(),
+        partial_result_params: PartialResultParams::default(),
+    };
+
+    let result_id = match backend.workspace_diagnostic(request(Vec::new())).await.unwrap() {
+        WorkspaceDiagnosticReportResult::Report(report) => match report.items.as_slice() {
+            [WorkspaceDocumentDiagnosticReport::Full(full)] => {
+                assert_eq!(full.uri, uri);
+                assert!(!full.full_document_diagnostic_report.items.is_empty(), "Closed files should be checked");
+                full.full_document_diagnostic_report.result_id.clone().unwrap()
+            }
+            other => panic!("Expected one full report, got {:?}", other),
+        },
+        other => panic!("Expected a report, got {:?}", other),
+    };
+
+    let previous = vec![PreviousResultId { uri: uri.clone(), value: result_id }];
+    match backend.workspace_diagnostic(request(previous)).await.unwrap() {
+        WorkspaceDiagnosticReportResult::Report(report) => {
+            assert!(matches!(report.items.as_slice(), [WorkspaceDocumentDiagnosticReport::Unchanged(_)]));
+        }
+        other => panic!("Expected a report, got {:?}", other),
+    }
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_lsp_redefined_function() {
+    let backend = Backend::detached();
+    let uri = Url::parse("file:///redefined.pain").unwrap();
+    let code = r#"
+fn foo() -> int:
+    return 1
+
+fn foo() -> int:
+    return 2
+"#;
+
+    let diagnostics = backend.check_document_with_uri(&uri, code);
+    let redefinitions: Vec<_> = diagnostics
+        .iter()
+        .filter(|d| d.message == "`foo` is defined multiple times")
+        .collect();
+    assert_eq!(redefinitions.len(), 1, "Only the second definition should be flagged");
+    assert_eq!(redefinitions[0].severity, Some(DiagnosticSeverity::ERROR));
+    assert_eq!(redefinitions[0].range.start, Position { line: 4, character: 3 });
+
+    let related = redefinitions[0].related_information.as_ref().expect("Should point at the first definition");
+    assert_eq!(related[0].location.range.start, Position { line: 1, character: 3 });
+}
+
+#[tokio::test]
+async fn test_lsp_crlf_diagnostic_columns() {
+    let backend = Backend::detached();
+    let uri = Url::parse("file:///crlf.pain").unwrap();
+    let lf = "fn main():\n    let s = \"é😀\"\n    let x = 0\n    x = 1\n    print(x)\n    print(s)\n";
+    let crlf = lf.replace('\n', "\r\n");
+
+    let diagnostics = backend.compute_diagnostics(&uri, &crlf).await;
+    let assignment = diagnostics
+        .iter()
+        .find(|d| d.code == Some(NumberOrString::String(pain_lsp::IMMUTABLE_ASSIGNMENT_CODE.to_string())))
+        .expect("Assigning to a `let` binding should be reported");
+    assert_eq!(assignment.range.start, Position { line: 3, character: 4 });
+
+    // `\r\n` terminators must not shift anything
+    assert_eq!(diagnostics, backend.compute_diagnostics(&uri, lf).await);
+}
+
+#[tokio::test]
+async fn test_lsp_tab_indented_diagnostic_columns() {
+    let backend = Backend::detached();
+    let uri = Url::parse("file:///tabs.pain").unwrap();
+    let code = "fn main():\n\tlet x = undefined_variable\n\tprint(x)\n";
+
+    let diagnostics = backend.compute_diagnostics(&uri, code).await;
+    let undefined = diagnostics
+        .iter()
+        .find(|d| d.severity == Some(DiagnosticSeverity::ERROR) && d.message.to_lowercase().contains("undefined"))
+        .expect("The undefined variable should be reported");
+    // One code unit for the tab, then `let x = `
+    assert_eq!(undefined.range.start, Position { line: 1, character: 9 });
+}
+
+#[tokio::test]
+async fn test_lsp_dead_code_range_spans_all_lines() {
+    let backend = Backend::detached();
+    let code = r#"
+fn answer() -> int:
+    return 42
+    let a = 1
+    let b = 2
+    print(a + b)
+
+fn main():
+    print(answer())
+"#;
+    let diagnostics = backend.check_document(code);
+    let dead = diagnostics
+        .iter()
+        .find(|d| d.message.starts_with("dead code") || d.message.starts_with("unreachable code"))
+        .expect("Statements after `return` should be reported");
+
+    // Lines 4-6 hold the three statements after `return`
+    assert_eq!(dead.range.start.line, 3, "Range should start at the first dead statement");
+    assert_eq!(dead.range.end.line, 5, "Range should cover every dead statement");
+}
+
+#[tokio::test]
+async fn test_lsp_method_returning_wrong_field_type() {
+    let backend = Backend::detached();
+    let code = r#"
+class Counter:
+    let value: int
+    let label: str
+
+    fn new(start: int) -> Counter:
+        let c = Counter()
+        c.value = start
+        return c
+
+    fn get() -> int:
+        return self.label
+
+fn main():
+    let c = Counter.new(1)
+    print(c.get())
+"#;
+    let diagnostics = backend.check_document(code);
+    let mismatch = diagnostics
+        .iter()
+        .find(|d| d.range.start.line == 11 && d.severity == Some(DiagnosticSeverity::ERROR))
+        .expect("Returning a `str` field from an `int` method should be reported");
+    assert_eq!(mismatch.data.as_ref().map(|data| data["fullMessage"].is_string()), Some(true));
+
+    // `return c` in `new` matches its declared type
+    assert!(!diagnostics.iter().any(|d| d.range.start.line == 8 && d.severity == Some(DiagnosticSeverity::ERROR)));
+}
+
+#[tokio::test]
+async fn test_lsp_method_returning_matching_field_type() {
+    let backend = Backend::detached();
+    let code = r#"
+class Counter:
+    let value: int
+
+    fn get() -> int:
+        return self.value
+
+fn main():
+    let c = Counter()
+    print(c.get())
+"#;
+    let diagnostics = backend.check_document(code);
+    assert!(
+        !diagnostics.iter().any(|d| d.severity == Some(DiagnosticSeverity::ERROR)),
+        "Matching return types should not be reported: {:?}",
+        diagnostics
+    );
+}
+
+#[tokio::test]
+async fn test_lsp_type_error_in_method_body() {
+    let backend = Backend::detached();
+    let code = r#"
+class Counter:
+    let value: int
+
+    fn bump(step: int):
+        let label: str = step
+        print(label)
+
+fn main():
+    let c = Counter()
+    c.bump(1)
+"#;
+    let diagnostics = backend.check_document(code);
+    assert!(
+        diagnostics
+            .iter()
+            .any(|d| d.range.start.line == 5 && d.severity == Some(DiagnosticSeverity::ERROR)),
+        "A mistyped `let` inside a method should be reported: {:?}",
+        diagnostics
+    );
+}
+
+#[tokio::test]
+async fn test_lsp_diagnostics_sorted_by_position() {
+    let backend = Backend::detached();
+    // The type error on line 5 is found before the method return mismatches on lines 2 and 8
+    let code = "class A:\n    fn get() -> int:\n        return \"a\"\n\nfn main():\n    let n: int = \"five\"\nclass B:\n    fn get() -> bool:\n        return 1\n";
+    let diagnostics = backend.check_document(code);
+
+    let mut error_lines: Vec<u32> = diagnostics
+        .iter()
+        .filter(|d| d.severity == Some(DiagnosticSeverity::ERROR))
+        .map(|d| d.range.start.line)
+        .collect();
+    error_lines.dedup();
+    assert_eq!(error_lines, vec![2, 5, 8], "Errors should come back in line order: {:?}", diagnostics);
+
+    let positions: Vec<_> = diagnostics
+        .iter()
+        .map(|d| (d.range.start.line, d.range.start.character, d.severity))
+        .collect();
+    let mut sorted = positions.clone();
+    sorted.sort();
+    assert_eq!(positions, sorted, "All diagnostics should be ordered by position, then severity");
+}
+
+#[tokio::test]
+async fn test_lsp_no_diagnostics_in_commented_out_code() {
+    let backend = Backend::detached();
+    let code = "fn main():\n    let x = 1\n    # let y = undefined_variable + x\n    print(\"undefined_variable\")\n    print(x)\n";
+    let diagnostics = backend.check_document(code);
+    assert!(
+        !diagnostics.iter().any(|d| d.range.start.line == 2 || d.range.start.line == 3),
+        "Comments and string contents should not be flagged: {:?}",
+        diagnostics
+    );
+
+    // What a heuristic would report inside them is dropped
+    let tokens = pain_lsp::lexer::tokenize(code);
+    let diagnostic = |line, start, end, code: Option<&str>| Diagnostic {
+        range: Range::new(Position::new(line, start), Position::new(line, end)),
+        severity: Some(DiagnosticSeverity::ERROR),
+        code: code.map(|code| NumberOrString::String(code.to_string())),
+        message: "undefined variable".to_string(),
+        ..Default::default()
+    };
+    assert!(pain_lsp::is_in_comment_or_string(&tokens, &diagnostic(2, 14, 32, None)));
+    assert!(pain_lsp::is_in_comment_or_string(&tokens, &diagnostic(3, 11, 29, None)));
+    // Diagnostics about the literal itself, or from the parser, are kept
+    assert!(!pain_lsp::is_in_comment_or_string(&tokens, &diagnostic(3, 10, 11, None)));
+    assert!(!pain_lsp::is_in_comment_or_string(&tokens, &diagnostic(3, 11, 29, Some(pain_lsp::PARSE_ERROR_CODE))));
+    assert!(!pain_lsp::is_in_comment_or_string(&tokens, &diagnostic(4, 10, 11, None)));
+}
+
+#[tokio::test]
+async fn test_lsp_type_error_keeps_full_message() {
+    let backend = Backend::detached();
+    let code = "fn main():\n    let x = undefined_var + 1\n";
+    let diagnostics = backend.check_document(code);
+    let error = diagnostics
+        .iter()
+        .find(|d| d.severity == Some(DiagnosticSeverity::ERROR))
+        .expect("Should report the undefined variable");
+
+    assert!(!error.message.contains('\n'), "The message should stay on one line");
+    let full = error
+        .data
+        .as_ref()
+        .and_then(|data| data["fullMessage"].as_str())
+        .expect("The formatted error should be kept in data");
+    assert!(full.starts_with(&error.message), "Full text should extend the short message: {:?}", full);
+}
+
+#[tokio::test]
+async fn test_lsp_type_context_built_once_per_edit() {
+    let backend = Backend::detached();
+    let uri = url::Url::parse("file:///context.pain").unwrap();
+    let code = "fn add(a: int, b: int) -> int:\n    return a + b\n\nfn main():\n    print(add(1, 2))\n";
+    let builds = || backend.type_context_builds.load(std::sync::atomic::Ordering::Relaxed);
+
+    let first = backend.check_document_with_uri(&uri, code);
+    let second = backend.check_document_with_uri(&uri, code);
+    assert_eq!(builds(), 1, "Checking unchanged text should reuse the checked type context");
+    assert_eq!(first, second, "A cached type context should give the same diagnostics");
+
+    let edited = code.replace("add(1, 2)", "add(3, 4)");
+    backend.check_document_with_uri(&uri, &edited);
+    backend.check_document_with_uri(&uri, &edited);
+    assert_eq!(builds(), 2, "An edit should type check the document once");
+}
+
+#[tokio::test]
+async fn test_lsp_duplicate_parameter_error() {
+    let backend = Backend::detached();
+    let uri = url::Url::parse("file:///duplicate_param.pain").unwrap();
+    let code = "fn add(a: int, a: int) -> int:\n    return a\n\nfn main():\n    print(add(1, 2))\n";
+    let diagnostics = backend.check_document_with_uri(&uri, code);
+
+    let duplicate = diagnostics
+        .iter()
+        .find(|d| d.message == "duplicate parameter `a`")
+        .expect("Should report the repeated parameter");
+    assert_eq!(duplicate.severity, Some(DiagnosticSeverity::ERROR));
+    assert_eq!(duplicate.range.start, Position { line: 0, character: 15 }, "Should point at the second `a`");
+    let related = duplicate.related_information.as_ref().expect("Should point back to the first `a`");
+    assert_eq!(related[0].location.range.start, Position { line: 0, character: 7 });
+}
+
+#[tokio::test]
+async fn test_lsp_parameter_shadowed_by_local_is_not_duplicate() {
+    let backend = Backend::detached();
+    let code = "fn scale(factor: int) -> int:\n    let factor = 2\n    return factor\n\nfn main():\n    print(scale(3))\n";
+    let diagnostics = backend.check_document(code);
+    assert!(
+        !diagnostics.iter().any(|d| d.message.starts_with("duplicate parameter")),
+        "A local shadowing a parameter is not a duplicate parameter: {:?}",
+        diagnostics
+    );
+}
+
+#[tokio::test]
+async fn test_lsp_keeps_stale_type_diagnostics_while_typing() {
+    let backend = Backend::detached();
+    let mut settings = backend.settings();
+    settings.keep_stale_diagnostics_while_typing = true;
+    backend.update_settings(settings);
+    let uri = url::Url::parse("file:///typing.pain").unwrap();
+
+    let code = "fn main():\n    let n: int = \"five\"\n    print(n)\n";
+    let before = backend.compute_diagnostics(&uri, code).await;
+    let type_error = before
+        .iter()
+        .find(|d| d.range.start.line == 1 && d.severity == Some(DiagnosticSeverity::ERROR))
+        .expect("Should report the type mismatch")
+        .clone();
+
+    // Mid-edit: a new function header is only half typed
+    let editing = format!("{}\nfn helper(:\n", code);
+    let during = backend.compute_diagnostics(&uri, &editing).await;
+    assert!(during.iter().any(|d| d.range.start.line >= 4), "The parse error should be live: {:?}", during);
+    assert!(during.contains(&type_error), "The earlier type error should stay visible: {:?}", during);
+
+    // Once its line is edited, the old error no longer describes it
+    let fixed = editing.replace("\"five\"", "5");
+    let after = backend.compute_diagnostics(&uri, &fixed).await;
+    assert!(!after.contains(&type_error), "Stale errors on edited lines should be dropped: {:?}", after);
+}
+
+#[tokio::test]
+async fn test_lsp_use_before_declaration() {
+    let backend = Backend::detached();
+    let code = "fn main():\n    print(total)\n    let total = 1\n    print(total)\n";
+    let diagnostics = backend.check_document(code);
+    let early: Vec<_> = diagnostics.iter().filter(|d| d.message == "use of `total` before its declaration").collect();
+    assert_eq!(early.len(), 1, "Only the use above the `let` should be reported: {:?}", diagnostics);
+    assert_eq!(early[0].severity, Some(DiagnosticSeverity::ERROR));
+    assert_eq!(early[0].range.start, Position { line: 1, character: 10 });
+    let related = early[0].related_information.as_ref().expect("Should point at the declaration");
+    assert_eq!(related[0].location.range.start, Position { line: 2, character: 8 });
+}
+
+#[tokio::test]
+async fn test_lsp_use_after_declaration_is_not_reported() {
+    let backend = Backend::detached();
+    let code = "fn main():\n    var count = 0\n    for i in range(3):\n        count = count + i\n    print(count)\n";
+    let diagnostics = backend.check_document(code);
+    assert!(
+        !diagnostics.iter().any(|d| d.message.ends_with("before its declaration")),
+        "Uses after the binding are fine: {:?}",
+        diagnostics
+    );
+}
+
+#[tokio::test]
+async fn test_lsp_incomplete_statement_errors_stay_in_bounds() {
+    let backend = Backend::detached();
+    let inputs = [
+        "fn main():\n    let x = \n",
+        "fn main():\n    if \n",
+        "fn main():\n    return \n",
+        "fn main():\n    x + \n",
+        "fn \n",
+        "fn main():\n    let y = ",
+    ];
+    for code in inputs {
+        let lines: Vec<&str> = code.split('\n').collect();
+        let diagnostics = backend.check_document(code);
+        assert!(!diagnostics.is_empty(), "Incomplete code should be reported: {:?}", code);
+        for diagnostic in &diagnostics {
+            let Range { start, end } = diagnostic.range;
+            let content = lines.get(start.line as usize).unwrap_or_else(|| panic!("{:?} is past the last line of {:?}", start, code));
+            assert!(!content.trim().is_empty(), "{:?} is on a blank line of {:?}", start, code);
+            assert!(end.line == start.line && end.character as usize <= content.len(), "{:?} overruns its line in {:?}", diagnostic.range, code);
+        }
+    }
+
+    // The cut-off assignment is marked at its `=`
+    let diagnostics = backend.check_document("fn main():\n    let x = \n");
+    assert!(
+        diagnostics.iter().any(|d| d.range.start == Position { line: 1, character: 10 }),
+        "Expected a squiggle on `=`: {:?}",
+        diagnostics
+    );
+}
+
+#[tokio::test]
+async fn test_lsp_arity_mismatch_underlines_whole_call() {
+    let backend = Backend::detached();
+    let uri = Url::parse("file:///arity.pain").unwrap();
+    let code = "fn add(a: int, b: int) -> int:\n    return a + b\n\nfn main():\n    print(add(1, 2, 3))\n";
+    let diagnostics = backend.check_document_with_uri(&uri, code);
+    let arity = diagnostics
+        .iter()
+        .find(|d| d.message == "`add` expects 2 arguments, found 3")
+        .unwrap_or_else(|| panic!("Expected an arity diagnostic: {:?}", diagnostics));
+    assert_eq!(
+        arity.range,
+        Range { start: Position { line: 4, character: 10 }, end: Position { line: 4, character: 22 } },
+        "The underline should run from `add` through the closing paren"
+    );
+    let related = arity.related_information.as_ref().expect("Should point at the declaration");
+    assert_eq!(related[0].location.range.start, Position { line: 0, character: 3 });
+}
@@ -0,0 +1,61 @@
+// LSP document symbol tests - test the outline and deprecation tags
+
+use pain_lsp::Backend;
+use tower_lsp::lsp_types::*;
+use tower_lsp::LanguageServer;
+
+const CODE: &str = "@deprecated\nfn old_add(a: int, b: int) -> int:\n    return a + b\n\nclass Counter:\n    let count: int\n\n    fn increment():\n        pass\n\nfn main():\n    print(old_add(1, 2))\n";
+
+#[tokio::test]
+async fn test_deprecated_function_is_tagged_in_outline() {
+    let backend = Backend::detached();
+    let uri = Url::parse("file:///outline.pain").unwrap();
+    backend
+        .did_open(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "pain".to_string(),
+                version: 1,
+                text: CODE.to_string(),
+            },
+        })
+        .await;
+
+    let response = backend
+        .document_symbol(DocumentSymbolParams {
+            text_document: TextDocumentIdentifier { uri },
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        })
+        .await
+        .unwrap();
+    let Some(DocumentSymbolResponse::Nested(symbols)) = response else { panic!("Expected nested symbols") };
+
+    let names: Vec<&str> = symbols.iter().map(|symbol| symbol.name.as_str()).collect();
+    assert_eq!(names, vec!["old_add", "Counter", "main"]);
+    assert_eq!(symbols[0].tags, Some(vec![SymbolTag::DEPRECATED]), "`@deprecated` should tag the symbol");
+    assert_eq!(symbols[0].selection_range.start, Position { line: 1, character: 3 });
+    assert_eq!(symbols[2].tags, None);
+
+    let methods = symbols[1].children.as_ref().expect("Classes list their methods");
+    assert_eq!(methods.len(), 1);
+    assert_eq!(methods[0].name, "increment");
+    assert_eq!(methods[0].kind, SymbolKind::METHOD);
+}
+
+#[tokio::test]
+async fn test_call_to_deprecated_function_is_faded() {
+    let backend = Backend::detached();
+    let uri = Url::parse("file:///outline.pain").unwrap();
+    let diagnostics = backend.check_document_with_uri(&uri, CODE);
+
+    let deprecated = diagnostics
+        .iter()
+        .find(|d| d.message == "`old_add` is deprecated")
+        .expect("The call should be reported");
+    assert_eq!(deprecated.range.start, Position { line: 11, character: 10 });
+    assert_eq!(deprecated.tags, Some(vec![DiagnosticTag::DEPRECATED]));
+    assert_eq!(deprecated.severity, Some(DiagnosticSeverity::HINT));
+    let related = deprecated.related_information.as_ref().expect("Should point at the definition");
+    assert_eq!(related[0].location.range.start, Position { line: 1, character: 3 });
+}
@@ -0,0 +1,384 @@
+// LSP document sync tests - test how didOpen/didChange update the stored text
+
+use pain_lsp::{apply_content_changes, Backend};
+use tower_lsp::lsp_types::*;
+use tower_lsp::LanguageServer;
+
+fn change(range: Option<Range>, text: &str) -> TextDocumentContentChangeEvent {
+    TextDocumentContentChangeEvent {
+        range,
+        range_length: None,
+        text: text.to_string(),
+    }
+}
+
+fn range(start: (u32, u32), end: (u32, u32)) -> Range {
+    Range {
+        start: Position { line: start.0, character: start.1 },
+        end: Position { line: end.0, character: end.1 },
+    }
+}
+
+#[tokio::test]
+async fn test_did_change_applies_all_events() {
+    let backend = Backend::detached();
+    let uri = Url::parse("file:///sync.pain").unwrap();
+
+    backend
+        .did_open(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "pain".to_string(),
+                version: 1,
+                text: "fn main():\n    let x = 1\n".to_string(),
+            },
+        })
+        .await;
+
+    backend
+        .did_change(DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier {
+                uri: uri.clone(),
+                version: 2,
+            },
+            content_changes: vec![
+                // Rename `x` to `count`, then change the initializer
+                change(Some(range((1, 8), (1, 9))), "count"),
+                change(Some(range((1, 16), (1, 17))), "42"),
+            ],
+        })
+        .await;
+
+    let docs = backend.documents.read().await;
+    assert_eq!(
+        docs.get(&uri).map(String::as_str),
+        Some("fn main():\n    let count = 42\n"),
+        "Both change events should be applied in order"
+    );
+}
+
+#[test]
+fn test_full_change_after_ranged_change() {
+    let text = apply_content_changes(
+        "let a = 1\n",
+        &[
+            change(Some(range((0, 4), (0, 5))), "b"),
+            change(None, "let c = 3\n"),
+        ],
+        &PositionEncodingKind::UTF16,
+    );
+    assert_eq!(text, "let c = 3\n", "A full-text change replaces earlier edits");
+}
+
+#[test]
+fn test_ranged_change_utf16_columns() {
+    // `é` is one UTF-16 unit but two bytes
+    let text = apply_content_changes(
+        "let s = \"é\" + x\n",
+        &[change(Some(range((0, 14), (0, 15))), "y")],
+        &PositionEncodingKind::UTF16,
+    );
+    assert_eq!(text, "let s = \"é\" + y\n");
+}
+
+#[tokio::test]
+async fn test_did_open_stores_oversized_document() {
+    let mut backend = Backend::detached();
+    backend.max_document_size = 16;
+    let uri = Url::parse("file:///large.pain").unwrap();
+    let text = "fn main():\n    print(\"hello\")\n".to_string();
+
+    backend
+        .did_open(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "pain".to_string(),
+                version: 1,
+                text: text.clone(),
+            },
+        })
+        .await;
+
+    let docs = backend.documents.read().await;
+    assert_eq!(docs.get(&uri), Some(&text), "Oversized documents should still be stored");
+}
+
+#[tokio::test]
+async fn test_stale_diagnostics_are_dropped() {
+    let backend = Backend::detached();
+    let uri = Url::parse("file:///versions.pain").unwrap();
+
+    backend
+        .did_open(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "pain".to_string(),
+                version: 1,
+                text: "fn main():\n    pass\n".to_string(),
+            },
+        })
+        .await;
+    backend
+        .did_change(DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier { uri: uri.clone(), version: 2 },
+            content_changes: vec![change(None, "fn main():\n    print(1)\n")],
+        })
+        .await;
+
+    // Results computed for version 1 arrive after version 2 was seen
+    assert!(!backend.publish_diagnostics_if_current(uri.clone(), Vec::new(), Some(1)).await);
+    assert!(backend.publish_diagnostics_if_current(uri, Vec::new(), Some(2)).await);
+}
+
+async fn open(backend: &Backend, uri: &Url, text: &str) {
+    backend
+        .did_open(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "pain".to_string(),
+                version: 1,
+                text: text.to_string(),
+            },
+        })
+        .await;
+}
+
+// Clock advanced by hand so eviction can be tested without waiting
+#[derive(Debug)]
+struct ManualClock(std::sync::Mutex<std::time::Instant>);
+
+impl ManualClock {
+    fn advance(&self, by: std::time::Duration) {
+        *self.0.lock().unwrap() += by;
+    }
+}
+
+impl pain_lsp::Clock for ManualClock {
+    fn now(&self) -> std::time::Instant {
+        *self.0.lock().unwrap()
+    }
+}
+
+#[tokio::test]
+async fn test_idle_cache_entries_are_evicted() {
+    let clock = std::sync::Arc::new(ManualClock(std::sync::Mutex::new(std::time::Instant::now())));
+    let backend = Backend::detached();
+    backend.set_clock(clock.clone());
+    let idle = Url::parse("file:///idle.pain").unwrap();
+    let busy = Url::parse("file:///busy.pain").unwrap();
+    let text = "fn main():\n    pass\n";
+    open(&backend, &idle, text).await;
+    open(&backend, &busy, text).await;
+
+    // Only `busy` is used again shortly before the interval ends
+    clock.advance(std::time::Duration::from_secs(200));
+    backend.get_or_parse(&busy, text).await;
+    clock.advance(std::time::Duration::from_secs(200));
+
+    assert_eq!(backend.evict_idle_cache_entries().await, 1);
+    let cache = backend.parsed_cache.read().await;
+    assert!(!cache.contains_key(&idle), "Untouched entries are evicted after the interval");
+    assert!(cache.contains_key(&busy), "Recently used entries stay");
+    drop(cache);
+    assert!(backend.documents.read().await.contains_key(&idle), "Open documents are never evicted");
+}
+
+#[tokio::test]
+async fn test_untitled_document_is_analyzed() {
+    let backend = Backend::detached();
+    let uri = Url::parse("untitled:Untitled-1").unwrap();
+    let text = "fn double(n: int) -> int:\n    return n * 2\n\nfn main():\n    let x = 0\n    x = double(x)\n    print(x)\n";
+    open(&backend, &uri, text).await;
+
+    let diagnostics = backend.published_diagnostics.read().await.get(&uri).cloned().unwrap_or_default();
+    assert!(
+        diagnostics.iter().any(|d| d.range.start == Position { line: 5, character: 4 }),
+        "Unsaved documents should get diagnostics: {:?}",
+        diagnostics
+    );
+
+    let position = TextDocumentPositionParams {
+        text_document: TextDocumentIdentifier { uri: uri.clone() },
+        position: Position { line: 5, character: 9 },
+    };
+    let hover = backend
+        .hover(HoverParams {
+            text_document_position_params: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri: uri.clone() },
+                position: Position { line: 4, character: 8 },
+            },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        })
+        .await
+        .unwrap();
+    assert!(hover.is_some(), "Hover should work on unsaved documents");
+
+    let completion = backend
+        .completion(CompletionParams {
+            text_document_position: position,
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+            context: None,
+        })
+        .await
+        .unwrap();
+    // Only what matches the typed `d` is offered, so the list is incomplete
+    let Some(CompletionResponse::List(list)) = completion else { panic!("Expected a completion list") };
+    assert!(list.is_incomplete);
+    assert!(list.items.iter().any(|item| item.label == "double"));
+
+    // Nothing on disk to link to
+    let links = backend
+        .document_link(DocumentLinkParams {
+            text_document: TextDocumentIdentifier { uri },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        })
+        .await
+        .unwrap();
+    assert!(links.is_none());
+}
+
+#[test]
+fn test_only_file_uris_have_local_paths() {
+    use pain_lsp::workspace::url_to_path;
+    assert!(url_to_path(&Url::parse("file:///tmp/main.pain").unwrap()).is_some());
+    assert!(url_to_path(&Url::parse("untitled:Untitled-1").unwrap()).is_none());
+    assert!(url_to_path(&Url::parse("vscode-vfs://github/org/repo/main.pain").unwrap()).is_none());
+}
+
+#[tokio::test]
+async fn test_shutdown_clears_state_and_stops_background_tasks() {
+    let backend = Backend::detached();
+    let uri = Url::parse("file:///shutdown.pain").unwrap();
+    backend
+        .did_open(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "pain".to_string(),
+                version: 1,
+                text: "fn main():\n    let x = 1\n".to_string(),
+            },
+        })
+        .await;
+    backend.get_or_parse(&uri, "fn main():\n    let x = 1\n").await;
+
+    assert!(backend.shutdown().await.is_ok(), "Shutdown should succeed");
+    assert!(backend.documents.read().await.is_empty(), "Documents should be cleared");
+    assert!(backend.parsed_cache.read().await.is_empty(), "Parse cache should be cleared");
+    assert!(backend.type_contexts.read().unwrap().is_empty(), "Type contexts should be cleared");
+    assert!(backend.background_tasks.lock().unwrap().is_empty(), "Background tasks should be aborted");
+}
+
+#[test]
+fn test_windows_drive_letter_uri_to_path() {
+    use pain_lsp::workspace::url_to_path;
+    let uri = Url::parse("file:///c%3A/projects/my%20app/main.pain").unwrap();
+    assert_eq!(url_to_path(&uri), Some(std::path::PathBuf::from("c:/projects/my app/main.pain")));
+
+    let uri = Url::parse("file:///C:/projects/main.pain").unwrap();
+    assert_eq!(url_to_path(&uri), Some(std::path::PathBuf::from("C:/projects/main.pain")));
+}
+
+#[tokio::test]
+async fn test_shutdown_cancels_spawned_tasks() {
+    let backend = Backend::detached();
+    let (sender, receiver) = tokio::sync::oneshot::channel::<()>();
+    let task = tokio::spawn(async move {
+        let _sender = sender;
+        std::future::pending::<()>().await;
+    });
+    backend.background_tasks.lock().unwrap().push(task);
+
+    backend.shutdown().await.unwrap();
+    assert!(receiver.await.is_err(), "The task should be cancelled, dropping its sender");
+    assert_eq!(backend.abort_background_tasks().await, 0, "Nothing should be left running");
+}
+
+#[tokio::test]
+async fn test_publish_task_is_tracked_for_shutdown() {
+    let backend = Backend::detached();
+    let tracked = || backend.background_tasks.lock().unwrap().len();
+    let before = tracked();
+    backend
+        .did_open(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri: Url::parse("file:///publish.pain").unwrap(),
+                language_id: "pain".to_string(),
+                version: 1,
+                text: "fn main():\n    let x = 1\n".to_string(),
+            },
+        })
+        .await;
+    assert_eq!(tracked(), before + 1, "The diagnostics publish task should be tracked");
+
+    backend.shutdown().await.unwrap();
+    assert_eq!(backend.abort_background_tasks().await, 0, "Nothing should be left running");
+}
+
+#[tokio::test]
+async fn test_identical_documents_share_one_parse() {
+    let backend = Backend::detached();
+    let first = Url::parse("file:///first.pain").unwrap();
+    let second = Url::parse("file:///second.pain").unwrap();
+    let text = "fn add(a: int, b: int) -> int:\n    return a + b\n";
+
+    open(&backend, &first, text).await;
+    open(&backend, &second, text).await;
+    assert_eq!(
+        backend.parse_count.load(std::sync::atomic::Ordering::Relaxed),
+        1,
+        "Identical content should be parsed once"
+    );
+    let cache = backend.parsed_cache.read().await;
+    assert!(cache.contains_key(&first) && cache.contains_key(&second), "Both documents have an entry");
+    drop(cache);
+
+    // Editing one document leaves the other's shared parse in place
+    open(&backend, &second, "fn main():\n    pass\n").await;
+    backend.get_or_parse(&first, text).await;
+    assert_eq!(backend.parse_count.load(std::sync::atomic::Ordering::Relaxed), 2);
+}
+
+// Tracing writer collecting the formatted log in memory
+#[derive(Clone, Default)]
+struct CapturedLog(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl std::io::Write for CapturedLog {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_slow_requests_are_logged_with_uri_and_position() {
+    let clock = std::sync::Arc::new(ManualClock(std::sync::Mutex::new(std::time::Instant::now())));
+    let mut backend = Backend::detached();
+    backend.set_clock(clock.clone());
+    backend.slow_request_threshold = Some(pain_lsp::SLOW_REQUEST_THRESHOLD);
+    let uri = Url::parse("file:///slow.pain").unwrap();
+    let position = Position { line: 3, character: 7 };
+
+    let log = CapturedLog::default();
+    let writer = log.clone();
+    let subscriber = tracing_subscriber::fmt().with_writer(move || writer.clone()).with_ansi(false).finish();
+    tracing::subscriber::with_default(subscriber, || {
+        let fast = backend.time_request("hover", &uri, position);
+        clock.advance(std::time::Duration::from_millis(10));
+        drop(fast);
+
+        let slow = backend.time_request("completion", &uri, position);
+        clock.advance(std::time::Duration::from_millis(120));
+        drop(slow);
+    });
+
+    let output = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+    assert!(!output.contains("slow hover"), "Fast requests are not logged: {}", output);
+    assert!(output.contains("WARN"), "{}", output);
+    assert!(output.contains("slow completion: 120ms (uri=file:///slow.pain, position=3:7)"), "{}", output);
+}
@@ -0,0 +1,35 @@
+// LSP formatting tests - test document formatting and its fixpoint behavior
+
+use pain_lsp::{format_text, formatting_edits};
+
+const MESSY: &str = "/// Adds two numbers   \n\nfn add(a: int, b: int) -> int:  \n    return a + b\n\n\n\n\nfn main():\t\n    print(add(1, 2))\n\n\n";
+
+#[test]
+fn test_formatting_is_idempotent() {
+    let edits = formatting_edits(MESSY);
+    assert_eq!(edits.len(), 1, "Messy input should need formatting");
+    let formatted = &edits[0].new_text;
+
+    assert!(formatting_edits(formatted).is_empty(), "Formatting twice should produce no edits:\n{}", formatted);
+    assert_eq!(&format_text(formatted), formatted);
+    assert!(!formatted.lines().any(|line| line != line.trim_end()), "Trailing whitespace should be gone");
+    assert!(!formatted.contains("\n\n\n\n"), "At most two blank lines should remain");
+    assert!(formatted.ends_with("print(add(1, 2))\n"), "Exactly one trailing newline should remain");
+}
+
+#[test]
+fn test_formatting_keeps_doc_comment_attached() {
+    let formatted = format_text(MESSY);
+    assert!(
+        formatted.starts_with("/// Adds two numbers\nfn add(a: int, b: int) -> int:\n"),
+        "The doc comment should stay directly above its function:\n{}",
+        formatted
+    );
+}
+
+#[test]
+fn test_formatting_keeps_crlf_line_endings() {
+    let formatted = format_text("fn main():  \r\n    pass\r\n\r\n");
+    assert_eq!(formatted, "fn main():\r\n    pass\r\n");
+    assert!(formatting_edits(&formatted).is_empty());
+}
@@ -1,7 +1,7 @@
 // LSP hover tests - test hover tooltip accuracy
 
 use pain_compiler::parse_with_recovery;
-use pain_lsp::{find_function_at_position, format_function_signature};
+use pain_lsp::{find_function_at_position, format_function_signature, keyword_hover};
 use tower_lsp::lsp_types::*;
 
 #[test]
@@ -55,6 +55,26 @@ fn add(a: int, b: int) -> int:
     }
 }
 
+#[test]
+fn test_doc_comment_args_and_returns() {
+    use pain_lsp::{format_doc_markdown, parse_doc_comment};
+
+    let doc = "This function adds two numbers\n\nArgs:\n  a: First number\n  b: Second number,\n     never negative\n\nReturns:\n  Sum of a and b";
+    let parsed = parse_doc_comment(doc).expect("Doc follows the Args/Returns convention");
+    assert_eq!(parsed.summary, "This function adds two numbers");
+    assert_eq!(parsed.args[0], ("a".to_string(), "First number".to_string()));
+    assert_eq!(parsed.args[1], ("b".to_string(), "Second number, never negative".to_string()));
+    assert_eq!(parsed.returns.as_deref(), Some("Sum of a and b"));
+
+    let markdown = format_doc_markdown(doc);
+    assert!(markdown.contains("- `a`: First number"), "{}", markdown);
+    assert!(markdown.contains("**Returns**: Sum of a and b"), "{}", markdown);
+
+    // Free-form docs are shown as written
+    assert!(parse_doc_comment("Prints a greeting").is_none());
+    assert_eq!(format_doc_markdown("Prints a greeting"), "Prints a greeting");
+}
+
 #[test]
 fn test_hover_no_function_at_position() {
     let code = r#"
@@ -123,3 +143,283 @@ fn outer():
     }
 }
 
+
+#[test]
+fn test_hover_keyword_while() {
+    let code = r#"
+fn main():
+    var i = 0
+    while i < 10:
+        i = i + 1
+"#;
+
+    // Cursor on `while` (line 3, character 6)
+    let hover = keyword_hover(code, Position { line: 3, character: 6 });
+    assert!(hover.is_some(), "Should return documentation for `while`");
+
+    if let Some(Hover { contents: HoverContents::Markup(markup), .. }) = hover {
+        assert_eq!(markup.kind, MarkupKind::Markdown, "Keyword docs should be Markdown");
+        assert!(markup.value.contains("loop"), "Docs for `while` should mention loop");
+        assert!(markup.value.contains("```pain"), "Docs should include an example");
+    } else {
+        panic!("Keyword hover should use Markdown content");
+    }
+}
+
+#[test]
+fn test_hover_keyword_ignores_identifiers() {
+    let code = "fn main():\n    let whiley = 1\n";
+
+    // `whiley` is an identifier, not the `while` keyword
+    assert!(keyword_hover(code, Position { line: 1, character: 10 }).is_none());
+}
+
+fn variable_hover_text(code: &str, line: u32, character: u32) -> Option<String> {
+    let (parse_result, _) = parse_with_recovery(code);
+    let program = parse_result.ok()?;
+    match pain_lsp::variable_hover(&program, code, Position { line, character })?.contents {
+        HoverContents::Markup(markup) => Some(markup.value),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_hover_variable_constant_value() {
+    let code = r#"
+fn main():
+    let x = 2 + 3 * 4
+    print(x)
+"#;
+
+    let hover = variable_hover_text(code, 3, 10).expect("Should hover the local `x`");
+    assert!(hover.contains("x: int = 14"), "Hover should show the folded value: {}", hover);
+}
+
+#[test]
+fn test_hover_variable_without_constant_value() {
+    let code = r#"
+fn main(n: int):
+    let y = n + 1
+    print(y)
+"#;
+
+    let hover = variable_hover_text(code, 3, 10).expect("Should hover the local `y`");
+    assert!(!hover.contains(" = "), "Non-constant initializers should not show a value: {}", hover);
+}
+
+#[test]
+fn test_hover_stdlib_function_usages() {
+    let code = r#"
+fn main():
+    let items = [1, 2, 3]
+    print(len(items))
+"#;
+
+    let (parse_result, _) = parse_with_recovery(code);
+    let program = parse_result.expect("code should parse");
+
+    for (name, character) in [("print", 5), ("len", 11)] {
+        let info = pain_lsp::find_stdlib_function_at_position(&program, code, Position { line: 3, character })
+            .unwrap_or_else(|| panic!("Should hover stdlib `{}`", name));
+        let expected = pain_compiler::stdlib::get_stdlib_functions()
+            .into_iter()
+            .find(|func| func.name == name)
+            .expect("function should be in the stdlib");
+        assert!(info.signature.starts_with(&format!("{}(", name)), "Signature: {}", info.signature);
+        assert_eq!(info.doc.as_deref(), Some(expected.description.as_str()));
+    }
+}
+
+#[test]
+fn test_hover_user_function_shadows_stdlib() {
+    let code = r#"
+fn len(items: list[int]) -> int:
+    return 0
+
+fn main():
+    print(len([1]))
+"#;
+
+    let (parse_result, _) = parse_with_recovery(code);
+    let program = parse_result.expect("code should parse");
+    assert!(pain_lsp::find_stdlib_function_at_position(&program, code, Position { line: 5, character: 11 }).is_none());
+}
+
+#[tokio::test]
+async fn test_hover_reuses_cached_parse() {
+    use tower_lsp::LanguageServer;
+
+    let backend = pain_lsp::Backend::detached();
+    let uri = Url::parse("file:///cached.pain").unwrap();
+
+    backend
+        .did_open(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "pain".to_string(),
+                version: 1,
+                text: "fn add(a: int, b: int) -> int:\n    return a + b\n".to_string(),
+            },
+        })
+        .await;
+    let parses_after_open = backend.parse_count.load(std::sync::atomic::Ordering::Relaxed);
+    assert_eq!(parses_after_open, 1, "Diagnostics should parse the opened document once");
+
+    for _ in 0..3 {
+        let _ = backend
+            .hover(HoverParams {
+                text_document_position_params: TextDocumentPositionParams {
+                    text_document: TextDocumentIdentifier { uri: uri.clone() },
+                    position: Position { line: 0, character: 4 },
+                },
+                work_done_progress_params: WorkDoneProgressParams::default(),
+            })
+            .await;
+    }
+    assert_eq!(
+        backend.parse_count.load(std::sync::atomic::Ordering::Relaxed),
+        parses_after_open,
+        "Hovering an unchanged document should reuse the cached parse"
+    );
+}
+
+#[test]
+fn test_hover_for_loop_variable_over_int_list() {
+    let code = r#"
+fn main():
+    for i in [1, 2, 3]:
+        print(i)
+"#;
+
+    let hover = variable_hover_text(code, 3, 14).expect("Should hover the loop variable");
+    assert!(hover.contains("i: int"), "Loop variable should be typed from the list: {}", hover);
+}
+
+#[test]
+fn test_hover_for_loop_variable_over_typed_array() {
+    let code = r#"
+fn total(values: array[float64]) -> float64:
+    var sum = 0.0
+    for v in values:
+        sum = sum + v
+    return sum
+"#;
+
+    let hover = variable_hover_text(code, 4, 20).expect("Should hover the loop variable");
+    assert!(hover.contains("v: float64"), "Loop variable should take the array element type: {}", hover);
+}
+
+#[test]
+fn test_hover_for_loop_variable_over_map_keys() {
+    let code = r#"
+fn main(ages: map[str, int]):
+    for name in ages:
+        print(name)
+"#;
+
+    let hover = variable_hover_text(code, 3, 15).expect("Should hover the loop variable");
+    assert!(hover.contains("name: str"), "Iterating a map yields its keys: {}", hover);
+}
+
+const NODE_CODE: &str = r#"
+class Counter:
+    let count: int
+
+    fn increment(step: int):
+        self.count = self.count + step
+
+fn main():
+    let c = Counter()
+    c.increment(2)
+    print(c.count)
+"#;
+
+fn node_kind(line: usize, column: usize) -> Option<(pain_lsp::analysis::NodeKind, String)> {
+    let (parse_result, _) = parse_with_recovery(NODE_CODE);
+    let program = parse_result.expect("code should parse");
+    pain_lsp::analysis::node_at_position(&program, NODE_CODE, line, column).map(|node| (node.kind, node.name))
+}
+
+#[test]
+fn test_node_at_position_declarations() {
+    use pain_lsp::analysis::NodeKind;
+
+    assert_eq!(node_kind(1, 7), Some((NodeKind::Class, "Counter".to_string())));
+    assert_eq!(node_kind(4, 8), Some((NodeKind::Function, "increment".to_string())));
+    assert_eq!(node_kind(4, 19), Some((NodeKind::Parameter, "step".to_string())));
+    assert_eq!(node_kind(8, 8), Some((NodeKind::Declaration, "c".to_string())));
+}
+
+#[test]
+fn test_node_at_position_identifiers_and_calls() {
+    use pain_lsp::analysis::NodeKind;
+
+    assert_eq!(node_kind(8, 13), Some((NodeKind::Call, "Counter".to_string())));
+    assert_eq!(node_kind(9, 4), Some((NodeKind::Identifier, "c".to_string())));
+    assert_eq!(node_kind(9, 8), Some((NodeKind::Member, "increment".to_string())));
+    assert_eq!(node_kind(10, 4), Some((NodeKind::Call, "print".to_string())));
+    // `step` used in the body is a name, not the parameter declaration
+    assert_eq!(node_kind(5, 35), Some((NodeKind::Identifier, "step".to_string())));
+}
+
+#[test]
+fn test_node_at_position_falls_back_to_enclosing_item() {
+    use pain_lsp::analysis::NodeKind;
+
+    // On the `=` in the method body: the method itself
+    let (parse_result, _) = parse_with_recovery(NODE_CODE);
+    let program = parse_result.expect("code should parse");
+    let node = pain_lsp::analysis::node_at_position(&program, NODE_CODE, 5, 19).expect("Inside a method");
+    assert_eq!((node.kind, node.name.as_str()), (NodeKind::Function, "increment"));
+    assert_eq!(node.class.map(|class| class.name.as_str()), Some("Counter"));
+
+    // On the `:` of a field: the class
+    assert_eq!(node_kind(2, 13), Some((NodeKind::Class, "Counter".to_string())));
+    // Between items: nothing
+    assert_eq!(node_kind(6, 0), None);
+}
+
+#[test]
+fn test_function_hover_uses_node_at_position() {
+    let (parse_result, _) = parse_with_recovery(NODE_CODE);
+    let program = parse_result.expect("code should parse");
+    let hover = |line, character| pain_lsp::function_hover(&program, NODE_CODE, Position { line, character });
+
+    // Method name in its header and in a member call
+    let header = hover(4, 8).expect("Should hover the method header");
+    assert!(header.signature.contains("fn increment(step: int)"), "Signature: {}", header.signature);
+    let call = hover(9, 8).expect("Should hover the method call");
+    assert_eq!(call.signature, header.signature);
+
+    // The body of a function is not its name
+    assert!(hover(5, 19).is_none());
+}
+
+fn literal_hover_text(code: &str, line: u32, character: u32) -> Option<String> {
+    let (parse_result, _) = parse_with_recovery(code);
+    let program = parse_result.ok()?;
+    match pain_lsp::collection_literal_hover(&program, code, Position { line, character })?.contents {
+        HoverContents::Markup(markup) => Some(markup.value),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_hover_int_list_literal() {
+    let code = "fn main():\n    let xs = [1, 2, 3]\n    let mixed = [1, \"two\"]\n    print(xs[0])\n    print(mixed)\n";
+    let hover = literal_hover_text(code, 1, 13).expect("Should hover the list literal");
+    assert!(hover.contains("list[int]"), "Hover: {}", hover);
+
+    let hover = literal_hover_text(code, 2, 16).expect("Should hover the mixed literal");
+    assert!(hover.contains("list[dynamic]"), "Heterogeneous lists fall back to dynamic: {}", hover);
+
+    // Indexing is not a literal
+    assert!(literal_hover_text(code, 3, 12).is_none());
+}
+
+#[test]
+fn test_hover_string_keyed_map_literal() {
+    let code = "fn main():\n    let counts = {\"a\": 1, \"b\": 2}\n    print(counts)\n";
+    let hover = literal_hover_text(code, 1, 17).expect("Should hover the map literal");
+    assert!(hover.contains("map[str, int]"), "Hover: {}", hover);
+}
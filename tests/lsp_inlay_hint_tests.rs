@@ -0,0 +1,66 @@
+// LSP inlay hint tests - test parameter name hints at call sites
+
+use pain_lsp::Backend;
+use tower_lsp::lsp_types::*;
+use tower_lsp::LanguageServer;
+
+async fn inlay_hints(backend: &Backend, code: &str) -> Vec<InlayHint> {
+    let uri = Url::parse("file:///hints.pain").unwrap();
+    backend
+        .did_open(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "pain".to_string(),
+                version: 1,
+                text: code.to_string(),
+            },
+        })
+        .await;
+
+    let line_count = code.lines().count() as u32;
+    backend
+        .inlay_hint(InlayHintParams {
+            work_done_progress_params: Default::default(),
+            text_document: TextDocumentIdentifier { uri },
+            range: Range {
+                start: Position { line: 0, character: 0 },
+                end: Position { line: line_count, character: 0 },
+            },
+        })
+        .await
+        .unwrap()
+        .unwrap_or_default()
+}
+
+fn label(hint: &InlayHint) -> &str {
+    match &hint.label {
+        InlayHintLabel::String(label) => label,
+        InlayHintLabel::LabelParts(_) => panic!("Expected a plain label"),
+    }
+}
+
+#[tokio::test]
+async fn test_parameter_hints_for_two_argument_call() {
+    let backend = Backend::detached();
+    let code = "fn add(a: int, b: int) -> int:\n    return a + b\n\nfn main():\n    print(add(1, 2))\n";
+    let hints = inlay_hints(&backend, code).await;
+
+    // The hint for `print`'s own argument sits at column 10
+    let add_hints: Vec<_> = hints.iter().filter(|hint| hint.position.line == 4 && hint.position.character > 10).collect();
+    assert_eq!(add_hints.len(), 2, "Each argument of `add` should get a hint: {:?}", hints);
+    assert_eq!(label(add_hints[0]), "a:");
+    assert_eq!(add_hints[0].position, Position { line: 4, character: 14 });
+    assert_eq!(label(add_hints[1]), "b:");
+    assert_eq!(add_hints[1].position, Position { line: 4, character: 17 });
+    assert!(add_hints.iter().all(|hint| hint.kind == Some(InlayHintKind::PARAMETER)));
+}
+
+#[tokio::test]
+async fn test_parameter_hint_skipped_for_matching_variable() {
+    let backend = Backend::detached();
+    let code = "fn add(a: int, b: int) -> int:\n    return a + b\n\nfn main():\n    let a = 1\n    let total = add(a, 2)\n";
+    let hints = inlay_hints(&backend, code).await;
+
+    let labels: Vec<&str> = hints.iter().filter(|hint| hint.position.line == 5).map(label).collect();
+    assert_eq!(labels, vec!["b:"], "`a` passed as `a` needs no hint");
+}
@@ -0,0 +1,139 @@
+// LSP position tests - test position encoding negotiation and conversions
+
+use pain_lsp::position::*;
+use tower_lsp::lsp_types::*;
+
+// `é` is 2 bytes / 1 UTF-16 unit, `😀` is 4 bytes / 2 UTF-16 units
+const MULTIBYTE: &str = "fn main():\n    print(\"é😀\" + x)\n";
+
+#[test]
+fn test_negotiate_prefers_utf8_when_offered() {
+    let offered = vec![PositionEncodingKind::UTF16, PositionEncodingKind::UTF8];
+    assert_eq!(negotiate_position_encoding(Some(&offered)), PositionEncodingKind::UTF8);
+}
+
+#[test]
+fn test_negotiate_defaults_to_utf16() {
+    assert_eq!(negotiate_position_encoding(None), PositionEncodingKind::UTF16);
+    let offered = vec![PositionEncodingKind::UTF32];
+    assert_eq!(negotiate_position_encoding(Some(&offered)), PositionEncodingKind::UTF16);
+}
+
+#[test]
+fn test_utf16_position_math_on_multibyte_line() {
+    let x_offset = MULTIBYTE.find('x').unwrap();
+    // 4 spaces + `print(` + `"` + é + 😀 (2 units) + `"` + ` + ` = 18
+    let position = Position { line: 1, character: 18 };
+
+    assert_eq!(position_to_offset(MULTIBYTE, position, &PositionEncodingKind::UTF16), Some(x_offset));
+    assert_eq!(offset_to_position(MULTIBYTE, x_offset, &PositionEncodingKind::UTF16), position);
+}
+
+#[test]
+fn test_utf8_position_math_on_multibyte_line() {
+    let x_offset = MULTIBYTE.find('x').unwrap();
+    // UTF-8 columns are byte columns: 4 + 6 + 1 + 2 + 4 + 1 + 3 = 21
+    let position = Position { line: 1, character: 21 };
+
+    assert_eq!(position_to_offset(MULTIBYTE, position, &PositionEncodingKind::UTF8), Some(x_offset));
+    assert_eq!(offset_to_position(MULTIBYTE, x_offset, &PositionEncodingKind::UTF8), position);
+}
+
+#[test]
+fn test_byte_position_round_trip() {
+    let utf16 = Position { line: 1, character: 18 };
+    let bytes = to_byte_position(MULTIBYTE, utf16, &PositionEncodingKind::UTF16);
+    assert_eq!(bytes, Position { line: 1, character: 21 });
+    assert_eq!(from_byte_position(MULTIBYTE, bytes, &PositionEncodingKind::UTF16), utf16);
+}
+
+#[test]
+fn test_position_past_end_of_line_clamps() {
+    let position = Position { line: 0, character: 100 };
+    assert_eq!(position_to_offset(MULTIBYTE, position, &PositionEncodingKind::UTF16), Some(10));
+    assert_eq!(position_to_offset(MULTIBYTE, Position { line: 9, character: 0 }, &PositionEncodingKind::UTF16), None);
+}
+
+#[test]
+fn test_public_offset_conversion_ascii() {
+    let text = "fn main():\n    print(x)\n";
+    let position = Position { line: 1, character: 10 };
+    assert_eq!(pain_lsp::lsp_position_to_offset(text, position, PositionEncodingKind::UTF16), Some(21));
+    assert_eq!(pain_lsp::offset_to_lsp_position(text, 21, PositionEncodingKind::UTF16), Some(position));
+    assert_eq!(pain_lsp::lsp_position_to_offset(text, Position { line: 5, character: 0 }, PositionEncodingKind::UTF16), None);
+    assert_eq!(pain_lsp::offset_to_lsp_position(text, text.len() + 1, PositionEncodingKind::UTF16), None);
+}
+
+#[test]
+fn test_public_offset_conversion_crlf() {
+    let text = "let a = 1\r\nlet b = 2\r\n";
+    // Past the end of the first line clamps before the `\r`
+    let offset = pain_lsp::lsp_position_to_offset(text, Position { line: 0, character: 40 }, PositionEncodingKind::UTF16);
+    assert_eq!(offset, Some(9));
+    // The second line starts after the `\r\n`
+    let b = text.find('b').unwrap();
+    let position = Position { line: 1, character: 4 };
+    assert_eq!(pain_lsp::lsp_position_to_offset(text, position, PositionEncodingKind::UTF16), Some(b));
+    assert_eq!(pain_lsp::offset_to_lsp_position(text, b, PositionEncodingKind::UTF16), Some(position));
+}
+
+#[test]
+fn test_public_offset_conversion_multibyte() {
+    let x_offset = MULTIBYTE.find('x').unwrap();
+    for (encoding, character) in [
+        (PositionEncodingKind::UTF16, 18),
+        (PositionEncodingKind::UTF8, 21),
+        (PositionEncodingKind::UTF32, 17),
+    ] {
+        let position = Position { line: 1, character };
+        assert_eq!(pain_lsp::lsp_position_to_offset(MULTIBYTE, position, encoding.clone()), Some(x_offset));
+        assert_eq!(pain_lsp::offset_to_lsp_position(MULTIBYTE, x_offset, encoding), Some(position));
+    }
+}
+
+#[test]
+fn test_public_offset_conversion_inside_character() {
+    let e_offset = MULTIBYTE.find('é').unwrap();
+    let emoji_offset = MULTIBYTE.find('😀').unwrap();
+
+    // One byte into `é` rounds down to the column before it
+    let position = pain_lsp::offset_to_lsp_position(MULTIBYTE, e_offset + 1, PositionEncodingKind::UTF16);
+    assert_eq!(position, Some(Position { line: 1, character: 11 }));
+    // Any byte inside the surrogate-pair emoji rounds down to its start
+    for inside in 1..4 {
+        let position = pain_lsp::offset_to_lsp_position(MULTIBYTE, emoji_offset + inside, PositionEncodingKind::UTF16);
+        assert_eq!(position, Some(Position { line: 1, character: 12 }));
+    }
+    let position = offset_to_position(MULTIBYTE, emoji_offset + 2, &PositionEncodingKind::UTF8);
+    assert_eq!(position, Position { line: 1, character: 13 });
+}
+
+#[test]
+fn test_tab_indented_position_math() {
+    let code = "fn main():\n\tlet x = y\n";
+    let y_offset = code.find('y').unwrap();
+    // The tab counts as one code unit whatever width the editor draws it with
+    let position = Position { line: 1, character: 9 };
+
+    for encoding in [PositionEncodingKind::UTF8, PositionEncodingKind::UTF16, PositionEncodingKind::UTF32] {
+        assert_eq!(position_to_offset(code, position, &encoding), Some(y_offset));
+        assert_eq!(offset_to_position(code, y_offset, &encoding), position);
+        assert_eq!(to_byte_position(code, position, &encoding), position);
+    }
+}
+
+#[test]
+fn test_huge_span_positions_saturate() {
+    use pain_compiler::span::{Position as SpanPosition, Span};
+
+    assert_eq!(clamp_to_u32(42), 42);
+    assert_eq!(clamp_to_u32(u32::MAX as usize + 5), u32::MAX, "Should saturate, not wrap to 4");
+
+    let mut span = Span::new(SpanPosition::start(), SpanPosition::start());
+    span.start.line = usize::MAX;
+    span.start.column = u32::MAX as usize + 10;
+    span.end = span.start.clone();
+    let range = pain_lsp::span_to_range(&span, "fn main():\n    pass\n");
+    assert_eq!(range.start, Position { line: u32::MAX, character: u32::MAX });
+    assert_eq!(range.end, Position { line: u32::MAX, character: u32::MAX });
+}
@@ -0,0 +1,48 @@
+// LSP rename tests - test rename edit computation
+
+use pain_compiler::parse_with_recovery;
+use pain_lsp::rename_edits;
+use tower_lsp::lsp_types::*;
+
+const CODE: &str = r#"
+/// Adds two numbers
+fn add(a: int, b: int) -> int:
+    return a + b
+
+/// Doubles a number - see also add, not add_all
+fn double(x: int) -> int:
+    return add(x, x)
+
+fn add_all(xs: list[int]) -> int:
+    return 0
+"#;
+
+fn edit_lines(update_doc_comments: bool) -> Vec<(u32, u32)> {
+    let (parse_result, _) = parse_with_recovery(CODE);
+    let program = parse_result.expect("code should parse");
+    // Cursor on `add` in `fn add`
+    let edits = rename_edits(&program, CODE, Position { line: 2, character: 4 }, "sum", update_doc_comments)
+        .expect("`add` should be renamable");
+    assert!(edits.iter().all(|edit| edit.new_text == "sum"));
+    edits.iter().map(|edit| (edit.range.start.line, edit.range.start.character)).collect()
+}
+
+#[test]
+fn test_rename_function_and_calls() {
+    // Declaration and the call in `double` - `add_all` is left alone
+    assert_eq!(edit_lines(false), vec![(2, 3), (7, 11)]);
+}
+
+#[test]
+fn test_rename_updates_doc_comments_when_enabled() {
+    // `see also add` is renamed, `add_all` in the same comment is not
+    assert_eq!(edit_lines(true), vec![(2, 3), (5, 32), (7, 11)]);
+}
+
+#[test]
+fn test_rename_rejects_invalid_names() {
+    assert!(pain_lsp::is_identifier("total_2"));
+    assert!(!pain_lsp::is_identifier("2total"));
+    assert!(!pain_lsp::is_identifier("while"));
+    assert!(!pain_lsp::is_identifier(""));
+}
@@ -0,0 +1,136 @@
+// LSP semantic tokens tests - test full and delta token requests
+
+use pain_compiler::parse_with_recovery;
+use pain_lsp::{semantic_token_edits, semantic_tokens, Backend, SEMANTIC_TOKEN_MODIFIERS, SEMANTIC_TOKEN_TYPES};
+use tower_lsp::lsp_types::*;
+use tower_lsp::LanguageServer;
+
+const CODE: &str = r#"
+class Point:
+    let x: int
+
+fn add(a: int, b: int) -> int:
+    return a + b
+
+fn main():
+    let total = add(1, 2)
+"#;
+
+#[test]
+fn test_semantic_tokens_classify_identifiers() {
+    let (parse_result, _) = parse_with_recovery(CODE);
+    let program = parse_result.expect("code should parse");
+    let tokens = semantic_tokens(&program, CODE, &PositionEncodingKind::UTF16);
+
+    // Resolve the relative encoding back to absolute (line, column, type)
+    let mut line = 0;
+    let mut column = 0;
+    let absolute: Vec<(u32, u32, SemanticTokenType)> = tokens
+        .iter()
+        .map(|token| {
+            if token.delta_line > 0 {
+                column = 0;
+            }
+            line += token.delta_line;
+            column += token.delta_start;
+            (line, column, SEMANTIC_TOKEN_TYPES[token.token_type as usize].clone())
+        })
+        .collect();
+
+    assert!(absolute.contains(&(1, 0, SemanticTokenType::KEYWORD)), "`class` should be a keyword");
+    assert!(absolute.contains(&(1, 6, SemanticTokenType::CLASS)), "`Point` should be a class");
+    assert!(absolute.contains(&(4, 3, SemanticTokenType::FUNCTION)), "`add` should be a function");
+    assert!(absolute.contains(&(8, 8, SemanticTokenType::VARIABLE)), "`total` should be a variable");
+    assert!(absolute.contains(&(8, 16, SemanticTokenType::FUNCTION)), "The call to `add` should be a function");
+    assert!(absolute.contains(&(8, 20, SemanticTokenType::NUMBER)), "`1` should be a number");
+}
+
+#[test]
+fn test_semantic_token_modifiers_mark_readonly_bindings() {
+    let code = "fn scale(factor: int) -> int:\n    let fixed = 2\n    var count = 0\n    count = fixed * factor\n    return count\n";
+    let (parse_result, _) = parse_with_recovery(code);
+    let program = parse_result.expect("code should parse");
+    let tokens = semantic_tokens(&program, code, &PositionEncodingKind::UTF16);
+
+    // Modifier bits keyed by absolute (line, column)
+    let mut line = 0;
+    let mut column = 0;
+    let mut modifiers = std::collections::HashMap::new();
+    for token in &tokens {
+        if token.delta_line > 0 {
+            column = 0;
+        }
+        line += token.delta_line;
+        column += token.delta_start;
+        modifiers.insert((line, column), token.token_modifiers_bitset);
+    }
+    let bit = |modifier: SemanticTokenModifier| {
+        1 << SEMANTIC_TOKEN_MODIFIERS.iter().position(|known| *known == modifier).expect("modifier in legend")
+    };
+    let readonly = bit(SemanticTokenModifier::READONLY);
+
+    assert_ne!(modifiers[&(1, 8)] & readonly, 0, "`let fixed` should be readonly");
+    assert_ne!(modifiers[&(3, 12)] & readonly, 0, "Uses of `fixed` should be readonly");
+    assert_ne!(modifiers[&(3, 20)] & readonly, 0, "The parameter `factor` should be readonly");
+    assert_eq!(modifiers[&(2, 8)] & readonly, 0, "`var count` should not be readonly");
+    let declaration = bit(SemanticTokenModifier::DECLARATION) | bit(SemanticTokenModifier::DEFINITION);
+    assert_eq!(modifiers[&(0, 3)] & declaration, declaration, "`fn scale` declares and defines `scale`");
+}
+
+#[test]
+fn test_semantic_token_edits_replace_changed_tokens() {
+    let token = |length| SemanticToken { delta_line: 1, delta_start: 0, length, token_type: 0, token_modifiers_bitset: 0 };
+    let previous = vec![token(1), token(2), token(3)];
+    let current = vec![token(1), token(5), token(6), token(3)];
+
+    let edits = semantic_token_edits(&previous, &current);
+    assert_eq!(edits.len(), 1);
+    assert_eq!(edits[0].start, 5, "The first token is unchanged");
+    assert_eq!(edits[0].delete_count, 5, "Only the second token is replaced");
+    assert_eq!(edits[0].data, Some(vec![token(5), token(6)]));
+}
+
+#[tokio::test]
+async fn test_semantic_tokens_delta_unchanged_document_is_empty() {
+    let backend = Backend::detached();
+    let uri = Url::parse("file:///tokens.pain").unwrap();
+    backend
+        .did_open(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "pain".to_string(),
+                version: 1,
+                text: CODE.to_string(),
+            },
+        })
+        .await;
+
+    let full = backend
+        .semantic_tokens_full(SemanticTokensParams {
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+        })
+        .await
+        .unwrap();
+    let Some(SemanticTokensResult::Tokens(full)) = full else { panic!("Expected full semantic tokens") };
+    assert!(!full.data.is_empty());
+    let result_id = full.result_id.expect("Full tokens should carry a result id");
+
+    let delta = backend
+        .semantic_tokens_full_delta(SemanticTokensDeltaParams {
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+            text_document: TextDocumentIdentifier { uri },
+            previous_result_id: result_id.clone(),
+        })
+        .await
+        .unwrap();
+    match delta {
+        Some(SemanticTokensFullDeltaResult::TokensDelta(delta)) => {
+            assert!(delta.edits.is_empty(), "An unchanged document should have no edits");
+            assert_eq!(delta.result_id, Some(result_id), "An unchanged document keeps its result id");
+        }
+        other => panic!("Expected a delta, got {:?}", other),
+    }
+}
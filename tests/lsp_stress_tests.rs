@@ -231,3 +231,53 @@ fn test_concurrent_parsing_simulation() {
     assert!(result3.is_ok(), "Third parse should succeed");
 }
 
+#[tokio::test]
+async fn test_cached_program_shared_not_cloned() {
+    // Same 1000-function file as test_large_file_parsing
+    let mut code = String::new();
+    for i in 0..1000 {
+        code.push_str(&format!("fn func_{}() -> int:\n    return {}\n\n", i, i));
+    }
+
+    let backend = pain_lsp::Backend::detached();
+    let uri = url::Url::parse("file:///stress.pain").unwrap();
+
+    let first = backend.get_or_parse(&uri, &code).await.program.expect("Should parse large file");
+    let second = backend.get_or_parse(&uri, &code).await.program.expect("Should hit the cache");
+    assert!(std::sync::Arc::ptr_eq(&first, &second), "Cache hits should share the parsed program");
+}
+
+#[test]
+fn test_statement_walkers_survive_pathological_nesting() {
+    const LEVELS: usize = 5000;
+    let mut code = String::from("fn main() -> int:\n");
+    for i in 0..LEVELS {
+        let indent = "    ".repeat(i + 1);
+        code.push_str(&format!("{}let v{} = {}\n{}if true:\n", indent, i, i, indent));
+    }
+    code.push_str(&format!("{}return 0\n", "    ".repeat(LEVELS + 1)));
+
+    // The compiler's own recursion is not under test - parse and drop the
+    // program on a thread with room for it
+    let parse = code.clone();
+    let program = std::thread::Builder::new()
+        .stack_size(1 << 30)
+        .spawn(move || parse_with_recovery(&parse).0.ok())
+        .unwrap()
+        .join()
+        .unwrap()
+        .expect("deeply nested program should parse");
+
+    let mut variables = std::collections::HashSet::new();
+    for item in &program.items {
+        if let pain_compiler::ast::Item::Function(func) = item {
+            pain_lsp::extract_variables_from_statements(&func.body, &mut variables);
+        }
+    }
+    assert!(!variables.is_empty(), "Shallow bindings are still found");
+    assert!(variables.len() < LEVELS, "The walk should stop at its depth limit");
+    let _ = pain_lsp::find_shadowed_bindings(&program, &code);
+    let _ = pain_lsp::find_missing_returns(&program, &code);
+
+    std::thread::Builder::new().stack_size(1 << 30).spawn(move || drop(program)).unwrap().join().unwrap();
+}
@@ -0,0 +1,103 @@
+// LSP type hierarchy tests - test class relationships across documents
+
+use pain_lsp::Backend;
+use tower_lsp::lsp_types::*;
+use tower_lsp::LanguageServer;
+
+async fn open(backend: &Backend, uri: &Url, text: &str) {
+    backend
+        .did_open(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "pain".to_string(),
+                version: 1,
+                text: text.to_string(),
+            },
+        })
+        .await;
+}
+
+const POINT: &str = "class Point:\n    let x: int\n    let y: int\n\n    fn new(x: int, y: int) -> Point:\n        return Point(x, y)\n";
+const SEGMENT: &str = "class Segment:\n    let start: Point\n    let end: Point\n\nfn main():\n    let s: Segment\n";
+
+#[tokio::test]
+async fn test_type_hierarchy_between_classes() {
+    let backend = Backend::detached();
+    let point_uri = Url::parse("file:///point.pain").unwrap();
+    let segment_uri = Url::parse("file:///segment.pain").unwrap();
+    open(&backend, &point_uri, POINT).await;
+    open(&backend, &segment_uri, SEGMENT).await;
+
+    // `Point` in `let start: Point` resolves to the class in the other document
+    let items = backend
+        .prepare_type_hierarchy(TypeHierarchyPrepareParams {
+            text_document_position_params: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri: segment_uri.clone() },
+                position: Position { line: 1, character: 16 },
+            },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        })
+        .await
+        .unwrap()
+        .expect("Cursor is on a class name");
+    assert_eq!(items.len(), 1);
+    let point = items[0].clone();
+    assert_eq!(point.name, "Point");
+    assert_eq!(point.uri, point_uri);
+    assert_eq!(point.selection_range, Range::new(Position::new(0, 6), Position::new(0, 11)));
+    assert_eq!(point.range.end.line, 5, "The range covers the class body");
+
+    // Segment uses Point, so it shows up below Point and Point above Segment
+    let subtypes = backend
+        .subtypes(TypeHierarchySubtypesParams {
+            item: point.clone(),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    let names: Vec<&str> = subtypes.iter().map(|item| item.name.as_str()).collect();
+    assert_eq!(names, vec!["Segment"], "Point's own constructor should not list itself");
+
+    let supertypes = backend
+        .supertypes(TypeHierarchySupertypesParams {
+            item: subtypes[0].clone(),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    let names: Vec<&str> = supertypes.iter().map(|item| item.name.as_str()).collect();
+    assert_eq!(names, vec!["Point"]);
+}
+
+#[tokio::test]
+async fn test_type_hierarchy_ignores_non_classes() {
+    let backend = Backend::detached();
+    let uri = Url::parse("file:///segment.pain").unwrap();
+    open(&backend, &uri, SEGMENT).await;
+
+    // `main` is a function
+    let items = backend
+        .prepare_type_hierarchy(TypeHierarchyPrepareParams {
+            text_document_position_params: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri },
+                position: Position { line: 4, character: 4 },
+            },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        })
+        .await
+        .unwrap();
+    assert!(items.is_none());
+}
+
+#[test]
+fn test_class_outlines_collect_references() {
+    let outlines = pain_lsp::workspace::class_outlines(SEGMENT);
+    assert_eq!(outlines.len(), 1);
+    assert_eq!(outlines[0].name, "Segment");
+    assert!(outlines[0].references.contains("Point"));
+    assert_eq!(outlines[0].range.end.line, 2, "The body ends before `fn main`");
+}
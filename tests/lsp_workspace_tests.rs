@@ -0,0 +1,220 @@
+// LSP workspace tests - test workspace file discovery and symbol indexing
+
+use pain_lsp::workspace::{find_pain_files, index_document};
+use tower_lsp::lsp_types::*;
+
+/// Create an empty scratch directory unique to this test
+fn scratch_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("pain_lsp_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn test_find_pain_files_skips_hidden_and_build_dirs() {
+    let dir = scratch_dir("find_files");
+    std::fs::create_dir_all(dir.join("src")).unwrap();
+    std::fs::create_dir_all(dir.join(".git")).unwrap();
+    std::fs::create_dir_all(dir.join("target")).unwrap();
+    std::fs::write(dir.join("main.pain"), "fn main():\n    pass\n").unwrap();
+    std::fs::write(dir.join("src/util.pain"), "fn util():\n    pass\n").unwrap();
+    std::fs::write(dir.join("src/notes.txt"), "not pain").unwrap();
+    std::fs::write(dir.join(".git/hidden.pain"), "fn hidden():\n    pass\n").unwrap();
+    std::fs::write(dir.join("target/built.pain"), "fn built():\n    pass\n").unwrap();
+
+    let files = find_pain_files(&dir);
+    assert_eq!(files, vec![dir.join("main.pain"), dir.join("src/util.pain")]);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_index_document_symbols() {
+    let code = r#"
+class Point:
+    let x: int
+
+    fn distance() -> float64:
+        return 0.0
+
+fn main():
+    pass
+"#;
+
+    let symbols = index_document(code);
+    let summary: Vec<_> = symbols
+        .iter()
+        .map(|s| (s.name.as_str(), s.kind, s.container.as_deref()))
+        .collect();
+    assert!(summary.contains(&("Point", SymbolKind::CLASS, None)));
+    assert!(summary.contains(&("distance", SymbolKind::METHOD, Some("Point"))));
+    assert!(summary.contains(&("main", SymbolKind::FUNCTION, None)));
+
+    let main = symbols.iter().find(|s| s.name == "main").unwrap();
+    assert_eq!(main.range.start, Position { line: 7, character: 3 });
+}
+
+#[test]
+fn test_document_links_for_existing_files_only() {
+    let dir = scratch_dir("document_links");
+    std::fs::write(dir.join("config.pml"), "key = 1\n").unwrap();
+    let code = "fn main():\n    let config = pml_load_file(\"config.pml\")\n    let other = pml_load_file(\"missing.pml\")\n";
+
+    let links = pain_lsp::workspace::find_document_links(code, &dir);
+    assert_eq!(links.len(), 1, "Only the existing file should be linked");
+    assert_eq!(links[0].target, Some(Url::from_file_path(dir.join("config.pml")).unwrap()));
+    assert_eq!(
+        links[0].range,
+        Range {
+            start: Position { line: 1, character: 32 },
+            end: Position { line: 1, character: 42 },
+        },
+        "The link should cover the path inside the quotes"
+    );
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_watched_file_events_update_index() {
+    use tower_lsp::LanguageServer;
+
+    let dir = scratch_dir("watched_files");
+    let path = dir.join("util.pain");
+    std::fs::write(&path, "fn helper() -> int:\n    return 1\n").unwrap();
+    let uri = Url::from_file_path(&path).unwrap();
+    let backend = pain_lsp::Backend::detached();
+    let event = |typ| DidChangeWatchedFilesParams {
+        changes: vec![FileEvent { uri: uri.clone(), typ }],
+    };
+
+    backend.did_change_watched_files(event(FileChangeType::CREATED)).await;
+    let names: Vec<String> = backend.workspace_index.read().await[&uri].iter().map(|s| s.name.clone()).collect();
+    assert_eq!(names, vec!["helper".to_string()]);
+
+    // Edited outside the editor
+    std::fs::write(&path, "fn renamed() -> int:\n    return 1\n").unwrap();
+    backend.did_change_watched_files(event(FileChangeType::CHANGED)).await;
+    let names: Vec<String> = backend.workspace_index.read().await[&uri].iter().map(|s| s.name.clone()).collect();
+    assert_eq!(names, vec!["renamed".to_string()]);
+
+    std::fs::remove_file(&path).unwrap();
+    backend.published_diagnostics.write().await.insert(uri.clone(), Vec::new());
+    backend.did_change_watched_files(event(FileChangeType::DELETED)).await;
+    assert!(!backend.workspace_index.read().await.contains_key(&uri), "Deleted files should leave the index");
+    assert!(!backend.published_diagnostics.read().await.contains_key(&uri), "Their diagnostics should be cleared");
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_deleting_an_open_file_keeps_its_analysis() {
+    use tower_lsp::LanguageServer;
+
+    let dir = scratch_dir("deleted_open_file");
+    let path = dir.join("main.pain");
+    let code = "fn main():\n    let total = 1\n";
+    std::fs::write(&path, code).unwrap();
+    let uri = Url::from_file_path(&path).unwrap();
+    let backend = pain_lsp::Backend::detached();
+    backend
+        .did_open(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "pain".to_string(),
+                version: 1,
+                text: code.to_string(),
+            },
+        })
+        .await;
+    backend.published_diagnostics.write().await.insert(uri.clone(), Vec::new());
+
+    // The buffer is still open, so it stays the source of truth
+    std::fs::remove_file(&path).unwrap();
+    backend
+        .did_change_watched_files(DidChangeWatchedFilesParams {
+            changes: vec![FileEvent { uri: uri.clone(), typ: FileChangeType::DELETED }],
+        })
+        .await;
+    assert!(backend.published_diagnostics.read().await.contains_key(&uri), "Open documents keep their diagnostics");
+    assert!(backend.documents.read().await.contains_key(&uri));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_document_links_follow_path_resolution_base() {
+    use pain_lsp::config::{PathResolutionBase, Settings};
+    use tower_lsp::LanguageServer;
+
+    let dir = scratch_dir("path_resolution_base");
+    std::fs::create_dir_all(dir.join("src")).unwrap();
+    std::fs::write(dir.join("config.pml"), "key = 1\n").unwrap();
+    std::fs::write(dir.join("src/config.pml"), "key = 2\n").unwrap();
+    let uri = Url::from_file_path(dir.join("src/main.pain")).unwrap();
+    let code = "fn main():\n    let config = pml_load_file(\"config.pml\")\n";
+
+    let backend = pain_lsp::Backend::detached();
+    *backend.workspace_roots.write().await = vec![Url::from_file_path(&dir).unwrap()];
+    backend
+        .did_open(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "pain".to_string(),
+                version: 1,
+                text: code.to_string(),
+            },
+        })
+        .await;
+    let params = || DocumentLinkParams {
+        text_document: TextDocumentIdentifier { uri: uri.clone() },
+        work_done_progress_params: Default::default(),
+        partial_result_params: Default::default(),
+    };
+
+    let links = backend.document_link(params()).await.unwrap().expect("Files on disk get links");
+    assert_eq!(links[0].target, Some(Url::from_file_path(dir.join("src/config.pml")).unwrap()), "Next to the document by default");
+
+    backend.update_settings(Settings { path_resolution_base: PathResolutionBase::WorkspaceRoot, ..Settings::default() });
+    let links = backend.document_link(params()).await.unwrap().expect("Files on disk get links");
+    assert_eq!(links[0].target, Some(Url::from_file_path(dir.join("config.pml")).unwrap()), "At the workspace root");
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_workspace_symbol_ranges_use_negotiated_encoding() {
+    use pain_lsp::workspace::IndexedSymbol;
+    use tower_lsp::LanguageServer;
+
+    let dir = scratch_dir("symbol_encoding");
+    let path = dir.join("cafe.pain");
+    std::fs::write(&path, "fn café():\n    pass\n").unwrap();
+    let uri = Url::from_file_path(&path).unwrap();
+    let backend = pain_lsp::Backend::detached();
+    // The index holds byte columns: `é` takes two bytes
+    let symbol = IndexedSymbol {
+        name: "café".to_string(),
+        kind: SymbolKind::FUNCTION,
+        range: Range {
+            start: Position { line: 0, character: 3 },
+            end: Position { line: 0, character: 8 },
+        },
+        container: None,
+    };
+    backend.workspace_index.write().await.insert(uri.clone(), vec![symbol]);
+
+    let symbols = backend
+        .symbol(WorkspaceSymbolParams { query: "caf".to_string(), ..Default::default() })
+        .await
+        .unwrap()
+        .expect("Should answer with symbols");
+    assert_eq!(symbols.len(), 1);
+    assert_eq!(symbols[0].location.uri, uri);
+    // Closed files are read from disk to convert to UTF-16 columns
+    assert_eq!(symbols[0].location.range.start, Position { line: 0, character: 3 });
+    assert_eq!(symbols[0].location.range.end, Position { line: 0, character: 7 });
+
+    let _ = std::fs::remove_dir_all(&dir);
+}